@@ -0,0 +1,310 @@
+# Deutsche Meldungen für po
+
+unknown = unbekannt
+
+## po show
+show-path =        Pfad           { $path }
+show-hash =        Hash           { $hash }
+show-imported-by = importiert von { $user }
+show-sequence =    Serie          { $sequence } ({ $frames } Bilder)
+show-group =       Gruppe         { $group } ({ $files } Dateien)
+show-tags =        Tags           { $tags }
+show-people =      Personen       { $people }
+show-prints =      Abzüge         { $count }
+show-duration =    Dauer          { $seconds } s
+show-text =        Text           { $text }
+show-derived =     abgeleitet     { $path } ({ $preset })
+
+## po inspect
+inspect-taken = { $path } aufgenommen am { $taken } mit { $camera }
+
+## po jobs
+job-running = läuft
+job-interrupted = unterbrochen
+job-summary = { $kind } ({ $args }) { $done }/{ $total } ({ $percent }%) { $state }
+job-remaining = , noch etwa { $minutes } Min.
+job-times = gestartet { $started }, letzter Checkpoint { $updated }
+job-cancelled = Auftrag { $kind } nach { $done } von { $total } Elementen abgebrochen
+job-task = { $name } (Daemon, seit { $started })
+
+## po log --audit
+audit-entry = { $time } { $action } von { $user } ({ $count ->
+        [one] 1 Datei
+       *[other] { $count } Dateien
+    }) `{ $command }`
+
+## po import --dry-run
+dry-run-new =             neu       { $path } -> { $destination }
+dry-run-in-library =      vorhanden { $path } -> { $existing }
+dry-run-duplicate-input = doppelt   { $path }, gleich wie { $other }
+dry-run-temporary =       übersprungen { $path }, von einem früheren Import übrig
+dry-run-inside-library =  übersprungen { $path }, liegt in der Bibliothek
+dry-run-dangling-link =   übersprungen { $path }, Verknüpfung ohne Ziel
+dry-run-in-other-library = in { $library } { $path } -> { $existing }
+dry-run-summary = würde { $new } Dateien importieren, { $skipped } übersprungen
+
+## po changes
+changes-added =    { $generation } hinzugefügt { $path }
+changes-removed =  { $generation } entfernt    { $path }
+changes-modified = { $generation } geändert    { $path }
+changes-moved =    { $generation } verschoben  { $previous } -> { $path }
+
+## po simulate
+simulate-collision = Konflikt { $path } -> { $destination }, dort liegt schon etwas anderes
+simulate-directory = { $dir }/ { $files ->
+        [one] 1 Datei
+       *[other] { $files } Dateien
+    } ({ $new } neu)
+simulate-summary = { $new } Dateien kämen in { $directories } Verzeichnisse, { $skipped } übersprungen, { $collisions } Konflikte
+
+## po verify
+verify-missing =    fehlt      { $path }
+verify-mismatched = verändert  { $path }, jetzt { $hash }
+verify-orphan =     nicht im Index { $path }
+verify-summary = { $checked } Dateien geprüft, { $missing } fehlen, { $mismatched } verändert, { $orphans } nicht im Index
+
+## po query --verify-state
+query-verified = geprüft am { $date }
+query-verified-stale = geprüft am { $date }, wieder fällig
+query-never-verified = nie geprüft
+query-verify-failed = Prüfung am { $date } FEHLGESCHLAGEN
+
+## po checksums
+checksums-written = Prüfsummen für { $files ->
+        [one] 1 Datei
+       *[other] { $files } Dateien
+    } geschrieben
+
+## po doctor
+doctor-ok =      ok       { $message }
+doctor-warning = Warnung  { $message }
+doctor-problem = Problem  { $message }
+doctor-fix =     Lösung   { $fix }
+doctor-summary = { $problems ->
+        [one] 1 Problem
+       *[other] { $problems } Probleme
+    }, { $warnings ->
+        [one] 1 Warnung
+       *[other] { $warnings } Warnungen
+    }
+
+## po rename
+rename-planned =   umbenennen { $from } -> { $to }
+rename-exists =    Konflikt   { $from } -> { $to }, existiert bereits
+rename-duplicate = Konflikt   { $from } -> { $to }, so soll auch { $other } heißen
+rename-collisions = { $count ->
+        [one] nichts umbenannt, 1 neuer Name kollidiert mit einer anderen Datei
+       *[other] nichts umbenannt, { $count } neue Namen kollidieren mit anderen Dateien
+    }
+rename-dry-run = { $count ->
+        [one] würde 1 Datei umbenennen
+       *[other] würde { $count } Dateien umbenennen
+    }
+
+## po prune
+prune-planned = { $action } { $path } (Tag { $tag })
+prune-dry-run = { $count ->
+        [one] 1 Datei ist
+       *[other] { $count } Dateien sind
+    } fällig, ohne --dry-run ausführen, um sie zu bearbeiten
+prune-done = { $trashed } in den Papierkorb, { $deleted } gelöscht und { $archived } archiviert
+
+## po remove
+remove-planned = entfernen  { $path }
+remove-deleted = gelöscht   { $path }
+remove-trashed = verschoben { $path }
+remove-dry-run = { $count ->
+        [one] würde 1 Datei entfernen
+       *[other] würde { $count } Dateien entfernen
+    }
+
+## po rollback
+rollback-nothing = der letzte Import wurde abgeschlossen, es gibt nichts zurückzunehmen
+rollback-done = letzten Import zurückgenommen, { $restored ->
+        [one] 1 Datei
+       *[other] { $restored } Dateien
+    } zurück in die Eingaben verschoben und { $removed ->
+        [one] 1 Kopie
+       *[other] { $removed } Kopien
+    } von dort noch vorhandenen Dateien entfernt
+rollback-missing = { $count ->
+        [one] 1 Datei wurde
+       *[other] { $count } Dateien wurden
+    } weder in der Bibliothek noch in den Eingaben gefunden, siehe oben
+rollback-kept = { $count ->
+        [one] 1 Datei wurde
+       *[other] { $count } Dateien wurden
+    } in der Bibliothek behalten, da in den Eingaben jetzt etwas anderes liegt, siehe oben
+
+## po dedupe
+dedupe-group = { $count } Fotos sehen gleich aus:
+dedupe-summary = { $groups ->
+        [one] 1 Gruppe
+       *[other] { $groups } Gruppen
+    } ähnlicher Fotos, { $files } Fotos insgesamt
+
+## po registry
+registry-synced = { $count } Dateien als { $name } in der Registry eingetragen
+
+## po exif
+exif-planned =   umschreiben  { $path }
+exif-rewritten = umgeschrieben { $path }
+exif-skipped =   übersprungen { $path }, nur JPEG-Dateien können umgeschrieben werden
+exif-xmp-kept = { $path } hat auch XMP, das noch den alten Urheber und die alten Rechte enthält. po schreibt nur EXIF
+exif-dry-run = { $count ->
+        [one] würde 1 Datei umschreiben
+       *[other] würde { $count } Dateien umschreiben
+    }
+exif-summary = { $count ->
+        [one] 1 Datei umgeschrieben
+       *[other] { $count } Dateien umgeschrieben
+    }, { $skipped } übersprungen
+
+## po gallery
+gallery-up-to-date = die Galerie ist aktuell
+gallery-written = { $pages ->
+        [one] 1 Seite
+       *[other] { $pages } Seiten
+    } und { $thumbnails ->
+        [one] 1 Vorschaubild
+       *[other] { $thumbnails } Vorschaubilder
+    } geschrieben
+
+## po thumbs
+thumbs-made = Vorschauen für { $made ->
+        [one] 1 Datei
+       *[other] { $made } Dateien
+    } erstellt, { $existing } hatten schon welche, { $skipped } können keine haben{ $removed ->
+        [0] {""}
+       *[other] , { $removed } nicht mehr benötigte entfernt
+    }
+
+## po remux
+remux-summary = { $remuxed ->
+        [one] 1 Video
+       *[other] { $remuxed } Videos
+    } neu verpackt, { $up_to_date } schon aktuell, { $skipped } übersprungen
+
+## po mirror
+mirror-summary = { $copied ->
+        [one] 1 Datei
+       *[other] { $copied } Dateien
+    } kopiert, { $updated } aktualisiert, { $removed } entfernt, { $up_to_date } schon aktuell, { $skipped } übersprungen
+mirror-interrupted = unterbrochen, po mirror erneut ausführen, um fertig zu werden
+
+## po map
+map-written = { $placed ->
+        [one] 1 Foto
+       *[other] { $placed } Fotos
+    } auf der Karte in { $path }, { $unplaced } ohne Ort
+
+## po tag
+tag-file = { $path }: { $tags }
+tag-file-none = { $path }: keine Tags
+tag-count = { $tag } ({ $count ->
+        [one] 1 Datei
+       *[other] { $count } Dateien
+    })
+
+## po prints
+prints-file = { $path }: { $count ->
+        [one] 1 Abzug
+       *[other] { $count } Abzüge
+    }
+prints-total = { $count ->
+        [one] 1 Abzug zu bestellen
+       *[other] { $count } Abzüge zu bestellen
+    }
+
+## po roll
+roll-name = { $roll } ({ $files ->
+        [one] 1 Datei
+       *[other] { $files } Dateien
+    })
+roll-film-stock =   Film           { $stock }
+roll-camera =       Kamera         { $camera }
+roll-developed =    entwickelt     { $developed }
+
+## po export --print
+print-order-written = { $photos ->
+        [one] 1 Foto
+       *[other] { $photos } Fotos
+    } für { $prints ->
+        [one] 1 Abzug
+       *[other] { $prints } Abzüge
+    } und ihre order.csv nach { $path } geschrieben, { $skipped } übersprungen
+
+## po classify
+classify-summary = { $classified ->
+        [one] 1 Foto
+       *[other] { $classified } Fotos
+    } klassifiziert, { $changed } mit neuen Tags
+
+## po ocr
+ocr-summary = { $read ->
+        [one] 1 Foto
+       *[other] { $read } Fotos
+    } gelesen, { $changed } mit neuem Text
+
+## po config docs
+config-docs-key = { $key } ({ $kind })
+config-docs-optional = optional
+config-docs-set-with = setzbar über { $ways }
+config-docs-accepted = einer von { $values }
+config-docs-default = Standard { $value }
+config-docs-value = { $value }, gesetzt { $source ->
+        [cli] auf der Kommandozeile
+        [env] in der Umgebung
+        [file] in der Konfigurationsdatei
+       *[default] durch den Standardwert
+    }
+config-docs-unset = nicht gesetzt
+
+## po contains
+contains-found =   vorhanden { $path } -> { $library_path }
+contains-missing = fehlt     { $path }
+contains-summary-missing = { $missing } von { $total } Dateien sind nicht in der Bibliothek
+contains-summary-complete = alle { $total } Dateien sind in der Bibliothek
+
+## po diff
+diff-summary = { $only_dir } nur im Verzeichnis, { $only_library } nur in der Bibliothek, { $both } in beiden
+
+## po stats
+stats-files = { $files ->
+        [one] 1 Datei
+       *[other] { $files } Dateien
+    }, { $gib } GiB
+stats-year =     { $year }   { $files }
+stats-month =        { $month }   { $files }
+stats-undated =  ohne Datum { $files }
+stats-extensions = nach Endung: { $extensions }
+stats-largest = größte Dateien:
+stats-largest-file = { $mib } MiB  { $path }
+stats-last-import = letzter Import { $time }
+stats-heatmap = { $photos ->
+        [one] 1 Foto
+       *[other] { $photos } Fotos
+    } aus { $year } nach { $path } gezeichnet
+stats-duplicates = { $files ->
+        [one] 1 Datei hat denselben Inhalt wie eine andere
+       *[other] { $files } Dateien haben denselben Inhalt wie eine andere
+    }
+
+## po backup verify
+backup-missing = { $missing } von { $total } Dateien der Bibliothek sind nicht gesichert
+backup-complete = alle { $total } Dateien der Bibliothek sind gesichert
+
+## Importe und Wartung
+import-interrupted = unterbrochen, { $imported } von { $found } neuen Dateien importiert. Starte den Import erneut, um den Rest zu übernehmen
+progress-hashing = hashen
+progress-sorting = einsortieren
+progress-thumbs = Vorschauen erstellen
+shutdown-requested = Beende nach der aktuellen Datei, erneut Strg+C drücken, um sofort abzubrechen
+naming-renamed = { $count ->
+        [one] 1 Datei umbenannt
+       *[other] { $count } Dateien umbenannt
+    }
+clean-sources-removed = { $count ->
+        [one] 1 Ordner mit aufbewahrten Originalen gelöscht
+       *[other] { $count } Ordner mit aufbewahrten Originalen gelöscht
+    }
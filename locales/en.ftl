@@ -0,0 +1,311 @@
+# English messages for po. This is the reference translation, every other locale
+# falls back to it for messages it doesn't have.
+
+unknown = unknown
+
+## po show
+show-path =        path        { $path }
+show-hash =        hash        { $hash }
+show-imported-by = imported by { $user }
+show-sequence =    sequence    { $sequence } ({ $frames } frames)
+show-group =       group       { $group } ({ $files } files)
+show-tags =        tags        { $tags }
+show-people =      people      { $people }
+show-prints =      prints      { $count }
+show-duration =    duration    { $seconds }s
+show-text =        text        { $text }
+show-derived =     derived     { $path } ({ $preset })
+
+## po inspect
+inspect-taken = { $path } taken { $taken } on { $camera }
+
+## po jobs
+job-running = running
+job-interrupted = interrupted
+job-summary = { $kind } ({ $args }) { $done }/{ $total } ({ $percent }%) { $state }
+job-remaining = , about { $minutes }m left
+job-times = started { $started }, last checkpoint { $updated }
+job-cancelled = cancelled { $kind } job after { $done } of { $total } items
+job-task = { $name } (daemon, since { $started })
+
+## po log --audit
+audit-entry = { $time } { $action } by { $user } ({ $count ->
+        [one] 1 file
+       *[other] { $count } files
+    }) `{ $command }`
+
+## po import --dry-run
+dry-run-new =             new       { $path } -> { $destination }
+dry-run-in-library =      in library { $path } -> { $existing }
+dry-run-duplicate-input = duplicate { $path }, same as { $other }
+dry-run-temporary =       skip      { $path }, left behind by an earlier import
+dry-run-inside-library =  skip      { $path }, it is inside the library
+dry-run-dangling-link =   skip      { $path }, a dangling link
+dry-run-in-other-library = in { $library } { $path } -> { $existing }
+dry-run-summary = would import { $new } files, skipping { $skipped }
+
+## po changes
+changes-added =    { $generation } added    { $path }
+changes-removed =  { $generation } removed  { $path }
+changes-modified = { $generation } modified { $path }
+changes-moved =    { $generation } moved    { $previous } -> { $path }
+
+## po simulate
+simulate-collision = collision { $path } -> { $destination }, which already holds other content
+simulate-directory = { $dir }/ { $files ->
+        [one] 1 file
+       *[other] { $files } files
+    } ({ $new } new)
+simulate-summary = { $new } files would go into { $directories } directories, { $skipped } skipped, { $collisions } collisions
+
+## po verify
+verify-missing =    missing    { $path }
+verify-mismatched = changed    { $path }, now { $hash }
+verify-orphan =     not in index { $path }
+verify-summary = checked { $checked } files, { $missing } missing, { $mismatched } changed, { $orphans } not in the index
+
+## po query --verify-state
+query-verified = verified { $date }
+query-verified-stale = verified { $date }, due again
+query-never-verified = never verified
+query-verify-failed = FAILED verify on { $date }
+
+## po checksums
+checksums-written = wrote checksums for { $files ->
+        [one] 1 file
+       *[other] { $files } files
+    }
+
+## po doctor
+doctor-ok =      ok       { $message }
+doctor-warning = warning  { $message }
+doctor-problem = problem  { $message }
+doctor-fix =     fix      { $fix }
+doctor-summary = { $problems ->
+        [one] 1 problem
+       *[other] { $problems } problems
+    }, { $warnings ->
+        [one] 1 warning
+       *[other] { $warnings } warnings
+    }
+
+## po rename
+rename-planned =   rename    { $from } -> { $to }
+rename-exists =    collision { $from } -> { $to }, which already exists
+rename-duplicate = collision { $from } -> { $to }, which { $other } would also be renamed to
+rename-collisions = { $count ->
+        [one] not renaming anything, 1 new name collides with another file
+       *[other] not renaming anything, { $count } new names collide with other files
+    }
+rename-dry-run = { $count ->
+        [one] would rename 1 file
+       *[other] would rename { $count } files
+    }
+
+## po prune
+prune-planned = { $action } { $path } (tagged { $tag })
+prune-dry-run = { $count ->
+        [one] 1 file is
+       *[other] { $count } files are
+    } due, run without --dry-run to deal with them
+prune-done = trashed { $trashed }, deleted { $deleted }, archived { $archived }
+
+## po remove
+remove-planned = remove  { $path }
+remove-deleted = deleted { $path }
+remove-trashed = trashed { $path }
+remove-dry-run = { $count ->
+        [one] would remove 1 file
+       *[other] would remove { $count } files
+    }
+
+## po rollback
+rollback-nothing = the last import finished, there's nothing to roll back
+rollback-done = rolled back the last import, moved { $restored ->
+        [one] 1 file
+       *[other] { $restored } files
+    } back to the inputs and removed { $removed ->
+        [one] 1 copy
+       *[other] { $removed } copies
+    } of files still there
+rollback-missing = { $count ->
+        [one] 1 file
+       *[other] { $count } files
+    } couldn't be found in the library or its inputs, see above
+rollback-kept = { $count ->
+        [one] 1 file was
+       *[other] { $count } files were
+    } left in the library, as something else is at their inputs now, see above
+
+## po dedupe
+dedupe-group = { $count } photos look alike:
+dedupe-summary = { $groups ->
+        [one] 1 group
+       *[other] { $groups } groups
+    } of similar photos, { $files } photos in all
+
+## po registry
+registry-synced = recorded { $count } files in the registry as { $name }
+
+## po exif
+exif-planned =   rewrite { $path }
+exif-rewritten = rewrote { $path }
+exif-skipped =   skipped { $path }, only jpeg files can be rewritten
+exif-xmp-kept = { $path } also has XMP, which still holds its old creator and rights. po only writes EXIF
+exif-dry-run = { $count ->
+        [one] would rewrite 1 file
+       *[other] would rewrite { $count } files
+    }
+exif-summary = { $count ->
+        [one] rewrote 1 file
+       *[other] rewrote { $count } files
+    }, skipped { $skipped }
+
+## po gallery
+gallery-up-to-date = the gallery is up to date
+gallery-written = wrote { $pages ->
+        [one] 1 page
+       *[other] { $pages } pages
+    } and { $thumbnails ->
+        [one] 1 thumbnail
+       *[other] { $thumbnails } thumbnails
+    }
+
+## po thumbs
+thumbs-made = made previews of { $made ->
+        [one] 1 file
+       *[other] { $made } files
+    }, { $existing } already had them, { $skipped } can't have them{ $removed ->
+        [0] {""}
+       *[other] , removed { $removed } no longer needed
+    }
+
+## po remux
+remux-summary = remuxed { $remuxed ->
+        [one] 1 video
+       *[other] { $remuxed } videos
+    }, { $up_to_date } already up to date, skipped { $skipped }
+
+## po mirror
+mirror-summary = copied { $copied ->
+        [one] 1 file
+       *[other] { $copied } files
+    }, updated { $updated }, removed { $removed }, { $up_to_date } already up to date, skipped { $skipped }
+mirror-interrupted = interrupted, run po mirror again to finish
+
+## po map
+map-written = put { $placed ->
+        [one] 1 photo
+       *[other] { $placed } photos
+    } on the map in { $path }, { $unplaced } had no location
+
+## po tag
+tag-file = { $path }: { $tags }
+tag-file-none = { $path }: no tags
+tag-count = { $tag } ({ $count ->
+        [one] 1 file
+       *[other] { $count } files
+    })
+
+## po prints
+prints-file = { $path }: { $count ->
+        [one] 1 print
+       *[other] { $count } prints
+    }
+prints-total = { $count ->
+        [one] 1 print to order
+       *[other] { $count } prints to order
+    }
+
+## po roll
+roll-name = { $roll } ({ $files ->
+        [one] 1 file
+       *[other] { $files } files
+    })
+roll-film-stock =   film stock  { $stock }
+roll-camera =       camera      { $camera }
+roll-developed =    developed   { $developed }
+
+## po export --print
+print-order-written = wrote { $photos ->
+        [one] 1 photo
+       *[other] { $photos } photos
+    } for { $prints ->
+        [one] 1 print
+       *[other] { $prints } prints
+    } and their order.csv to { $path }, skipped { $skipped }
+
+## po classify
+classify-summary = classified { $classified ->
+        [one] 1 photo
+       *[other] { $classified } photos
+    }, { $changed } with new tags
+
+## po ocr
+ocr-summary = read { $read ->
+        [one] 1 photo
+       *[other] { $read } photos
+    }, { $changed } with new text
+
+## po config docs
+config-docs-key = { $key } ({ $kind })
+config-docs-optional = optional
+config-docs-set-with = set with { $ways }
+config-docs-accepted = one of { $values }
+config-docs-default = default { $value }
+config-docs-value = set to { $value } by { $source ->
+        [cli] the command line
+        [env] the environment
+        [file] the config file
+       *[default] its default
+    }
+config-docs-unset = unset
+
+## po contains
+contains-found =   in library { $path } -> { $library_path }
+contains-missing = missing    { $path }
+contains-summary-missing = { $missing } of { $total } files are not in the library
+contains-summary-complete = all { $total } files are in the library
+
+## po diff
+diff-summary = { $only_dir } only in the directory, { $only_library } only in the library, { $both } in both
+
+## po stats
+stats-files = { $files ->
+        [one] 1 file
+       *[other] { $files } files
+    }, { $gib } GiB
+stats-year =     { $year }   { $files }
+stats-month =        { $month }   { $files }
+stats-undated =  undated { $files }
+stats-extensions = by extension: { $extensions }
+stats-largest = largest files:
+stats-largest-file = { $mib } MiB  { $path }
+stats-last-import = last import { $time }
+stats-heatmap = drew { $photos ->
+        [one] 1 photo
+       *[other] { $photos } photos
+    } taken in { $year } to { $path }
+stats-duplicates = { $files ->
+        [one] 1 file has the same content as another
+       *[other] { $files } files have the same content as another
+    }
+
+## po backup verify
+backup-missing = { $missing } of { $total } library files are not backed up
+backup-complete = all { $total } library files are backed up
+
+## imports and maintenance
+import-interrupted = interrupted, imported { $imported } of { $found } new files. run the import again to pick up the rest
+progress-hashing = hashing
+progress-sorting = sorting
+progress-thumbs = making previews
+shutdown-requested = stopping after the current file, press Ctrl-C again to stop immediately
+naming-renamed = { $count ->
+        [one] renamed 1 file
+       *[other] renamed { $count } files
+    }
+clean-sources-removed = { $count ->
+        [one] removed 1 folder of held originals
+       *[other] removed { $count } folders of held originals
+    }
@@ -0,0 +1,50 @@
+use color_eyre::eyre::{eyre, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::hashing::HashAlgorithm;
+use crate::library::{FileHash, Library, LibraryFile};
+
+// git-annex only keeps short extensions in keys by default (annex.maxextensionlength)
+const MAX_EXTENSION_LENGTH: usize = 4;
+
+/// The SHA256E key git-annex would give this file
+pub fn key_for(library: &Library, file: &LibraryFile) -> Result<String> {
+    if file.hash.algorithm() != HashAlgorithm::Sha256 {
+        return Err(eyre!("git-annex keys need a SHA256 hash, {} was hashed with {}", file.path_in_library.display(), file.hash.algorithm().name()));
+    }
+
+    let size = fs::metadata(library.absolute_path(file))?.len();
+    let ext = file
+        .path_in_library
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .filter(|e| e.len() <= MAX_EXTENSION_LENGTH && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|e| format!(".{e}"))
+        .unwrap_or_default();
+
+    Ok(format!("SHA256E-s{size}--{}{ext}", file.hash.encode()))
+}
+
+/// If `path` is a git-annex symlink using a SHA256 backend, the hash recorded in its key
+pub fn hash_from_link(path: &Path) -> Option<FileHash> {
+    let target = fs::read_link(path).ok()?;
+    let in_annex = target
+        .components()
+        .any(|c| c.as_os_str() == "annex");
+
+    if !in_annex {
+        return None;
+    }
+
+    let key = target.file_name()?.to_str()?;
+    let (backend, rest) = key.split_once('-')?;
+    if backend != "SHA256E" && backend != "SHA256" {
+        return None;
+    }
+
+    // Key fields are separated from the hash by "--", e.g. SHA256E-s1234--<hash>.jpg
+    let (_, hash) = rest.split_once("--")?;
+    let hash = hash.split('.').next()?;
+    FileHash::decode(hash, HashAlgorithm::Sha256).ok()
+}
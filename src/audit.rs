@@ -0,0 +1,77 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::library::{FileHash, Library};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+pub struct AuditEntry {
+    pub time: String,
+    pub action: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    pub command: Vec<String>,
+    pub hashes: Vec<String>,
+}
+
+fn audit_path(library: &Library) -> PathBuf {
+    library.meta_root().join("audit")
+}
+
+/// Append a mutating `action` which touched `hashes` to the library's audit log
+pub fn record<'a>(
+    library: &Library,
+    action: &str,
+    hashes: impl IntoIterator<Item = &'a FileHash>,
+) -> Result<()> {
+    let entry = AuditEntry {
+        time: OffsetDateTime::now_utc().format(&Rfc3339)?,
+        action: action.to_string(),
+        user: Some(library.identity().to_string()),
+        command: std::env::args().collect(),
+        hashes: hashes.into_iter().map(|h| h.encode()).collect(),
+    };
+
+    debug!("recording audit entry {:?}", entry);
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path(library))
+        .wrap_err("when opening audit log")?;
+
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+pub fn read(library: &Library) -> Result<Vec<AuditEntry>> {
+    let path = audit_path(library);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    fs::read_to_string(path)?
+        .lines()
+        .map(|l| serde_json::from_str(l).wrap_err("when parsing audit log, likely library corruption"))
+        .collect()
+}
+
+/// When each file was first imported, by encoded hash. Files imported before the audit log
+/// existed aren't in it
+pub fn import_times(library: &Library) -> Result<HashMap<String, String>> {
+    let mut times = HashMap::new();
+    for entry in read(library)?.into_iter().filter(|e| e.action == "import") {
+        for hash in entry.hashes {
+            times.entry(hash).or_insert_with(|| entry.time.clone());
+        }
+    }
+
+    Ok(times)
+}
@@ -0,0 +1,191 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, instrument};
+
+use crate::hashing::HashAlgorithm;
+use crate::library::{Library, LibraryFile};
+
+#[derive(Debug)]
+pub enum BackupRepo {
+    /// A restic repository, and the host whose snapshots to check, this machine's if `None`
+    Restic { repo: String, host: Option<String> },
+    Borg(String),
+}
+
+/// What a backup snapshot holds
+#[derive(Debug, Default)]
+struct Snapshot {
+    /// The size of each file, by absolute path
+    sizes: HashMap<PathBuf, u64>,
+    /// SHA256 hashes of the files' content, when the backup records them
+    sha256: HashSet<String>,
+}
+
+/// Library files which are not present in the latest snapshot of `repo`
+#[instrument(skip(library))]
+pub fn uncovered<'a>(library: &'a Library, repo: &BackupRepo) -> Result<Vec<&'a LibraryFile>> {
+    let root = fs::canonicalize(library.output_root()).wrap_err(format!("when resolving {}", library.output_root().display()))?;
+    let snapshot = match repo {
+        BackupRepo::Restic { repo, host } => restic_snapshot(repo, &root, host.as_deref())?,
+        BackupRepo::Borg(repo) => borg_snapshot(repo)?,
+    };
+
+    missing_from(library, &snapshot)
+}
+
+/// Library files `snapshot` doesn't have. Files are matched by content where it records SHA256
+/// hashes and they were hashed with it, and by path and size otherwise
+fn missing_from<'a>(library: &'a Library, snapshot: &Snapshot) -> Result<Vec<&'a LibraryFile>> {
+    let mut missing = vec![];
+    for file in library.files() {
+        if file.hash.algorithm() == HashAlgorithm::Sha256 && snapshot.sha256.contains(&file.hash.encode()) {
+            continue;
+        }
+
+        let path = fs::canonicalize(library.absolute_path(file))?;
+        let size = fs::metadata(&path)?.len();
+        if snapshot.sizes.get(&path) != Some(&size) {
+            debug!("{} is not in the snapshot", path.display());
+            missing.push(file);
+        }
+    }
+
+    Ok(missing)
+}
+
+fn run(mut command: Command) -> Result<String> {
+    debug!("running {:?}", command);
+    let output = command
+        .output()
+        .wrap_err(format!("when running {:?}, is it installed?", command.get_program()))?;
+
+    if !output.status.success() {
+        return Err(eyre!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The latest snapshot of `root` taken on `host`, rather than whichever snapshot of anything is newest
+fn restic_snapshot(repo: &str, root: &Path, host: Option<&str>) -> Result<Snapshot> {
+    let host = match host {
+        Some(host) => host.to_string(),
+        None => run(Command::new("hostname"))?.trim().to_string(),
+    };
+    let root = root.to_string_lossy();
+
+    let mut command = Command::new("restic");
+    command.args(["--repo", repo, "snapshots", "--json", "--latest", "1", "--path", &root, "--host", &host]);
+    let snapshots: Vec<serde_json::Value> = serde_json::from_str(&run(command)?).wrap_err("when reading restic's snapshots")?;
+    let id = snapshots
+        .iter()
+        .filter_map(|s| Some((s["time"].as_str()?, s["id"].as_str()?)))
+        .max()
+        .map(|(_, id)| id.to_string())
+        .ok_or_else(|| eyre!("restic repository {repo} has no snapshot of {root} taken on {host}"))?;
+
+    let mut command = Command::new("restic");
+    command.args(["--repo", repo, "ls", &id, "--json"]);
+    let snapshot = parse_restic_ls(&run(command)?)?;
+
+    info!("restic snapshot {id} has {} files", snapshot.sizes.len());
+    Ok(snapshot)
+}
+
+fn parse_restic_ls(output: &str) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+    for line in output.lines() {
+        let node: serde_json::Value = serde_json::from_str(line)?;
+        if node["struct_type"] != "node" || node["type"] != "file" {
+            continue;
+        }
+
+        if let (Some(path), Some(size)) = (node["path"].as_str(), node["size"].as_u64()) {
+            snapshot.sizes.insert(PathBuf::from(path), size);
+        }
+    }
+
+    Ok(snapshot)
+}
+
+fn borg_snapshot(repo: &str) -> Result<Snapshot> {
+    let mut command = Command::new("borg");
+    command.args(["list", "--last", "1", "--short", repo]);
+    let archive = run(command)?.trim().to_string();
+    if archive.is_empty() {
+        return Err(eyre!("borg repository {repo} has no archives"));
+    }
+
+    let mut command = Command::new("borg");
+    command.args(["list", "--format", "{type} {size} {sha256} {path}{NL}", &format!("{repo}::{archive}")]);
+    let snapshot = parse_borg_list(&run(command)?);
+
+    info!("borg archive {} has {} files", archive, snapshot.sizes.len());
+    Ok(snapshot)
+}
+
+fn parse_borg_list(output: &str) -> Snapshot {
+    let mut snapshot = Snapshot::default();
+    for line in output.lines() {
+        let mut fields = line.splitn(4, ' ');
+        let (Some("-"), Some(size), Some(sha256), Some(path)) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+
+        // borg stores absolute paths without their leading `/`
+        if let Ok(size) = size.parse() {
+            snapshot.sizes.insert(Path::new("/").join(path), size);
+        }
+        snapshot.sha256.insert(sha256.to_string());
+    }
+
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+    use crate::library::FileHash;
+
+    #[test]
+    fn reads_restic_and_borg_listings() {
+        let restic = parse_restic_ls(concat!(
+            r#"{"struct_type": "snapshot", "id": "abc"}"#, "\n",
+            r#"{"struct_type": "node", "type": "dir", "path": "/lib"}"#, "\n",
+            r#"{"struct_type": "node", "type": "file", "path": "/lib/a b.jpg", "size": 3}"#, "\n",
+        ))
+        .unwrap();
+        assert_eq!(restic.sizes, HashMap::from([(PathBuf::from("/lib/a b.jpg"), 3)]));
+
+        let borg = parse_borg_list("d 0  lib\n- 3 abcd lib/a b.jpg\n");
+        assert_eq!(borg.sizes, HashMap::from([(PathBuf::from("/lib/a b.jpg"), 3)]));
+        assert_eq!(borg.sha256, HashSet::from(["abcd".to_string()]));
+    }
+
+    #[test]
+    fn matches_by_hash_or_else_path_and_size() {
+        let root = scratch_dir("backup");
+        let root = fs::canonicalize(&root).unwrap();
+        let mut blake3 = indexed("blake3", "blake3.jpg");
+        blake3.hash = FileHash::from_bytes(b"blake3", HashAlgorithm::Blake3);
+        for name in ["sha256", "blake3", "resized"] {
+            fs::write(root.join(format!("{name}.jpg")), name).unwrap();
+        }
+        let library = library(&root, vec![indexed("sha256", "sha256.jpg"), blake3, indexed("resized", "resized.jpg")]);
+
+        let snapshot = Snapshot {
+            sizes: HashMap::from([(root.join("blake3.jpg"), 6), (root.join("resized.jpg"), 1)]),
+            sha256: HashSet::from([FileHash::from_bytes(b"sha256", HashAlgorithm::Sha256).encode()]),
+        };
+        let missing: Vec<_> = missing_from(&library, &snapshot).unwrap().iter().map(|f| f.path_in_library.clone()).collect();
+        assert_eq!(missing, [PathBuf::from("resized.jpg")]);
+    }
+}
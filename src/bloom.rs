@@ -0,0 +1,176 @@
+use color_eyre::eyre::{eyre, Result};
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+
+use crate::library::FileHash;
+use crate::storage;
+
+const MAGIC: &[u8; 8] = b"pobloom1";
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+// Leave room for the library to grow before it's worth rebuilding
+const MIN_CAPACITY: usize = 10_000;
+
+/// A compact, on disk summary of every hash in the library. It can say a hash is definitely not
+/// in the library, or that it might be, in which case the index has to be checked
+#[derive(Clone)]
+pub struct BloomFilter {
+    hashes: u32,
+    capacity: usize,
+    entries: usize,
+    bits: Vec<u64>,
+}
+
+// The bits are just noise in logs
+impl Debug for BloomFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BloomFilter")
+            .field("hashes", &self.hashes)
+            .field("capacity", &self.capacity)
+            .field("entries", &self.entries)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BloomFilter {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as usize;
+        let hashes = ((bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+
+        Self {
+            hashes,
+            capacity,
+            entries: 0,
+            bits: vec![0; bits.div_ceil(64)],
+        }
+    }
+
+    /// Filled past capacity the false positive rate climbs, so it should be rebuilt bigger
+    pub fn is_saturated(&self) -> bool {
+        self.entries > self.capacity
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    // The hashes are already uniformly distributed, so double hashing over two
+    // slices of them stands in for independent hash functions
+    fn positions(&self, hash: &FileHash) -> impl Iterator<Item = usize> + '_ {
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("hash to be at least 16 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("hash to be at least 16 bytes"));
+        let len = (self.bits.len() * 64) as u64;
+
+        (0..u64::from(self.hashes)).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&mut self, hash: &FileHash) {
+        let positions: Vec<_> = self.positions(hash).collect();
+        for pos in positions {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+        self.entries += 1;
+    }
+
+    pub fn may_contain(&self, hash: &FileHash) -> bool {
+        self.positions(hash).all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read(path)?;
+        let (header, words) = content
+            .split_at_checked(MAGIC.len() + 4 + 8 + 8)
+            .ok_or_else(|| eyre!("bloom filter is truncated"))?;
+
+        let (magic, rest) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(eyre!("bloom filter has an unknown format"));
+        }
+
+        let hashes = u32::from_le_bytes(rest[..4].try_into()?);
+        let capacity = u64::from_le_bytes(rest[4..12].try_into()?) as usize;
+        let entries = u64::from_le_bytes(rest[12..20].try_into()?) as usize;
+
+        if words.is_empty() || words.len() % 8 != 0 {
+            return Err(eyre!("bloom filter is truncated"));
+        }
+
+        let bits = words
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().expect("chunk to be 8 bytes")))
+            .collect();
+
+        Ok(Self { hashes, capacity, entries, bits })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut out = Vec::with_capacity(MAGIC.len() + 20 + self.bits.len() * 8);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.hashes.to_le_bytes());
+        out.extend_from_slice(&(self.capacity as u64).to_le_bytes());
+        out.extend_from_slice(&(self.entries as u64).to_le_bytes());
+        for word in &self.bits {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+
+        storage::write_atomically(path, &out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgorithm;
+    use crate::library::tests::scratch_dir;
+
+    fn hash(n: usize) -> FileHash {
+        FileHash::from_bytes(&n.to_le_bytes(), HashAlgorithm::Sha256)
+    }
+
+    #[test]
+    fn has_no_false_negatives() {
+        let mut bloom = BloomFilter::with_capacity(100);
+        for n in 0..1000 {
+            bloom.insert(&hash(n));
+        }
+        assert!((0..1000).all(|n| bloom.may_contain(&hash(n))));
+        assert_eq!(bloom.entries(), 1000);
+        assert!(!bloom.is_saturated());
+
+        // Well above the target rate would mean the sizing is off
+        let false_positives = (1000..11_000).filter(|n| bloom.may_contain(&hash(*n))).count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn round_trips_through_disk() {
+        let dir = scratch_dir("bloom");
+        let path = dir.join("bloom");
+        let mut bloom = BloomFilter::with_capacity(10);
+        bloom.insert(&hash(1));
+        bloom.write(&path).unwrap();
+
+        let read = BloomFilter::read(&path).unwrap();
+        assert_eq!(read.entries(), 1);
+        assert!(read.may_contain(&hash(1)));
+        assert_eq!(read.bits, bloom.bits);
+    }
+
+    #[test]
+    fn rejects_truncated_filters() {
+        let dir = scratch_dir("bloom-truncated");
+        let path = dir.join("bloom");
+        let mut bloom = BloomFilter::with_capacity(10);
+        bloom.insert(&hash(1));
+        bloom.write(&path).unwrap();
+
+        let content = fs::read(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 3]).unwrap();
+        assert!(BloomFilter::read(&path).is_err());
+        fs::write(&path, &content[..10]).unwrap();
+        assert!(BloomFilter::read(&path).is_err());
+    }
+}
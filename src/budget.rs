@@ -0,0 +1,121 @@
+use color_eyre::eyre::{eyre, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+static LIMIT: OnceLock<u64> = OnceLock::new();
+static IO_WORKERS: AtomicUsize = AtomicUsize::new(1);
+
+// Rough size of one loaded index entry, path and properties included
+const INDEX_ENTRY_BYTES: u64 = 256;
+
+// Rough memory one thumbnail takes to render, a decoded 24 megapixel photo and its scaled copies
+const RENDER_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Parse sizes like `512MiB`, `1G` or `800000000`
+pub fn parse_size(raw: &str) -> Result<u64> {
+    let raw = raw.trim();
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| eyre!("{raw:?} is not a size, expected something like 512MiB"))?;
+
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        other => return Err(eyre!("unknown size unit {other:?} in {raw:?}")),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| eyre!("{raw:?} is too big a size"))
+}
+
+/// Set the memory budget for this run. Only the first call has any effect
+pub fn set_limit(bytes: u64) {
+    info!("limiting memory use to {} MiB", bytes / (1024 * 1024));
+    let _ = LIMIT.set(bytes);
+}
+
+pub fn limit() -> Option<u64> {
+    LIMIT.get().copied()
+}
+
+/// Set how many threads do IO at once, each getting an equal share of the IO budget
+pub fn set_io_workers(workers: usize) {
+    IO_WORKERS.store(workers.max(1), Ordering::Relaxed);
+}
+
+/// How many bytes of IO buffers to use, when `wanted` would be ideal and `minimum` is needed
+/// to work at all. IO gets an eighth of the budget, split between the threads doing it
+pub fn io_buffer_bytes(wanted: usize, minimum: usize) -> usize {
+    let workers = IO_WORKERS.load(Ordering::Relaxed) as u64;
+    match limit() {
+        Some(limit) => ((limit / 8 / workers) as usize).clamp(minimum, wanted.max(minimum)),
+        None => wanted,
+    }
+}
+
+/// How many images to render at once, so their decoded pixels fit in half the budget. 0 for one
+/// per CPU, as rayon takes it, when there's no budget
+pub fn render_workers() -> usize {
+    limit().map_or(0, render_workers_within)
+}
+
+fn render_workers_within(limit: u64) -> usize {
+    (limit / 2 / RENDER_BYTES).max(1) as usize
+}
+
+/// How many bytes of answers a daemon may keep cached, an eighth of the budget
+pub fn cache_bytes() -> Option<u64> {
+    limit().map(|limit| limit / 8)
+}
+
+/// Warn when a loaded index of `entries` files takes up a large part of the budget
+pub fn check_index(entries: usize) {
+    let Some(limit) = limit() else {
+        return;
+    };
+
+    let estimated = entries as u64 * INDEX_ENTRY_BYTES;
+    if estimated > limit / 4 {
+        warn!(
+            "the loaded index is around {} MiB, a large part of the {} MiB memory limit. \
+             consider `po index per-year` so commands only load the years they need",
+            estimated / (1024 * 1024),
+            limit / (1024 * 1024)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("800000000").unwrap(), 800_000_000);
+        assert_eq!(parse_size("12b").unwrap(), 12);
+        assert_eq!(parse_size("4k").unwrap(), 4096);
+        assert_eq!(parse_size("512MiB").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size("1 G").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn renders_at_least_one_image_at_a_time() {
+        assert_eq!(render_workers_within(parse_size("512MiB").unwrap()), 1);
+        assert_eq!(render_workers_within(parse_size("1GiB").unwrap()), 2);
+        assert_eq!(render_workers_within(parse_size("8GiB").unwrap()), 16);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for raw in ["", "MiB", "-1", "1.5G", "1T", "18446744073709551615k", "99999999999999999999"] {
+            assert!(parse_size(raw).is_err(), "{raw:?} should not parse");
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::library::{FileHash, LibraryFile};
+use crate::snapshot;
+
+/// Append-only feed of changes to the library's files, one JSON line per change, within `_pometa`
+pub const CHANGES_FILE: &str = "changes";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// The file moved within the library, or its tags changed. Its content is the same
+    Modified,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct Change {
+    /// The library generation the change happened in, see [`crate::library::Library::generation`]
+    pub generation: u64,
+    pub time: String,
+    pub kind: ChangeKind,
+    pub hash: String,
+    pub path: String,
+    /// Where a modified file was before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_path: Option<String>,
+}
+
+impl Change {
+    pub fn new(generation: u64, kind: ChangeKind, hash: &FileHash, path: &Path) -> Change {
+        Change {
+            generation,
+            time: OffsetDateTime::now_utc().format(&Rfc3339).expect("the current time to format"),
+            kind,
+            hash: hash.encode(),
+            path: path.to_string_lossy().to_string(),
+            previous_path: None,
+        }
+    }
+
+    pub fn moved(generation: u64, hash: &FileHash, from: &Path, to: &Path) -> Change {
+        Change {
+            previous_path: Some(from.to_string_lossy().to_string()),
+            ..Change::new(generation, ChangeKind::Modified, hash, to)
+        }
+    }
+}
+
+/// What changed when the library's files went from `old` to `new` in one step, as in a rollback
+pub fn between(generation: u64, old: &[LibraryFile], new: &[LibraryFile]) -> Vec<Change> {
+    snapshot::diff(old, new)
+        .into_iter()
+        .map(|change| match change {
+            snapshot::Change::Added(f) => Change::new(generation, ChangeKind::Added, &f.hash, &f.path_in_library),
+            snapshot::Change::Removed(f) => Change::new(generation, ChangeKind::Removed, &f.hash, &f.path_in_library),
+            snapshot::Change::Moved { from, to } => {
+                Change::moved(generation, &to.hash, &from.path_in_library, &to.path_in_library)
+            }
+        })
+        .collect()
+}
+
+fn feed_path(meta_root: &Path) -> PathBuf {
+    meta_root.join(CHANGES_FILE)
+}
+
+pub fn append(meta_root: &Path, changes: &[Change]) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    debug!("recording {} changes", changes.len());
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(feed_path(meta_root))
+        .wrap_err("when opening change feed")?;
+
+    let mut lines = String::new();
+    for change in changes {
+        lines.push_str(&serde_json::to_string(change)?);
+        lines.push('\n');
+    }
+
+    // One write, so readers polling the feed don't see half of a batch
+    file.write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+/// Changes made after `generation`, oldest first
+pub fn since(meta_root: &Path, generation: u64) -> Result<Vec<Change>> {
+    let (changes, _) = read_from(meta_root, 0)?.unwrap_or_default();
+    Ok(changes.into_iter().filter(|c| c.generation > generation).collect())
+}
+
+/// Changes recorded after byte `offset` of the feed, and the offset to read on from next time.
+/// `None` if the feed is shorter than that, so it isn't the feed the offset came from
+pub fn read_from(meta_root: &Path, offset: u64) -> Result<Option<(Vec<Change>, u64)>> {
+    let path = feed_path(meta_root);
+    if !path.exists() {
+        return Ok((offset == 0).then_some((vec![], 0)));
+    }
+
+    let mut file = fs::File::open(&path).wrap_err("when opening change feed")?;
+    if file.metadata()?.len() < offset {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut feed = String::new();
+    file.read_to_string(&mut feed)?;
+
+    let (mut changes, mut read) = (vec![], offset);
+    for line in feed.split_inclusive('\n') {
+        // A writer is part way through this one
+        if !line.ends_with('\n') {
+            break;
+        }
+
+        changes.push(serde_json::from_str(line).wrap_err("when parsing change feed, likely library corruption")?);
+        read += line.len() as u64;
+    }
+
+    Ok(Some((changes, read)))
+}
@@ -0,0 +1,148 @@
+use clap::ValueEnum;
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::changes::{Change, ChangeKind};
+use crate::hashing::HashAlgorithm;
+use crate::library::{LibraryFile, TEMPORARY_SUFFIX};
+
+/// Companion checksum files kept next to library files, so the library can be checked with
+/// `sha256sum -c`, or `b3sum -c` for blake3 libraries, without po
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub enum ChecksumFiles {
+    /// A `SHA256SUMS` in each directory, listing every file in it
+    PerDirectory,
+    /// A `<name>.sha256` next to each file
+    PerFile,
+}
+
+/// The changes to each directory's checksums, by file name, `None` for files it no longer has
+type Updates = BTreeMap<PathBuf, BTreeMap<String, Option<String>>>;
+
+/// What the per-directory file is called for `algorithm`, and the extension of per-file ones
+fn names(algorithm: HashAlgorithm) -> (&'static str, &'static str) {
+    match algorithm {
+        HashAlgorithm::Sha256 => ("SHA256SUMS", "sha256"),
+        HashAlgorithm::Blake3 => ("B3SUMS", "b3"),
+    }
+}
+
+/// Whether `name` is one of the checksum files po keeps, rather than a library file
+pub fn is_checksum_file(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    [HashAlgorithm::Sha256, HashAlgorithm::Blake3].into_iter().any(|algorithm| {
+        let (sums, extension) = names(algorithm);
+        name == sums || name.strip_suffix(extension).is_some_and(|n| n.ends_with('.'))
+    })
+}
+
+/// Bring the checksum files in the library at `root` up to date with `changes` to its files
+pub fn apply(root: &Path, mode: ChecksumFiles, algorithm: HashAlgorithm, changes: &[Change]) -> Result<()> {
+    let mut updates = Updates::new();
+    for change in changes {
+        if let Some(previous) = &change.previous_path {
+            note(&mut updates, Path::new(previous), None);
+        }
+        let hash = (change.kind != ChangeKind::Removed).then(|| change.hash.clone());
+        note(&mut updates, Path::new(&change.path), hash);
+    }
+
+    write(root, mode, algorithm, updates, false)
+}
+
+/// Write the checksum files for every one of `files` in the library at `root`, replacing the
+/// per-directory files outright, for libraries which had files before checksum files were kept
+pub fn write_all(root: &Path, mode: ChecksumFiles, algorithm: HashAlgorithm, files: &[LibraryFile]) -> Result<()> {
+    let mut updates = Updates::new();
+    for file in files {
+        note(&mut updates, &file.path_in_library, Some(file.hash.encode()));
+    }
+
+    write(root, mode, algorithm, updates, true)
+}
+
+fn note(updates: &mut Updates, path_in_library: &Path, hash: Option<String>) {
+    let (Some(dir), Some(name)) = (path_in_library.parent(), path_in_library.file_name()) else {
+        return;
+    };
+    let name = name.to_string_lossy();
+    // sha256sum escapes these, and not every tool reading the files unescapes them
+    if name.contains(['\n', '\\']) {
+        warn!("{} can't be listed in a checksum file, leaving it out", path_in_library.display());
+        return;
+    }
+    updates.entry(dir.to_path_buf()).or_default().insert(name.into_owned(), hash);
+}
+
+fn write(root: &Path, mode: ChecksumFiles, algorithm: HashAlgorithm, updates: Updates, replace: bool) -> Result<()> {
+    let (sums_name, extension) = names(algorithm);
+    for (dir, entries) in updates {
+        let dir = root.join(dir);
+        match mode {
+            ChecksumFiles::PerDirectory => {
+                let path = dir.join(sums_name);
+                let existing = if replace { BTreeMap::new() } else { read(&path)? };
+                let mut sums = existing.clone();
+                for (name, hash) in entries {
+                    match hash {
+                        Some(hash) => sums.insert(name, hash),
+                        None => sums.remove(&name),
+                    };
+                }
+                if replace || sums != existing {
+                    write_file(&path, &sums)?;
+                }
+            }
+            ChecksumFiles::PerFile => {
+                for (name, hash) in entries {
+                    let path = dir.join(format!("{name}.{extension}"));
+                    write_file(&path, &hash.map(|h| (name, h)).into_iter().collect())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The hashes listed in the checksum file at `path`, by file name
+fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    let sums = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        sums => sums.wrap_err(format!("when reading {}", path.display()))?,
+    };
+
+    Ok(sums
+        .lines()
+        .filter_map(|line| {
+            // Text and binary mode lines, `<hash>  <name>` and `<hash> *<name>`
+            let (hash, name) = line.split_once(' ')?;
+            let name = name.strip_prefix([' ', '*'])?;
+            Some((name.to_string(), hash.to_string()))
+        })
+        .collect())
+}
+
+/// Write `sums` to `path` in the format sha256sum reads, or remove it once nothing is left to list
+fn write_file(path: &Path, sums: &BTreeMap<String, String>) -> Result<()> {
+    if sums.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            removed => removed.wrap_err(format!("when removing {}", path.display())),
+        };
+    }
+
+    debug!("writing {} checksums to {}", sums.len(), path.display());
+    let content: String = sums.iter().map(|(name, hash)| format!("{hash}  {name}\n")).collect();
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(TEMPORARY_SUFFIX);
+    fs::write(&partial, content).wrap_err(format!("when writing {}", path.display()))?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::Config;
+use confique::serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::library::{FileHash, Library};
+use crate::transcode;
+
+#[cfg(feature = "classify")]
+use crate::onnx::Model;
+#[cfg(not(feature = "classify"))]
+use unsupported::Model;
+
+/// Tags po works out itself start with this, so they can be told apart from, and never
+/// replace, the ones people add
+pub const AUTO_PREFIX: &str = "auto:";
+
+#[derive(Config, Debug, Serialize, Clone)]
+#[serde(crate = "confique::serde")]
+#[config(layer_attr(derive(clap::Args)))]
+pub struct ClassifyConfig {
+    /// An ONNX image classifier for `po classify`, such as MobileNet. Only used by po built
+    /// with `--features classify`
+    #[config(env = "PO_CLASSIFY_MODEL", layer_attr(arg(long = "classify-model")))]
+    pub model: Option<PathBuf>,
+
+    /// The tag for each of the model's classes, one per line in the order it outputs them, e.g.
+    /// `pets`. Blank lines are classes which don't get a tag
+    #[config(env = "PO_CLASSIFY_LABELS", layer_attr(arg(long = "classify-labels")))]
+    pub labels: Option<PathBuf>,
+
+    /// How sure the model has to be of a tag to add it, from 0 to 1
+    #[config(env = "PO_CLASSIFY_THRESHOLD", default = 0.5, layer_attr(arg(long = "classify-threshold")))]
+    pub threshold: f32,
+
+    /// Width and height of the images the model takes, in pixels
+    #[config(env = "PO_CLASSIFY_INPUT_SIZE", default = 224, layer_attr(arg(long = "classify-input-size")))]
+    pub input_size: u32,
+
+    /// Classify photos as they're imported
+    #[config(env = "PO_CLASSIFY_ON_IMPORT", default = false, layer_attr(arg(long = "classify-on-import")))]
+    pub on_import: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub classified: usize,
+    /// Files whose auto tags changed
+    pub changed: Vec<FileHash>,
+}
+
+/// Tag each of `hashes` which is a photo with the broad categories the model puts it in, as
+/// `auto:pets` and the like, replacing any auto tags it already had
+pub fn classify(library: &mut Library, config: &ClassifyConfig, hashes: &[FileHash]) -> Result<Report> {
+    let classifier = Classifier::load(config)?;
+
+    let wanted: HashSet<_> = hashes.iter().collect();
+    let files: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| wanted.contains(&f.hash))
+        .map(|f| (f.path_in_library.clone(), library.absolute_path(f), f.tags.clone()))
+        .collect();
+
+    let mut report = Report::default();
+    for (path_in_library, path, existing) in files {
+        if !transcode::can_render(&path).unwrap_or(false) {
+            debug!("{} is not a photo which can be classified, skipping", path.display());
+            continue;
+        }
+
+        let tags = match classifier.tags(&path) {
+            Ok(tags) => tags,
+            Err(e) => {
+                warn!("could not classify {}: {e:#}", path.display());
+                continue;
+            }
+        };
+        report.classified += 1;
+
+        let stale: Vec<_> = existing.iter().filter(|t| t.starts_with(AUTO_PREFIX) && !tags.contains(t)).cloned().collect();
+        if stale.is_empty() && tags.iter().all(|t| existing.contains(t)) {
+            continue;
+        }
+
+        info!("classified {} as {:?}", path_in_library.display(), tags);
+        let file = library.retag(&path_in_library, &tags, &stale)?;
+        report.changed.push(file.hash.clone());
+    }
+
+    Ok(report)
+}
+
+struct Classifier {
+    model: Model,
+    /// The tag of each of the model's classes, `None` for those without one
+    labels: Vec<Option<String>>,
+    threshold: f32,
+}
+
+impl Classifier {
+    fn load(config: &ClassifyConfig) -> Result<Classifier> {
+        let (Some(model), Some(labels)) = (&config.model, &config.labels) else {
+            return Err(eyre!("there is no classifier to use, set `model` and `labels` in the [classify] section of the config"));
+        };
+
+        let labels = read_labels(labels)?;
+        if labels.iter().all(Option::is_none) {
+            return Err(eyre!("none of the classifier's labels are tags, so it has nothing to add"));
+        }
+
+        Ok(Classifier { model: Model::load(model, config.input_size)?, labels, threshold: config.threshold })
+    }
+
+    /// The tags the model is sure enough of for the photo at `path`. The probabilities of every
+    /// class with the same tag are added up, so a model which tells 100 breeds of dog apart can
+    /// still be sure a photo is of a pet
+    fn tags(&self, path: &Path) -> Result<Vec<String>> {
+        let scores = self.model.scores(path)?;
+        if scores.len() != self.labels.len() {
+            warn!("the classifier has {} classes but {} labels, check the labels file", scores.len(), self.labels.len());
+        }
+
+        let mut totals: BTreeMap<&str, f32> = BTreeMap::new();
+        for (label, probability) in self.labels.iter().zip(probabilities(&scores)) {
+            if let Some(label) = label {
+                *totals.entry(label).or_default() += probability;
+            }
+        }
+
+        Ok(totals
+            .into_iter()
+            .filter(|(_, total)| *total >= self.threshold)
+            .map(|(label, _)| format!("{AUTO_PREFIX}{label}"))
+            .collect())
+    }
+}
+
+/// Stands in for [`crate::onnx::Model`] in builds without the classify feature
+#[cfg(not(feature = "classify"))]
+mod unsupported {
+    use color_eyre::eyre::{eyre, Result};
+    use std::path::Path;
+
+    pub struct Model;
+
+    impl Model {
+        pub fn load(_path: &Path, _input_size: u32) -> Result<Model> {
+            Err(eyre!("this po was built without image classification, build it with `--features classify` to use it"))
+        }
+
+        pub fn scores(&self, _path: &Path) -> Result<Vec<f32>> {
+            unreachable!("models can't be loaded without the classify feature")
+        }
+    }
+}
+
+/// Models end in either probabilities or raw scores, the latter need a softmax
+fn probabilities(scores: &[f32]) -> Vec<f32> {
+    let sum: f32 = scores.iter().sum();
+    if scores.iter().all(|s| (0.0..=1.0).contains(s)) && (sum - 1.0).abs() < 0.01 {
+        return scores.to_vec();
+    }
+
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<_> = scores.iter().map(|s| (s - max).exp()).collect();
+    let total: f32 = exp.iter().sum();
+    exp.into_iter().map(|e| e / total).collect()
+}
+
+fn read_labels(path: &Path) -> Result<Vec<Option<String>>> {
+    let labels = fs::read_to_string(path).wrap_err(format!("when reading classifier labels {}", path.display()))?;
+    labels
+        .lines()
+        .map(|line| {
+            let label = line.trim();
+            if label.is_empty() {
+                return Ok(None);
+            }
+            if label.contains(char::is_whitespace) {
+                return Err(eyre!("classifier label {label:?} has whitespace in it, which tags can't"));
+            }
+            Ok(Some(label.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,158 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command, ValueEnum};
+use confique::meta::{Expr, FieldKind, LeafKind, Meta};
+use confique::serde::Serialize;
+use serde_json::Value;
+
+use crate::library::{IndexLayout, NamingScheme, SortPolicy, SourceRetention};
+use crate::registry::RegistryPolicy;
+
+/// Which layer a config value came from, highest precedence first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Cli,
+    Env,
+    File,
+    Default,
+}
+
+/// Everything about one config key, for `po config docs`
+#[derive(Debug)]
+pub struct KeyDocs {
+    /// Dotted for nested sections, like `serve.listen`
+    pub key: String,
+    pub doc: String,
+    pub kind: String,
+    pub optional: bool,
+    pub default: Option<String>,
+    /// Values the key takes, written as they would be in the config file
+    pub accepted: Vec<String>,
+    /// The value po runs with, `None` when unset
+    pub value: Option<String>,
+    pub source: Source,
+    /// The command line flag which sets the key, if there is one
+    pub flag: Option<String>,
+    /// The environment variable which sets the key, if there is one
+    pub env: Option<&'static str>,
+}
+
+impl KeyDocs {
+    /// Whether po can't run without the key being set
+    pub fn is_required(&self) -> bool {
+        !self.optional && self.default.is_none()
+    }
+}
+
+/// Where the loaded config came from, to tell which layer each value was set in
+pub struct Layers<'a> {
+    /// The parsed config file, empty if there isn't one
+    pub file: &'a toml::Table,
+    pub cli: &'a ArgMatches,
+    pub command: &'a Command,
+    /// Keys set through other command line flags, such as `source_retention` by `--copy`
+    pub cli_overrides: &'a [&'a str],
+}
+
+/// Document every key of `meta`, with its value from the loaded `config`
+pub fn collect(meta: &Meta, config: &impl Serialize, layers: &Layers) -> Vec<KeyDocs> {
+    let effective = serde_json::to_value(config).unwrap_or(Value::Null);
+    let mut docs = vec![];
+    walk(meta, "", &effective, Some(layers.file), layers, &mut docs);
+    docs
+}
+
+/// Required keys of `meta` which none of the layers set, for when the config fails to load
+pub fn missing(meta: &Meta, layers: &Layers) -> Vec<KeyDocs> {
+    let mut docs = vec![];
+    walk(meta, "", &Value::Null, Some(layers.file), layers, &mut docs);
+    docs.retain(|key| key.is_required() && key.source == Source::Default);
+    docs
+}
+
+fn walk(meta: &Meta, prefix: &str, effective: &Value, file: Option<&toml::Table>, layers: &Layers, docs: &mut Vec<KeyDocs>) {
+    for field in meta.fields {
+        let key = format!("{prefix}{}", field.name);
+        let value = effective.get(field.name).unwrap_or(&Value::Null);
+        let in_file = file.and_then(|f| f.get(field.name));
+
+        match &field.kind {
+            FieldKind::Nested { meta } => {
+                walk(meta, &format!("{key}."), value, in_file.and_then(toml::Value::as_table), layers, docs);
+            }
+            FieldKind::Leaf { kind, env } => {
+                let (optional, default) = match kind {
+                    LeafKind::Optional => (true, None),
+                    LeafKind::Required { default } => (false, default.as_ref().map(expr_to_json)),
+                };
+
+                // Clap names the flags of nested sections after their field too, e.g. `listen` for `--serve-listen`
+                let on_cli = layers.cli.try_contains_id(field.name).is_ok()
+                    && layers.cli.value_source(field.name) == Some(ValueSource::CommandLine);
+                let source = if on_cli || layers.cli_overrides.contains(&key.as_str()) {
+                    Source::Cli
+                } else if env.is_some_and(|e| std::env::var_os(e).is_some()) {
+                    Source::Env
+                } else if in_file.is_some() {
+                    Source::File
+                } else {
+                    Source::Default
+                };
+
+                let shown = if value.is_null() { None } else { Some(value) };
+                docs.push(KeyDocs {
+                    doc: field.doc.iter().map(|line| line.trim()).collect::<Vec<_>>().join(" "),
+                    kind: shown.or(default.as_ref()).map_or("", kind_of).to_string(),
+                    optional,
+                    default: default.as_ref().map(Value::to_string),
+                    accepted: accepted_values(&key),
+                    value: shown.map(Value::to_string),
+                    source,
+                    flag: layers
+                        .command
+                        .get_arguments()
+                        .find(|arg| arg.get_id() == field.name)
+                        .and_then(|arg| arg.get_long())
+                        .map(|long| format!("--{long}")),
+                    env: *env,
+                    key,
+                });
+            }
+        }
+    }
+}
+
+fn expr_to_json(expr: &Expr) -> Value {
+    serde_json::to_value(expr).unwrap_or(Value::Null)
+}
+
+fn kind_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "table",
+    }
+}
+
+fn accepted_values(key: &str) -> Vec<String> {
+    match key {
+        "sort_policy" => spellings::<SortPolicy>(),
+        "naming" => spellings::<NamingScheme>(),
+        "index" => spellings::<IndexLayout>(),
+        "source_retention" => spellings::<SourceRetention>(),
+        "registry_duplicates" => spellings::<RegistryPolicy>(),
+        _ => vec![],
+    }
+}
+
+/// The variants of `T` as serde writes them, which is how the config file spells them
+fn spellings<T: ValueEnum + Serialize>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|v| serde_json::to_value(v).ok())
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
@@ -0,0 +1,287 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::budget;
+use crate::library::META_DIR;
+use crate::query::QueryFilters;
+use crate::schema::{QueryOutput, ShowOutput};
+
+/// Socket a running daemon answers commands on, within the library's metadata
+const SOCKET_FILE: &str = "daemon.sock";
+
+pub fn socket_path(output_root: &Path) -> PathBuf {
+    output_root.join(META_DIR).join(SOCKET_FILE)
+}
+
+/// A command sent to a running daemon, one JSON line per connection
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde", tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Query {
+        queries: Vec<String>,
+        not: Vec<String>,
+        #[serde(flatten)]
+        filters: Box<QueryFilters>,
+        #[serde(default)]
+        groups: bool,
+        #[serde(default)]
+        verify_state: bool,
+    },
+    Show {
+        path: PathBuf,
+    },
+    /// Add and remove tags on the file at `path`, or count every tag in the library without one
+    Tag {
+        path: Option<PathBuf>,
+        #[serde(default)]
+        add: Vec<String>,
+        #[serde(default)]
+        remove: Vec<String>,
+    },
+    /// What the daemon is busy with, answered even in the middle of an import
+    Tasks,
+}
+
+/// What `po tag` reports, whether it ran here or in the daemon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum TagOutput {
+    /// How many files have each tag
+    Counts(BTreeMap<String, usize>),
+    /// The tags a file has now
+    File { path: PathBuf, tags: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum Response {
+    Query(QueryOutput),
+    Show(Box<ShowOutput>),
+    Tag(TagOutput),
+    Tasks(Vec<Task>),
+    Error(String),
+}
+
+/// Something a running daemon is doing, for `po jobs`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "confique::serde")]
+pub struct Task {
+    pub name: String,
+    /// RFC 3339
+    pub started: String,
+}
+
+/// What a daemon is doing, shared between its work and the socket so it can be answered at any time
+#[derive(Default)]
+pub struct Tasks {
+    running: Mutex<Vec<(u64, Task)>>,
+    next: AtomicU64,
+}
+
+impl Tasks {
+    /// Record that `name` started, until the returned guard is dropped
+    pub fn start(&self, name: impl Into<String>) -> TaskGuard<'_> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let started = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+        self.running.lock().expect("tasks lock not to be poisoned").push((id, Task { name: name.into(), started }));
+        TaskGuard { tasks: self, id }
+    }
+
+    /// Oldest first
+    pub fn list(&self) -> Vec<Task> {
+        self.running.lock().expect("tasks lock not to be poisoned").iter().map(|(_, task)| task.clone()).collect()
+    }
+}
+
+/// A running task, which ends when this is dropped
+pub struct TaskGuard<'a> {
+    tasks: &'a Tasks,
+    id: u64,
+}
+
+impl Drop for TaskGuard<'_> {
+    fn drop(&mut self) {
+        self.tasks.running.lock().expect("tasks lock not to be poisoned").retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Most answers any one daemon gives are to the same few queries, so this stays small
+const CACHE_ENTRIES: usize = 256;
+
+/// Answers the daemon has already worked out, for as long as the library stays the same
+#[derive(Default)]
+pub struct ResponseCache {
+    generation: u64,
+    responses: HashMap<String, Response>,
+    /// Roughly how much the cached answers take up, as JSON
+    bytes: u64,
+}
+
+impl ResponseCache {
+    /// The cached answer to `request`, or the one `answer` gives, cached if it isn't an error.
+    /// `generation` is the library's, see [`crate::library::Library::generation`]
+    pub fn get_or_answer(&mut self, request: Request, generation: u64, answer: impl FnOnce(Request) -> Response) -> Response {
+        // Tagging changes the library, so has to reach it every time
+        if matches!(request, Request::Tag { .. } | Request::Tasks) {
+            return answer(request);
+        }
+
+        if generation != self.generation {
+            self.clear();
+            self.generation = generation;
+        }
+
+        let key = serde_json::to_string(&request).expect("requests to serialize");
+        if let Some(response) = self.responses.get(&key) {
+            tracing::debug!("answering from cache");
+            return response.clone();
+        }
+
+        let response = answer(request);
+        if !matches!(response, Response::Error(_)) {
+            // Within the memory budget, so a few queries matching the whole library can't hold on to it
+            let bytes = (key.len() + serde_json::to_string(&response).map_or(0, |r| r.len())) as u64;
+            let budget = budget::cache_bytes().unwrap_or(u64::MAX);
+            if self.responses.len() >= CACHE_ENTRIES || self.bytes + bytes > budget {
+                self.clear();
+            }
+            if bytes <= budget {
+                self.responses.insert(key, response.clone());
+                self.bytes += bytes;
+            }
+        }
+
+        response
+    }
+
+    fn clear(&mut self) {
+        self.responses.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use tracing::{debug, info, warn};
+
+    /// How often the listener checks whether it should stop
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+    /// How long the daemon waits for a command once something connects, so a client which never
+    /// sends one can't stop it answering anyone else
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How long po waits for the daemon to answer. It only answers between imports, so this allows
+    /// for a large one
+    const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+    pub struct Server {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Server {
+        /// Listen on the library's socket, taking it over from a daemon which didn't clean up after itself
+        pub fn bind(output_root: &Path) -> Result<Server> {
+            let path = socket_path(output_root);
+            if path.exists() {
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(eyre!("another po daemon is already running on this library ({})", path.display()));
+                }
+
+                debug!("removing stale socket {}", path.display());
+                std::fs::remove_file(&path)?;
+            }
+
+            let listener = UnixListener::bind(&path).wrap_err(format!("when listening on {}", path.display()))?;
+            listener.set_nonblocking(true)?;
+            info!("answering commands on {}", path.display());
+            Ok(Server { listener, path })
+        }
+
+        /// Answer requests with `handle` until `stop` is set
+        pub fn serve(&self, stop: &AtomicBool, mut handle: impl FnMut(Request) -> Response) -> Result<()> {
+            while !stop.load(Ordering::Relaxed) {
+                let stream = match self.listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                if let Err(e) = answer(stream, &mut handle) {
+                    warn!("could not answer command: {e:#}");
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn answer(stream: UnixStream, handle: &mut impl FnMut(Request) -> Response) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => {
+                debug!("received {:?}", request);
+                handle(request)
+            }
+            Err(e) => Response::Error(format!("could not read command: {e}")),
+        };
+
+        let mut stream = stream;
+        writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+        Ok(())
+    }
+
+    /// Send `request` to the daemon running on the library, `None` if there isn't one
+    pub fn send(output_root: &Path, request: &Request) -> Result<Option<Response>> {
+        let path = socket_path(output_root);
+        let mut stream = match UnixStream::connect(&path) {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("no daemon at {}: {e}", path.display());
+                return Ok(None);
+            }
+        };
+
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        writeln!(stream, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut line)
+            .wrap_err(format!("when waiting for the daemon on {} to answer", path.display()))?;
+        Ok(Some(serde_json::from_str(&line).wrap_err("when reading the daemon's response")?))
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{send, Server};
+
+/// Daemons can only be reached over Unix sockets, so other platforms never find one
+#[cfg(not(unix))]
+pub fn send(_output_root: &Path, _request: &Request) -> Result<Option<Response>> {
+    Ok(None)
+}
@@ -0,0 +1,118 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::library::{FileHash, Library};
+
+/// Copies of library files rendered with an export preset, as JSON lines. Later lines for a path
+/// replace earlier ones
+const STORE_FILE: &str = "derivatives";
+
+/// A copy of a library file made by `po export --preset` or `po remux`. It has content of its
+/// own, so it's tracked by its own hash alongside the hash of the file it was made from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct Derivative {
+    /// Hash of the library file it was rendered from
+    pub original: String,
+    pub hash: String,
+    /// Where it was written, outside the library
+    pub path: PathBuf,
+    /// The export preset it was rendered with, or `remux`
+    pub preset: String,
+    /// The settings it was made with, so changing them makes it out of date
+    pub settings: String,
+    pub time: String,
+}
+
+/// What is already at an export destination
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Existing {
+    /// A derivative rendered from the same file with the same settings, untouched since
+    UpToDate,
+    /// A derivative po rendered, but from another file or with other settings
+    OutOfDate,
+    /// Something po didn't render, or a derivative which has been edited since
+    Unrecorded,
+}
+
+/// The derivatives of a library's files, by where they were written
+pub struct Derivatives {
+    store: PathBuf,
+    by_path: HashMap<PathBuf, Derivative>,
+}
+
+impl Derivatives {
+    pub fn load(library: &Library) -> Result<Derivatives> {
+        let store = library.meta_root().join(STORE_FILE);
+        let mut by_path = HashMap::new();
+        if store.exists() {
+            for line in fs::read_to_string(&store)?.lines() {
+                let derivative: Derivative =
+                    serde_json::from_str(line).wrap_err("when parsing derivatives, likely library corruption")?;
+                by_path.insert(derivative.path.clone(), derivative);
+            }
+        }
+
+        Ok(Derivatives { store, by_path })
+    }
+
+    /// Whether what's at `path` is what making it from `original` with `name` and `settings`,
+    /// such as a preset, would give
+    pub fn check(&self, path: &Path, original: &FileHash, name: &str, settings: &impl Serialize) -> Result<Existing> {
+        let Some(derivative) = self.by_path.get(&std::path::absolute(path)?) else {
+            return Ok(Existing::Unrecorded);
+        };
+
+        if FileHash::from_file(&path.to_path_buf(), original.algorithm())?.encode() != derivative.hash {
+            return Ok(Existing::Unrecorded);
+        }
+
+        if derivative.original == original.encode() && derivative.preset == name && derivative.settings == encode(settings)? {
+            Ok(Existing::UpToDate)
+        } else {
+            Ok(Existing::OutOfDate)
+        }
+    }
+
+    /// Record that `path` was just made from `original` with `name` and `settings`
+    pub fn record(&mut self, path: &Path, original: &FileHash, name: &str, settings: &impl Serialize) -> Result<()> {
+        let derivative = Derivative {
+            original: original.encode(),
+            hash: FileHash::from_file(&path.to_path_buf(), original.algorithm())?.encode(),
+            path: std::path::absolute(path)?,
+            preset: name.to_string(),
+            settings: encode(settings)?,
+            time: OffsetDateTime::now_utc().format(&Rfc3339)?,
+        };
+        debug!("recording derivative {:?}", derivative);
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.store)
+            .wrap_err("when opening derivatives")?;
+        writeln!(file, "{}", serde_json::to_string(&derivative)?)?;
+
+        self.by_path.insert(derivative.path.clone(), derivative);
+        Ok(())
+    }
+
+    /// The derivatives rendered from the file with content `original`, by path
+    pub fn of(&self, original: &FileHash) -> Vec<&Derivative> {
+        let original = original.encode();
+        let mut derived: Vec<_> = self.by_path.values().filter(|d| d.original == original).collect();
+        derived.sort_by(|a, b| a.path.cmp(&b.path));
+        derived
+    }
+}
+
+fn encode(settings: &impl Serialize) -> Result<String> {
+    Ok(serde_json::to_string(settings)?)
+}
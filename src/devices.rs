@@ -0,0 +1,146 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::HashMap;
+use std::path::Path;
+use time::PrimitiveDateTime;
+use time::macros::format_description;
+
+/// A file named following an action camera or drone's conventions
+#[derive(Debug)]
+pub struct DeviceFile {
+    /// Whose conventions it follows, one of [`DEVICES`]
+    pub device: &'static str,
+    /// Shared by a recording and its sidecars
+    pub key: String,
+    /// Low-res proxies, thumbnails and telemetry, which belong next to their recording
+    pub sidecar: bool,
+    /// When the recording started, from the file name. This is camera local time
+    pub taken: Option<PrimitiveDateTime>,
+}
+
+/// The devices `device_templates` can give templates for
+pub const DEVICES: &[&str] = &["dji", "gopro", "insta360"];
+
+const SIDECAR_EXTENSIONS: &[&str] = &["lrv", "thm", "lrf", "srt"];
+
+/// Recognise DJI, GoPro and Insta360 file names, e.g. `DJI_20240314153012_0001_D.MP4`,
+/// `GX010123.MP4` / `GL010123.LRV` or `VID_20240314_153012_00_001.insv`
+pub fn recognise(path: &Path) -> Option<DeviceFile> {
+    let name = path.file_name()?.to_str()?;
+    // Only the part before the first dot, so hash suffixed library names still match
+    let (stem, _) = name.split_once('.')?;
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let sidecar = SIDECAR_EXTENSIONS.contains(&ext.as_str());
+
+    if let Some(rest) = stem.strip_prefix("DJI_") {
+        // Newer models put the start time in the name, older ones only a counter
+        let taken = rest.split('_').next().and_then(parse_compact);
+        return Some(DeviceFile { device: "dji", key: format!("dji:{stem}"), sidecar, taken });
+    }
+
+    if stem.len() == 8 && (stem.starts_with("GOPR") || stem.starts_with("GP")) {
+        return Some(DeviceFile { device: "gopro", key: format!("gopro:{stem}"), sidecar, taken: None });
+    }
+
+    // GX/GH are the recording (by codec), GL is its low-res proxy. The rest is chapter and number
+    if stem.len() == 8
+        && let Some(number) = ["GX", "GH", "GL"].iter().find_map(|p| stem.strip_prefix(p))
+        && number.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Some(DeviceFile { device: "gopro", key: format!("gopro:{number}"), sidecar, taken: None });
+    }
+
+    // Phones use the same IMG_ prefix, so only trust Insta360's own formats
+    if matches!(ext.as_str(), "insv" | "insp" | "lrv") {
+        let parts: Vec<&str> = stem.split('_').collect();
+        if let [kind @ ("VID" | "IMG" | "LRV"), date, time, _lens, number] = parts[..] {
+            let taken = parse_compact(&format!("{date}{time}"));
+            return Some(DeviceFile {
+                device: "insta360",
+                key: format!("insta360:{date}_{time}_{number}"),
+                sidecar: sidecar || kind == "LRV",
+                taken,
+            });
+        }
+    }
+
+    None
+}
+
+/// Refuse templates for devices po doesn't recognise, or which aren't valid path templates
+pub fn check_templates(templates: &HashMap<String, String>) -> Result<()> {
+    for (device, template) in templates {
+        if !DEVICES.contains(&device.as_str()) {
+            return Err(eyre!("device_templates has a template for {device:?}, which isn't one of {}", DEVICES.join(", ")));
+        }
+        crate::library::check_path_template(template).wrap_err(format!("in the device template for {device}"))?;
+    }
+    Ok(())
+}
+
+fn parse_compact(raw: &str) -> Option<PrimitiveDateTime> {
+    let format = format_description!("[year][month][day][hour][minute][second]");
+    PrimitiveDateTime::parse(raw, &format).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgorithm;
+    use crate::library::tests::{library, scratch_dir};
+    use crate::library::{Clock, FileHash, ImportOptions, SortPolicy, UnsortedFile};
+    use std::fs;
+    use std::path::PathBuf;
+    use time::macros::datetime;
+
+    #[test]
+    fn recognises_each_devices_names() {
+        let dji = recognise(Path::new("DJI_20240314153012_0001_D.MP4")).unwrap();
+        assert_eq!((dji.device, dji.sidecar, dji.taken), ("dji", false, Some(datetime!(2024-03-14 15:30:12))));
+
+        let proxy = recognise(Path::new("GL010123.LRV")).unwrap();
+        assert_eq!((proxy.device, proxy.sidecar, proxy.key.as_str()), ("gopro", true, "gopro:010123"));
+        assert_eq!(recognise(Path::new("GX010123.MP4")).unwrap().key, proxy.key);
+
+        let insta = recognise(Path::new("VID_20240314_153012_00_001.insv")).unwrap();
+        assert_eq!((insta.device, insta.taken), ("insta360", Some(datetime!(2024-03-14 15:30:12))));
+        assert!(recognise(Path::new("IMG_20240314_153012_00_001.jpg")).is_none());
+    }
+
+    #[test]
+    fn routes_recordings_by_their_devices_template() {
+        let root = scratch_dir("devices");
+        let input = root.join("in");
+        fs::create_dir_all(&input).unwrap();
+        let new_files = ["DJI_20240314153012_0001_D.MP4", "DJI_20240314153012_0001_D.SRT", "plain.jpg"]
+            .into_iter()
+            .map(|name| {
+                let path = input.join(name);
+                fs::write(&path, name).unwrap();
+                UnsortedFile {
+                    hash: FileHash::from_bytes(name.as_bytes(), HashAlgorithm::Sha256),
+                    metadata: fs::metadata(&path).unwrap(),
+                    path,
+                    is_symlink: false,
+                }
+            })
+            .collect();
+
+        let mut templates = HashMap::from([("dji".to_string(), "drone/{year}/{original_name}".to_string())]);
+        check_templates(&templates).unwrap();
+        let options = ImportOptions { device_templates: templates.clone(), clock: Clock::Fixed(datetime!(2023-01-02 03:04:05)), ..Default::default() };
+        let mut library = library(&root.join("lib"), vec![]);
+        let plan = library.plan_sort(new_files, SortPolicy::Date, &options).unwrap();
+        let paths: Vec<_> = plan.iter().map(|p| p.path_in_library.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("drone/2024/DJI_20240314153012_0001_D.MP4"),
+                PathBuf::from("2023/1/2/plain.jpg"),
+                PathBuf::from("drone/2024/DJI_20240314153012_0001_D.SRT"),
+            ]
+        );
+
+        templates.insert("canon".to_string(), "{year}/{original_name}".to_string());
+        assert!(check_templates(&templates).is_err());
+    }
+}
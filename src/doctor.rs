@@ -0,0 +1,300 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::jobs;
+use crate::journal;
+use crate::library::{Library, META_DIR};
+use crate::lock;
+use crate::storage;
+use crate::verify;
+
+/// How bad what a check found is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Ok,
+    /// Worth knowing about, but po works regardless
+    Warning,
+    /// Stops po from working, or means the library is damaged
+    Problem,
+}
+
+/// The outcome of one check
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    /// What to do about it
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(message: impl Into<String>) -> Finding {
+        Finding { severity: Severity::Ok, message: message.into(), fix: None }
+    }
+
+    fn warning(message: impl Into<String>, fix: impl Into<String>) -> Finding {
+        Finding { severity: Severity::Warning, message: message.into(), fix: Some(fix.into()) }
+    }
+
+    fn problem(message: impl Into<String>, fix: impl Into<String>) -> Finding {
+        Finding { severity: Severity::Problem, message: message.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Check that po can import from `inputs` into the library at `output`, and that the library
+/// is in a state po can work with, without changing anything. Files are compared with the
+/// index by size only, `po verify` hashes them
+pub fn run(inputs: &[PathBuf], output: &Path, others: &[(String, Vec<PathBuf>)]) -> Result<Vec<Finding>> {
+    let mut findings = vec![];
+    check_inputs(inputs, &mut findings);
+    if !output.is_dir() {
+        findings.push(Finding::warning(
+            format!("the library {} doesn't exist yet", output.display()),
+            "it's created on the first import, check `output` if it should already be there",
+        ));
+        return Ok(findings);
+    }
+
+    let existing: Vec<_> = inputs.iter().filter(|i| i.exists()).cloned().collect();
+    if let Err(e) = check_roots(&existing, output, others) {
+        findings.push(Finding::problem(
+            format!("{e:#}"),
+            "change `inputs` or `output` so none of them are inside another, or another library's",
+        ));
+    }
+    check_writable(output, "the library", &mut findings);
+
+    let meta_root = output.join(META_DIR);
+    if !meta_root.is_dir() {
+        findings.push(Finding::ok(format!("{} has no metadata yet, it's set up on the first import", output.display())));
+        return Ok(findings);
+    }
+    check_writable(&meta_root, "the library's metadata", &mut findings);
+    let index_current = check_index(&meta_root, &mut findings);
+
+    let holder = lock::in_use_by(&meta_root);
+    if let Some(holder) = &holder {
+        findings.push(Finding::warning(
+            format!("the library is in use by {holder}"),
+            "wait for it to finish before running anything which changes the library",
+        ));
+    }
+
+    if journal::pending(&meta_root) {
+        findings.push(Finding::problem(
+            "the last import didn't finish, some of its files are in the library but not the index",
+            "run `po rollback` to put them back, then import again",
+        ));
+    }
+
+    let staging = meta_root.join("staging");
+    if fs::read_dir(&staging).is_ok_and(|mut entries| entries.next().is_some()) {
+        findings.push(Finding::warning(
+            format!("{} has files left over from an interrupted import", staging.display()),
+            "check them for anything missing from the library, then delete them",
+        ));
+    }
+
+    // Upgrading the index would change it, so the rest needs one this po can read as it is
+    if !index_current {
+        return Ok(findings);
+    }
+
+    let library = match Library::read_into_memory(output.to_path_buf()) {
+        Ok(library) => library,
+        Err(e) => {
+            findings.push(Finding::problem(
+                format!("the library's metadata can't be read: {e:#}"),
+                "restore _pometa from a backup or snapshot",
+            ));
+            return Ok(findings);
+        }
+    };
+
+    let interrupted: Vec<_> = jobs::list(&meta_root)?.into_iter().filter(|j| !j.is_running()).map(|j| j.kind).collect();
+    if !interrupted.is_empty() {
+        findings.push(Finding::warning(
+            format!("these jobs were interrupted: {}", interrupted.join(", ")),
+            "resume them with `--resume`, or forget them with `po jobs cancel`",
+        ));
+    }
+
+    // Whatever holds the lock may be moving files, which would show up as drift
+    if holder.is_some() {
+        findings.push(Finding::ok("the index wasn't compared with the disk, as the library is in use"));
+    } else {
+        check_drift(&library, &mut findings)?;
+    }
+    Ok(findings)
+}
+
+/// Refuse configurations where inputs and the output overlap, which would have po
+/// rediscover files it has already sorted, or move files onto themselves. `others` are the
+/// roots of other libraries sharing the registry, which this one's mustn't overlap either.
+/// Returns the canonical roots, the output first
+pub fn check_roots(inputs: &[PathBuf], output: &Path, others: &[(String, Vec<PathBuf>)]) -> Result<Vec<PathBuf>> {
+    let output = fs::canonicalize(output)?;
+    let inputs = inputs
+        .iter()
+        .map(|i| fs::canonicalize(i).wrap_err(format!("when resolving input {}", i.display())))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (idx, input) in inputs.iter().enumerate() {
+        if output.starts_with(input) {
+            return Err(eyre!("output {} is inside input {}", output.display(), input.display()));
+        }
+
+        if input.starts_with(&output) {
+            return Err(eyre!("input {} is inside output {}", input.display(), output.display()));
+        }
+
+        for other in &inputs[idx + 1..] {
+            if input.starts_with(other) || other.starts_with(input) {
+                return Err(eyre!("inputs {} and {} overlap", input.display(), other.display()));
+            }
+        }
+    }
+
+    let roots: Vec<_> = std::iter::once(output).chain(inputs).collect();
+    for (library, theirs) in others {
+        for root in &roots {
+            if let Some(other) = theirs.iter().find(|other| root.starts_with(other) || other.starts_with(root)) {
+                return Err(eyre!("{} overlaps {} of library {library}", root.display(), other.display()));
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+fn check_inputs(inputs: &[PathBuf], findings: &mut Vec<Finding>) {
+    if inputs.is_empty() {
+        findings.push(Finding::warning("no inputs are configured", "set `inputs`, or give paths to `po import`"));
+    }
+
+    for input in inputs {
+        if !input.exists() {
+            // po creates missing inputs, which hides an SD card that isn't mounted
+            findings.push(Finding::warning(
+                format!("input {} doesn't exist", input.display()),
+                "mount it, or fix its path in `inputs`. po creates it empty otherwise",
+            ));
+        } else if !input.is_dir() {
+            findings.push(Finding::problem(format!("input {} isn't a directory", input.display()), "point `inputs` at the directory it's in"));
+        } else if let Err(e) = fs::read_dir(input) {
+            findings.push(Finding::problem(
+                format!("input {} can't be read: {e}", input.display()),
+                "check its permissions, po needs to list and read it",
+            ));
+        } else {
+            findings.push(Finding::ok(format!("input {} is readable", input.display())));
+        }
+    }
+}
+
+/// Whether po can create files in `dir`, by creating one and removing it again
+fn check_writable(dir: &Path, what: &str, findings: &mut Vec<Finding>) {
+    let probe = dir.join(format!(".po-doctor-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            findings.push(Finding::ok(format!("{what} at {} is writable", dir.display())));
+        }
+        Err(e) => findings.push(Finding::problem(
+            format!("{what} at {} can't be written to: {e}", dir.display()),
+            "check it's owned by the user running po, and the disk isn't mounted read-only",
+        )),
+    }
+}
+
+/// Whether this po can read the index, without upgrading it
+fn check_index(meta_root: &Path, findings: &mut Vec<Finding>) -> bool {
+    let version = storage::schema_version(meta_root);
+    let current = matches!(version, Ok(Some((found, supported))) if found == supported) || matches!(version, Ok(None));
+    match version {
+        Ok(Some((found, supported))) if found > supported => findings.push(Finding::problem(
+            format!("the index is from a newer po (schema {found}, this po knows up to {supported})"),
+            "update po with `po self-update`",
+        )),
+        Ok(Some((found, supported))) if found < supported => findings.push(Finding::ok(format!(
+            "the index is at schema {found}, po upgrades it to {supported} when it next opens it"
+        ))),
+        Ok(Some((found, _))) => findings.push(Finding::ok(format!("the index is at schema {found}"))),
+        Ok(None) if meta_root.join(storage::HASH_FILE).exists() => findings.push(Finding::ok(
+            "the index is kept in text files by an older po, po moves it into SQLite when it next opens it",
+        )),
+        Ok(None) => findings.push(Finding::ok("the library has no index yet")),
+        Err(e) => findings.push(Finding::problem(
+            format!("the index can't be read: {e:#}"),
+            "check the permissions of _pometa/library.db, or restore it from a backup or snapshot",
+        )),
+    }
+    current
+}
+
+/// Compare the index with the files on disk, by path and size
+fn check_drift(library: &Library, findings: &mut Vec<Finding>) -> Result<()> {
+    let files = library.files();
+    let mut missing = 0;
+    let mut resized = 0;
+    for file in files {
+        let path = library.absolute_path(file);
+        match fs::metadata(&path) {
+            Err(_) => {
+                debug!("{} is missing", path.display());
+                missing += 1;
+            }
+            Ok(metadata) if file.size.is_some_and(|size| size != metadata.len()) => {
+                debug!("{} has changed size", path.display());
+                resized += 1;
+            }
+            Ok(_) => {}
+        }
+    }
+
+    let known: HashSet<_> = files.iter().map(|f| f.path_in_library.as_path()).collect();
+    let orphans = verify::library_tree(library.output_root())?
+        .iter()
+        .filter(|p| p.strip_prefix(library.output_root()).is_ok_and(|p| !known.contains(p)))
+        .count();
+
+    if missing + resized + orphans == 0 {
+        findings.push(Finding::ok(format!("all {} files in the index are on disk", files.len())));
+        return Ok(());
+    }
+
+    findings.push(Finding::problem(
+        format!(
+            "the index and the disk disagree: {missing} files are missing, {resized} have changed size, and {orphans} \
+             aren't in the index"
+        ),
+        "run `po verify` to list them. if the library was copied from another machine, check the copy finished",
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::scratch_dir;
+
+    #[test]
+    fn refuses_roots_overlapping_another_librarys() {
+        let dir = scratch_dir("roots");
+        let root = fs::canonicalize(&dir).unwrap();
+        for dir in ["lib", "in", "other"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let others = [("work".to_string(), vec![root.join("other")])];
+        let roots = check_roots(&[root.join("in")], &root.join("lib"), &others).unwrap();
+        assert_eq!(roots, [root.join("lib"), root.join("in")]);
+
+        let others = [("work".to_string(), vec![root.join("other"), root.clone()])];
+        assert!(check_roots(&[root.join("in")], &root.join("lib"), &others).is_err());
+        assert!(check_roots(&[root.join("lib")], &root.join("lib"), &[]).is_err());
+    }
+}
@@ -0,0 +1,460 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::PrimitiveDateTime;
+use tracing::{debug, info, instrument, warn};
+use ureq::unversioned::multipart::{Form, Part};
+
+use crate::derivatives::{Derivatives, Existing};
+use crate::library::{copy_file, created_at, with_hash_suffix, Library, LibraryFile};
+use crate::metadata;
+use crate::template;
+use crate::transcode::{self, Preset};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ExportLayout {
+    /// Mirror the paths files have within the library
+    #[default]
+    Library,
+    /// `YYYY/MM/YYYYMMDD_HHMMSS_HASH.ext`, as PhotoPrism lays out its originals
+    Photoprism,
+    /// Every file straight into the destination under its own name, with a short hash added to
+    /// names more than one exported file has, `IMG_0001.a1b2c3d4.jpg`
+    Flat,
+}
+
+/// Where exported files go within the destination
+#[derive(Debug, Clone)]
+pub enum ExportPaths {
+    Layout(ExportLayout),
+    /// A path template such as `{date}_{name}.{ext}`, see [`expand_template`]
+    Template(String),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ExportSort {
+    /// By path within the library
+    #[default]
+    Path,
+    /// Oldest first, by when each file was taken
+    CaptureDate,
+}
+
+/// How `to_directory` should write files
+#[derive(Debug)]
+pub struct ExportOptions<'a> {
+    pub paths: ExportPaths,
+    /// The name of the preset to render with, and its settings
+    pub preset: Option<(&'a str, &'a Preset)>,
+    /// Copy files the preset can't render as they are, rather than skipping them
+    pub copy_unrenderable: bool,
+    pub sort: ExportSort,
+    /// Prefix names with their position in the export, `0001_...`, so players which sort
+    /// by name show files in order
+    pub number: bool,
+    /// Only export the first file in export order for each distinct content hash
+    pub unique_content: bool,
+}
+
+#[instrument(skip(library, files))]
+pub fn to_directory(
+    library: &Library,
+    files: &[&LibraryFile],
+    dest: &Path,
+    options: &ExportOptions,
+) -> Result<()> {
+    info!("exporting {} files", files.len());
+    let mut skipped = 0;
+    let mut up_to_date = 0;
+    let mut derivatives = Derivatives::load(library)?;
+
+    let mut files = files.to_vec();
+    match options.sort {
+        ExportSort::Path => files.sort_by(|a, b| a.path_in_library.cmp(&b.path_in_library)),
+        ExportSort::CaptureDate => {
+            let mut dated = files
+                .into_iter()
+                .map(|f| Ok((taken(&library.absolute_path(f), f)?, f)))
+                .collect::<Result<Vec<_>>>()?;
+            dated.sort_by(|(a, af), (b, bf)| a.cmp(b).then_with(|| af.path_in_library.cmp(&bf.path_in_library)));
+            files = dated.into_iter().map(|(_, f)| f).collect();
+        }
+    }
+
+    if options.unique_content {
+        let mut seen = HashSet::new();
+        let before = files.len();
+        files.retain(|f| seen.insert(&f.hash));
+        if files.len() < before {
+            info!("skipping {} files with the same content as another", before - files.len());
+        }
+    }
+
+    // How many of the files have each name, as flattened files with the same one would collide
+    let mut names: HashMap<_, usize> = HashMap::new();
+    for file in &files {
+        *names.entry(file.path_in_library.file_name()).or_default() += 1;
+    }
+
+    let width = files.len().to_string().len().max(4);
+    for (position, file) in files.iter().enumerate() {
+        let source = library.absolute_path(file);
+        let mut output = dest.join(match &options.paths {
+            ExportPaths::Layout(layout) => export_path(&source, file, *layout)?,
+            ExportPaths::Template(template) => expand_template(template, &source, file)?,
+        });
+
+        let name = file.path_in_library.file_name();
+        if matches!(options.paths, ExportPaths::Layout(ExportLayout::Flat)) && names[&name] > 1 {
+            output.set_file_name(with_hash_suffix(name.expect("library path to name a file"), &file.hash));
+        }
+
+        if options.number {
+            let mut numbered = OsString::from(format!("{:0width$}_", position + 1));
+            numbered.push(output.file_name().expect("export path to name a file"));
+            output.set_file_name(numbered);
+        }
+
+        let mut preset = options.preset;
+        if let Some((_, settings)) = preset {
+            if !transcode::can_render(&source)? {
+                if !options.copy_unrenderable {
+                    warn!("{} is not an image the preset can be applied to, skipping", source.display());
+                    skipped += 1;
+                    continue;
+                }
+                debug!("{} is not an image the preset can be applied to, copying it as it is", source.display());
+                preset = None;
+            } else if let Some(format) = settings.format {
+                output.set_extension(format.extension());
+            }
+        }
+
+        if output.exists() {
+            let existing = match preset {
+                Some((name, preset)) => derivatives.check(&output, &file.hash, name, preset)?,
+                None => Existing::Unrecorded,
+            };
+
+            match existing {
+                Existing::UpToDate => {
+                    debug!("{} is up to date, skipping", output.display());
+                    up_to_date += 1;
+                    continue;
+                }
+                Existing::OutOfDate => info!("{} is out of date, rendering it again", output.display()),
+                Existing::Unrecorded => {
+                    warn!("{} already exists, skipping", output.display());
+                    skipped += 1;
+                    continue;
+                }
+            }
+        }
+
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        info!("exporting {} to {}", source.display(), output.display());
+        match preset {
+            Some((name, preset)) => {
+                transcode::render(&source, &output, preset)
+                    .wrap_err(format!("when rendering {} to {}", source.display(), output.display()))?;
+                derivatives.record(&output, &file.hash, name, preset)?;
+            }
+            None => {
+                copy_file(&source, &output)
+                    .wrap_err(format!("when copying {} to {}", source.display(), output.display()))?;
+            }
+        }
+    }
+
+    info!(
+        "exported {} files, skipped {}, {} already up to date",
+        files.len() - skipped - up_to_date,
+        skipped,
+        up_to_date
+    );
+    Ok(())
+}
+
+fn export_path(source: &Path, file: &LibraryFile, layout: ExportLayout) -> Result<PathBuf> {
+    match layout {
+        ExportLayout::Library => Ok(file.path_in_library.clone()),
+        ExportLayout::Flat => Ok(file.path_in_library.file_name().expect("library path to name a file").into()),
+        ExportLayout::Photoprism => {
+            let created = taken(source, file)?;
+            let ext = source
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+
+            let hash = file.hash.encode()[..8].to_uppercase();
+            let name = format!(
+                "{:04}{:02}{:02}_{:02}{:02}{:02}_{hash}.{ext}",
+                created.year(),
+                created.month() as u8,
+                created.day(),
+                created.hour(),
+                created.minute(),
+                created.second(),
+            );
+
+            let mut p = PathBuf::new();
+            p.push(format!("{:04}", created.year()));
+            p.push(format!("{:02}", created.month() as u8));
+            p.push(name);
+            Ok(p)
+        }
+    }
+}
+
+/// When a file was taken, going by its metadata and falling back to the filesystem
+pub fn capture_date(source: &Path) -> Result<PrimitiveDateTime> {
+    match metadata::read_capture_info(source).ok().and_then(|info| info.taken) {
+        Some(taken) => Ok(taken),
+        None => created_at(source),
+    }
+}
+
+/// When `file`, at `source`, was taken, as the index has it or else as [`capture_date`] finds it
+fn taken(source: &Path, file: &LibraryFile) -> Result<PrimitiveDateTime> {
+    file.taken().map_or_else(|| capture_date(source), Ok)
+}
+
+/// Fill in a path template for `file`. Placeholders are `{year}`, `{month}`, `{day}`,
+/// `{date}` (YYYY-MM-DD), `{time}` (HHMMSS), `{name}`, `{ext}`, `{hash}` (the first 8 characters)
+/// and `{dir}`, the directory the file is in within the library. `/` creates directories
+pub fn expand_template(template: &str, source: &Path, file: &LibraryFile) -> Result<PathBuf> {
+    let mut taken = None;
+    let expanded = template::expand(template, |placeholder| {
+        let value = match placeholder {
+            "name" => file
+                .path_in_library
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            "ext" => file
+                .path_in_library
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            "hash" => file.hash.encode()[..8].to_string(),
+            "dir" => file
+                .path_in_library
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            "year" | "month" | "day" | "date" | "time" => {
+                let taken = match taken {
+                    Some(taken) => taken,
+                    None => *taken.insert(self::taken(source, file)?),
+                };
+
+                match placeholder {
+                    "year" => format!("{:04}", taken.year()),
+                    "month" => format!("{:02}", taken.month() as u8),
+                    "day" => format!("{:02}", taken.day()),
+                    "date" => format!("{:04}-{:02}-{:02}", taken.year(), taken.month() as u8, taken.day()),
+                    _ => format!("{:02}{:02}{:02}", taken.hour(), taken.minute(), taken.second()),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
+    })?;
+
+    template::relative_path(template, &expanded)
+}
+
+#[instrument(skip(library, files, api_key))]
+pub fn to_immich(library: &Library, files: &[&LibraryFile], server: &str, api_key: &str) -> Result<()> {
+    info!("uploading {} files", files.len());
+    let api = format!("{}/api", server.trim_end_matches('/'));
+
+    let mut duplicates = 0;
+    // The Immich ids of the files with each tag
+    let mut tagged: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for file in files {
+        let source = library.absolute_path(file);
+        let info = metadata::read_capture_info(&source).ok();
+        let taken = match file.taken().or(info.as_ref().and_then(|i| i.taken)) {
+            Some(taken) => taken,
+            None => created_at(&source)?,
+        };
+        let taken = taken.assume_utc().format(&Rfc3339)?;
+
+        // Immich dedupes on device + device asset id, so a stable id makes re-exports idempotent
+        let asset_id = format!("po-{}", file.hash.encode());
+        let file_name = file
+            .path_in_library
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| asset_id.clone());
+
+        let form = Form::new()
+            .text("deviceAssetId", &asset_id)
+            .text("deviceId", "po")
+            .text("fileCreatedAt", &taken)
+            .text("fileModifiedAt", &taken)
+            .part("assetData", Part::file(&source)?.file_name(&file_name));
+
+        let mut response = ureq::post(format!("{api}/assets"))
+            .header("x-api-key", api_key)
+            .header("Accept", "application/json")
+            .send(form)
+            .wrap_err(format!("when uploading {}", source.display()))?;
+
+        let body: serde_json::Value = response.body_mut().read_json()?;
+        let id = match (body.get("status").and_then(|s| s.as_str()), body.get("id").and_then(|i| i.as_str())) {
+            (Some("duplicate"), Some(id)) => {
+                info!("{} already on server", file.path_in_library.display());
+                duplicates += 1;
+                id.to_string()
+            }
+            (Some(_), Some(id)) => {
+                info!("uploaded {}", file.path_in_library.display());
+                id.to_string()
+            }
+            _ => return Err(eyre!("unexpected response from server: {body}")),
+        };
+
+        // Immich reads dates and places out of the file itself, but po's may be better, e.g. for
+        // files dated by their names
+        let mut asset = serde_json::json!({ "dateTimeOriginal": taken });
+        if let Some((latitude, longitude)) = info.and_then(|i| i.location) {
+            asset["latitude"] = latitude.into();
+            asset["longitude"] = longitude.into();
+        }
+        ureq::put(format!("{api}/assets/{id}"))
+            .header("x-api-key", api_key)
+            .send_json(&asset)
+            .wrap_err(format!("when setting the metadata of {}", file.path_in_library.display()))?;
+
+        for tag in &file.tags {
+            tagged.entry(tag).or_default().push(id.clone());
+        }
+    }
+
+    if !tagged.is_empty() {
+        tag_immich_assets(&api, api_key, &tagged)?;
+    }
+
+    info!("uploaded {} files, {} were already present", files.len() - duplicates, duplicates);
+    Ok(())
+}
+
+/// Give the Immich assets with each id in `tagged` that tag, creating the tags it doesn't have
+fn tag_immich_assets(api: &str, api_key: &str, tagged: &BTreeMap<&str, Vec<String>>) -> Result<()> {
+    let mut response = ureq::put(format!("{api}/tags"))
+        .header("x-api-key", api_key)
+        .send_json(serde_json::json!({ "tags": tagged.keys().collect::<Vec<_>>() }))
+        .wrap_err("when creating tags")?;
+
+    let tags: Vec<serde_json::Value> = response.body_mut().read_json()?;
+    for tag in tags {
+        let (Some(id), Some(value)) = (tag.get("id").and_then(|i| i.as_str()), tag.get("value").and_then(|v| v.as_str())) else {
+            return Err(eyre!("unexpected tag from server: {tag}"));
+        };
+        let Some(assets) = tagged.get(value) else {
+            continue;
+        };
+
+        ureq::put(format!("{api}/tags/assets"))
+            .header("x-api-key", api_key)
+            .send_json(serde_json::json!({ "tagIds": [id], "assetIds": assets }))
+            .wrap_err(format!("when tagging files {value}"))?;
+        info!("tagged {} files {value}", assets.len());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[test]
+    fn photoprism_layout_goes_by_capture_time() {
+        let mut file = indexed("a", "2024/1/2/IMG_0001.JPG");
+        file.taken = Some("2024-01-02T03:04:05".into());
+
+        let hash = file.hash.encode()[..8].to_uppercase();
+        let path = export_path(Path::new("/nowhere/IMG_0001.JPG"), &file, ExportLayout::Photoprism).unwrap();
+        assert_eq!(path, PathBuf::from(format!("2024/01/20240102_030405_{hash}.jpg")));
+    }
+
+    #[test]
+    fn immich_gets_capture_times_and_tags() {
+        let root = scratch_dir("immich");
+        let mut first = indexed("first", "first.jpg");
+        first.taken = Some("2024-01-02T03:04:05".into());
+        first.tags = vec!["family".into(), "trip".into()];
+        let mut second = indexed("second", "second.jpg");
+        second.taken = Some("2024-01-03T00:00:00".into());
+        second.tags = vec!["family".into()];
+        fs::write(root.join("first.jpg"), "first").unwrap();
+        fs::write(root.join("second.jpg"), "second").unwrap();
+        let library = library(&root, vec![first, second]);
+
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", server.server_addr());
+        let requests = Mutex::new(vec![]);
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut uploads = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(mut request) = server.recv_timeout(Duration::from_millis(20)).unwrap() else {
+                        continue;
+                    };
+                    let mut body = vec![];
+                    request.as_reader().read_to_end(&mut body).unwrap();
+                    let body = String::from_utf8_lossy(&body).to_string();
+
+                    let response = match request.url() {
+                        "/api/assets" => {
+                            uploads += 1;
+                            format!(r#"{{"id": "asset-{uploads}", "status": "created"}}"#)
+                        }
+                        "/api/tags" => r#"[{"id": "t1", "value": "family"}, {"id": "t2", "value": "trip"}]"#.to_string(),
+                        _ => "{}".to_string(),
+                    };
+                    requests.lock().unwrap().push((request.method().to_string(), request.url().to_string(), body));
+                    request.respond(tiny_http::Response::from_string(response)).unwrap();
+                }
+            });
+
+            let files: Vec<_> = library.files().iter().collect();
+            to_immich(&library, &files, &address, "key").unwrap();
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let requests = requests.into_inner().unwrap();
+        let urls: Vec<_> = requests.iter().map(|(method, url, _)| format!("{method} {url}")).collect();
+        assert_eq!(urls, [
+            "POST /api/assets",
+            "PUT /api/assets/asset-1",
+            "POST /api/assets",
+            "PUT /api/assets/asset-2",
+            "PUT /api/tags",
+            "PUT /api/tags/assets",
+            "PUT /api/tags/assets",
+        ]);
+
+        assert!(requests[0].2.contains("2024-01-02T03:04:05Z"));
+        let json = |i: usize| serde_json::from_str::<serde_json::Value>(&requests[i].2).unwrap();
+        assert_eq!(json(1), serde_json::json!({ "dateTimeOriginal": "2024-01-02T03:04:05Z" }));
+        assert_eq!(json(4), serde_json::json!({ "tags": ["family", "trip"] }));
+        assert_eq!(json(5), serde_json::json!({ "tagIds": ["t1"], "assetIds": ["asset-1", "asset-2"] }));
+        assert_eq!(json(6), serde_json::json!({ "tagIds": ["t2"], "assetIds": ["asset-1"] }));
+    }
+}
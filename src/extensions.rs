@@ -0,0 +1,107 @@
+use color_eyre::eyre::{eyre, Result};
+use infer::MatcherType;
+use std::path::Path;
+use tracing::debug;
+
+/// In place of extensions, takes any file whose content looks like a photo, video or audio file
+pub const WILDCARD: &str = "*";
+
+const PHOTOS: &[&str] = &[
+    "avif", "gif", "heic", "heif", "insp", "jpeg", "jpg", "jxl", "png", "tif", "tiff", "webp",
+];
+
+const RAW: &[&str] = &[
+    "3fr", "ari", "arw", "bay", "cr2", "cr3", "crw", "dcr", "dng", "erf", "fff", "iiq", "k25",
+    "kdc", "mef", "mos", "mrw", "nef", "nrw", "orf", "pef", "raf", "raw", "rw2", "rwl", "sr2",
+    "srf", "srw", "x3f",
+];
+
+// Includes the low-res proxies and thumbnails action cameras and drones record alongside
+const VIDEO: &[&str] = &[
+    "3gp", "avi", "insv", "lrf", "lrv", "m2ts", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "mts",
+    "thm", "webm", "wmv",
+];
+
+// Edits and settings which editors keep next to the files they apply to
+const SIDECARS: &[&str] = &["aae", "dop", "pp3", "xmp"];
+
+fn preset(name: &str) -> Option<Vec<&'static str>> {
+    match name {
+        "photos" => Some(PHOTOS.to_vec()),
+        "raw" => Some(RAW.to_vec()),
+        "video" => Some(VIDEO.to_vec()),
+        "sidecars" => Some(SIDECARS.to_vec()),
+        "all-media" => Some([PHOTOS, RAW, VIDEO].concat()),
+        _ => None,
+    }
+}
+
+/// Expand `@preset` entries into the extensions they stand for, lowercasing everything
+pub fn expand(extensions: &[String]) -> Result<Vec<String>> {
+    let mut expanded: Vec<String> = vec![];
+
+    for ext in extensions {
+        let ext = ext.to_lowercase();
+        match ext.strip_prefix('@') {
+            Some(name) => {
+                let exts = preset(name).ok_or_else(|| eyre!(
+                    "unknown extension preset @{name}, expected one of @photos, @raw, @video, @sidecars, @all-media"
+                ))?;
+                expanded.extend(exts.into_iter().map(String::from));
+            }
+            None => expanded.push(ext),
+        }
+    }
+
+    expanded.sort();
+    expanded.dedup();
+    Ok(expanded)
+}
+
+/// Whether `path` has one of `extensions`
+pub fn is_listed(extensions: &[String], path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| extensions.contains(&e))
+}
+
+/// Whether `path` is a raw file, going by its extension
+pub fn is_raw(path: &Path) -> bool {
+    has_extension(RAW, path)
+}
+
+/// Whether `path` is a video, going by its extension
+pub fn is_video(path: &Path) -> bool {
+    has_extension(VIDEO, path)
+}
+
+/// Whether `path` is an editor's sidecar, like Lightroom's `.xmp`, going by its extension
+pub fn is_sidecar(path: &Path) -> bool {
+    has_extension(SIDECARS, path)
+}
+
+fn has_extension(list: &[&str], path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| list.contains(&e.as_str()))
+}
+
+/// Whether `extensions` has the wildcard, so files without a listed extension should be sniffed
+pub fn has_wildcard(extensions: &[String]) -> bool {
+    extensions.iter().any(|e| e == WILDCARD)
+}
+
+/// Whether the content of `path` is a photo, video or audio file, going by its first bytes
+pub fn is_media(path: &Path) -> bool {
+    match infer::get_from_path(path) {
+        Ok(Some(kind)) => {
+            debug!("{} looks like {}", path.display(), kind.mime_type());
+            matches!(kind.matcher_type(), MatcherType::Image | MatcherType::Video | MatcherType::Audio)
+        }
+        Ok(None) => false,
+        Err(e) => {
+            debug!("could not sniff {}: {e}", path.display());
+            false
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use color_eyre::eyre::{Result, WrapErr};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::library::PEOPLE_SEPARATOR;
+use crate::metadata;
+
+/// Where Picasa keeps the faces it found in a folder's photos, and who they are
+const PICASA_INI: &str = ".picasa.ini";
+
+/// Picasa's id for faces it found but nobody named
+const PICASA_UNKNOWN: &str = "ffffffffffffffff";
+
+/// Reads the names of people other tools have marked in photos. po doesn't look for faces
+/// itself, it only carries over what Lightroom, digiKam, Picasa and the like already know
+pub struct Faces {
+    /// Picasa contact ids to names, from its `contacts.xml`
+    contacts: HashMap<String, String>,
+    /// Each folder's `.picasa.ini`, `None` where there isn't one
+    folders: HashMap<PathBuf, Option<PicasaFolder>>,
+}
+
+#[derive(Default)]
+struct PicasaFolder {
+    /// Contacts Picasa copied into the folder's ini, as it does for photos it's written faces for
+    contacts: HashMap<String, String>,
+    /// Contact ids of the faces in each photo, by lowercase file name
+    faces: HashMap<String, Vec<String>>,
+}
+
+impl Faces {
+    /// `picasa_contacts` is Picasa's `contacts.xml`, for folders whose ini doesn't name everyone
+    pub fn new(picasa_contacts: Option<&Path>) -> Result<Faces> {
+        let contacts = match picasa_contacts {
+            Some(path) => {
+                let xml = fs::read_to_string(path).wrap_err(format!("when reading Picasa contacts {}", path.display()))?;
+                read_contacts(&xml)
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(Faces { contacts, folders: HashMap::new() })
+    }
+
+    /// The people marked in `path`, from its XMP face regions, those of an XMP sidecar next to it,
+    /// and Picasa. Sorted, without duplicates
+    pub fn people(&mut self, path: &Path) -> Vec<String> {
+        let mut people = vec![];
+        for xmp in self.xmp_packets(path) {
+            people.extend(regions(&xmp));
+        }
+        people.extend(self.picasa(path));
+
+        let mut people: Vec<_> = people.iter().map(|p| clean_name(p)).collect();
+        people.retain(|p| !p.is_empty());
+        people.sort();
+        people.dedup();
+        if !people.is_empty() {
+            debug!("{} has {}", path.display(), people.join(", "));
+        }
+        people
+    }
+
+    fn xmp_packets(&self, path: &Path) -> Vec<String> {
+        let mut packets = vec![];
+        let is_xmp = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("xmp"));
+        let embedded = if is_xmp { fs::read_to_string(path).map(Some).map_err(Into::into) } else { metadata::read_xmp(path) };
+        match embedded {
+            Ok(packet) => packets.extend(packet),
+            Err(e) => debug!("could not read XMP from {}: {e:#}", path.display()),
+        }
+
+        if is_xmp {
+            return packets;
+        }
+
+        // Lightroom replaces the extension, darktable and digiKam append to it
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(".xmp");
+        for sidecar in [path.with_extension("xmp"), path.with_extension("XMP"), PathBuf::from(appended)] {
+            if let Ok(xmp) = fs::read_to_string(&sidecar) {
+                packets.push(xmp);
+            }
+        }
+        packets
+    }
+
+    fn picasa(&mut self, path: &Path) -> Vec<String> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return vec![];
+        };
+
+        let folder = self.folders.entry(dir.to_path_buf()).or_insert_with(|| {
+            let ini = dir.join(PICASA_INI);
+            match fs::read_to_string(&ini) {
+                Ok(ini) => Some(read_picasa_ini(&ini)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    warn!("could not read {}: {e}", ini.display());
+                    None
+                }
+            }
+        });
+        let Some(folder) = folder else {
+            return vec![];
+        };
+
+        let ids = folder.faces.get(&name.to_string_lossy().to_lowercase());
+        ids.into_iter()
+            .flatten()
+            .filter(|id| id.as_str() != PICASA_UNKNOWN)
+            .filter_map(|id| {
+                let name = folder.contacts.get(id).or_else(|| self.contacts.get(id));
+                if name.is_none() {
+                    debug!("{} has a face of Picasa contact {id}, who isn't in its contacts", path.display());
+                }
+                name.cloned()
+            })
+            .collect()
+    }
+}
+
+/// `name` on one line and without the separator the index keeps people apart with, either of
+/// which would corrupt the index entry it's written to
+fn clean_name(name: &str) -> String {
+    name.split(|c: char| c.is_whitespace() || c.is_control() || PEOPLE_SEPARATOR.contains(c))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Names of the face regions in an XMP packet, following the Metadata Working Group's schema
+fn regions(xmp: &str) -> Vec<String> {
+    let Some((_, list)) = xmp.split_once("mwg-rs:RegionList") else {
+        return vec![];
+    };
+
+    list.split("<rdf:li")
+        .skip(1)
+        .filter(|region| value(region, "mwg-rs:Type").is_none_or(|t| t == "Face"))
+        .filter_map(|region| value(region, "mwg-rs:Name"))
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
+/// The value of `name` in `xml`, written either as an attribute or as an element
+fn value(xml: &str, name: &str) -> Option<String> {
+    let mut rest = xml;
+    while let Some(i) = rest.find(name) {
+        let after = &rest[i + name.len()..];
+        if let Some(attribute) = after.trim_start().strip_prefix('=') {
+            let attribute = attribute.trim_start();
+            let quote = attribute.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let (value, _) = attribute[1..].split_once(quote)?;
+            return Some(unescape(value));
+        }
+        if let Some(element) = after.strip_prefix('>') {
+            let (value, _) = element.split_once('<')?;
+            return Some(unescape(value));
+        }
+        rest = after;
+    }
+    None
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else { break };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Contact ids to names from Picasa's `contacts.xml`, `<contact id="..." name="..."/>`
+fn read_contacts(xml: &str) -> HashMap<String, String> {
+    xml.split("<contact ")
+        .skip(1)
+        .filter_map(|contact| {
+            let tag = contact.split('>').next()?;
+            Some((value(tag, "id")?, value(tag, "name")?))
+        })
+        .collect()
+}
+
+/// A `.picasa.ini` has a `[Contacts2]` section of `id=Name;;` lines, and a section for each
+/// photo with a `faces=rect64(...),id;rect64(...),id` line
+fn read_picasa_ini(ini: &str) -> PicasaFolder {
+    let mut folder = PicasaFolder::default();
+    let mut section = String::new();
+    for line in ini.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.to_string();
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else { continue };
+
+        if section == "Contacts2" {
+            let name = value.split(';').next().unwrap_or_default().trim();
+            folder.contacts.insert(key.trim().to_string(), name.to_string());
+        } else if key.trim() == "faces" {
+            let ids = value.split(';').filter_map(|face| face.split_once(',')).map(|(_, id)| id.trim().to_string());
+            folder.faces.entry(section.to_lowercase()).or_default().extend(ids);
+        }
+    }
+    folder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_cleaned_for_the_index() {
+        let xmp = r#"<mwg-rs:RegionList><rdf:Bag>
+            <rdf:li mwg-rs:Name="Amy;Bo" mwg-rs:Type="Face"/>
+            <rdf:li mwg-rs:Name="Cy&#10;  Dee"/>
+            <rdf:li mwg-rs:Name=" ; "/>
+            <rdf:li mwg-rs:Name="Pet" mwg-rs:Type="Pet"/>
+        </rdf:Bag></mwg-rs:RegionList>"#;
+
+        let names: Vec<_> = regions(xmp).iter().map(|n| clean_name(n)).collect();
+        assert_eq!(names, ["Amy Bo", "Cy Dee", ""]);
+    }
+
+    #[test]
+    fn reads_picasa_faces() {
+        let ini = "[Contacts2]\nabc=Amy;;\n[IMG_0001.JPG]\nfaces=rect64(1),abc;rect64(2),ffffffffffffffff\n";
+        let folder = read_picasa_ini(ini);
+        assert_eq!(folder.contacts["abc"], "Amy");
+        assert_eq!(folder.faces["img_0001.jpg"], ["abc", PICASA_UNKNOWN]);
+    }
+}
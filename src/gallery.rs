@@ -0,0 +1,392 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use serde_json::json;
+use std::path::{Component, Path, PathBuf};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+
+use crate::budget;
+use crate::changes::{self, ChangeKind};
+use crate::library::{Library, LibraryFile, TEMPORARY_SUFFIX};
+use crate::thumbs;
+use crate::transcode::{self, OutputFormat, Preset};
+
+/// The library generation the gallery was last brought up to date with, within the gallery
+const STATE_FILE: &str = "gallery.json";
+/// Thumbnails, named by the content hash of the file they show
+const THUMBS_DIR: &str = "thumbs";
+/// One page per library folder
+const PAGES_DIR: &str = "pages";
+/// How many of the most recently imported files the feeds list
+const FEED_ITEMS: usize = 50;
+
+const THUMBNAIL: Preset = Preset {
+    max_dimension: Some(320),
+    format: Some(OutputFormat::Jpeg),
+    quality: Some(80),
+    strip_gps: true,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+struct State {
+    generation: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub pages: usize,
+    pub thumbnails: usize,
+}
+
+/// Write a static gallery of the library to `dest`: an index of its folders, a page of thumbnails
+/// for each, linking to the originals. Only folders which changed since the gallery was last
+/// written are redone, going by the change feed. Given the `url` it's served at, RSS and JSON
+/// feeds of recent imports are written too
+pub fn generate(library: &Library, dest: &Path, url: Option<&str>) -> Result<Report> {
+    fs::create_dir_all(dest.join(THUMBS_DIR)).wrap_err(format!("when creating the gallery in {}", dest.display()))?;
+    fs::create_dir_all(dest.join(PAGES_DIR))?;
+
+    let generation = library.generation();
+    let state_path = dest.join(STATE_FILE);
+    let state = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<State>(&s).ok());
+
+    let mut folders: BTreeMap<PathBuf, Vec<&LibraryFile>> = BTreeMap::new();
+    for file in library.files() {
+        folders.entry(folder_of(&file.path_in_library)).or_default().push(file);
+    }
+
+    // Folders whose pages need writing again, `None` for all of them
+    let dirty = match state {
+        Some(state) if state.generation == generation => {
+            debug!("gallery is up to date with generation {generation}");
+            if let Some(url) = url {
+                write_feeds(library, dest, url)?;
+            }
+            return Ok(Report::default());
+        }
+        Some(state) if state.generation < generation => {
+            let changes = changes::since(library.meta_root(), state.generation)?;
+            debug!("{} changes since the gallery was written at generation {}", changes.len(), state.generation);
+
+            let present: HashSet<_> = library.files().iter().map(|f| f.hash.encode()).collect();
+            for change in changes.iter().filter(|c| c.kind == ChangeKind::Removed && !present.contains(&c.hash)) {
+                remove_if_present(&thumb_path(dest, &change.hash))?;
+            }
+
+            Some(
+                changes
+                    .iter()
+                    .flat_map(|c| [Some(&c.path), c.previous_path.as_ref()])
+                    .flatten()
+                    .map(|p| folder_of(Path::new(p)))
+                    .collect::<BTreeSet<_>>(),
+            )
+        }
+        // Never written, or written from a library which has since gone back in time
+        _ => {
+            info!("writing the whole gallery");
+            remove_stale(dest, &folders, library)?;
+            None
+        }
+    };
+
+    let to_write: Vec<_> = match &dirty {
+        Some(dirty) => dirty.iter().collect(),
+        None => folders.keys().collect(),
+    };
+
+    // By thumbnail, as files with the same content share one
+    let wanted: BTreeMap<_, _> = to_write
+        .iter()
+        .filter_map(|folder| folders.get(*folder))
+        .flatten()
+        .map(|f| {
+            let hash = f.hash.encode();
+            (thumb_path(dest, &hash), (library.absolute_path(f), thumbs::thumbnail_path(library, &hash)))
+        })
+        .filter(|(thumb, _)| !thumb.exists())
+        .collect();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(budget::render_workers()).build()?;
+    let thumbnails = pool.install(|| {
+        wanted
+            .into_par_iter()
+            .filter(|(thumb, (source, cached))| match render_thumbnail(source, cached, thumb) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    warn!("could not make a thumbnail of {}: {e:#}", source.display());
+                    false
+                }
+            })
+            .count()
+    });
+
+    let originals = relative_link(&dest.join(PAGES_DIR), library.output_root())?;
+    for folder in &to_write {
+        let page = dest.join(PAGES_DIR).join(page_name(folder));
+        match folders.get(*folder) {
+            Some(files) => fs::write(&page, folder_page(dest, folder, files, &originals))?,
+            None => remove_if_present(&page)?,
+        }
+    }
+    fs::write(dest.join("index.html"), index_page(&folders))?;
+    if let Some(url) = url {
+        write_feeds(library, dest, url)?;
+    }
+
+    fs::write(&state_path, serde_json::to_string(&State { generation })?)?;
+    Ok(Report { pages: to_write.len(), thumbnails })
+}
+
+/// The folder whose page a file at `path` in the library shows up on
+fn folder_of(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn page_name(folder: &Path) -> String {
+    if folder.as_os_str().is_empty() {
+        return "_.html".to_string();
+    }
+    let parts: Vec<_> = folder.iter().map(|p| p.to_string_lossy()).collect();
+    format!("{}.html", parts.join("_"))
+}
+
+fn thumb_path(dest: &Path, hash: &str) -> PathBuf {
+    dest.join(THUMBS_DIR).join(format!("{hash}.jpg"))
+}
+
+/// Whether a thumbnail could be made, as it can't of raw files and videos unless `po thumbs`
+/// made one of them at `cached`
+fn render_thumbnail(source: &Path, cached: &Path, thumb: &Path) -> Result<bool> {
+    let source = match transcode::can_render(source)? {
+        true => source,
+        false if cached.exists() => cached,
+        false => return Ok(false),
+    };
+
+    // So a thumbnail cut short by a crash isn't taken as done next time
+    let mut partial = thumb.as_os_str().to_owned();
+    partial.push(TEMPORARY_SUFFIX);
+    transcode::render(source, Path::new(&partial), &THUMBNAIL)?;
+    fs::rename(&partial, thumb)?;
+    Ok(true)
+}
+
+/// Clear out pages and thumbnails of files no longer in the library
+fn remove_stale(dest: &Path, folders: &BTreeMap<PathBuf, Vec<&LibraryFile>>, library: &Library) -> Result<()> {
+    let pages: HashSet<_> = folders.keys().map(|f| page_name(f)).collect();
+    for entry in fs::read_dir(dest.join(PAGES_DIR))? {
+        let entry = entry?;
+        if !pages.contains(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    let thumbs: HashSet<_> = library.files().iter().map(|f| format!("{}.jpg", f.hash.encode())).collect();
+    for entry in fs::read_dir(dest.join(THUMBS_DIR))? {
+        let entry = entry?;
+        if !thumbs.contains(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_if_present(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// A link from pages in `from` to `to`, e.g. `../../library/`
+pub fn relative_link(from: &Path, to: &Path) -> Result<String> {
+    let from = fs::canonicalize(from)?;
+    let to = fs::canonicalize(to)?;
+    let common = from.components().zip(to.components()).take_while(|(a, b)| a == b).count();
+
+    let mut link = String::new();
+    for _ in from.components().skip(common) {
+        link.push_str("../");
+    }
+    for part in to.components().skip(common) {
+        if let Component::Normal(part) = part {
+            link.push_str(&url_encode(&part.to_string_lossy()));
+            link.push('/');
+        }
+    }
+    Ok(link)
+}
+
+pub fn url_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn folder_title(folder: &Path) -> String {
+    if folder.as_os_str().is_empty() { "/".to_string() } else { folder.to_string_lossy().to_string() }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}a{color:inherit}\
+.grid{display:flex;flex-wrap:wrap;gap:8px}.grid a{width:160px;height:160px;display:flex;\
+align-items:center;justify-content:center;background:#eee;overflow:hidden;word-break:break-all}\
+.grid img{max-width:100%;max-height:100%}";
+
+fn html(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{STYLE}</style></head>\n<body>\n{body}</body></html>\n",
+        escape(title)
+    )
+}
+
+fn folder_page(dest: &Path, folder: &Path, files: &[&LibraryFile], originals: &str) -> String {
+    let mut files = files.to_vec();
+    files.sort_by(|a, b| a.path_in_library.cmp(&b.path_in_library));
+
+    let title = folder_title(folder);
+    let mut body = format!("<p><a href=\"../index.html\">all folders</a></p>\n<h1>{}</h1>\n<div class=\"grid\">\n", escape(&title));
+    for file in files {
+        let name = file.path_in_library.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let link = format!("{originals}{}", url_encode(&file.path_in_library.to_string_lossy()));
+        let hash = file.hash.encode();
+        let tile = if thumb_path(dest, &hash).exists() {
+            format!("<img src=\"../{THUMBS_DIR}/{hash}.jpg\" alt=\"{}\" loading=\"lazy\">", escape(&name))
+        } else {
+            escape(&name)
+        };
+        body.push_str(&format!("<a href=\"{}\" title=\"{}\">{tile}</a>\n", escape(&link), escape(&name)));
+    }
+    body.push_str("</div>\n");
+
+    html(&title, &body)
+}
+
+fn index_page(folders: &BTreeMap<PathBuf, Vec<&LibraryFile>>) -> String {
+    let mut body = String::from("<h1>library</h1>\n<ul>\n");
+    // Newest first, for dated folders
+    for (folder, files) in folders.iter().rev() {
+        body.push_str(&format!(
+            "<li><a href=\"{PAGES_DIR}/{}\">{}</a> ({})</li>\n",
+            escape(&url_encode(&page_name(folder))),
+            escape(&folder_title(folder)),
+            files.len()
+        ));
+    }
+    body.push_str("</ul>\n");
+
+    html("library", &body)
+}
+
+/// An RSS feed, `feed.xml`, and a JSON feed, `feed.json`, of the files imported most recently,
+/// for people to follow new additions in a feed reader. Left alone when nothing changed, so
+/// readers polling them aren't sent the same feed again
+fn write_feeds(library: &Library, dest: &Path, url: &str) -> Result<()> {
+    let base = if url.ends_with('/') { url.to_string() } else { format!("{url}/") };
+    let originals = join_url(&base, &relative_link(dest, library.output_root())?);
+    let title = library
+        .output_root()
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "library".to_string());
+
+    let mut recent: Vec<_> = library.files().iter().filter(|f| f.imported_at.is_some()).collect();
+    recent.sort_by(|a, b| b.imported_at.cmp(&a.imported_at).then_with(|| a.path_in_library.cmp(&b.path_in_library)));
+    recent.truncate(FEED_ITEMS);
+
+    let mut rss = String::new();
+    let mut items = vec![];
+    for file in recent {
+        let imported_at = file.imported_at.as_deref().expect("recent files to have an import time");
+        let imported_at = OffsetDateTime::parse(imported_at, &Rfc3339)
+            .wrap_err(format!("when reading when {} was imported", file.path_in_library.display()))?;
+        let name = file.path_in_library.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let hash = file.hash.encode();
+        let link = format!("{originals}{}", url_encode(&file.path_in_library.to_string_lossy()));
+        let page = format!("{base}{PAGES_DIR}/{}", url_encode(&page_name(&folder_of(&file.path_in_library))));
+        let thumb = fs::metadata(thumb_path(dest, &hash)).ok().map(|m| (format!("{base}{THUMBS_DIR}/{hash}.jpg"), m.len()));
+
+        let content = match &thumb {
+            Some((thumb, _)) => format!("<a href=\"{}\"><img src=\"{}\" alt=\"{}\"></a>", escape(&link), escape(thumb), escape(&name)),
+            None => format!("<a href=\"{}\">{}</a>", escape(&link), escape(&name)),
+        };
+
+        rss.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"false\">{hash}</guid><pubDate>{}</pubDate><description>{}</description>",
+            escape(&name),
+            escape(&page),
+            imported_at.format(&Rfc2822)?,
+            escape(&content)
+        ));
+        if let Some((thumb, length)) = &thumb {
+            rss.push_str(&format!("<enclosure url=\"{}\" length=\"{length}\" type=\"image/jpeg\"/>", escape(thumb)));
+        }
+        rss.push_str("</item>\n");
+
+        let mut item = json!({
+            "id": hash,
+            "url": page,
+            "title": name,
+            "content_html": content,
+            "date_published": imported_at.format(&Rfc3339)?,
+        });
+        if let Some((thumb, _)) = thumb {
+            item["image"] = json!(thumb);
+        }
+        items.push(item);
+    }
+
+    let rss = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\"><channel><title>{}</title><link>{}</link><description>recently imported</description>\n{rss}</channel></rss>\n",
+        escape(&title),
+        escape(&base)
+    );
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": title,
+        "home_page_url": base,
+        "feed_url": format!("{base}feed.json"),
+        "items": items,
+    });
+
+    write_if_changed(&dest.join("feed.xml"), &rss)?;
+    write_if_changed(&dest.join("feed.json"), &serde_json::to_string_pretty(&feed)?)
+}
+
+/// `relative` resolved against the directory URL `base`, as a browser would
+fn join_url(base: &str, relative: &str) -> String {
+    let scheme_end = base.find("://").map_or(0, |i| i + 3);
+    let path_start = base[scheme_end..].find('/').map_or(base.len(), |i| scheme_end + i);
+    let mut path = base[path_start..].trim_end_matches('/').to_string();
+
+    let mut relative = relative;
+    while let Some(rest) = relative.strip_prefix("../") {
+        path.truncate(path.rfind('/').unwrap_or(0));
+        relative = rest;
+    }
+    format!("{}{path}/{relative}", &base[..path_start])
+}
+
+fn write_if_changed(path: &Path, content: &str) -> Result<()> {
+    if fs::read_to_string(path).is_ok_and(|old| old == content) {
+        return Ok(());
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
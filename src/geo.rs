@@ -0,0 +1,118 @@
+use color_eyre::eyre::{Result, WrapErr};
+use rayon::prelude::*;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+use tracing::{info, warn};
+
+use crate::gallery::escape;
+use crate::library::{Library, LibraryFile};
+use crate::metadata;
+
+/// Where a library file was taken, according to its EXIF
+pub struct Point<'a> {
+    pub file: &'a LibraryFile,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub taken: Option<PrimitiveDateTime>,
+}
+
+impl Point<'_> {
+    /// ISO 8601 without an offset, as EXIF doesn't say which time zone it's in
+    fn date(&self) -> Option<String> {
+        let format = format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]");
+        self.taken.and_then(|t| t.format(format).ok())
+    }
+
+    pub fn name(&self) -> String {
+        self.file.path_in_library.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+    }
+}
+
+/// The points of `files` which have a location, and how many don't
+pub fn locate<'a>(library: &Library, files: &[&'a LibraryFile]) -> (Vec<Point<'a>>, usize) {
+    let paths: Vec<_> = files.iter().map(|f| (*f, library.absolute_path(f))).collect();
+    let found: Vec<_> = paths
+        .into_par_iter()
+        .map(|(file, path)| {
+            let info = match metadata::read_capture_info(&path) {
+                Ok(info) => info,
+                Err(e) => {
+                    warn!("could not read the metadata of {}: {e:#}", path.display());
+                    return None;
+                }
+            };
+            let (latitude, longitude) = info.location?;
+            Some(Point { file, latitude, longitude, taken: info.taken })
+        })
+        .collect();
+
+    let unplaced = found.iter().filter(|p| p.is_none()).count();
+    let mut points: Vec<_> = found.into_iter().flatten().collect();
+    points.sort_by(|a, b| a.taken.cmp(&b.taken).then_with(|| a.file.path_in_library.cmp(&b.file.path_in_library)));
+    (points, unplaced)
+}
+
+/// Write where each of `files` was taken to `geojson` and `kml`, for GIS tools and Google Earth
+pub fn export(library: &Library, files: &[&LibraryFile], geojson: Option<&Path>, kml: Option<&Path>) -> Result<()> {
+    let (points, unplaced) = locate(library, files);
+    for (path, content) in [(geojson, to_geojson(&points)?), (kml, to_kml(&points))] {
+        if let Some(path) = path {
+            fs::write(path, content).wrap_err(format!("when writing {}", path.display()))?;
+            info!("wrote {} points to {}", points.len(), path.display());
+        }
+    }
+
+    if unplaced > 0 {
+        info!("{unplaced} files had no location");
+    }
+    Ok(())
+}
+
+fn to_geojson(points: &[Point]) -> Result<String> {
+    let features: Vec<_> = points
+        .iter()
+        .map(|p| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [p.longitude, p.latitude] },
+                "properties": {
+                    "name": p.name(),
+                    "hash": p.file.hash.encode(),
+                    "path": p.file.path_in_library.to_string_lossy(),
+                    "date": p.date(),
+                },
+            })
+        })
+        .collect();
+
+    Ok(serde_json::to_string_pretty(&json!({ "type": "FeatureCollection", "features": features }))?)
+}
+
+fn to_kml(points: &[Point]) -> String {
+    let mut kml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document>\n",
+    );
+    for point in points {
+        kml.push_str(&format!("<Placemark><name>{}</name>", escape(&point.name())));
+        if let Some(date) = point.date() {
+            kml.push_str(&format!("<TimeStamp><when>{date}</when></TimeStamp>"));
+        }
+        kml.push_str("<ExtendedData>");
+        for (name, value) in [
+            ("hash", point.file.hash.encode()),
+            ("path", point.file.path_in_library.to_string_lossy().to_string()),
+            ("date", point.date().unwrap_or_default()),
+        ] {
+            kml.push_str(&format!("<Data name=\"{name}\"><value>{}</value></Data>", escape(&value)));
+        }
+        kml.push_str(&format!(
+            "</ExtendedData><Point><coordinates>{},{}</coordinates></Point></Placemark>\n",
+            point.longitude, point.latitude
+        ));
+    }
+    kml.push_str("</Document></kml>\n");
+    kml
+}
@@ -0,0 +1,153 @@
+use clap::ValueEnum;
+use confique::serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+/// Read buffer for hashing when not going through io_uring
+pub const READ_BUFFER_BYTES: usize = 1024 * 1024;
+
+static FILES: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+static NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// How file contents are hashed. Chosen when a library is set up and recorded alongside it
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Several times faster than SHA256 on most CPUs, for fast disks where hashing holds imports up
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// What the algorithm is called in the index and config
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The algorithm called `name`, see [`HashAlgorithm::name`]
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        HashAlgorithm::value_variants().iter().copied().find(|a| a.name() == name)
+    }
+}
+
+/// Hashes content fed to it with either algorithm
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Hasher {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, content: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(content),
+            Hasher::Blake3(hasher) => {
+                hasher.update(content);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The CPU extension `algorithm` will hash with. sha2 and blake3 detect these themselves at
+/// runtime, this is only so we can tell people what they're getting
+pub fn acceleration(algorithm: HashAlgorithm) -> &'static str {
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            if std::arch::is_x86_feature_detected!("sha") {
+                return "SHA-NI";
+            }
+
+            #[cfg(target_arch = "aarch64")]
+            if std::arch::is_aarch64_feature_detected!("sha2") {
+                return "ARMv8 SHA2";
+            }
+        }
+        HashAlgorithm::Blake3 => {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                if std::arch::is_x86_feature_detected!("avx512f") {
+                    return "AVX-512";
+                }
+                if std::arch::is_x86_feature_detected!("avx2") {
+                    return "AVX2";
+                }
+                if std::arch::is_x86_feature_detected!("sse4.1") {
+                    return "SSE4.1";
+                }
+            }
+
+            #[cfg(target_arch = "aarch64")]
+            return "NEON";
+        }
+    }
+
+    "none"
+}
+
+/// The implementation of `algorithm` po was built with
+pub fn backend(algorithm: HashAlgorithm) -> &'static str {
+    match algorithm {
+        HashAlgorithm::Sha256 if cfg!(feature = "sha2-asm") => "sha2 (asm)",
+        HashAlgorithm::Sha256 => "sha2",
+        HashAlgorithm::Blake3 => "blake3",
+    }
+}
+
+pub fn record(bytes: u64, took: Duration) {
+    FILES.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+    NANOS.fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Log how much was hashed so far with `algorithm` and how fast
+pub fn report(algorithm: HashAlgorithm) {
+    let files = FILES.load(Ordering::Relaxed);
+    if files == 0 {
+        return;
+    }
+
+    let mib = BYTES.load(Ordering::Relaxed) as f64 / (1024.0 * 1024.0);
+    let took = Duration::from_nanos(NANOS.load(Ordering::Relaxed));
+    debug!(
+        "hashed {} files, {:.1} MiB in {:.2?} ({:.1} MiB/s) with {}, acceleration: {}",
+        files,
+        mib,
+        took,
+        mib / took.as_secs_f64().max(f64::EPSILON),
+        backend(algorithm),
+        acceleration(algorithm),
+    );
+}
@@ -0,0 +1,156 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use time::{Date, Month};
+use tracing::warn;
+
+use crate::export::capture_date;
+use crate::library::Library;
+
+/// Size of each day's square, and the gap between them
+const CELL: u32 = 11;
+const GAP: u32 = 3;
+/// Room for month names above the grid and weekday names to its left, in the SVG
+const TOP: u32 = 20;
+const LEFT: u32 = 30;
+
+/// No photos, then increasingly many, as GitHub colours contributions
+const COLOURS: [[u8; 3]; 5] = [
+    [0xeb, 0xed, 0xf0],
+    [0x9b, 0xe9, 0xa8],
+    [0x40, 0xc4, 0x63],
+    [0x30, 0xa1, 0x4e],
+    [0x21, 0x6e, 0x39],
+];
+
+/// The year in a `--heatmap` spec like `year=2024`
+pub fn parse_year(spec: &str) -> Result<i32> {
+    let year = spec.strip_prefix("year=").unwrap_or(spec);
+    year.parse()
+        .ok()
+        .filter(|y| (1..=9999).contains(y))
+        .ok_or_else(|| eyre!("{spec:?} is not a heatmap to draw, expected a year like year=2024"))
+}
+
+/// How many photos were taken on each day of `year`. Files imported before po recorded when
+/// they were taken have it read from their metadata instead
+pub fn counts(library: &Library, year: i32) -> BTreeMap<Date, usize> {
+    let (recorded, unrecorded): (Vec<_>, Vec<_>) = library.files().iter().partition(|f| f.taken.is_some());
+
+    let paths: Vec<_> = unrecorded.into_iter().map(|f| library.absolute_path(f)).collect();
+    let read: Vec<_> = paths
+        .into_par_iter()
+        .filter_map(|path| match capture_date(&path) {
+            Ok(taken) => Some(taken),
+            Err(e) => {
+                warn!("could not tell when {} was taken: {e:#}", path.display());
+                None
+            }
+        })
+        .collect();
+
+    let mut counts = BTreeMap::new();
+    let dates = recorded.iter().filter_map(|f| f.taken()).chain(read).map(|taken| taken.date());
+    for date in dates.filter(|d| d.year() == year) {
+        *counts.entry(date).or_default() += 1;
+    }
+    counts
+}
+
+/// Draw `counts` as a calendar of `year`, a column per week and a row per weekday, as an SVG
+/// or a PNG depending on `out`'s extension. PNGs have no labels
+pub fn write(counts: &BTreeMap<Date, usize>, year: i32, out: &Path) -> Result<()> {
+    let cells = cells(counts, year)?;
+    let weeks = cells.iter().map(|c| c.week).max().unwrap_or(0) + 1;
+    let extension = out.extension().map(|e| e.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("svg") => fs::write(out, svg(&cells, weeks, year)).wrap_err(format!("when writing {}", out.display())),
+        Some("png") => png(&cells, weeks).save(out).wrap_err(format!("when writing {}", out.display())),
+        _ => Err(eyre!("can't tell what to draw {} as, name it .svg or .png", out.display())),
+    }
+}
+
+struct Cell {
+    date: Date,
+    count: usize,
+    week: u32,
+    weekday: u32,
+    level: usize,
+}
+
+fn cells(counts: &BTreeMap<Date, usize>, year: i32) -> Result<Vec<Cell>> {
+    let most = counts.values().copied().max().unwrap_or(0);
+    let first = Date::from_calendar_date(year, Month::January, 1)?;
+    let offset = u32::from(first.weekday().number_days_from_sunday());
+
+    let mut cells = vec![];
+    let mut date = first;
+    while date.year() == year {
+        let count = counts.get(&date).copied().unwrap_or(0);
+        let day = offset + u32::from(date.ordinal()) - 1;
+        cells.push(Cell {
+            date,
+            count,
+            week: day / 7,
+            weekday: day % 7,
+            // Scaled to the busiest day, so any photos at all show up
+            level: if count == 0 { 0 } else { (count * 4).div_ceil(most).clamp(1, 4) },
+        });
+
+        let Some(next) = date.next_day() else { break };
+        date = next;
+    }
+    Ok(cells)
+}
+
+fn position(cell: &Cell) -> (u32, u32) {
+    (cell.week * (CELL + GAP), cell.weekday * (CELL + GAP))
+}
+
+fn svg(cells: &[Cell], weeks: u32, year: i32) -> String {
+    let width = LEFT + weeks * (CELL + GAP);
+    let height = TOP + 7 * (CELL + GAP) + 20;
+    let total: usize = cells.iter().map(|c| c.count).sum();
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" font-family=\"sans-serif\" font-size=\"10\">\n"
+    );
+    for (row, name) in [(1, "Mon"), (3, "Wed"), (5, "Fri")] {
+        svg.push_str(&format!("<text x=\"0\" y=\"{}\">{name}</text>\n", TOP + row * (CELL + GAP) + CELL - 2));
+    }
+    for cell in cells.iter().filter(|c| c.date.day() == 1) {
+        let (x, _) = position(cell);
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n", LEFT + x, TOP - 6, &cell.date.month().to_string()[..3]));
+    }
+    for cell in cells {
+        let (x, y) = position(cell);
+        let [r, g, b] = COLOURS[cell.level];
+        svg.push_str(&format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{CELL}\" height=\"{CELL}\" rx=\"2\" fill=\"#{r:02x}{g:02x}{b:02x}\"><title>{}: {}</title></rect>\n",
+            LEFT + x,
+            TOP + y,
+            cell.date,
+            cell.count
+        ));
+    }
+    svg.push_str(&format!("<text x=\"{LEFT}\" y=\"{}\">{total} photos in {year}</text>\n", height - 4));
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn png(cells: &[Cell], weeks: u32) -> RgbImage {
+    let mut image = RgbImage::from_pixel(weeks * (CELL + GAP) + GAP, 7 * (CELL + GAP) + GAP, Rgb([0xff, 0xff, 0xff]));
+    for cell in cells {
+        let (x, y) = position(cell);
+        for dx in 0..CELL {
+            for dy in 0..CELL {
+                image.put_pixel(GAP + x + dx, GAP + y + dy, Rgb(COLOURS[cell.level]));
+            }
+        }
+    }
+    image
+}
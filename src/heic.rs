@@ -0,0 +1,92 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::library::{FileHash, Library};
+
+/// The JPEG quality HEICs are transcoded at, heif-convert's own default
+const QUALITY: u8 = 90;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// JPEGs added to the library
+    pub added: Vec<FileHash>,
+    /// HEICs moved to the trash once their JPEG was in place
+    pub replaced: Vec<FileHash>,
+}
+
+/// Whether `path` is a HEIC or HEIF photo, going by its extension
+pub fn is_heic(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ["heic", "heif"].contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Add a JPEG beside each of `hashes` which is a HEIC, keeping its EXIF, for everything which
+/// can't open HEICs. Unless `keep_original`, each HEIC is moved to the trash once its JPEG is in
+/// the library. Needs `heif-convert` from libheif on the PATH
+pub fn transcode(library: &mut Library, hashes: &[FileHash], keep_original: bool) -> Result<Report> {
+    let wanted: HashSet<_> = hashes.iter().collect();
+    let files: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| wanted.contains(&f.hash) && is_heic(&f.path_in_library))
+        .map(|f| (f.path_in_library.clone(), library.absolute_path(f)))
+        .collect();
+
+    let mut report = Report::default();
+    if files.is_empty() {
+        return Ok(report);
+    }
+    if Command::new("heif-convert").arg("--help").output().is_err() {
+        return Err(eyre!("heif-convert wasn't found, install libheif's tools to transcode HEICs"));
+    }
+
+    let scratch = library.meta_root().join("staging").join(format!("heic-{}", std::process::id()));
+    for (path_in_library, path) in files {
+        let content = match to_jpeg(&path, &scratch) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("could not transcode {}: {e:#}", path.display());
+                continue;
+            }
+        };
+
+        let Some(jpeg) = library.add_beside(&path_in_library, "jpg", &content)? else {
+            warn!("{} already has a JPEG beside it, not transcoding it", path.display());
+            continue;
+        };
+        report.added.push(jpeg.hash.clone());
+
+        if !keep_original {
+            info!("moving {} to the trash, as its JPEG replaces it", path.display());
+            report.replaced.push(library.remove_file(&path_in_library, true)?);
+        }
+    }
+
+    Ok(report)
+}
+
+/// `source` as a JPEG, decoded with `heif-convert` into `scratch`, which is removed after
+fn to_jpeg(source: &Path, scratch: &Path) -> Result<Vec<u8>> {
+    fs::create_dir_all(scratch)?;
+    // heif-convert picks the format by the extension, and numbers the files of HEICs holding
+    // several images, so it's given a directory of its own and the first is kept
+    let output = scratch.join("image.jpg");
+    let mut command = Command::new("heif-convert");
+    command.args(["-q", &QUALITY.to_string()]).arg(source).arg(&output);
+    debug!("running {:?}", command);
+
+    let result = command.output().wrap_err("when running heif-convert").and_then(|out| {
+        if !out.status.success() {
+            return Err(eyre!("heif-convert failed: {}", String::from_utf8_lossy(&out.stderr).trim()));
+        }
+        let written = [output.clone(), scratch.join("image-1.jpg")].into_iter().find(|p| p.exists());
+        let written = written.ok_or_else(|| eyre!("heif-convert wrote nothing for {}", source.display()))?;
+        Ok(fs::read(written)?)
+    });
+    let _ = fs::remove_dir_all(scratch);
+    result
+}
@@ -0,0 +1,95 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+use unic_langid::LanguageIdentifier;
+
+static BUNDLE: OnceLock<FluentBundle<FluentResource>> = OnceLock::new();
+
+const ENGLISH: &str = include_str!("../locales/en.ftl");
+
+/// Translations shipped with po, by language
+const BUILT_IN: &[(&str, &str)] = &[("de", include_str!("../locales/de.ftl"))];
+
+/// Translate message `id`, as in `t!("naming-renamed", count = 3)`
+#[macro_export]
+macro_rules! t {
+    ($id:expr) => {
+        $crate::i18n::message($id, None)
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::message($id, Some(&args))
+    }};
+}
+
+/// The language from the usual locale environment variables, e.g. `de_DE.UTF-8` gives `de-DE`
+fn language_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|value| value.split(['.', '@']).next().unwrap_or_default().replace('_', "-"))
+}
+
+fn parse(name: &str, source: String) -> FluentResource {
+    FluentResource::try_new(source).unwrap_or_else(|(resource, errors)| {
+        warn!("{} has {} errors, some messages will be missing", name, errors.len());
+        resource
+    })
+}
+
+fn build(language: Option<&str>, translations: Option<&Path>) -> FluentBundle<FluentResource> {
+    let requested = language.map(str::to_string).or_else(language_from_env);
+    let langid: LanguageIdentifier = requested
+        .as_deref()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or_else(|| "en".parse().expect("en to be a valid language"));
+    debug!("using language {}", langid);
+
+    let mut bundle = FluentBundle::new_concurrent(vec![langid.clone()]);
+    // Terminals don't need the bidi isolation marks fluent puts around arguments
+    bundle.set_use_isolating(false);
+
+    // English first, so anything a translation doesn't cover falls back to it
+    bundle.add_resource_overriding(parse("en", ENGLISH.to_string()));
+
+    let language = langid.language.as_str();
+    if let Some((_, source)) = BUILT_IN.iter().find(|(l, _)| *l == language) {
+        bundle.add_resource_overriding(parse(language, source.to_string()));
+    }
+
+    // Community translations can be dropped in without rebuilding po, they win over built-in ones
+    if let Some(dir) = translations {
+        let path = dir.join(format!("{language}.ftl"));
+        match fs::read_to_string(&path) {
+            Ok(source) => bundle.add_resource_overriding(parse(&path.display().to_string(), source)),
+            Err(e) => debug!("no translation at {}: {e}", path.display()),
+        }
+    }
+
+    bundle
+}
+
+/// Pick the language for this run, `None` going by the environment. Only the first call has any effect
+pub fn init(language: Option<&str>, translations: Option<&Path>) {
+    let _ = BUNDLE.set(build(language, translations));
+}
+
+pub fn message(id: &str, args: Option<&FluentArgs>) -> String {
+    let bundle = BUNDLE.get_or_init(|| build(None, None));
+    let Some(pattern) = bundle.get_message(id).and_then(|m| m.value()) else {
+        warn!("no message {id}");
+        return id.to_string();
+    };
+
+    let mut errors = vec![];
+    let formatted = bundle.format_pattern(pattern, args, &mut errors);
+    if !errors.is_empty() {
+        debug!("errors formatting {id}: {errors:?}");
+    }
+    formatted.into_owned()
+}
@@ -0,0 +1,32 @@
+use color_eyre::eyre::{Result, WrapErr};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+use tracing::debug;
+
+/// Name of the gitignore-style file listing what po should leave alone
+pub const IGNORE_FILE: &str = ".poignore";
+
+/// The patterns deciding which files in `input` are not imported. `exclude` from the config comes
+/// first, then the library's `.poignore`, then the input's own, so later ones can re-include
+/// files with `!pattern`. Patterns are relative to the input either way
+pub fn for_input(input: &Path, library_root: &Path, excludes: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(input);
+    for pattern in excludes {
+        builder
+            .add_line(None, pattern)
+            .wrap_err(format!("when reading exclude pattern {pattern:?}"))?;
+    }
+
+    for file in [library_root.join(IGNORE_FILE), input.join(IGNORE_FILE)] {
+        if !file.exists() {
+            continue;
+        }
+
+        debug!("reading ignores from {}", file.display());
+        if let Some(e) = builder.add(&file) {
+            return Err(e).wrap_err(format!("when reading {}", file.display()));
+        }
+    }
+
+    Ok(builder.build()?)
+}
@@ -0,0 +1,276 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{debug, info};
+
+use crate::library::{FileHash, Library, TEMPORARY_SUFFIX};
+use crate::t;
+
+// Checkpoint at most this often, rewriting the done set for every file would be slow
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+// A job which hasn't checkpointed for this long is assumed to have been interrupted
+const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Progress of a long running operation, checkpointed to `_pometa/jobs/<kind>` so it can be
+/// resumed after being interrupted
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+pub struct Job {
+    pub kind: String,
+    /// What the job was started with, a resume has to match
+    pub args: String,
+    pub started: String,
+    pub updated: String,
+    pub total: usize,
+    done: HashSet<String>,
+    /// Items done a second between the last two checkpoints. A resumed job's start says nothing
+    /// about how fast it's going now, so this is what its ETA goes by
+    #[serde(default)]
+    rate: Option<f64>,
+    #[serde(skip)]
+    last_checkpoint: Option<Instant>,
+    /// How many items were done at the last checkpoint
+    #[serde(skip)]
+    done_at_checkpoint: usize,
+}
+
+/// Every kind of job there is, each with a checkpoint file of that name
+pub const KINDS: &[&str] = &["naming"];
+
+fn jobs_root(meta_root: &Path) -> PathBuf {
+    meta_root.join("jobs")
+}
+
+/// Where a `kind` job is checkpointed. Only known kinds, so one given on the command line can't
+/// point outside the jobs folder
+fn job_path(meta_root: &Path, kind: &str) -> Result<PathBuf> {
+    if !KINDS.contains(&kind) {
+        return Err(eyre!("there is no kind of job called {kind:?}, expected one of {}", KINDS.join(", ")));
+    }
+    Ok(jobs_root(meta_root).join(kind))
+}
+
+fn now() -> Result<String> {
+    Ok(OffsetDateTime::now_utc().format(&Rfc3339)?)
+}
+
+fn read(path: &Path) -> Result<Job> {
+    serde_json::from_str(&fs::read_to_string(path)?).wrap_err(format!("when reading checkpoint {}", path.display()))
+}
+
+/// Every job with a checkpoint in the library with metadata in `meta_root`, whether still
+/// running or interrupted. Only the checkpoints are read, so this works while a job has the
+/// library locked
+pub fn list(meta_root: &Path) -> Result<Vec<Job>> {
+    let root = jobs_root(meta_root);
+    if !root.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut jobs = vec![];
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        // Skip checkpoints caught halfway through being written
+        if path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        jobs.push(read(&path)?);
+    }
+
+    jobs.sort_by(|a, b| a.started.cmp(&b.started));
+    Ok(jobs)
+}
+
+pub fn load(meta_root: &Path, kind: &str) -> Result<Job> {
+    let path = job_path(meta_root, kind)?;
+    if !path.exists() {
+        return Err(eyre!("there is no {kind} job"));
+    }
+    read(&path)
+}
+
+/// Drop a job's checkpoint. A running job notices at its next checkpoint and stops
+pub fn cancel(meta_root: &Path, kind: &str) -> Result<()> {
+    let job = load(meta_root, kind)?;
+    fs::remove_file(job_path(meta_root, kind)?)?;
+    info!("{}", t!("job-cancelled", kind = kind, done = job.progress(), total = job.total));
+    Ok(())
+}
+
+impl Job {
+    /// Start a `kind` job over `total` items, picking up the last checkpoint if `resume` is set
+    pub fn start(library: &Library, kind: &str, args: &str, total: usize, resume: bool) -> Result<Self> {
+        let path = job_path(library.meta_root(), kind)?;
+
+        if path.exists() && resume {
+            let job = read(&path)?;
+
+            if job.args != args {
+                return Err(eyre!(
+                    "the interrupted {kind} job was started with {}, not {args}. run it with those to resume",
+                    job.args
+                ));
+            }
+
+            info!("resuming {} job, {} of {} already done", kind, job.done.len(), job.total);
+            let mut job = Job { total, last_checkpoint: None, done_at_checkpoint: job.done.len(), ..job };
+            job.checkpoint(library)?;
+            return Ok(job);
+        }
+
+        if path.exists() {
+            return Err(eyre!(
+                "an interrupted {kind} job was found in {}. pass --resume to continue it",
+                path.display()
+            ));
+        }
+
+        if resume {
+            info!("no interrupted {} job to resume, starting from the beginning", kind);
+        }
+
+        // Checkpoint straight away so the job shows up in `po jobs`
+        let mut job = Job {
+            kind: kind.to_string(),
+            args: args.to_string(),
+            started: now()?,
+            updated: now()?,
+            total,
+            done: HashSet::new(),
+            rate: None,
+            last_checkpoint: None,
+            done_at_checkpoint: 0,
+        };
+        job.checkpoint(library)?;
+        Ok(job)
+    }
+
+    pub fn progress(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Whether the job has checkpointed recently enough that it is probably still going
+    pub fn is_running(&self) -> bool {
+        OffsetDateTime::parse(&self.updated, &Rfc3339)
+            .is_ok_and(|updated| OffsetDateTime::now_utc() - updated < STALE_AFTER)
+    }
+
+    /// How much longer the job should take, going by its rate between the last checkpoints, or
+    /// else its rate so far
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        let remaining = self.total.saturating_sub(self.done.len());
+        if let Some(rate) = self.rate.filter(|r| *r > 0.0) {
+            return Duration::try_from_secs_f64(remaining as f64 / rate).ok();
+        }
+
+        let started = OffsetDateTime::parse(&self.started, &Rfc3339).ok()?;
+        let updated = OffsetDateTime::parse(&self.updated, &Rfc3339).ok()?;
+        let elapsed: Duration = (updated - started).try_into().ok()?;
+        if self.done.is_empty() || elapsed.is_zero() {
+            return None;
+        }
+
+        let per_item = elapsed / self.done.len() as u32;
+        Some(per_item * remaining as u32)
+    }
+
+    pub fn is_done(&self, hash: &FileHash) -> bool {
+        self.done.contains(&hash.encode())
+    }
+
+    pub fn mark_done(&mut self, hash: &FileHash) {
+        self.done.insert(hash.encode());
+    }
+
+    /// Whether enough time has passed that the job should checkpoint
+    pub fn checkpoint_due(&self) -> bool {
+        self.last_checkpoint.is_none_or(|at| at.elapsed() >= CHECKPOINT_INTERVAL)
+    }
+
+    /// Record progress so far. Callers should persist whatever the completed items changed first
+    pub fn checkpoint(&mut self, library: &Library) -> Result<()> {
+        self.updated = now()?;
+        if let Some(at) = self.last_checkpoint {
+            let elapsed = at.elapsed().as_secs_f64();
+            let progress = self.done.len().saturating_sub(self.done_at_checkpoint);
+            if elapsed > 0.0 && progress > 0 {
+                self.rate = Some(progress as f64 / elapsed);
+            }
+        }
+        let root = jobs_root(library.meta_root());
+        fs::create_dir_all(&root)?;
+
+        // Once written, the checkpoint only disappears if someone ran `po jobs cancel`
+        let path = root.join(&self.kind);
+        if self.last_checkpoint.is_some() && !path.exists() {
+            return Err(eyre!("the {} job was cancelled after {} of {} items", self.kind, self.done.len(), self.total));
+        }
+
+        // Write then rename, so a crash mid-write leaves the previous checkpoint intact
+        let partial = root.join(format!(".{}{TEMPORARY_SUFFIX}", self.kind));
+        fs::write(&partial, serde_json::to_string(self)?)?;
+        fs::rename(&partial, &path)?;
+
+        debug!("checkpointed {} job at {}/{}", self.kind, self.done.len(), self.total);
+        self.last_checkpoint = Some(Instant::now());
+        self.done_at_checkpoint = self.done.len();
+        Ok(())
+    }
+
+    /// The job completed, so there's nothing left to resume
+    pub fn finish(self, library: &Library) -> Result<()> {
+        let path = jobs_root(library.meta_root()).join(&self.kind);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{library, scratch_dir};
+
+    #[test]
+    fn only_known_kinds_are_looked_up() {
+        let root = scratch_dir("jobs-kinds");
+        let library = library(&root, vec![]);
+        fs::write(root.join("outside"), "{}").unwrap();
+
+        for kind in ["../../outside", "/etc/passwd", ""] {
+            let error = load(library.meta_root(), kind).unwrap_err();
+            assert!(error.to_string().contains("no kind of job"), "{kind:?} gave {error}");
+        }
+        assert!(load(library.meta_root(), "naming").unwrap_err().to_string().contains("there is no naming job"));
+    }
+
+    #[test]
+    fn estimates_from_the_rate_between_checkpoints() {
+        let root = scratch_dir("jobs-rate");
+        let library = library(&root, vec![]);
+        let mut job = Job::start(&library, "naming", "HashSuffix", 4, false).unwrap();
+        job.started = "2020-01-01T00:00:00Z".to_string();
+        job.mark_done(&FileHash::from_bytes(b"a", crate::hashing::HashAlgorithm::Sha256));
+
+        // Before a rate is known it goes by the time since the job started, here years
+        assert!(job.estimated_remaining().unwrap() > Duration::from_secs(3600));
+
+        std::thread::sleep(Duration::from_millis(20));
+        job.checkpoint(&library).unwrap();
+        assert!(job.estimated_remaining().unwrap() < Duration::from_secs(3600));
+
+        let listed = list(library.meta_root()).unwrap();
+        // The rate goes through JSON, which may round its last digit
+        assert_eq!(listed[0].progress(), 1);
+        assert!((listed[0].rate.unwrap() - job.rate.unwrap()).abs() < 1e-9);
+        cancel(library.meta_root(), "naming").unwrap();
+        assert!(list(library.meta_root()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,310 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::hashing::HashAlgorithm;
+use crate::library::{copy_file, FileHash, Library, UnsortedFile};
+
+/// What the import in progress has done so far, within the library's metadata. Removed once
+/// the index records the import, so one left behind means it never finished
+const JOURNAL_FILE: &str = "journal";
+
+/// A line of the journal, each written before the step it describes is taken
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde", tag = "step", rename_all = "lowercase")]
+enum Entry {
+    /// The import started, passing files through `staging`
+    Start { staging: PathBuf, pid: u32 },
+    /// A file is about to be moved or copied from `source` into the library, by way of `staged`
+    Sort {
+        source: PathBuf,
+        staged: PathBuf,
+        output: PathBuf,
+        path_in_library: PathBuf,
+        hash: String,
+        algorithm: String,
+    },
+    /// The original of a copied file was moved into the holding area
+    Held { source: PathBuf, held: PathBuf },
+}
+
+/// The journal of an import, appended to as files are sorted
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Start the journal for an import. Fails if the last import didn't finish, as its files
+    /// are in the library but not the index
+    pub fn begin(meta_root: &Path) -> Result<Journal> {
+        let path = meta_root.join(JOURNAL_FILE);
+        let file = match fs::OpenOptions::new().append(true).create_new(true).open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(eyre!(
+                    "the last import into this library didn't finish, so some of its files were moved without \
+                     being recorded. run `po rollback` to put them back where they came from, then import again"
+                ));
+            }
+            file => file.wrap_err(format!("when creating import journal {}", path.display()))?,
+        };
+
+        Ok(Journal { file, path })
+    }
+
+    /// Note that the import passes files through `staging`
+    pub fn staging(&mut self, staging: &Path) -> Result<()> {
+        self.write(&Entry::Start { staging: staging.to_path_buf(), pid: std::process::id() })
+    }
+
+    /// Note that `file` is about to be sorted into the library at `output`
+    pub fn sort(&mut self, file: &UnsortedFile, staged: &Path, output: &Path, path_in_library: &Path) -> Result<()> {
+        self.write(&Entry::Sort {
+            source: file.path.clone(),
+            staged: staged.to_path_buf(),
+            output: output.to_path_buf(),
+            path_in_library: path_in_library.to_path_buf(),
+            hash: file.hash.encode(),
+            algorithm: file.hash.algorithm().name().to_string(),
+        })
+    }
+
+    /// Note that the original at `source` was moved to `held`
+    pub fn held(&mut self, source: &Path, held: &Path) -> Result<()> {
+        self.write(&Entry::Held { source: source.to_path_buf(), held: held.to_path_buf() })
+    }
+
+    /// The import is recorded in the index, so there's nothing left to undo
+    pub fn commit(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path).wrap_err(format!("when removing import journal {}", self.path.display()))
+    }
+
+    fn write(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        // So the entry is on disk before the step it describes is taken
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Whether the last import into the library whose metadata is in `meta_root` didn't finish
+pub fn pending(meta_root: &Path) -> bool {
+    meta_root.join(JOURNAL_FILE).exists()
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Files moved back to their inputs
+    pub restored: usize,
+    /// Copies removed from the library, whose originals were still in their inputs
+    pub removed: usize,
+    /// Files which couldn't be found anywhere they were meant to be
+    pub missing: usize,
+    /// Copies left in the library and its index, as something else is at their source now
+    pub kept: usize,
+    /// Files which made it into the index and were dropped from it again
+    pub forgotten: Vec<FileHash>,
+}
+
+/// Undo the last import if it didn't finish, putting each file it sorted back where it came
+/// from and dropping it from the index. `None` if there's nothing to undo
+pub fn rollback(library: &mut Library) -> Result<Option<Report>> {
+    let path = library.meta_root().join(JOURNAL_FILE);
+    let journal = match fs::read_to_string(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        journal => journal.wrap_err(format!("when reading import journal {}", path.display()))?,
+    };
+
+    // The last line may have been cut short by a crash, and its step never taken
+    let entries: Vec<Entry> = journal.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    let held: HashMap<_, _> = entries
+        .iter()
+        .filter_map(|e| match e {
+            Entry::Held { source, held } => Some((source.clone(), held.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut report = Report::default();
+    let mut staging_dirs = vec![];
+    for entry in entries.iter().rev() {
+        match entry {
+            Entry::Start { staging, pid } => {
+                debug!("rolling back the import by pid {pid}");
+                staging_dirs.push(staging.clone());
+            }
+            Entry::Sort { source, staged, output, path_in_library, hash, algorithm } => {
+                let algorithm = HashAlgorithm::from_name(algorithm)
+                    .ok_or_else(|| eyre!("unknown hash algorithm {algorithm} in the import journal"))?;
+                let hash = FileHash::decode(hash, algorithm)?;
+                let kept = undo(source, staged, output, &hash, held.get(source), &mut report)?;
+                if !kept && let Some(hash) = library.forget_file(path_in_library) {
+                    report.forgotten.push(hash);
+                }
+            }
+            Entry::Held { .. } => {}
+        }
+    }
+
+    for staging in staging_dirs {
+        match fs::remove_dir(&staging) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("could not remove {}: {e}, check it for anything left behind", staging.display()),
+            Ok(()) => {}
+        }
+    }
+
+    fs::remove_file(&path).wrap_err(format!("when removing import journal {}", path.display()))?;
+    Ok(Some(report))
+}
+
+/// Put one sorted file back, wherever it got to. Returns whether the library's copy was kept, as
+/// the file at its source has different content and it'd otherwise be lost
+fn undo(source: &Path, staged: &Path, output: &Path, hash: &FileHash, held: Option<&PathBuf>, report: &mut Report) -> Result<bool> {
+    // Only the file this import put there, in case it never got as far as replacing another
+    let content = [output, staged]
+        .into_iter()
+        .find(|p| FileHash::from_file(&p.to_path_buf(), hash.algorithm()).is_ok_and(|h| &h == hash));
+    let Some(content) = content else {
+        if !source.exists() {
+            warn!("could not find {} in the library or its staging area, it may be lost", source.display());
+            report.missing += 1;
+        }
+        return Ok(false);
+    };
+
+    if let Some(held) = held.filter(|h| h.exists() && !source.exists()) {
+        info!("moving {} back from the holding area", source.display());
+        fs::rename(held, source)?;
+    }
+
+    if source.exists() {
+        let original = FileHash::from_file(&source.to_path_buf(), hash.algorithm())?;
+        if &original != hash {
+            warn!("{} has changed since it was imported, keeping {}", source.display(), content.display());
+            report.kept += 1;
+            return Ok(true);
+        }
+
+        info!("removing {}, its original is still at {}", content.display(), source.display());
+        fs::remove_file(content)?;
+        report.removed += 1;
+        return Ok(false);
+    }
+
+    info!("moving {} back to {}", content.display(), source.display());
+    if let Some(parent) = source.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(content, source) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_file(content, source)?;
+            fs::remove_file(content)?;
+        }
+        moved => moved.wrap_err(format!("when moving {} back to {}", content.display(), source.display()))?,
+    }
+    report.restored += 1;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+
+    fn sorted(input: &Path, root: &Path, name: &str) -> Entry {
+        Entry::Sort {
+            source: input.join(name),
+            staged: root.join("_pometa/staging").join(name),
+            output: root.join(name),
+            path_in_library: name.into(),
+            hash: FileHash::from_bytes(name.as_bytes(), HashAlgorithm::Sha256).encode(),
+            algorithm: "sha256".into(),
+        }
+    }
+
+    #[test]
+    fn nothing_to_roll_back() {
+        let root = scratch_dir("journal-none");
+        let mut library = library(&root, vec![]);
+        assert!(rollback(&mut library).unwrap().is_none());
+    }
+
+    #[test]
+    fn rolls_back_an_unfinished_import() {
+        let root = scratch_dir("journal-rollback");
+        let input = scratch_dir("journal-rollback-input");
+        let mut library = library(&root, vec![indexed("moved.jpg", "moved.jpg"), indexed("other.jpg", "other.jpg")]);
+
+        // One moved in, one copied in leaving its original, and one lost
+        fs::write(root.join("moved.jpg"), "moved.jpg").unwrap();
+        fs::write(root.join("copied.jpg"), "copied.jpg").unwrap();
+        fs::write(input.join("copied.jpg"), "copied.jpg").unwrap();
+
+        let entries = [
+            Entry::Start { staging: root.join("_pometa/staging"), pid: 1 },
+            sorted(&input, &root, "moved.jpg"),
+            sorted(&input, &root, "copied.jpg"),
+            sorted(&input, &root, "lost.jpg"),
+        ];
+        let mut journal: String = entries.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        // Cut short by a crash
+        journal.push_str("{\"step\":\"so");
+        fs::write(library.meta_root().join(JOURNAL_FILE), journal).unwrap();
+        assert!(pending(library.meta_root()));
+
+        let report = rollback(&mut library).unwrap().expect("there to be an import to roll back");
+        assert_eq!((report.restored, report.removed, report.missing), (1, 1, 1));
+        assert_eq!(report.forgotten, [FileHash::from_bytes(b"moved.jpg", HashAlgorithm::Sha256)]);
+
+        assert_eq!(fs::read_to_string(input.join("moved.jpg")).unwrap(), "moved.jpg");
+        assert!(!root.join("moved.jpg").exists());
+        assert!(!root.join("copied.jpg").exists());
+        assert!(input.join("copied.jpg").exists());
+        assert_eq!(library.files().len(), 1);
+        assert!(!pending(library.meta_root()));
+    }
+
+    #[test]
+    fn leaves_files_it_did_not_put_there() {
+        let root = scratch_dir("journal-foreign");
+        let input = scratch_dir("journal-foreign-input");
+        let mut library = library(&root, vec![]);
+
+        // Something else is at the output, so the import never got that far
+        fs::write(root.join("moved.jpg"), "something else").unwrap();
+        let journal = serde_json::to_string(&sorted(&input, &root, "moved.jpg")).unwrap();
+        fs::write(library.meta_root().join(JOURNAL_FILE), journal).unwrap();
+
+        let report = rollback(&mut library).unwrap().unwrap();
+        assert_eq!(report.missing, 1);
+        assert_eq!(fs::read_to_string(root.join("moved.jpg")).unwrap(), "something else");
+    }
+
+    #[test]
+    fn keeps_copies_whose_source_has_changed() {
+        let root = scratch_dir("journal-changed");
+        let input = scratch_dir("journal-changed-input");
+        let mut library = library(&root, vec![indexed("copied.jpg", "copied.jpg")]);
+
+        // Edited after the import copied it, so the library has the only copy as it was
+        fs::write(root.join("copied.jpg"), "copied.jpg").unwrap();
+        fs::write(input.join("copied.jpg"), "edited").unwrap();
+        let journal = serde_json::to_string(&sorted(&input, &root, "copied.jpg")).unwrap();
+        fs::write(library.meta_root().join(JOURNAL_FILE), journal).unwrap();
+
+        let report = rollback(&mut library).unwrap().unwrap();
+        assert_eq!((report.removed, report.kept), (0, 1));
+        assert!(report.forgotten.is_empty());
+        assert_eq!(fs::read_to_string(root.join("copied.jpg")).unwrap(), "copied.jpg");
+        assert_eq!(library.files().len(), 1);
+    }
+}
@@ -0,0 +1,228 @@
+//! The library behind the po binary, for building other tools on top of po libraries without
+//! shelling out to it.
+//!
+//! [`Library`] is a po library on disk: its index of [`LibraryFile`]s, each known by the
+//! [`FileHash`] of its content, and the settings it was set up with, such as its [`SortPolicy`].
+//! Importing is done in steps, so a tool can stop after any of them:
+//!
+//! 1. [`Library::process_inputs`] hashes the [`InputFile`]s and picks out the ones which are new
+//!    to the library, giving a [`Scan`] of them and of those skipped
+//! 2. [`Library::plan_sort`] works out where each new file belongs, as [`PlannedFile`]s, without
+//!    touching anything
+//! 3. [`Library::execute_sort`] moves or copies them into place, and
+//!    [`Library::persist_to_disk`] records them in the index
+//!
+//! ```no_run
+//! use po::{ImportOptions, InputFile, Library, SortPolicy};
+//! use std::path::PathBuf;
+//!
+//! # fn main() -> color_eyre::eyre::Result<()> {
+//! let mut library = Library::read_from_disk(PathBuf::from("/srv/photos"), None, true)?;
+//! library.reconcile_settings(Some(SortPolicy::ExifDate), None, None, None, None)?;
+//!
+//! let options = ImportOptions::default();
+//! let inputs = vec![InputFile::from_path(PathBuf::from("/media/sd/DCIM/IMG_0001.JPG"))?];
+//! let scan = library.process_inputs(inputs, &options)?;
+//!
+//! let policy = library.settings().sort_policy.clone();
+//! let plan = library.plan_sort(scan.new, policy, &options)?;
+//! for planned in &plan {
+//!     println!("{} -> {}", planned.file.path.display(), planned.path_in_library.display());
+//! }
+//!
+//! library.execute_sort(plan, &options)?;
+//! library.persist_to_disk()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only [`library`] and [`hashing`] are meant to be built on. The other modules are public so
+//! the binary can use them, and change whenever it needs them to.
+
+pub mod hashing;
+
+pub mod library;
+
+pub use hashing::HashAlgorithm;
+pub use library::{
+    FileHash, ImportOptions, InputFile, Library, LibraryFile, PlannedFile, Scan, Skip, SortPolicy, UnsortedFile,
+};
+
+#[doc(hidden)]
+pub mod annex;
+
+#[doc(hidden)]
+pub mod audit;
+
+#[doc(hidden)]
+pub mod backup;
+
+#[doc(hidden)]
+pub mod bloom;
+
+#[doc(hidden)]
+pub mod budget;
+
+#[doc(hidden)]
+pub mod changes;
+
+#[doc(hidden)]
+pub mod checksums;
+
+#[doc(hidden)]
+pub mod classify;
+
+#[doc(hidden)]
+pub mod config_docs;
+
+#[doc(hidden)]
+pub mod daemon;
+
+#[doc(hidden)]
+pub mod doctor;
+
+#[doc(hidden)]
+pub mod derivatives;
+
+#[doc(hidden)]
+pub mod devices;
+
+#[doc(hidden)]
+pub mod export;
+
+#[doc(hidden)]
+pub mod extensions;
+
+#[doc(hidden)]
+pub mod faces;
+
+#[doc(hidden)]
+pub mod gallery;
+
+#[doc(hidden)]
+pub mod geo;
+
+#[doc(hidden)]
+pub mod heatmap;
+
+#[doc(hidden)]
+pub mod heic;
+
+#[doc(hidden)]
+pub mod i18n;
+
+#[doc(hidden)]
+pub mod ignores;
+
+#[doc(hidden)]
+pub mod jobs;
+
+#[doc(hidden)]
+pub mod journal;
+
+#[doc(hidden)]
+pub mod lock;
+
+#[doc(hidden)]
+pub mod manifest;
+
+#[doc(hidden)]
+pub mod map;
+
+#[doc(hidden)]
+pub mod metadata;
+
+#[doc(hidden)]
+pub mod mirror;
+
+#[doc(hidden)]
+pub mod mqtt;
+
+#[doc(hidden)]
+pub mod ocr;
+
+#[cfg(feature = "classify")]
+#[doc(hidden)]
+pub mod onnx;
+
+#[doc(hidden)]
+pub mod pairing;
+
+#[doc(hidden)]
+pub mod print;
+
+#[doc(hidden)]
+pub mod progress;
+
+#[doc(hidden)]
+pub mod prune;
+
+#[doc(hidden)]
+pub mod query;
+
+#[doc(hidden)]
+pub mod registry;
+
+#[doc(hidden)]
+pub mod remux;
+
+#[doc(hidden)]
+pub mod rename;
+
+#[doc(hidden)]
+pub mod retention;
+
+#[doc(hidden)]
+pub mod rolls;
+
+#[doc(hidden)]
+pub mod schema;
+
+#[doc(hidden)]
+pub mod serve;
+
+#[doc(hidden)]
+pub mod shutdown;
+
+#[doc(hidden)]
+pub mod similar;
+
+#[doc(hidden)]
+pub mod simulate;
+
+#[doc(hidden)]
+pub mod snapshot;
+
+#[doc(hidden)]
+pub mod stats;
+
+#[doc(hidden)]
+pub mod storage;
+
+#[doc(hidden)]
+pub mod template;
+
+#[doc(hidden)]
+pub mod thumbs;
+
+#[doc(hidden)]
+pub mod timelapse;
+
+#[doc(hidden)]
+pub mod transcode;
+
+#[doc(hidden)]
+pub mod update;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[doc(hidden)]
+pub mod uring;
+
+#[doc(hidden)]
+pub mod verify;
+
+#[doc(hidden)]
+pub mod video;
+
+#[doc(hidden)]
+pub mod watch;
@@ -1,28 +1,235 @@
 use color_eyre::eyre::{eyre, ContextCompat, Result, WrapErr};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
-use std::path::PathBuf;
-use sha2::{Sha256, Digest};
+use std::path::{Path, PathBuf};
 use std::{io, fs};
-use tracing::{debug, info, instrument};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument, warn};
 use clap::ValueEnum;
+use rayon::prelude::*;
 use confique::serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::format_description::BorrowedFormatItem;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
 
+use crate::annex;
+use crate::bloom::BloomFilter;
+use crate::changes::{self, Change, ChangeKind};
+use crate::checksums::{self, ChecksumFiles};
+use crate::classify::ClassifyConfig;
+use crate::budget;
+use crate::devices;
+use crate::extensions;
+use crate::faces::Faces;
+use crate::hashing::{self, HashAlgorithm, Hasher};
+use crate::jobs::Job;
+use crate::journal::Journal;
+use crate::lock::{self, Access, LibraryLock};
+use crate::metadata;
+use crate::ocr::OcrConfig;
+use crate::pairing;
+use crate::progress::Progress;
+use crate::registry::{Registry, RegistryOptions, RegistryPolicy};
+use crate::retention;
+use crate::rolls;
+use crate::shutdown;
+use crate::storage::{self, FileStorage, MemoryStorage, SqliteStorage, Storage, DATABASE_FILE};
+use crate::template;
+use crate::timelapse;
+
+/// A file found in an input, carrying the metadata gathered while scanning so the rest of
+/// the import doesn't have to stat it again
+#[derive(Debug)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Metadata of the file, or of its target if it's a symlink. `None` for dangling links
+    pub metadata: Option<fs::Metadata>,
+    pub is_symlink: bool,
+}
+
+impl InputFile {
+    /// The file at `entry`, from a directory listing
+    pub fn from_entry(entry: &fs::DirEntry) -> Result<Self> {
+        let path = entry.path();
+        // Usually free, as it comes from the directory listing itself
+        let is_symlink = entry.file_type()?.is_symlink();
+        let metadata = if is_symlink { fs::metadata(&path).ok() } else { Some(entry.metadata()?) };
+
+        Ok(Self { path, metadata, is_symlink })
+    }
+
+    /// The file at `path`, which has to exist, though a symlink there may dangle
+    pub fn from_path(path: PathBuf) -> Result<Self> {
+        let is_symlink = fs::symlink_metadata(&path)
+            .wrap_err(format!("when reading metadata of {}", path.display()))?
+            .is_symlink();
+        let metadata = fs::metadata(&path).ok();
+
+        Ok(Self { path, metadata, is_symlink })
+    }
+}
+
+/// An input file which is new to the library, hashed and ready to be sorted into it
 #[derive(Debug)]
 pub struct UnsortedFile {
     pub hash: FileHash,
-    pub path: PathBuf
+    pub path: PathBuf,
+    pub metadata: fs::Metadata,
+    pub is_symlink: bool,
+}
+
+/// What looking through the inputs found
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub new: Vec<UnsortedFile>,
+    /// Files which won't be imported, and why
+    pub skipped: Vec<(PathBuf, Skip)>,
+}
+
+/// Why an input file won't be imported
+#[derive(Debug)]
+pub enum Skip {
+    /// Left behind by an earlier po run
+    Temporary,
+    /// The input is within the library itself
+    InsideLibrary,
+    /// A symlink to nothing
+    DanglingLink,
+    /// The same content is already in the library, at this path
+    InLibrary(PathBuf),
+    /// The same content is being imported from this other input file
+    DuplicateInput(PathBuf),
+    /// Another library sharing the registry has the same content, at this path
+    InOtherLibrary { library: String, path: PathBuf },
 }
 
+/// A file an import will sort, and where it's going
 #[derive(Debug)]
+pub struct PlannedFile {
+    pub file: UnsortedFile,
+    pub path_in_library: PathBuf,
+    /// The timelapse sequence the file is a frame of
+    pub sequence: Option<String>,
+    /// The group of paired files it belongs to, see [`crate::pairing`]
+    pub group: Option<String>,
+}
+
+/// A file in the library's index
+#[derive(Debug, Clone)]
 pub struct LibraryFile {
     pub hash: FileHash,
-    pub path_in_library: PathBuf
+    /// Where it is relative to the library's root, see [`Library::absolute_path`]
+    pub path_in_library: PathBuf,
+    pub imported_by: Option<String>,
+    /// The timelapse sequence this file is a frame of
+    pub sequence: Option<String>,
+    /// The group of paired files this file belongs to, like a raw file, its JPEG and its XMP
+    pub group: Option<String>,
+    /// Sorted, without duplicates, see [`Library::retag`]
+    pub tags: Vec<String>,
+    /// How many prints of it to order, see [`crate::print`]. 0 for none
+    pub prints: u32,
+    /// Names of the people other tools marked in it, see [`crate::faces`]. Sorted, without duplicates
+    pub people: Vec<String>,
+    /// Bytes, as of when it was imported or last rewritten. `None` for files imported before
+    /// po kept track
+    pub size: Option<u64>,
+    /// RFC 3339, `None` for files imported before po kept track
+    pub imported_at: Option<String>,
+    /// When it was taken, by its metadata or else the filesystem, as `YYYY-MM-DDTHH:MM:SS` in
+    /// whatever time zone the camera was set to. `None` for files imported before po kept track
+    pub taken: Option<String>,
+    /// Text read out of it, on a single line, see [`crate::ocr`]
+    pub text: Option<String>,
+    /// How long it runs in seconds, for videos whose container says
+    pub duration: Option<f64>,
 }
 
-#[derive(ValueEnum, Clone, Debug, Serialize, Deserialize)]
+impl LibraryFile {
+    /// When it was taken, if po kept track when it was imported
+    pub fn taken(&self) -> Option<PrimitiveDateTime> {
+        PrimitiveDateTime::parse(self.taken.as_deref()?, TAKEN_FORMAT).ok()
+    }
+
+    /// Extra metadata, stored as indented `key value` lines under the file's entry
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut props = vec![];
+        // Left out for SHA256, which every file was hashed with before there was a choice
+        if self.hash.algorithm() != HashAlgorithm::Sha256 {
+            props.push(("algorithm", self.hash.algorithm().name().to_string()));
+        }
+        if let Some(user) = &self.imported_by {
+            props.push(("imported_by", user.clone()));
+        }
+        if let Some(sequence) = &self.sequence {
+            props.push(("sequence", sequence.clone()));
+        }
+        if let Some(group) = &self.group {
+            props.push(("group", group.clone()));
+        }
+        if !self.tags.is_empty() {
+            props.push(("tags", self.tags.join(" ")));
+        }
+        if self.prints > 0 {
+            props.push(("prints", self.prints.to_string()));
+        }
+        if !self.people.is_empty() {
+            props.push(("people", self.people.join(PEOPLE_SEPARATOR)));
+        }
+        if let Some(size) = self.size {
+            props.push(("size", size.to_string()));
+        }
+        if let Some(time) = &self.imported_at {
+            props.push(("imported_at", time.clone()));
+        }
+        if let Some(time) = &self.taken {
+            props.push(("taken", time.clone()));
+        }
+        if let Some(text) = &self.text {
+            props.push(("text", text.clone()));
+        }
+        if let Some(duration) = self.duration {
+            props.push(("duration", format!("{duration:.3}")));
+        }
+        props
+    }
+
+    fn set_property(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "algorithm" => {
+                self.hash.algorithm = HashAlgorithm::from_name(value)
+                    .ok_or_else(|| eyre!("unknown hash algorithm {value}, likely library corruption"))?;
+            }
+            "imported_by" => self.imported_by = Some(value.to_string()),
+            "sequence" => self.sequence = Some(value.to_string()),
+            "group" => self.group = Some(value.to_string()),
+            "tags" => self.tags = value.split_whitespace().map(str::to_string).collect(),
+            "prints" => self.prints = value.parse().wrap_err("could not parse prints, likely library corruption")?,
+            "people" => self.people = value.split(PEOPLE_SEPARATOR).map(str::to_string).collect(),
+            "size" => self.size = Some(value.parse().wrap_err("could not parse size, likely library corruption")?),
+            "imported_at" => self.imported_at = Some(value.to_string()),
+            "taken" => self.taken = Some(value.to_string()),
+            "text" => self.text = Some(value.to_string()),
+            "duration" => self.duration = Some(value.parse().wrap_err("could not parse duration, likely library corruption")?),
+            _ => return Err(eyre!("unknown property {key}, likely library corruption")),
+        }
+        Ok(())
+    }
+}
+
+/// How an import decides where files go in the library
+#[derive(ValueEnum, Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(crate = "confique::serde")] 
 pub enum SortPolicy {
+    /// Into `YYYY/M/D` folders by when each file was created according to the filesystem
     Date,
+    /// Like Date, but by when photos were taken according to their EXIF, falling back to the
+    /// filesystem for files without it
+    ExifDate,
+    /// Straight into the root of the library
     MoveToRoot
 }
 
@@ -32,194 +239,1670 @@ impl Default for SortPolicy {
     }
 }
     
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub enum NamingScheme {
+    /// Keep the name the file was imported with
+    #[default]
+    Original,
+    /// Append a short content hash to the name, e.g. `IMG_0001.a1b2c3d4.jpg`
+    HashSuffix,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub enum IndexLayout {
+    /// Load the whole index whenever the library is opened
+    #[default]
+    Single,
+    /// Only load the years of the index a command needs, for commands which only look at some years
+    PerYear,
+}
+
+/// What happens to an input file once it has been imported
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub enum SourceRetention {
+    /// Move the file into the library, so it's gone from the input
+    #[default]
+    Move,
+    /// Copy the file into the library, and move the original into a dated folder under
+    /// `imported-originals` in its input until `po clean-sources` removes it
+    Hold,
+    /// Copy the file into the library, leaving the input untouched, e.g. for read-only SD cards
+    Copy,
+}
+
+/// Settings intrinsic to a library, kept in `_pometa/config` so the library describes itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub struct LibrarySettings {
+    pub sort_policy: SortPolicy,
+    #[serde(default)]
+    pub naming: NamingScheme,
+    #[serde(default)]
+    pub index: IndexLayout,
+    pub hash_algorithm: HashAlgorithm,
+    /// Where the date policies put files, instead of `YYYY/M/D/<name>`, e.g.
+    /// `{year}/{year}-{month:02}-{day:02}/{original_name}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_template: Option<String>,
+}
+
+/// How an import should treat the files it sorts
+#[derive(Debug, Default)]
+pub struct ImportOptions {
+    /// Group runs of at least this many frames shot at a fixed interval into their own folder
+    pub timelapse_min_frames: Option<usize>,
+    /// Rehash files at their final path once moved there
+    pub verify_after_move: bool,
+    pub source_retention: SourceRetention,
+    /// Leave a manifest of what was imported in each source directory
+    pub source_manifests: bool,
+    /// Work out perceptual hashes of imported photos, see [`crate::similar`]
+    pub perceptual_hashes: bool,
+    /// Files to hash at once, `None` for one per CPU
+    pub hash_workers: Option<usize>,
+    pub clock: Clock,
+    /// Draw progress bars while hashing and sorting, when stderr is a terminal
+    pub progress: bool,
+    /// Check new files against a registry shared with other libraries, and record them in it
+    pub registry: Option<RegistryOptions>,
+    /// Picasa's `contacts.xml`, to name the faces it found, see [`crate::faces`]
+    pub picasa_contacts: Option<PathBuf>,
+    /// Tag imported photos with an image classifier, see [`crate::classify`]
+    pub classify: Option<ClassifyConfig>,
+    /// Read the text in imported documents and screenshots, see [`crate::ocr`]
+    pub ocr: Option<OcrConfig>,
+    /// Keep every file together under `rolls/<name>`, tagged `roll:<name>`, as the scans of one
+    /// film roll or scanning session, see [`crate::rolls`]
+    pub roll: Option<String>,
+    /// Add a JPEG beside each imported HEIC, see [`crate::heic`]
+    pub transcode_heic: bool,
+    /// Keep HEICs once they're transcoded, rather than moving them to the trash
+    pub keep_heic: bool,
+    /// Sort files by date into this path template instead of the library's, as `po watch` rules do
+    pub path_template: Option<String>,
+    /// Path templates for recordings from these devices, see [`devices::DEVICES`]. Their
+    /// sidecars follow them
+    pub device_templates: HashMap<String, String>,
+}
+
+/// Where sorting by date gets a file's creation time from, when nothing better dates it
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Clock {
+    /// Ask the filesystem
+    #[default]
+    Filesystem,
+    /// Pretend every file was created at this time, as files copied onto a disk then would be
+    Fixed(PrimitiveDateTime),
+}
+
+/// A po library: the files sorted under its root, and the index, settings and history kept
+/// about them in `_pometa`. Changes are made to the index in memory, and only written back by
+/// [`Library::persist_to_disk`]
 #[derive(Debug)]
 pub struct Library {
     output_root: PathBuf,
     meta_root: PathBuf,
-    files: Vec<LibraryFile>
+    identity: String,
+    settings: Option<LibrarySettings>,
+    /// The years of a per-year index which were loaded, `None` when everything was
+    loaded_years: Option<Vec<String>>,
+    /// Where the index and settings are read from and written to
+    storage: Box<dyn Storage>,
+    bloom: BloomFilter,
+    files: Vec<LibraryFile>,
+    /// Bumped whenever `files` changes, so anything derived from them knows to recompute.
+    /// Carries on from the last run's, kept by the storage
+    generation: u64,
+    /// The generation in storage when the library was read
+    stored_generation: u64,
+    /// Changes to `files` not yet appended to the change feed
+    changes: Vec<Change>,
+    /// Companion checksum files to keep in step with `files`, see [`crate::checksums`]
+    checksum_files: Option<ChecksumFiles>,
+    /// A registry shared with other libraries to keep in step with `files`, see [`crate::registry`]
+    registry: Option<RegistryOptions>,
+    /// Held for as long as the library is open, `None` for copies read into memory
+    lock: Option<LibraryLock>,
+    /// The journal of an import whose files aren't in the stored index yet
+    journal: Option<Journal>,
 }
 
+/// How [`LibraryFile::taken`] is written, ISO 8601 without an offset as EXIF doesn't have one
+const TAKEN_FORMAT: &[BorrowedFormatItem] = format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]");
+
+/// Names can have spaces, and [`crate::faces`] keeps semicolons out of them
+pub(crate) const PEOPLE_SEPARATOR: &str = ";";
+
+/// Directory within the library holding po's own files
+pub const META_DIR: &str = "_pometa";
+/// Where `po remove --trash` moves files, within the meta directory
+const TRASH_DIR: &str = "trash";
+
 const CONTENT_SENTINEL: &'static str = "--START-CONTENT--";
-const SUPPORTED_VERSION_MAX: u16 = 1;
-const CURRENT_VERSION: u16 = 1;
+const PROPERTY_INDENT: &str = "  ";
+const SUPPORTED_VERSION_MAX: u16 = 2;
+const CURRENT_VERSION: u16 = 2;
 const HASH_LENGTH: u8 = 64;
+const SHORT_HASH_LENGTH: usize = 8;
 
 impl Library {
-    pub fn persist_to_disk(self) -> Result<()> {
-        let meta_root = self.meta_root;
+    /// Write the index, and everything else changed since the library was read, back to `_pometa`
+    pub fn persist_to_disk(&mut self) -> Result<()> {
+        if self.is_shared() {
+            if !self.changes.is_empty() || self.generation != self.stored_generation {
+                return Err(eyre!("the library was only opened for reading, so its changes can't be saved"));
+            }
+            return Ok(());
+        }
+
+        let layout = self.settings.as_ref().map(|s| s.index).unwrap_or_default();
+        self.storage.update_index(layout, &self.files, &self.changes, self.loaded_years.as_deref())?;
+        self.storage.write_bloom(&self.bloom)?;
+
+        if let (Some(mode), Some(settings)) = (self.checksum_files, &self.settings) {
+            checksums::apply(&self.output_root, mode, settings.hash_algorithm, &self.changes)?;
+        }
+
+        if let Some(registry) = &self.registry
+            && !self.changes.is_empty()
         {
-            let mut hash_path = meta_root.clone();
-            hash_path.push("hashes");
-
-            assert!(hash_path.exists(), "hash path should exist");
-
-            let hash_content = self.files.into_iter()
-                .fold(String::new(), |mut a, b| {
-                    a.push_str(&b.hash.encode());
-                    a.push_str(" ");
-                    a.push_str(&b.path_in_library.to_string_lossy());
-                    a.push_str("\n");
-                    a
-                });
-            fs::write(
-                hash_path,
-                format!(
-                    "{}\n{}\n{}",
-                    CURRENT_VERSION.to_string(),
-                    CONTENT_SENTINEL.to_string(),
-                    hash_content
-                )
-            )
-        }?;
+            Registry::open(registry)?.apply(&self.changes, &self.files)?;
+        }
+
+        // Feed first, so whoever sees the new generation also finds what changed in it
+        self.storage.append_changes(&self.changes)?;
+        self.changes.clear();
+        if self.generation != self.stored_generation {
+            self.storage.write_generation(self.generation)?;
+            self.stored_generation = self.generation;
+        }
+
+        // The index has the import's files now, so it can't be rolled back
+        if let Some(journal) = self.journal.take() {
+            journal.commit()?;
+        }
         Ok(())
     }
 
-    fn ensure_meta_file(&self, file_name: &'static str) -> Result<(PathBuf, bool)> {
-        let path = self.meta_root.join(file_name);
+    /// Check the settings requested by the config against the ones the library was set up with.
+    /// Libraries without settings adopt the requested ones, falling back to defaults
+    pub fn reconcile_settings(
+        &mut self,
+        sort_policy: Option<SortPolicy>,
+        naming: Option<NamingScheme>,
+        index: Option<IndexLayout>,
+        hash_algorithm: Option<HashAlgorithm>,
+        path_template: Option<String>,
+    ) -> Result<()> {
+        if let Some(template) = &path_template {
+            check_path_template(template)?;
+        }
 
-        if !path.exists() {
-            fs::File::create(&path)
-                .wrap_err(format!("when creating meta file {} ({})", file_name, path.display()))?;
-            Ok((path, true))
-        } else {
-            Ok((path, false))    
+        let config_path = self.meta_root.join("config");
+        let mismatch = |key: &str, have: &dyn Debug, want: &dyn Debug| eyre!(
+            "this library is organised with {key} {have:?} but the config asks for {want:?}. \
+             remove {key} from the config to use the library's, or change it in {}",
+            config_path.display()
+        );
+
+        match &self.settings {
+            Some(settings) => {
+                if let Some(requested) = sort_policy && requested != settings.sort_policy {
+                    return Err(mismatch("sort_policy", &settings.sort_policy, &requested));
+                }
+
+                if let Some(requested) = naming && requested != settings.naming {
+                    return Err(mismatch("naming", &settings.naming, &requested));
+                }
+
+                if let Some(requested) = index && requested != settings.index {
+                    return Err(mismatch("index", &settings.index, &requested));
+                }
+
+                // Files already sorted by another template would be laid out two ways
+                if let Some(requested) = path_template && Some(&requested) != settings.path_template.as_ref() {
+                    let have = settings.path_template.as_deref().unwrap_or("{year}/{month}/{day}/{original_name}");
+                    return Err(mismatch("path_template", &have, &requested));
+                }
+
+                // Files already in the library couldn't be found by their content under another algorithm
+                if let Some(requested) = hash_algorithm && requested != settings.hash_algorithm {
+                    return Err(eyre!(
+                        "this library is hashed with {} but the config asks for {}. remove hash_algorithm from the config",
+                        settings.hash_algorithm.name(),
+                        requested.name()
+                    ));
+                }
+            }
+            None => {
+                let settings = LibrarySettings {
+                    sort_policy: sort_policy.unwrap_or_default(),
+                    naming: naming.unwrap_or_default(),
+                    index: index.unwrap_or_default(),
+                    hash_algorithm: hash_algorithm.unwrap_or_default(),
+                    path_template,
+                };
+
+                self.settings = Some(settings);
+                // Left for the next po which changes the library to record
+                if !self.is_shared() {
+                    info!("recording library settings: {:?}", self.settings());
+                    self.write_settings()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_settings(&mut self) -> Result<()> {
+        let settings = self.settings().clone();
+        self.storage.write_settings(&settings)
+    }
+
+    /// Switch the library to `scheme`, renaming every file to match. Returns the hashes
+    /// of renamed files. Progress is checkpointed to `job`, skipping files it has already done
+    #[instrument(skip(self, job))]
+    pub fn apply_naming(&mut self, scheme: NamingScheme, job: &mut Job) -> Result<Vec<FileHash>> {
+        let mut renamed = vec![];
+
+        // Recorded up front so new imports already follow the scheme while the conversion runs
+        self.settings
+            .as_mut()
+            .expect("settings to be reconciled after loading")
+            .naming = scheme;
+        self.write_settings()?;
+
+        for i in 0..self.files.len() {
+            if shutdown::requested() {
+                self.persist_to_disk()?;
+                job.checkpoint(self)?;
+                return Err(eyre!(
+                    "interrupted after {} of {} files, run again with --resume to continue",
+                    job.progress(),
+                    job.total
+                ));
+            }
+
+            let file = &self.files[i];
+            if job.is_done(&file.hash) {
+                continue;
+            }
+
+            let hash = file.hash.clone();
+            let current = file.path_in_library.file_name().expect("library path to be a file");
+            let original = strip_hash_suffix(current, &file.hash);
+            let wanted = match scheme {
+                NamingScheme::Original => original,
+                NamingScheme::HashSuffix => with_hash_suffix(&original, &file.hash),
+            };
+
+            if wanted != current {
+                let new_path = file.path_in_library.with_file_name(&wanted);
+                let (from, to) = (self.output_root.join(&file.path_in_library), self.output_root.join(&new_path));
+
+                if !from.exists() && to.exists() {
+                    // Renamed before an interruption, but not recorded in the index
+                    debug!("{} was already renamed to {}", from.display(), to.display());
+                } else if to.exists() {
+                    warn!("not renaming {} as {} already exists", from.display(), to.display());
+                    continue;
+                } else {
+                    info!("renaming {} to {}", from.display(), to.display());
+                    if let Err(e) = fs::rename(&from, &to) {
+                        // Keep going so the files already renamed still get recorded
+                        warn!("could not rename {}: {e}", from.display());
+                        continue;
+                    }
+                }
+
+                self.generation += 1;
+                let change = Change::moved(self.generation, &hash, &self.files[i].path_in_library, &new_path);
+                self.changes.push(change);
+                self.files[i].path_in_library = new_path;
+                renamed.push(hash.clone());
+            }
+
+            job.mark_done(&hash);
+            if job.checkpoint_due() {
+                self.persist_to_disk()?;
+                job.checkpoint(self)?;
+            }
         }
+
+        Ok(renamed)
     }
 
-    fn read_hash_file(&self) -> Result<Vec<LibraryFile>> {
-        let (hash_path, file_created) = self.ensure_meta_file("hashes")?;
-        if file_created {
-            return Ok(vec![])
+    /// Replace the library's settings with `settings`, as they were when a snapshot was taken
+    pub fn replace_settings(&mut self, settings: LibrarySettings) -> Result<()> {
+        if self.loaded_years.is_some() && settings.index != self.settings().index {
+            return Err(eyre!("cannot change the index layout of a partially loaded library"));
         }
 
-        let content = fs::read_to_string(hash_path)?;
-        let (version, hashes) = content
-            .split_once(CONTENT_SENTINEL)
-            .wrap_err("could not find content sentinel, likely library corruption")?;
+        self.settings = Some(settings);
+        self.write_settings()
+    }
 
-        let version = version
-            .trim()
-            .parse::<u16>()
-            .wrap_err("could not parse version information, likely library corruption")?;
-        
-        if version > SUPPORTED_VERSION_MAX {
-            return Err(eyre!("version {version} is not supported. max supported version is {SUPPORTED_VERSION_MAX}"));
-        }
-
-        hashes
-            .trim()
-            .lines()
-            .map(|l| {
-                let (hash_raw, path) = l.split_at(HASH_LENGTH.into());
-                Ok(LibraryFile {
-                    hash: FileHash::decode(hash_raw.trim())?,
-                    path_in_library: path.trim().into()
-                })
-            })
-            .collect::<Result<Vec<LibraryFile>>>()
-            .wrap_err("when parsing file hashes from hash file")
+    /// Switch how the index is stored, the index is rewritten in the new layout when the
+    /// library is next persisted
+    pub fn set_index_layout(&mut self, layout: IndexLayout) -> Result<()> {
+        if self.loaded_years.is_some() {
+            return Err(eyre!("cannot change the index layout of a partially loaded library"));
+        }
+
+        self.settings
+            .as_mut()
+            .expect("settings to be reconciled after loading")
+            .index = layout;
+        self.write_settings()
+    }
+
+    /// The name a file should have in the library, following the naming scheme
+    fn library_file_name(&self, file: &UnsortedFile) -> OsString {
+        let fname = file.path.file_name().expect("path to be a normal file");
+        match self.settings().naming {
+            NamingScheme::Original => fname.to_owned(),
+            NamingScheme::HashSuffix => with_hash_suffix(fname, &file.hash),
+        }
+    }
+
+    /// Where the date policies put `file`, taken at `taken`. `YYYY/M/D/<name>` unless there's a
+    /// path template, `overridden` or else the library's
+    fn dated_path(&self, taken: PrimitiveDateTime, file: &UnsortedFile, overridden: Option<&str>) -> Result<PathBuf> {
+        let Some(path_template) = overridden.or(self.settings().path_template.as_deref()) else {
+            return Ok(date_directory(taken).join(self.library_file_name(file)));
+        };
+
+        let name = file.path.file_name().expect("path to be a normal file");
+        let expanded = template::expand(path_template, |placeholder| {
+            let value = match placeholder {
+                "year" => taken.year().to_string(),
+                "month" => (taken.month() as u8).to_string(),
+                "day" => taken.day().to_string(),
+                "hour" => taken.hour().to_string(),
+                "minute" => taken.minute().to_string(),
+                "second" => taken.second().to_string(),
+                "original_name" => name.to_string_lossy().to_string(),
+                "stem" => Path::new(name).file_stem().unwrap_or_default().to_string_lossy().to_string(),
+                "ext" => Path::new(name).extension().unwrap_or_default().to_string_lossy().to_string(),
+                "hash" => file.hash.encode()[..SHORT_HASH_LENGTH].to_string(),
+                _ => return Ok(None),
+            };
+            Ok(Some(value))
+        })?;
+
+        let path = template::relative_path(path_template, &expanded)?;
+        let fname = path.file_name().expect("relative path to end in a name");
+        Ok(match self.settings().naming {
+            NamingScheme::Original => path.clone(),
+            NamingScheme::HashSuffix => path.with_file_name(with_hash_suffix(fname, &file.hash)),
+        })
+    }
+
+    /// Name files planned from now on with `naming`, leaving the library's settings as they were
+    /// on disk. For previewing a different scheme on a library read into memory
+    pub fn preview_naming(&mut self, naming: NamingScheme) {
+        self.settings
+            .as_mut()
+            .expect("settings to be reconciled after loading")
+            .naming = naming;
+    }
+
+    /// Panics before [`Library::reconcile_settings`] has been called
+    pub fn settings(&self) -> &LibrarySettings {
+        self.settings.as_ref().expect("settings to be reconciled after loading")
+    }
+
+    /// Load the library, only reading index entries from `years` (and those outside any year)
+    /// if it has a per-year index. Libraries with a single index are always read whole.
+    /// Locks the library first, waiting for any other po using it if `wait_for_lock` is set
+    pub fn read_from_disk(output_root: PathBuf, years: Option<Vec<String>>, wait_for_lock: bool) -> Result<Library> {
+        let meta_root = output_root.join(META_DIR);
+        fs::create_dir_all(&meta_root)
+            .wrap_err(format!("when creating meta directory {}", meta_root.display()))?;
+
+        let lock = lock::acquire(&meta_root, wait_for_lock, Access::Exclusive)?;
+        let storage = storage::open(&meta_root)?;
+        let mut library = Self::open(output_root, storage, years)?;
+        library.lock = Some(lock);
+        Ok(library)
+    }
+
+    /// Load the library as [`Library::read_from_disk`] does, but only to read it, so other po can
+    /// read it at the same time. Metadata which needs upgrading is upgraded under an exclusive
+    /// lock first, and nothing is written back
+    pub fn read_shared(output_root: PathBuf, years: Option<Vec<String>>, wait_for_lock: bool) -> Result<Library> {
+        let meta_root = output_root.join(META_DIR);
+        fs::create_dir_all(&meta_root)
+            .wrap_err(format!("when creating meta directory {}", meta_root.display()))?;
+
+        if storage::needs_upgrade(&meta_root)? {
+            let _upgrading = lock::acquire(&meta_root, wait_for_lock, Access::Exclusive)?;
+            storage::open(&meta_root)?;
+        }
+
+        let lock = lock::acquire(&meta_root, wait_for_lock, Access::Shared)?;
+        let storage = SqliteStorage::open_read_only(&meta_root)?;
+        let mut library = Self::open(output_root, Box::new(storage), years)?;
+        library.lock = Some(lock);
+        Ok(library)
     }
 
-    pub fn read_from_disk(output_root: PathBuf) -> Result<Library> {
-        let meta_root = output_root.join("_pometa");
+    /// Whether the library was loaded with [`Library::read_shared`], so can't be changed
+    fn is_shared(&self) -> bool {
+        self.lock.as_ref().is_some_and(|l| l.access() == Access::Shared)
+    }
+
+    /// Load a copy of the library's metadata into memory, so nothing done to it is written back
+    pub fn read_into_memory(output_root: PathBuf) -> Result<Library> {
+        let meta_root = output_root.join(META_DIR);
+        let storage = if meta_root.join(DATABASE_FILE).exists() {
+            MemoryStorage::copy_of(&SqliteStorage::open_read_only(&meta_root)?)?
+        } else if meta_root.exists() {
+            // Not migrated yet, or a new library
+            MemoryStorage::copy_of(&FileStorage::new(meta_root))?
+        } else {
+            MemoryStorage::default()
+        };
+
+        Self::open(output_root, Box::new(storage), None)
+    }
+
+    /// Load the library at `output_root` from `storage`, as [`Library::read_from_disk`] does
+    pub fn open(output_root: PathBuf, storage: Box<dyn Storage>, years: Option<Vec<String>>) -> Result<Library> {
         let mut s = Self {
             files: vec![],
+            identity: current_user(),
+            settings: None,
+            loaded_years: None,
+            bloom: BloomFilter::with_capacity(0),
+            meta_root: output_root.join(META_DIR),
             output_root,
-            meta_root
+            storage,
+            generation: 0,
+            stored_generation: 0,
+            changes: vec![],
+            checksum_files: None,
+            registry: None,
+            lock: None,
+            journal: None,
         };
 
-        s.files = s.read_hash_file()?;
+        s.settings = s.storage.read_settings()?;
+        s.stored_generation = s.storage.read_generation()?;
+        s.generation = s.stored_generation;
+        if s.settings.as_ref().is_some_and(|s| s.index == IndexLayout::PerYear) {
+            s.loaded_years = years;
+        }
+        let layout = s.settings.as_ref().map(|s| s.index).unwrap_or_default();
+        let loaded = s.loaded_years.as_deref();
+        s.files = s.storage.read_index(layout, &|year| year.is_none_or(|year| loaded.is_none_or(|y| y.iter().any(|l| l == year))))?;
+
+        match s.storage.read_bloom() {
+            // A partial load can't tell if the filter is stale, so trust it
+            Ok(bloom) if s.loaded_years.is_some() => s.bloom = bloom,
+            Ok(bloom) if bloom.entries() == s.files.len() && !bloom.is_saturated() => s.bloom = bloom,
+            Ok(_) => {
+                debug!("bloom filter is out of date, rebuilding it");
+                s.rebuild_bloom();
+            }
+            Err(e) => {
+                debug!("could not read bloom filter ({e}), rebuilding it");
+                s.load_remaining_index()?;
+                s.rebuild_bloom();
+            }
+        }
         
         Ok(s)
     }
 
+    fn rebuild_bloom(&mut self) {
+        self.bloom = BloomFilter::with_capacity(self.files.len() * 2);
+        for file in &self.files {
+            self.bloom.insert(&file.hash);
+        }
+    }
+
+    /// Load the rest of a partially loaded per-year index
+    pub fn load_remaining_index(&mut self) -> Result<()> {
+        let Some(loaded) = self.loaded_years.take() else {
+            return Ok(());
+        };
+
+        debug!("loading the rest of the index");
+        let layout = self.settings().index;
+        let remaining = self.storage.read_index(layout, &|year| year.is_some_and(|year| !loaded.iter().any(|l| l == year)))?;
+        self.files.extend(remaining);
+
+        if self.bloom.is_saturated() {
+            self.rebuild_bloom();
+        }
+        Ok(())
+    }
+
+    /// Library files with content `hash`. Most hashes looked up aren't in the library, so the bloom
+    /// filter saves searching, or even loading, the index
+    pub fn files_with_hash(&mut self, hash: &FileHash) -> Result<Vec<&LibraryFile>> {
+        if !self.bloom.may_contain(hash) {
+            return Ok(vec![]);
+        }
+
+        self.load_remaining_index()?;
+        Ok(self.files.iter().filter(|f| &f.hash == hash).collect())
+    }
+
+    fn add_file(&mut self, file: LibraryFile) {
+        self.bloom.insert(&file.hash);
+        self.generation += 1;
+        self.changes.push(Change::new(self.generation, ChangeKind::Added, &file.hash, &file.path_in_library));
+        self.files.push(file);
+    }
+
+    /// Hash `inputs`, picking out the files which should be imported: those which aren't
+    /// already in the library, another input, or, with a registry, another library
     #[instrument(skip_all)]
-    pub fn process_inputs(&mut self, inputs: &[PathBuf]) -> Result<Vec<UnsortedFile>> {
-        let mut new_files = vec![];
-        
-        for path in inputs {
-            let hash = FileHash::from_file(path)?;
-            if self.files.iter().find(|f| f.hash == hash).is_some() {
+    pub fn process_inputs(&mut self, inputs: Vec<InputFile>, options: &ImportOptions) -> Result<Scan> {
+        let mut scan = Scan::default();
+        let mut candidates = vec![];
+        for input in inputs {
+            let path = &input.path;
+            if is_temporary_file(path) {
+                debug!("{} is a po temporary file, skipping it", path.display());
+                scan.skipped.push((input.path, Skip::Temporary));
+                continue;
+            }
+
+            if self.contains_path(path)? {
+                warn!("{} is inside the library, skipping it", path.display());
+                scan.skipped.push((input.path, Skip::InsideLibrary));
+                continue;
+            }
+
+            let InputFile { path, metadata: Some(metadata), is_symlink } = input else {
+                warn!("{} is a dangling link, skipping it", path.display());
+                scan.skipped.push((input.path, Skip::DanglingLink));
+                continue;
+            };
+            candidates.push((path, metadata, is_symlink));
+        }
+
+        // Reading is what takes the time, so keep several files in flight. Zero lets rayon pick
+        let workers = options.hash_workers.unwrap_or(0);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(workers).build()?;
+        budget::set_io_workers(pool.current_num_threads());
+        debug!("hashing {} files with {} workers", candidates.len(), pool.current_num_threads());
+
+        let total_bytes = candidates.iter().map(|(_, metadata, _)| metadata.len()).sum();
+        let progress = Progress::bytes(options.progress, total_bytes, crate::t!("progress-hashing"));
+
+        let algorithm = self.settings().hash_algorithm;
+        let hashes: Vec<Option<Result<FileHash>>> = pool.install(|| {
+            candidates
+                .par_iter()
+                .map(|(path, metadata, _)| {
+                    // Nothing has been moved yet, so there's nothing to finish
+                    if shutdown::requested() {
+                        return None;
+                    }
+
+                    let annexed = annex::hash_from_link(path).filter(|h| h.algorithm() == algorithm);
+                    let hash = match annexed {
+                        Some(hash) => {
+                            debug!("using hash from annex key for {}", path.display());
+                            Ok(hash)
+                        }
+                        None => FileHash::from_file(path, algorithm).wrap_err(format!("when hashing {}", path.display())),
+                    };
+                    progress.inc(metadata.len());
+                    Some(hash)
+                })
+                .collect()
+        });
+        drop(progress);
+
+        let registry = options.registry.as_ref().map(Registry::open).transpose()?;
+        for ((path, metadata, is_symlink), hash) in candidates.into_iter().zip(hashes) {
+            let Some(hash) = hash else {
+                break;
+            };
+            let hash = hash?;
+
+            if let Some(existing) = self.files_with_hash(&hash)?.first() {
                 debug!("file already in library: {} ({})", path.display(), hash.encode());
+                let existing = existing.path_in_library.clone();
+                scan.skipped.push((path, Skip::InLibrary(existing)));
+            } else if let Some(other) = scan.new.iter().find(|f| f.hash == hash) {
+                debug!("file duplicates another being imported: {} ({})", path.display(), hash.encode());
+                let other = other.path.clone();
+                scan.skipped.push((path, Skip::DuplicateInput(other)));
             } else {
+                if let Some((registry, options)) = registry.as_ref().zip(options.registry.as_ref())
+                    && let Some((library, existing)) = registry.elsewhere(&hash)?
+                {
+                    if options.duplicates == RegistryPolicy::Skip {
+                        info!("{} is already in {library} at {}, skipping it", path.display(), existing.display());
+                        scan.skipped.push((path, Skip::InOtherLibrary { library, path: existing }));
+                        continue;
+                    }
+                    warn!("{} is already in {library} at {}, importing it anyway", path.display(), existing.display());
+                }
+
                 debug!("found new file: {} ({})", path.display(), hash.encode());
-                new_files.push(UnsortedFile { hash, path: path.clone() });
+                scan.new.push(UnsortedFile {
+                    hash,
+                    path,
+                    metadata,
+                    is_symlink,
+                });
             }
         }
 
-        Ok(new_files)
+        Ok(scan)
     }
 
+    /// Work out where each of `new_files` belongs in the library, without moving anything
     #[instrument(skip(self, new_files))]
-    pub fn sort_files(
+    pub fn plan_sort(
         &mut self,
         new_files: Vec<UnsortedFile>,
-        sort_policy: SortPolicy
-    ) -> Result<()> {
-        info!("sorting {} files", new_files.len());
+        sort_policy: SortPolicy,
+        options: &ImportOptions
+    ) -> Result<Vec<PlannedFile>> {
+        // Paired files follow the lead of their group, wherever it goes
+        let mut groups = HashMap::new();
+        let mut leads = HashMap::new();
+        for group in pairing::detect(&new_files) {
+            debug!("pairing {} files as {}", group.members.len() + 1, group.name);
+            leads.insert(group.lead.clone(), group.name.clone());
+            for member in group.members {
+                groups.insert(member, group.name.clone());
+            }
+        }
+
+        // Sort recordings before their sidecars, and leads before the rest of their group, so
+        // the others can follow them
+        let mut new_files = new_files;
+        new_files.sort_by_key(|f| (devices::recognise(&f.path).is_some_and(|d| d.sidecar), groups.contains_key(&f.hash)));
+        if new_files.last().and_then(|f| devices::recognise(&f.path)).is_some_and(|d| d.sidecar) {
+            // Their recording could be anywhere in the library
+            self.load_remaining_index()?;
+        }
+        let mut recordings = self.recording_directories();
+
+        // Timelapses are only grouped when sorting by date, where their frames would otherwise flood a day
+        let mut sequences = HashMap::new();
+        if let (SortPolicy::Date | SortPolicy::ExifDate, Some(min_frames), None) =
+            (&sort_policy, options.timelapse_min_frames, &options.roll)
+        {
+            for sequence in timelapse::detect(&new_files, min_frames) {
+                info!("grouping {} frames into {}", sequence.frames.len(), sequence.name());
+                let first = new_files.iter().find(|f| sequence.frames.first() == Some(&f.hash)).expect("frames to be new files");
+                let dated = self.dated_path(sequence.start, first, options.path_template.as_deref())?;
+                let dir = dated.parent().unwrap_or(Path::new("")).join(sequence.name());
+                for hash in &sequence.frames {
+                    sequences.insert(hash.clone(), (sequence.name(), dir.clone()));
+                }
+            }
+        }
+
+        let roll_dir = options.roll.as_deref().map(rolls::dir);
+        let mut group_dirs: HashMap<String, PathBuf> = HashMap::new();
+        let mut plan = vec![];
         for file in new_files {
-            match sort_policy {
-                SortPolicy::MoveToRoot => {
-                    let fname = file.path.file_name().expect("path to be a normal file");
-                    let mut output = self.output_root.clone();
-                    output.push(fname);
-                    
-                    info!("sorting {} into {}", file.path.display(), output.display());
-                    fs::rename(&file.path, output)?;
-                    
-                    self.files.push(LibraryFile {
-                        hash: file.hash,
-                        path_in_library: fname.into()
-                    })
-                },
-                SortPolicy::Date => {
-                    let meta = file.path.metadata()?;
-                    
-                    let created = meta.created()?
-                        .duration_since(std::time::UNIX_EPOCH)?;
-                    let epoch = time::macros::datetime!(1970-01-01 0:00);
-                    let created_dt = epoch + created;
-
-                    let mut in_lib = {
-                        let mut p = PathBuf::new();
-                        p.push(created_dt.year().to_string());
-                        p.push((created_dt.month() as u8).to_string());
-                        p.push(created_dt.day().to_string());
-                        p
+            let fname = self.library_file_name(&file);
+            let group = leads.get(&file.hash).or_else(|| groups.get(&file.hash)).cloned();
+            let (path_in_library, sequence) = match (&roll_dir, &sort_policy) {
+                // Scans go with the rest of their roll, whenever they were scanned
+                (Some(dir), _) => (dir.join(fname), None),
+                (None, SortPolicy::MoveToRoot) => (PathBuf::from(fname), None),
+                (None, SortPolicy::Date | SortPolicy::ExifDate) => {
+                    let device = devices::recognise(&file.path);
+                    let recording_dir = device
+                        .as_ref()
+                        .filter(|d| d.sidecar)
+                        .and_then(|d| recordings.get(&d.key));
+
+                    let sequence = sequences.remove(&file.hash);
+                    let lead_dir = groups.get(&file.hash).and_then(|g| group_dirs.get(g));
+                    let in_lib = match (lead_dir, &sequence, recording_dir) {
+                        (Some(dir), _, _) => {
+                            debug!("{} is paired, keeping it with the rest of its group in {}", file.path.display(), dir.display());
+                            dir.join(fname)
+                        }
+                        (None, Some((_, dir)), _) => dir.join(fname),
+                        (None, None, Some(dir)) => {
+                            debug!("{} is a sidecar, keeping it with its recording in {}", file.path.display(), dir.display());
+                            dir.join(fname)
+                        }
+                        (None, None, None) => {
+                            let created_dt = match device.as_ref().and_then(|d| d.taken) {
+                                Some(taken) => taken,
+                                None if sort_policy == SortPolicy::ExifDate => capture_time(&file)
+                                    .ok_or_else(|| eyre!("could not tell when {} was taken", file.path.display()))?,
+                                None => match options.clock {
+                                    Clock::Filesystem => creation_time(&file.metadata)?,
+                                    Clock::Fixed(time) => time,
+                                },
+                            };
+
+                            let template = options
+                                .path_template
+                                .as_ref()
+                                .or_else(|| device.as_ref().and_then(|d| options.device_templates.get(d.device)));
+                            self.dated_path(created_dt, &file, template.map(String::as_str))?
+                        }
                     };
 
-                    dbg!(&in_lib);
+                    if let Some(device) = device && !device.sidecar {
+                        recordings.insert(device.key, in_lib.parent().unwrap_or(Path::new("")).to_path_buf());
+                    }
+                    if let Some(name) = leads.get(&file.hash) {
+                        group_dirs.insert(name.clone(), in_lib.parent().unwrap_or(Path::new("")).to_path_buf());
+                    }
 
-                    // Do this before adding fname so we only try and make the dirs
-                    fs::create_dir_all(&self.output_root.join(&in_lib))?;
+                    (in_lib, sequence.map(|(name, _)| name))
+                }
+            };
+
+            plan.push(PlannedFile { file, path_in_library, sequence, group });
+        }
 
-                    let fname = file.path.file_name().expect("path to be a normal file");
-                    in_lib.push(fname);
-                    let output = self.output_root.join(&in_lib);
-                    
-                    info!("sorting {} into {}", file.path.display(), output.display());
-                    fs::rename(file.path, output)?;
+        self.deconflict(&mut plan)?;
+        Ok(plan)
+    }
 
-                    dbg!(&in_lib);
-                    
-                    self.files.push(LibraryFile {
-                        hash: file.hash,
-                        path_in_library: in_lib
-                    })
+    /// Give each file in `plan` whose path already holds something else, in the index, on disk or
+    /// earlier in the plan, a hash suffix so sorting it doesn't replace anything. Fails before
+    /// anything moves if even that is taken
+    pub fn deconflict(&self, plan: &mut [PlannedFile]) -> Result<()> {
+        let mut claimed: HashSet<PathBuf> = self.files.iter().map(|f| f.path_in_library.clone()).collect();
+        let taken = |claimed: &HashSet<PathBuf>, path: &Path| {
+            claimed.contains(path) || fs::symlink_metadata(self.output_root.join(path)).is_ok()
+        };
+
+        for planned in plan.iter_mut() {
+            if taken(&claimed, &planned.path_in_library) {
+                let name = planned.path_in_library.file_name().expect("library path to be a file");
+                let renamed = planned.path_in_library.with_file_name(with_hash_suffix(name, &planned.file.hash));
+                if taken(&claimed, &renamed) {
+                    return Err(eyre!(
+                        "{} would be sorted to {}, which already holds other content",
+                        planned.file.path.display(),
+                        planned.path_in_library.display()
+                    ));
                 }
+
+                warn!(
+                    "{} already holds other content, sorting {} to {} instead",
+                    planned.path_in_library.display(),
+                    planned.file.path.display(),
+                    renamed.display()
+                );
+                planned.path_in_library = renamed;
+            }
+            claimed.insert(planned.path_in_library.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Move planned files into the library, returning the hashes of those which were sorted.
+    /// This is all of them unless interrupted
+    #[instrument(skip_all)]
+    pub fn execute_sort(&mut self, plan: Vec<PlannedFile>, options: &ImportOptions) -> Result<Vec<FileHash>> {
+        info!("sorting {} files", plan.len());
+        let mut journal = Journal::begin(&self.meta_root)?;
+        let staging = self.begin_staging()?;
+        journal.staging(&staging)?;
+        let progress = Progress::files(options.progress, plan.len() as u64, crate::t!("progress-sorting"));
+        let mut faces = Faces::new(options.picasa_contacts.as_deref())?;
+
+        let mut sorted = vec![];
+        for PlannedFile { file, path_in_library, sequence, group } in plan {
+            // Stop between files, so every file is either fully in the library and index or untouched
+            if shutdown::requested() {
+                break;
+            }
+
+            sorted.push(file.hash.clone());
+            let output = self.output_root.join(&path_in_library);
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // Read before the file moves, as Picasa and sidecars are found next to it
+            let people = faces.people(&file.path);
+            let taken = capture_time(&file).and_then(|t| t.format(TAKEN_FORMAT).ok());
+            let duration = if extensions::is_video(&file.path) { video_duration(&file.path) } else { None };
+
+            info!("sorting {} into {}", file.path.display(), output.display());
+            let staged = staging.join(file.hash.encode());
+            journal.sort(&file, &staged, &output, &path_in_library)?;
+            self.stage_and_finalize(&file, &staged, &output, options, &mut journal)?;
+
+            self.add_file(LibraryFile {
+                hash: file.hash,
+                path_in_library,
+                imported_by: Some(self.identity.clone()),
+                sequence,
+                group,
+                tags: options.roll.iter().map(|r| rolls::tag(r)).collect(),
+                prints: 0,
+                people,
+                size: Some(file.metadata.len()),
+                imported_at: OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+                taken,
+                text: None,
+                duration,
+            });
+            progress.inc(1);
+        }
+
+        fs::remove_dir(&staging)?;
+        self.journal = Some(journal);
+        Ok(sorted)
+    }
+
+    /// Move `new_files` into the library, see [`Library::plan_sort`] and [`Library::execute_sort`]
+    pub fn sort_files(
+        &mut self,
+        new_files: Vec<UnsortedFile>,
+        sort_policy: SortPolicy,
+        options: &ImportOptions
+    ) -> Result<Vec<FileHash>> {
+        let plan = self.plan_sort(new_files, sort_policy, options)?;
+        self.execute_sort(plan, options)
+    }
+
+    /// Where each camera recording already in the library lives, keyed for sidecar pairing
+    fn recording_directories(&self) -> HashMap<String, PathBuf> {
+        self.files
+            .iter()
+            .filter_map(|f| {
+                let device = devices::recognise(&f.path_in_library).filter(|d| !d.sidecar)?;
+                let dir = f.path_in_library.parent().unwrap_or(Path::new(""));
+                Some((device.key, dir.to_path_buf()))
+            })
+            .collect()
+    }
+
+    /// Create a directory under `_pometa/staging` for this run's files to pass through
+    fn begin_staging(&self) -> Result<PathBuf> {
+        let staging_root = self.meta_root.join("staging");
+        if staging_root.exists() && fs::read_dir(&staging_root)?.next().is_some() {
+            warn!(
+                "{} has files left over from an interrupted import, they were not added to the library",
+                staging_root.display()
+            );
+        }
+
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let session = staging_root.join(format!("{started}-{}", std::process::id()));
+        fs::create_dir_all(&session)?;
+
+        Ok(session)
+    }
+
+    /// Move a file into the library at `output`, passing it through `staged` and checking
+    /// its hash there first, so a crash never leaves partial content at a final library path.
+    /// Originals being held are only moved aside once the file is safely at `output`
+    fn stage_and_finalize(
+        &self,
+        file: &UnsortedFile,
+        staged: &Path,
+        output: &Path,
+        options: &ImportOptions,
+        journal: &mut Journal,
+    ) -> Result<()> {
+        let hold = options.source_retention == SourceRetention::Hold && !file.is_symlink;
+        let keep_source = options.source_retention != SourceRetention::Move;
+        let copied_across = move_into_library(file, staged, keep_source)?;
+
+        let staged_hash = FileHash::from_file(&staged.to_path_buf(), file.hash.algorithm())?;
+        if staged_hash != file.hash {
+            return Err(eyre!(
+                "{} changed while being imported (expected {}, got {}), it has been left in {}",
+                file.path.display(),
+                file.hash.encode(),
+                staged_hash.encode(),
+                staged.display()
+            ));
+        }
+
+        rename_no_clobber(staged, output)?;
+
+        if options.verify_after_move {
+            let final_hash = FileHash::from_file(&output.to_path_buf(), file.hash.algorithm())?;
+            if final_hash != file.hash {
+                return Err(eyre!(
+                    "{} does not match {} after being moved into the library (expected {}, got {}). \
+                     the drive may be faulty, the file was left there but not added to the index",
+                    output.display(),
+                    file.path.display(),
+                    file.hash.encode(),
+                    final_hash.encode()
+                ));
             }
+            debug!("verified {} after moving it", output.display());
+        }
+
+        // Only now that the copy is checked and in place is the original safe to remove
+        if copied_across {
+            fs::remove_file(&file.path)
+                .wrap_err(format!("when removing {} after copying it into the library", file.path.display()))?;
+        }
+
+        if hold {
+            let held = retention::hold(&file.path, &file.hash)?;
+            journal.held(&file.path, &held)?;
         }
 
         Ok(())
     }
 
+    /// The files in the index, only those of the loaded years if part of a per-year index was
     pub fn files(&self) -> &Vec<LibraryFile> {
         &self.files
     }
+
+    /// Who imports and changes are attributed to
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Attribute imports and changes to `identity`, rather than the user running po
+    pub fn set_identity(&mut self, identity: String) {
+        self.identity = identity;
+    }
+
+    /// Keep companion checksum files next to the library's files from now on
+    pub fn set_checksum_files(&mut self, mode: Option<ChecksumFiles>) {
+        self.checksum_files = mode;
+    }
+
+    /// Paths of the files whose text has every one of `words` in it, from the storage's index of it.
+    /// `None` when there isn't one, or it's behind changes not saved yet
+    pub fn search_text(&self, words: &[String]) -> Result<Option<HashSet<PathBuf>>> {
+        if !self.changes.is_empty() {
+            return Ok(None);
+        }
+        self.storage.search_text(words)
+    }
+
+    /// Record what's added to, moved in and removed from the library in a shared registry from now on
+    pub fn set_registry(&mut self, registry: Option<RegistryOptions>) {
+        self.registry = registry;
+    }
+
+    /// Swap the whole index for `files`, as when rolling back to a snapshot
+    pub fn replace_files(&mut self, files: Vec<LibraryFile>) {
+        self.generation += 1;
+        self.changes.extend(changes::between(self.generation, &self.files, &files));
+
+        // Files left where they were still change if their tags or the like did
+        let before: HashMap<&Path, &LibraryFile> = self.files.iter().map(|f| (f.path_in_library.as_path(), f)).collect();
+        for file in &files {
+            if let Some(old) = before.get(file.path_in_library.as_path())
+                && old.hash == file.hash
+                && old.properties() != file.properties()
+            {
+                self.changes.push(Change::new(self.generation, ChangeKind::Modified, &file.hash, &file.path_in_library));
+            }
+        }
+        self.files = files;
+        self.rebuild_bloom();
+    }
+
+    /// Move the file at `from` to `to` within the library, on disk and in the index. Returns its hash
+    pub fn rename_file(&mut self, from: &Path, to: &Path) -> Result<FileHash> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == from)
+            .ok_or_else(|| eyre!("{} is not in the library", from.display()))?;
+
+        let (from_abs, to_abs) = (self.output_root.join(from), self.output_root.join(to));
+        if to_abs.exists() {
+            return Err(eyre!("not renaming {} as {} already exists", from.display(), to.display()));
+        }
+        if let Some(parent) = to_abs.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        info!("renaming {} to {}", from_abs.display(), to_abs.display());
+        fs::rename(&from_abs, &to_abs).wrap_err(format!("when renaming {}", from_abs.display()))?;
+
+        let hash = self.files[i].hash.clone();
+        self.generation += 1;
+        self.changes.push(Change::moved(self.generation, &hash, from, to));
+        self.files[i].path_in_library = to.to_path_buf();
+        Ok(hash)
+    }
+
+    /// Delete the file at `path_in_library` and drop it from the index, returning its hash. With
+    /// `trash` it's moved under `_pometa/trash` at the same path instead, to be restored by hand
+    pub fn remove_file(&mut self, path_in_library: &Path, trash: bool) -> Result<FileHash> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let path = self.output_root.join(path_in_library);
+        let hash = self.files[i].hash.clone();
+        let removed = if trash {
+            let mut dest = self.meta_root.join(TRASH_DIR).join(path_in_library);
+            // An earlier file removed from the same path keeps its place
+            let taken = |path: &Path| fs::symlink_metadata(path).is_ok();
+            if taken(&dest) {
+                let name = dest.file_name().expect("library path to be a file").to_os_string();
+                dest.set_file_name(with_hash_suffix(&name, &hash));
+                if taken(&dest) {
+                    return Err(eyre!("{} would be trashed to {}, which is already taken", path_in_library.display(), dest.display()));
+                }
+            }
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            info!("moving {} to {}", path.display(), dest.display());
+            fs::rename(&path, &dest)
+        } else {
+            info!("deleting {}", path.display());
+            fs::remove_file(&path)
+        };
+
+        match removed {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warn!("{} was already gone, dropping it from the index", path.display());
+            }
+            removed => removed.wrap_err(format!("when removing {}", path.display()))?,
+        }
+
+        self.files.remove(i);
+        self.generation += 1;
+        self.changes.push(Change::new(self.generation, ChangeKind::Removed, &hash, path_in_library));
+        Ok(hash)
+    }
+
+    /// Move the file at `path_in_library` out of the library to the same path under `archive`, such
+    /// as slower storage, and drop it from the index. Returns its hash
+    pub fn archive_file(&mut self, path_in_library: &Path, archive: &Path) -> Result<FileHash> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let path = self.output_root.join(path_in_library);
+        let hash = self.files[i].hash.clone();
+        let dest = archive.join(path_in_library);
+        if dest.exists() {
+            return Err(eyre!("not archiving {} as {} already exists", path.display(), dest.display()));
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        info!("moving {} to {}", path.display(), dest.display());
+        match fs::rename(&path, &dest) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                debug!("{} is on another filesystem, copying it instead", archive.display());
+                let mut partial = dest.clone().into_os_string();
+                partial.push(TEMPORARY_SUFFIX);
+                let partial = PathBuf::from(partial);
+                copy_with_progress(&path, &partial).wrap_err(format!("when copying {} to {}", path.display(), dest.display()))?;
+                // The library's copy is only removed once the archive's is known to be whole
+                if FileHash::from_file(&partial, hash.algorithm())? != hash {
+                    let _ = fs::remove_file(&partial);
+                    return Err(eyre!("{} did not match {} once copied, it was left in the library", dest.display(), path.display()));
+                }
+                fs::rename(&partial, &dest)?;
+                fs::remove_file(&path).wrap_err(format!("when removing {} after archiving it", path.display()))?;
+            }
+            Err(e) => return Err(e).wrap_err(format!("when moving {} to {}", path.display(), dest.display())),
+        }
+
+        self.files.remove(i);
+        self.generation += 1;
+        self.changes.push(Change::new(self.generation, ChangeKind::Removed, &hash, path_in_library));
+        Ok(hash)
+    }
+
+    /// Drop the file at `path_in_library` from the index, leaving it on disk. Returns its hash,
+    /// or `None` if it wasn't in the index
+    pub fn forget_file(&mut self, path_in_library: &Path) -> Option<FileHash> {
+        let i = self.files.iter().position(|f| f.path_in_library == path_in_library)?;
+        let file = self.files.remove(i);
+        self.generation += 1;
+        self.changes.push(Change::new(self.generation, ChangeKind::Removed, &file.hash, path_in_library));
+        Some(file.hash)
+    }
+
+    /// Add `add` to the tags of the file at `path_in_library` and take `remove` away, returning the file
+    pub fn retag(&mut self, path_in_library: &Path, add: &[String], remove: &[String]) -> Result<&LibraryFile> {
+        if let Some(tag) = add.iter().find(|t| t.is_empty() || t.contains(char::is_whitespace)) {
+            return Err(eyre!("tags can't be empty or contain whitespace, got {tag:?}"));
+        }
+
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let file = &mut self.files[i];
+        let before = file.tags.clone();
+        file.tags.retain(|t| !remove.contains(t));
+        file.tags.extend(add.iter().cloned());
+        file.tags.sort();
+        file.tags.dedup();
+
+        if file.tags != before {
+            self.generation += 1;
+            self.changes.push(Change::new(self.generation, ChangeKind::Modified, &file.hash, &file.path_in_library));
+        }
+
+        Ok(&self.files[i])
+    }
+
+    /// Set how many prints of the file at `path_in_library` to order
+    pub fn set_prints(&mut self, path_in_library: &Path, prints: u32) -> Result<&LibraryFile> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let file = &mut self.files[i];
+        if file.prints != prints {
+            file.prints = prints;
+            self.generation += 1;
+            self.changes.push(Change::new(self.generation, ChangeKind::Modified, &file.hash, &file.path_in_library));
+        }
+
+        Ok(&self.files[i])
+    }
+
+    /// Set the text read out of the file at `path_in_library`, `None` for there being none
+    pub fn set_text(&mut self, path_in_library: &Path, text: Option<String>) -> Result<&LibraryFile> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let file = &mut self.files[i];
+        if file.text != text {
+            file.text = text;
+            self.generation += 1;
+            self.changes.push(Change::new(self.generation, ChangeKind::Modified, &file.hash, &file.path_in_library));
+        }
+
+        Ok(&self.files[i])
+    }
+
+    /// Replace the content of the file at `path_in_library` with what `rewrite` makes of it. The new
+    /// content is written beside the file and renamed over it, and under hash suffixed naming the
+    /// file is renamed to its new hash. Returns the file, or `None` if `rewrite` left it as it was
+    pub fn rewrite_file(
+        &mut self,
+        path_in_library: &Path,
+        rewrite: impl FnOnce(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Option<&LibraryFile>> {
+        let i = self
+            .files
+            .iter()
+            .position(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let path = self.absolute_path(&self.files[i]);
+        let old_hash = self.files[i].hash.clone();
+        let content = fs::read(&path).wrap_err(format!("when reading {}", path.display()))?;
+        if FileHash::from_bytes(&content, old_hash.algorithm()) != old_hash {
+            return Err(eyre!("{} has changed since it was imported, check it with `po verify`", path.display()));
+        }
+
+        let content = rewrite(&content)?;
+        let new_hash = FileHash::from_bytes(&content, old_hash.algorithm());
+        if new_hash == old_hash {
+            return Ok(None);
+        }
+
+        let name = path_in_library.file_name().expect("library path to be a file");
+        let new_path = match self.settings().naming {
+            NamingScheme::Original => path_in_library.to_path_buf(),
+            NamingScheme::HashSuffix => {
+                path_in_library.with_file_name(with_hash_suffix(&strip_hash_suffix(name, &old_hash), &new_hash))
+            }
+        };
+        let new_abs = self.output_root.join(&new_path);
+        if new_path != path_in_library && new_abs.exists() {
+            return Err(eyre!("not rewriting {} as {} already exists", path.display(), new_abs.display()));
+        }
+
+        let mut temp = path.clone().into_os_string();
+        temp.push(TEMPORARY_SUFFIX);
+        let temp = PathBuf::from(temp);
+        let written = (|| -> Result<()> {
+            let mut file = fs::File::create(&temp)?;
+            file.write_all(&content)?;
+            file.sync_all()?;
+            if FileHash::from_file(&temp, new_hash.algorithm())? != new_hash {
+                return Err(eyre!("{} did not read back as written", temp.display()));
+            }
+            fs::rename(&temp, &new_abs)?;
+            Ok(())
+        })();
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e.wrap_err(format!("when rewriting {}", path.display())));
+        }
+        // The new file is in place, so the index has to follow it even if the old one lingers
+        if new_abs != path && let Err(e) = fs::remove_file(&path) {
+            warn!("could not remove {} after rewriting it as {}: {e}", path.display(), new_abs.display());
+        }
+
+        info!("rewrote {} as {}", path.display(), new_hash.encode());
+        self.generation += 1;
+        self.changes.push(Change::new(self.generation, ChangeKind::Removed, &old_hash, path_in_library));
+        self.changes.push(Change::new(self.generation, ChangeKind::Added, &new_hash, &new_path));
+        self.bloom.insert(&new_hash);
+        self.files[i].hash = new_hash;
+        self.files[i].path_in_library = new_path;
+        self.files[i].size = Some(content.len() as u64);
+        Ok(Some(&self.files[i]))
+    }
+
+    /// Add `content`, made from the file at `path_in_library`, beside it under the same name with
+    /// `extension`, as when a HEIC is transcoded. It's dated and grouped as that file is. Returns
+    /// the new file, or `None` if there's already a file where it would go
+    pub fn add_beside(&mut self, path_in_library: &Path, extension: &str, content: &[u8]) -> Result<Option<&LibraryFile>> {
+        let source = self
+            .files
+            .iter()
+            .find(|f| f.path_in_library == path_in_library)
+            .ok_or_else(|| eyre!("{} is not in the library", path_in_library.display()))?;
+
+        let hash = FileHash::from_bytes(content, source.hash.algorithm());
+        let name = strip_hash_suffix(path_in_library.file_name().expect("library path to be a file"), &source.hash);
+        let name = Path::new(&name).with_extension(extension).into_os_string();
+        let new_path = match self.settings().naming {
+            NamingScheme::Original => path_in_library.with_file_name(name),
+            NamingScheme::HashSuffix => path_in_library.with_file_name(with_hash_suffix(&name, &hash)),
+        };
+        let new_abs = self.output_root.join(&new_path);
+        if new_abs.exists() || self.files.iter().any(|f| f.path_in_library == new_path) {
+            debug!("{} already exists, not adding one made from {}", new_abs.display(), path_in_library.display());
+            return Ok(None);
+        }
+
+        let file = LibraryFile {
+            hash: hash.clone(),
+            path_in_library: new_path,
+            imported_by: Some(self.identity.clone()),
+            sequence: source.sequence.clone(),
+            group: source.group.clone(),
+            tags: source.tags.clone(),
+            prints: 0,
+            people: source.people.clone(),
+            size: Some(content.len() as u64),
+            imported_at: OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+            taken: source.taken.clone(),
+            text: None,
+            duration: None,
+        };
+
+        let mut temp = new_abs.clone().into_os_string();
+        temp.push(TEMPORARY_SUFFIX);
+        let temp = PathBuf::from(temp);
+        let written = (|| -> Result<()> {
+            let mut out = fs::File::create(&temp)?;
+            out.write_all(content)?;
+            out.sync_all()?;
+            if FileHash::from_file(&temp, hash.algorithm())? != hash {
+                return Err(eyre!("{} did not read back as written", temp.display()));
+            }
+            fs::rename(&temp, &new_abs)?;
+            Ok(())
+        })();
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e.wrap_err(format!("when writing {}", new_abs.display())));
+        }
+
+        info!("added {} beside {}", new_abs.display(), path_in_library.display());
+        self.add_file(file);
+        Ok(self.files.last())
+    }
+
+    /// Changes whenever the library's files do
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether `path` lives under the output root (and so also `_pometa`)
+    pub fn contains_path(&self, path: &Path) -> Result<bool> {
+        let root = fs::canonicalize(&self.output_root)?;
+        match fs::canonicalize(path) {
+            Ok(p) => Ok(p.starts_with(root)),
+            // Dangling links and the like, which can't be in the library
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// The `_pometa` directory, where po keeps everything about the library
+    pub fn meta_root(&self) -> &Path {
+        &self.meta_root
+    }
+
+    /// Changes made since the library was last saved, which aren't in the change feed yet
+    pub fn unsaved_changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// The root of the library, which files are sorted into
+    pub fn output_root(&self) -> &Path {
+        &self.output_root
+    }
+
+    /// Where `file` is on disk
+    pub fn absolute_path(&self, file: &LibraryFile) -> PathBuf {
+        self.output_root.join(&file.path_in_library)
+    }
+}
+
+pub fn encode_hash_file<'a>(files: impl IntoIterator<Item = &'a LibraryFile>) -> String {
+    let hash_content = files.into_iter()
+        .fold(String::new(), |mut a, b| {
+            a.push_str(&b.hash.encode());
+            a.push(' ');
+            a.push_str(&b.path_in_library.to_string_lossy());
+            a.push('\n');
+            for (key, value) in b.properties() {
+                a.push_str(PROPERTY_INDENT);
+                a.push_str(key);
+                a.push(' ');
+                a.push_str(&value);
+                a.push('\n');
+            }
+            a
+        });
+
+    format!("{CURRENT_VERSION}\n{CONTENT_SENTINEL}\n{hash_content}")
+}
+
+pub fn decode_hash_file(content: &str) -> Result<Vec<LibraryFile>> {
+    let (version, hashes) = content
+        .split_once(CONTENT_SENTINEL)
+        .wrap_err("could not find content sentinel, likely library corruption")?;
+
+    let version = version
+        .trim()
+        .parse::<u16>()
+        .wrap_err("could not parse version information, likely library corruption")?;
+    
+    if version > SUPPORTED_VERSION_MAX {
+        return Err(eyre!("version {version} is not supported. max supported version is {SUPPORTED_VERSION_MAX}"));
+    }
+
+    let mut files: Vec<LibraryFile> = vec![];
+    for l in hashes.trim().lines() {
+        if l.starts_with(PROPERTY_INDENT) {
+            let (key, value) = l
+                .trim()
+                .split_once(' ')
+                .wrap_err("could not parse property line, likely library corruption")?;
+
+            files
+                .last_mut()
+                .wrap_err("property line before any file, likely library corruption")?
+                .set_property(key, value)?;
+        } else {
+            let (hash_raw, path) = l.split_at(HASH_LENGTH.into());
+            // An `algorithm` property follows for files not hashed with SHA256
+            let hash = FileHash::decode(hash_raw.trim(), HashAlgorithm::Sha256)
+                .wrap_err("when parsing file hashes from hash file")?;
+
+            files.push(LibraryFile {
+                hash,
+                path_in_library: path.trim().into(),
+                imported_by: None,
+                sequence: None,
+                group: None,
+                tags: vec![],
+                prints: 0,
+                people: vec![],
+                size: None,
+                imported_at: None,
+                taken: None,
+                text: None,
+                duration: None,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Fails unless `template` names files by something of their own, so files taken on the same
+/// day don't all get one name
+pub fn check_path_template(template: &str) -> Result<()> {
+    let name = template.rsplit('/').next().unwrap_or(template);
+    if !["{original_name", "{stem", "{hash"].iter().any(|p| name.contains(p)) {
+        return Err(eyre!(
+            "path_template {template:?} would give files taken together the same name, \
+             end it with {{original_name}}, or use {{stem}} or {{hash}} in the name"
+        ));
+    }
+    Ok(())
+}
+
+/// `IMG_0001.jpg` -> `IMG_0001.a1b2c3d4.jpg`
+pub fn with_hash_suffix(name: &OsStr, hash: &FileHash) -> OsString {
+    let name = Path::new(name);
+    let short = &hash.encode()[..SHORT_HASH_LENGTH];
+
+    let mut out = name.file_stem().unwrap_or_default().to_owned();
+    out.push(".");
+    out.push(short);
+    if let Some(ext) = name.extension() {
+        out.push(".");
+        out.push(ext);
+    }
+    out
+}
+
+/// Undo `with_hash_suffix`, leaving names without a matching suffix alone
+fn strip_hash_suffix(name: &OsStr, hash: &FileHash) -> OsString {
+    let suffix = format!(".{}", &hash.encode()[..SHORT_HASH_LENGTH]);
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match stem.strip_suffix(&suffix) {
+        Some(original) => {
+            let mut out = OsString::from(original);
+            if let Some(ext) = path.extension() {
+                out.push(".");
+                out.push(ext);
+            }
+            out
+        }
+        None => name.to_owned(),
+    }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct FileHash(Vec<u8>);
+/// Rename `from` to `to`, failing rather than replacing anything already at `to`. Linking fails
+/// when `to` exists where renaming would quietly replace it, so it's linked and then unlinked
+fn rename_no_clobber(from: &Path, to: &Path) -> Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from)?,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(eyre!("{} already exists, not replacing it with {}", to.display(), from.display()));
+        }
+        // Filesystems without hard links, like exFAT, can only be checked before renaming
+        Err(e) => {
+            debug!("could not link {} to {} ({e}), renaming it instead", from.display(), to.display());
+            if fs::symlink_metadata(to).is_ok() {
+                return Err(eyre!("{} already exists, not replacing it with {}", to.display(), from.display()));
+            }
+            fs::rename(from, to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Files po writes while they're incomplete carry this suffix
+pub const TEMPORARY_SUFFIX: &str = ".part";
+
+pub fn is_temporary_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|f| f.to_string_lossy().ends_with(TEMPORARY_SUFFIX))
+}
+
+/// The OS user po is running as
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Move a file into the library. Symlinks (such as git-annex'd files) have their
+/// target copied instead, leaving whatever owns the link intact, as do files with `keep_source`.
+/// Files on another filesystem than the library can't be renamed into it, so they are copied
+/// and this returns true, leaving the caller to remove the source once it has checked the copy
+fn move_into_library(file: &UnsortedFile, dest: &Path, keep_source: bool) -> Result<bool> {
+    let source = &file.path;
+    if file.is_symlink || keep_source {
+        copy_file(source, dest)?;
+        return Ok(false);
+    }
+
+    match fs::rename(source, dest) {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("{} is on another filesystem, copying it instead", source.display());
+            copy_with_progress(source, dest)
+                .wrap_err(format!("when copying {} to {}", source.display(), dest.display()))?;
+            Ok(true)
+        }
+        Err(e) => Err(e).wrap_err(format!("when moving {} to {}", source.display(), dest.display())),
+    }
+}
+
+/// How often a long copy logs how far it's got
+const COPY_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Copy `from` to `to` in chunks, logging progress for files which take a while, such as
+/// videos going from an SD card to a network mount. The copy is synced to disk before returning
+fn copy_with_progress(from: &Path, to: &Path) -> io::Result<u64> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let total = reader.metadata()?.len();
+    let mut buffer = vec![0; budget::io_buffer_bytes(hashing::READ_BUFFER_BYTES, 64 * 1024)];
+
+    let mut copied = 0;
+    let mut last_report = Instant::now();
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+
+        if last_report.elapsed() >= COPY_PROGRESS_INTERVAL {
+            info!(
+                "copying {}: {} of {} MiB ({}%)",
+                from.display(),
+                copied / (1024 * 1024),
+                total / (1024 * 1024),
+                copied * 100 / total.max(1)
+            );
+            last_report = Instant::now();
+        }
+    }
+
+    writer.sync_all()?;
+    Ok(copied)
+}
+
+/// `fs::copy`, through io_uring when it's enabled and available
+pub fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(result) = crate::uring::copy_file(from, to) {
+        return result;
+    }
+
+    fs::copy(from, to)
+}
+
+/// The per-year index shard a library path belongs in, if it's under a year directory
+pub fn year_shard(path_in_library: &Path) -> Option<String> {
+    let first = path_in_library.components().next()?.as_os_str().to_str()?;
+    let is_year = first.len() == 4 && first.bytes().all(|b| b.is_ascii_digit());
+    is_year.then(|| first.to_string())
+}
+
+/// `2025/7/14`, where the date policy sorts files from that day
+fn date_directory(date: PrimitiveDateTime) -> PathBuf {
+    let mut p = PathBuf::new();
+    p.push(date.year().to_string());
+    p.push((date.month() as u8).to_string());
+    p.push(date.day().to_string());
+    p
+}
+
+/// The filesystem creation time of a file, in UTC
+pub fn created_at(path: &Path) -> Result<PrimitiveDateTime> {
+    creation_time(&path.metadata()?)
+}
+
+/// When a file was captured, preferring embedded metadata over the filesystem
+pub fn capture_time(file: &UnsortedFile) -> Option<PrimitiveDateTime> {
+    match metadata::read_capture_info(&file.path) {
+        Ok(info) if info.taken.is_some() => info.taken,
+        Ok(_) => creation_time(&file.metadata).ok(),
+        Err(e) => {
+            debug!("could not read metadata from {}: {e}", file.path.display());
+            creation_time(&file.metadata).ok()
+        }
+    }
+}
+
+/// How long the video at `path` runs, by its container
+fn video_duration(path: &Path) -> Option<f64> {
+    match metadata::read_capture_info(path) {
+        Ok(info) => info.duration,
+        Err(e) => {
+            debug!("could not read the duration of {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// As `created_at`, from metadata which has already been read
+pub fn creation_time(meta: &fs::Metadata) -> Result<PrimitiveDateTime> {
+    let created = meta.created()?
+        .duration_since(std::time::UNIX_EPOCH)?;
+    let epoch = time::macros::datetime!(1970-01-01 0:00);
+    Ok(epoch + created)
+}
+
+/// The hash of a file's content, which is how po tells files apart, along with the algorithm
+/// which made it
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub struct FileHash {
+    algorithm: HashAlgorithm,
+    digest: Vec<u8>,
+}
 
 impl Debug for FileHash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -228,27 +1911,202 @@ impl Debug for FileHash {
 }
 
 impl FileHash {
+    /// The digest as hex. Both algorithms give 32 byte digests, so which one made it is kept
+    /// alongside rather than in here, see [`FileHash::algorithm`]
     pub fn encode(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(&self.digest)
+    }
+
+    /// The raw digest
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Which algorithm made it, as hashes by different ones can't be compared
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
     }
 
-    pub fn decode(value: &str) -> Result<Self> {
-        if value.len() != HASH_LENGTH.into() {
+    /// A hash from its hex digest, as given by [`FileHash::encode`]
+    pub fn decode(value: &str, algorithm: HashAlgorithm) -> Result<Self> {
+        if value.len() != usize::from(HASH_LENGTH) {
             return Err(eyre!("value was not {HASH_LENGTH} chars long. got {}", value.len()));
         }
         
         hex::decode(value)
-            .map(FileHash)
+            .map(|digest| FileHash { algorithm, digest })
             .wrap_err("could not decode hex string")
     }
 
-    pub fn from_file(path: &PathBuf) -> Result<Self> {
-        let mut hasher = Sha256::new();
-        let mut file = fs::File::open(path)?;
-        
-        io::copy(&mut file, &mut hasher)?;
-        let hash_bytes = hasher.finalize();
+    /// The hash of `content`
+    pub fn from_bytes(content: &[u8], algorithm: HashAlgorithm) -> Self {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(content);
+        FileHash { algorithm, digest: hasher.finalize() }
+    }
+
+    /// The hash of the content of the file at `path`
+    pub fn from_file(path: &PathBuf, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut hasher = Hasher::new(algorithm);
+        let started = Instant::now();
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(result) = crate::uring::read_file(path, |chunk| hasher.update(chunk)) {
+            let bytes = result?;
+            hashing::record(bytes, started.elapsed());
+            return Ok(FileHash { algorithm, digest: hasher.finalize() });
+        }
+
+        // Bigger reads than io::copy's default help a lot on network filesystems
+        let buffer = hashing::READ_BUFFER_BYTES;
+        let mut file = io::BufReader::with_capacity(budget::io_buffer_bytes(buffer, 8 * 1024), fs::File::open(path)?);
+        let bytes = io::copy(&mut file, &mut hasher)?;
+        hashing::record(bytes, started.elapsed());
         
-        Ok(Self(hash_bytes.to_vec()))
+        Ok(FileHash { algorithm, digest: hasher.finalize() })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A test's own directory, deleted along with everything in it when dropped
+    pub(crate) struct ScratchDir(PathBuf);
+
+    impl std::ops::Deref for ScratchDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for ScratchDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// An empty directory of its own for each test
+    pub(crate) fn scratch_dir(name: &str) -> ScratchDir {
+        let dir = std::env::temp_dir().join(format!("po-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("scratch dir to be created");
+        ScratchDir(dir)
+    }
+
+    /// An index entry for `content` at `path_in_library`, with nothing else known about it
+    pub(crate) fn indexed(content: &str, path_in_library: &str) -> LibraryFile {
+        LibraryFile {
+            hash: FileHash::from_bytes(content.as_bytes(), HashAlgorithm::Sha256),
+            path_in_library: path_in_library.into(),
+            imported_by: None,
+            sequence: None,
+            group: None,
+            tags: vec![],
+            prints: 0,
+            people: vec![],
+            size: None,
+            imported_at: None,
+            taken: None,
+            text: None,
+            duration: None,
+        }
+    }
+
+    /// A library at `root` whose metadata is only kept in memory, indexing `files`
+    pub(crate) fn library(root: &Path, files: Vec<LibraryFile>) -> Library {
+        let mut storage = MemoryStorage::default();
+        storage.write_index(IndexLayout::Single, &files, None).expect("index to be written");
+        let mut library = Library::open(root.to_path_buf(), Box::new(storage), None).expect("library to open");
+        library.reconcile_settings(None, None, None, None, None).expect("settings to be adopted");
+        fs::create_dir_all(library.meta_root()).expect("meta root to be created");
+        library
+    }
+
+    #[test]
+    fn trashing_keeps_earlier_files_from_the_same_path() {
+        let root = scratch_dir("trash");
+        let mut library = library(&root, vec![indexed("new", "a.jpg"), indexed("newer", "b.jpg")]);
+        let trash = library.meta_root().join(TRASH_DIR);
+        fs::create_dir_all(&trash).unwrap();
+        for name in ["a.jpg", "b.jpg"] {
+            fs::write(root.join(name), name).unwrap();
+            fs::write(trash.join(name), "old").unwrap();
+        }
+        let newer = FileHash::from_bytes(b"newer", HashAlgorithm::Sha256);
+        fs::write(trash.join(with_hash_suffix("b.jpg".as_ref(), &newer)), "old").unwrap();
+
+        let hash = library.remove_file(Path::new("a.jpg"), true).unwrap();
+        assert_eq!(fs::read_to_string(trash.join(with_hash_suffix("a.jpg".as_ref(), &hash))).unwrap(), "a.jpg");
+        assert_eq!(fs::read_to_string(trash.join("a.jpg")).unwrap(), "old");
+
+        assert!(library.remove_file(Path::new("b.jpg"), true).is_err());
+        assert!(root.join("b.jpg").exists());
+        assert_eq!(library.files().len(), 1);
+    }
+
+    #[test]
+    fn hash_file_round_trips() {
+        let plain = indexed("plain", "2024/1/2/plain.jpg");
+        let mut full = indexed("full", "2024/1/2/with spaces.jpg");
+        full.hash = FileHash::from_bytes(b"full", HashAlgorithm::Blake3);
+        full.imported_by = Some("amy".into());
+        full.sequence = Some("seq".into());
+        full.group = Some("IMG_0001".into());
+        full.tags = vec!["a".into(), "b".into()];
+        full.prints = 2;
+        full.people = vec!["Amy N".into(), "Bo".into()];
+        full.size = Some(1234);
+        full.imported_at = Some("2024-01-02T03:04:05Z".into());
+        full.taken = Some("2024-01-02T03:04:05".into());
+        full.text = Some("hello world".into());
+        full.duration = Some(1.5);
+
+        let decoded = decode_hash_file(&encode_hash_file([&plain, &full])).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].hash, plain.hash);
+        assert_eq!(decoded[0].path_in_library, plain.path_in_library);
+        assert!(decoded[0].properties().is_empty());
+
+        assert_eq!(decoded[1].hash, full.hash);
+        assert_eq!(decoded[1].path_in_library, full.path_in_library);
+        assert_eq!(decoded[1].properties(), full.properties());
+    }
+
+    #[test]
+    fn hash_file_rejects_newer_versions() {
+        let content = encode_hash_file([]).replacen(&CURRENT_VERSION.to_string(), "999", 1);
+        assert!(decode_hash_file(&content).is_err());
+    }
+
+    #[test]
+    fn forgetting_records_a_change() {
+        let root = scratch_dir("forget");
+        let mut library = library(&root, vec![indexed("a", "a.jpg"), indexed("b", "b.jpg")]);
+
+        assert_eq!(library.forget_file(Path::new("a.jpg")), Some(FileHash::from_bytes(b"a", HashAlgorithm::Sha256)));
+        assert_eq!(library.forget_file(Path::new("a.jpg")), None);
+        assert_eq!(library.files().len(), 1);
+        assert_eq!(library.changes.len(), 1);
+    }
+
+    #[test]
+    fn replacing_the_index_records_retagged_files() {
+        let root = scratch_dir("replace");
+        let mut library = library(&root, vec![indexed("a", "a.jpg"), indexed("b", "b.jpg")]);
+
+        let mut files = library.files().to_vec();
+        files[1].tags = vec!["later".into()];
+        library.replace_files(files);
+        let changed: Vec<_> = library.changes.iter().map(|c| (c.kind, c.path.as_str())).collect();
+        assert_eq!(changed, [(ChangeKind::Modified, "b.jpg")]);
     }
 }
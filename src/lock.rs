@@ -0,0 +1,132 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::fs::{self, File, TryLockError};
+use std::io::{Seek, Write};
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{debug, info};
+
+/// Locked by whichever po has the library open, within the library's metadata
+const LOCK_FILE: &str = "lock";
+
+/// Who holds the lock, written into the lock file so anyone waiting on it can be told
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+struct Holder {
+    pid: u32,
+    command: Vec<String>,
+    since: String,
+}
+
+/// How a library is locked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Any number of po can read the library at once, as long as none is changing it
+    Shared,
+    /// Only this po has the library open, so it can change it
+    Exclusive,
+}
+
+/// An advisory lock on a library, held until it's dropped. The OS releases it if po dies, so a
+/// crashed run never leaves the library locked
+#[derive(Debug)]
+pub struct LibraryLock {
+    _file: File,
+    access: Access,
+}
+
+impl LibraryLock {
+    pub fn access(&self) -> Access {
+        self.access
+    }
+}
+
+/// Lock the library whose metadata is in `meta_root`, waiting for whoever has it to finish if
+/// `wait` is set, and failing with who that is otherwise
+pub fn acquire(meta_root: &Path, wait: bool, access: Access) -> Result<LibraryLock> {
+    let path = meta_root.join(LOCK_FILE);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .wrap_err(format!("when opening lock file {}", path.display()))?;
+
+    let attempt = match access {
+        Access::Shared => file.try_lock_shared(),
+        Access::Exclusive => file.try_lock(),
+    };
+    match attempt {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            info!("waiting for {} to finish with the library", holder(&path));
+            match access {
+                Access::Shared => file.lock_shared(),
+                Access::Exclusive => file.lock(),
+            }
+            .wrap_err(format!("when locking {}", path.display()))?;
+        }
+        Err(TryLockError::WouldBlock) => {
+            return Err(eyre!(
+                "the library is in use by {}. wait for it to finish, or set `wait_for_lock = true` to wait for it",
+                holder(&path)
+            ));
+        }
+        Err(TryLockError::Error(e)) => return Err(e).wrap_err(format!("when locking {}", path.display())),
+    }
+
+    // With several readers this is whichever locked it last
+    let holder = Holder {
+        pid: std::process::id(),
+        command: std::env::args().collect(),
+        since: OffsetDateTime::now_utc().format(&Rfc3339)?,
+    };
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", serde_json::to_string(&holder)?)?;
+    debug!("locked {} ({access:?})", path.display());
+
+    Ok(LibraryLock { _file: file, access })
+}
+
+/// Who has the library whose metadata is in `meta_root` open, if anyone
+pub fn in_use_by(meta_root: &Path) -> Option<String> {
+    let path = meta_root.join(LOCK_FILE);
+    let file = File::open(&path).ok()?;
+    match file.try_lock() {
+        Err(TryLockError::WouldBlock) => Some(holder(&path)),
+        _ => None,
+    }
+}
+
+/// Describe who holds the lock at `path`, as well as the lock file says
+fn holder(path: &Path) -> String {
+    let holder = fs::read_to_string(path).ok().and_then(|s| serde_json::from_str::<Holder>(s.trim()).ok());
+    match holder {
+        Some(h) => format!("`{}` (pid {}, since {})", h.command.join(" "), h.pid, h.since),
+        None => "another po".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::scratch_dir;
+
+    #[test]
+    fn readers_share_but_writers_wait() {
+        let meta_root = scratch_dir("lock");
+        let first = acquire(&meta_root, false, Access::Shared).unwrap();
+        let second = acquire(&meta_root, false, Access::Shared).unwrap();
+        assert!(acquire(&meta_root, false, Access::Exclusive).is_err());
+        assert!(in_use_by(&meta_root).is_some());
+
+        drop((first, second));
+        let writer = acquire(&meta_root, false, Access::Exclusive).unwrap();
+        assert!(acquire(&meta_root, false, Access::Shared).is_err());
+        drop(writer);
+        assert!(in_use_by(&meta_root).is_none());
+    }
+}
@@ -1,21 +1,62 @@
-use clap::{Parser, Subcommand};
-use color_eyre::eyre::{Result, WrapErr};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Subcommand, ValueEnum};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use confique::Config;
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use time::macros::format_description;
 
 use fast_glob::glob_match;
+use regex::Regex;
+use ignore::gitignore::Gitignore;
 
-mod library;
-use library::{Library, SortPolicy};
+use po::t;
+use po::{
+    annex, audit, backup, budget, changes, checksums, classify, config_docs, daemon, devices, doctor,
+    export, extensions, gallery, geo, hashing, heatmap, heic, i18n, ignores, jobs, journal,
+    library, manifest, map, metadata, mirror, mqtt, ocr, print, progress, prune, remux, rename,
+    retention, rolls, schema, serve, shutdown, similar, simulate, snapshot, stats, thumbs, update,
+    verify, watch,
+};
+use po::backup::BackupRepo;
+use po::changes::ChangeKind;
+use po::checksums::ChecksumFiles;
+use po::classify::ClassifyConfig;
+use po::daemon::{Request, Response, ResponseCache, TagOutput, Task, Tasks};
+use po::derivatives::Derivatives;
+use po::doctor::Severity;
+use po::export::{ExportLayout, ExportOptions, ExportPaths, ExportSort};
+use po::hashing::HashAlgorithm;
+use po::jobs::Job;
+use po::library::{Clock, FileHash, ImportOptions, IndexLayout, InputFile, Library, LibraryFile, NamingScheme, PlannedFile, Skip, SortPolicy, SourceRetention, UnsortedFile};
+use po::metadata::ExifFields;
+use po::mqtt::MqttConfig;
+use po::ocr::OcrConfig;
+use po::print::PrintSize;
+use po::query::{QueryFilters, QueryIndexes};
+use po::registry::{Registry, RegistryOptions, RegistryPolicy};
+use po::rolls::Roll;
+use po::schema::{ChangesOutput, ContainedFile, ContainsOutput, DerivativeRecord, FileRecord, ImportReport, JobRecord, JobsOutput, QueryOutput, QueryRecord, ShowOutput, StatsOutput};
+use po::serve::ServeConfig;
+use po::simulate::{Placement, VirtualFs};
+use po::snapshot::{Change, Snapshot};
+use po::transcode::Preset;
+use po::verify::VerifyState;
+use po::watch::WatchRule;
 
-use tracing::{debug, debug_span, info, instrument};
+use tracing::{debug, debug_span, info, instrument, warn};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{EnvFilter, fmt};
 
-#[derive(Parser)]
+#[derive(clap::Parser)]
 struct Cli {
     /// Path to configuration file.
     #[arg(long, default_value = "po.toml", env = "PO_CONFIG_PATH")]
@@ -25,6 +66,19 @@ struct Cli {
     #[command(flatten)]
     cli_config: <AppConfig as Config>::Layer,
 
+    /// Print JSON to stdout instead of the usual output, see `po schema` for its format
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Copy files into the library and leave the inputs untouched, the same as `source_retention = "Copy"`
+    #[arg(long, global = true)]
+    copy: bool,
+
+    /// Run `query`, `show` and `tag` through the daemon running on the library, failing if there isn't one.
+    /// Without this they use a daemon when there is one, and read the library themselves otherwise
+    #[arg(long, global = true)]
+    connect: bool,
+
     #[command(subcommand)]
     action: Option<Action>,
 }
@@ -32,36 +86,946 @@ struct Cli {
 #[derive(Subcommand)]
 enum Action {
     /// Run an import using the config file and add all new pictures to the library
-    Import,
+    Import {
+        /// Print what would be imported, skipped and where files would be sorted, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Import from these paths instead of the configured inputs, for one-off imports
+        paths: Vec<PathBuf>,
+        /// Keep the scans of one film roll or scanning session together under `rolls/<ROLL>`,
+        /// tagged `roll:<ROLL>`, e.g. `Portra400-2024-09`
+        #[arg(long)]
+        roll: Option<String>,
+        /// What's known about the roll, see `po roll`
+        #[command(flatten)]
+        roll_details: Roll,
+    },
+    /// Predict the library's layout after an import, optionally organised differently. Nothing is
+    /// moved, and the library's metadata is left alone
+    Simulate {
+        /// Sort new files with this policy instead of the library's
+        #[arg(long)]
+        sort_policy: Option<SortPolicy>,
+        /// Name new files with this scheme instead of the library's
+        #[arg(long)]
+        naming: Option<NamingScheme>,
+        /// Date files by this time (`2025-07-14` or `2025-07-14 20:30`) instead of when the filesystem says
+        /// they were created, as if they were copied onto the disk then
+        #[arg(long)]
+        clock: Option<String>,
+    },
     /// Execute a query against the library
     ///
     /// The query should be a glob string which matches against library paths.
     ///
     /// For example, "2025/10/*.jpeg" will match all images taken in October, but only the jpeg previews.
+    #[command(alias = "ls")]
     Query {
-        /// The query to run. 
+        /// The query to run. Files matching any of several queries are listed
+        #[arg(required = true)]
+        queries: Vec<String>,
+
+        /// Leave out files matching this glob, can be given more than once
+        #[arg(long)]
+        not: Vec<String>,
+
+        #[command(flatten)]
+        filters: QueryFilters,
+
+        /// List whole groups of paired files, so a matching JPEG brings its raw file and XMP along
+        #[arg(long)]
+        groups: bool,
+
+        /// Show whether `po verify` passed each file recently, a while ago, never, or found it
+        /// missing or changed last time
+        #[arg(long)]
+        verify_state: bool,
+
+        /// `json` prints each file's hash, path, size and import time to stdout, like `--json`
+        #[arg(long, value_enum, default_value_t = QueryFormat::Text)]
+        format: QueryFormat,
+    },
+    /// Tag library files, e.g. `po tag add 2025/10/IMG_1.jpg vacation beach`. Tags can be
+    /// queried with `po query --tag`
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Set how many prints of library files to order, for `po export --print`, e.g.
+    /// `po prints set 2 "2025/7/IMG_1*.jpg"`
+    Prints {
+        #[command(subcommand)]
+        action: PrintsAction,
+    },
+    /// List the film rolls and scanning sessions imported with `po import --roll`, or record
+    /// their film stock, camera and when they were developed. Their scans can be queried with
+    /// `po query --roll` and `--film-stock`
+    Roll {
+        #[command(subcommand)]
+        action: RollAction,
+    },
+    /// Tag photos matching a query with the broad categories an image classifier puts them in,
+    /// like `auto:pets`. Needs po built with `--features classify`, and a model in the config
+    Classify {
+        /// The query selecting files, as with `query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+    },
+    /// Read the text in photos matching a query, such as scanned documents and screenshots, so
+    /// `po query --text` can find them. Needs po built with `--features ocr`
+    Ocr {
+        /// The query selecting files, as with `query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+    },
+    /// Show everything known about a library file
+    Show {
+        /// Path of the file within the library
+        path: PathBuf,
+    },
+    /// Check whether files are already in the library, by content, without importing them
+    ///
+    /// Exits with an error if any are not.
+    Contains {
+        /// Files to check. Directories are searched recursively
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Compare a directory with the library by content, listing files only in the directory (<),
+    /// only in the library (>) and in both (=)
+    Diff {
+        /// Directory to compare, searched recursively
+        dir: PathBuf,
+
+        /// Only compare against library files under this path, e.g. "2024/7"
+        #[arg(long)]
+        within: Option<PathBuf>,
+    },
+    /// Serve an upload page which phones can use to drop files into a staging directory, importing
+    /// them and anything else arriving in the inputs as `watch` does
+    Serve,
+    /// Subscribe to an MQTT topic and import the files referenced by incoming events
+    Mqtt,
+    /// Watch the inputs and import files as they appear, once they've stopped changing for
+    /// `watch_settle_secs`. Files already in the inputs are left for `po import`
+    Watch,
+    /// Export library files matching a query to a directory or another service
+    Export {
+        /// The query selecting files to export, as with `query`
+        query: String,
+
+        /// Copy matching files into this directory
+        #[arg(long, required_unless_present_any = ["immich", "geojson", "kml"])]
+        to: Option<PathBuf>,
+
+        /// How to lay out files copied with `--to`
+        #[arg(long, value_enum, default_value_t)]
+        layout: ExportLayout,
+
+        /// Copy every file straight into `--to`, the same as `--layout flat`
+        #[arg(long, requires = "to", conflicts_with_all = ["layout", "template"])]
+        flatten: bool,
+
+        /// Name files copied with `--to` by a template instead of a layout, e.g. "{date}_{name}.{ext}".
+        /// Placeholders are {year}, {month}, {day}, {date}, {time}, {name}, {ext}, {hash} and {dir}
+        #[arg(long, conflicts_with = "layout")]
+        template: Option<String>,
+
+        /// Resize and re-encode images with a preset from the `export` section of the config
+        #[arg(long, requires = "to")]
+        preset: Option<String>,
+
+        /// Scale images down so neither side is longer than this many pixels, for sharing without
+        /// setting up a preset. Files which aren't images are copied as they are
+        #[arg(long, value_name = "PIXELS", requires = "to", conflicts_with = "preset")]
+        resize: Option<u32>,
+
+        /// The order to export files in
+        #[arg(long, value_enum, default_value_t)]
+        sort: ExportSort,
+
+        /// Number files copied with `--to` in export order, `0001_...`, for slideshow players which sort by name
+        #[arg(long, requires = "to")]
+        number: bool,
+
+        /// Copy only one file for each distinct content, when several matching files are identical
+        #[arg(long, requires = "to")]
+        unique_content: bool,
+
+        /// Upload matching files to this Immich server, e.g. http://immich.local:2283
+        #[arg(long, conflicts_with = "to", requires = "immich_api_key")]
+        immich: Option<String>,
+
+        /// API key used to authenticate with Immich
+        #[arg(long, env = "PO_IMMICH_API_KEY", hide_env_values = true)]
+        immich_api_key: Option<String>,
+
+        /// Write where matching photos were taken to this GeoJSON file, for QGIS and the like
+        #[arg(long, conflicts_with_all = ["to", "immich"])]
+        geojson: Option<PathBuf>,
+
+        /// Write where matching photos were taken to this KML file, for Google Earth
+        #[arg(long, conflicts_with_all = ["to", "immich"])]
+        kml: Option<PathBuf>,
+
+        /// Write a print order to `--to` instead: JPEGs sized for prints like 6x4 or 15x10cm of
+        /// the matching files with prints to order, see `po prints`, and an order.csv listing them
+        #[arg(long, value_name = "SIZE", requires = "to", conflicts_with_all = ["preset", "resize", "template", "number", "unique_content"])]
+        print: Option<String>,
+
+        /// Resolution of a print order's JPEGs
+        #[arg(long, requires = "print", default_value_t = print::DEFAULT_DPI)]
+        dpi: u16,
+    },
+    /// Write a static web gallery of the library, with a page of thumbnails for each folder.
+    /// Run again, it only redoes the folders which changed since
+    Gallery {
+        /// Directory to write the gallery to
+        #[arg(long)]
+        to: PathBuf,
+
+        /// Where the gallery is served, e.g. https://photos.example.com/. With it, RSS and JSON
+        /// feeds of recent imports are written as `feed.xml` and `feed.json`
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Make previews of library files at each of `thumb_sizes` in `_pometa/thumbs`. Run again,
+    /// it only makes those of files which don't have them yet
+    Thumbs,
+    /// Copy videos matching a query to a directory with the date in their container fixed, for
+    /// action cams which write bogus ones. Streams are copied as they are and checked against the
+    /// originals, which stay untouched in the library. Needs po built with `--features ffmpeg`
+    Remux {
+        /// The query selecting files, as with `query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+        /// Directory to write the copies to, mirroring their paths within the library
+        #[arg(long)]
+        to: PathBuf,
+        /// When they were taken, `2025-01-01` or `2025-01-01 20:30`, rather than the date in the index
+        #[arg(long)]
+        date: Option<String>,
+    },
+    /// Keep a copy of the matching files in a directory, such as a drive to give someone, adding,
+    /// replacing and removing files so it matches the query again each time it's run
+    Mirror {
+        /// The query selecting files to mirror, as with `query`
+        query: String,
+        /// The directory to mirror them to, at their paths within the library
+        dest: PathBuf,
+    },
+    /// Write an HTML map of the matching photos which have a location, e.g.
+    /// `po map "2025/7/**" --out trip.html`
+    Map {
+        /// The query to run, as for `po query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+
+        /// Leave out files matching this glob, can be given more than once
+        #[arg(long)]
+        not: Vec<String>,
+
+        /// The HTML file to write
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Interoperate with git-annex
+    Annex {
+        #[command(subcommand)]
+        action: AnnexAction,
+    },
+    /// Check the library against backups
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// Record and restore snapshots of the library index
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Change how files in the library are named, renaming existing files to match
+    Naming {
+        scheme: NamingScheme,
+
+        /// Continue a conversion which was interrupted
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Rename library files whose names match a regex, e.g. `--pattern 'IMG_(\d+)' --to 'holiday_$1'`.
+    /// Nothing is renamed if any new name would collide with another file
+    Rename {
+        /// Regex matched against file names. Only the first match in each name is replaced
+        #[arg(long)]
+        pattern: String,
+        /// What to replace the match with, `$1` and `${name}` refer to the pattern's groups
+        #[arg(long)]
+        to: String,
+        /// Print what would be renamed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete library files matching a query from disk and drop them from the index
+    Remove {
+        /// The query selecting files to remove, as with `query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+        /// Move files to `_pometa/trash` instead of deleting them
+        #[arg(long)]
+        trash: bool,
+        /// Print what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Undo the last import if it didn't finish, e.g. because the disk filled up, moving the
+    /// files it sorted back to their inputs
+    Rollback,
+    /// Manage the registry of hashes shared with other libraries, see `registry`
+    Registry {
+        #[command(subcommand)]
+        action: RegistryAction,
+    },
+    /// Write metadata into library files
+    Exif {
+        #[command(subcommand)]
+        action: ExifAction,
+    },
+    /// Change how the library index is stored, rewriting it in the new layout
+    Index {
+        layout: IndexLayout,
+    },
+    /// Show library history
+    Log {
+        /// Show the audit log of every operation which changed the library
+        #[arg(long, required = true)]
+        audit: bool,
+
+        /// Also list the hashes each operation touched
+        #[arg(long)]
+        hashes: bool,
+    },
+    /// Find library files which are the same as others
+    Dedupe {
+        /// List photos which look alike, such as re-exports or copies at other compression
+        /// levels, by their perceptual hashes
+        #[arg(long, required = true)]
+        similar: bool,
+
+        /// How many of the 64 bits of two perceptual hashes can differ for photos to count as alike
+        #[arg(long, default_value_t = similar::DEFAULT_DISTANCE)]
+        distance: u32,
+
+        /// Only look at files matching this glob
+        #[arg(long, default_value = "**")]
         query: String,
+    },
+    /// Delete originals kept in the `imported-originals` folder of each input by `source_retention = "Hold"`
+    CleanSources {
+        /// Only delete originals held for longer than this, e.g. 30d or 2w
+        #[arg(long)]
+        older_than: String,
+    },
+    /// Trash, delete or archive files which have outgrown the `retention_policies` for their tags
+    Prune {
+        /// Apply the retention policies in the config
+        #[arg(long, required = true)]
+        policies: bool,
+        /// Print what would be done without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Carry out `delete` policies, which can't be undone. Without it po stops before deleting anything
+        #[arg(long)]
+        yes: bool,
+    },
+    /// See and manage long running operations, such as naming conversions
+    Jobs {
+        #[command(subcommand)]
+        action: JobsAction,
+    },
+    /// Print the JSON schema of a command's `--json` output, or list the commands which have one
+    Schema {
+        name: Option<String>,
+    },
+    /// Inspect po's configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Update po to the latest release, checking its signature first
+    SelfUpdate {
+        /// Only report whether there is a newer release
+        #[arg(long)]
+        check: bool,
+    },
+    /// Show how many files the library has, by year, and how much space they take up
+    Stats {
+        /// Draw a calendar of how many photos were taken on each day of a year instead, e.g. year=2024
+        #[arg(long, value_name = "year=YYYY", requires = "out")]
+        heatmap: Option<String>,
+
+        /// Where to write the heatmap, as .svg or .png
+        #[arg(long, requires = "heatmap")]
+        out: Option<PathBuf>,
+    },
+    /// Hash every file in the library again, reporting any which are missing or have changed,
+    /// and files in the library's folders which aren't in the index
+    Verify,
+    /// Write the checksum files `checksum_files` asks for, for every file in the library, such as
+    /// those imported before it was set
+    Checksums,
+    /// Check the config and the library for anything which would stop po working, like inputs
+    /// which aren't there, a library which can't be written to or an import which didn't finish,
+    /// and say how to fix it. Nothing is changed
+    Doctor,
+    /// List changes to the library's files, from the feed in `_pometa/changes`
+    Changes {
+        /// Only list changes after this generation
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+        /// Keep listing changes as they happen
+        #[arg(long)]
+        follow: bool,
+    },
+    /// Print the capture date and camera read from each file's embedded metadata
+    Inspect {
+        /// Files to inspect, these do not need to be in the library
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+impl Action {
+    /// The request a running daemon could answer this action with, if any
+    fn daemon_request(&self) -> Option<Request> {
+        match self {
+            Action::Query { queries, not, filters, groups, verify_state, .. } => Some(Request::Query {
+                queries: queries.clone(),
+                not: not.clone(),
+                filters: Box::new(filters.clone()),
+                groups: *groups,
+                verify_state: *verify_state,
+            }),
+            Action::Show { path } => Some(Request::Show { path: path.clone() }),
+            Action::Tag { action } => Some(match action {
+                TagAction::Add { path, tags } => Request::Tag { path: Some(path.clone()), add: tags.clone(), remove: vec![] },
+                TagAction::Rm { path, tags } => Request::Tag { path: Some(path.clone()), add: vec![], remove: tags.clone() },
+                TagAction::List { path } => Request::Tag { path: path.clone(), add: vec![], remove: vec![] },
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether this action has a `--json` output, with a schema in [`schema`]
+    fn has_json(&self) -> bool {
+        matches!(
+            self,
+            Action::Import { dry_run: false, .. }
+                | Action::Query { .. }
+                | Action::Show { .. }
+                | Action::Contains { .. }
+                | Action::Serve
+                | Action::Mqtt
+                | Action::Watch
+                | Action::Jobs { action: JobsAction::List | JobsAction::Status { .. } }
+                | Action::Schema { .. }
+                | Action::Stats { heatmap: None, .. }
+                | Action::Changes { .. }
+        )
     }
+
+    /// Whether this action only reads the library, so it can share it with other readers
+    fn only_reads(&self) -> bool {
+        matches!(
+            self,
+            Action::Query { .. }
+                | Action::Show { .. }
+                | Action::Diff { .. }
+                | Action::Map { .. }
+                | Action::Stats { heatmap: Some(_), .. }
+                | Action::Changes { .. }
+                | Action::Thumbs
+        )
+    }
+
+    /// The years of a per-year index this action needs, when it only looks at some of them
+    fn index_years(&self) -> Option<Vec<String>> {
+        let path = match self {
+            // Only scoped when every query is
+            Action::Query { queries, .. }
+            | Action::Map { queries, .. }
+            | Action::Remove { queries, .. }
+            | Action::Classify { queries }
+            | Action::Ocr { queries }
+            | Action::Remux { queries, .. } => {
+                let mut years: Vec<String> = queries
+                    .iter()
+                    .map(|q| library::year_shard(Path::new(q)))
+                    .collect::<Option<_>>()?;
+                years.sort();
+                years.dedup();
+                return Some(years);
+            }
+            Action::Show { path }
+            | Action::Diff { within: Some(path), .. }
+            | Action::Tag { action: TagAction::Add { path, .. } | TagAction::Rm { path, .. } | TagAction::List { path: Some(path) } } => path,
+            // Imports start from no years, loading the rest only if the bloom filter can't rule out a duplicate
+            Action::Import { .. } | Action::Mqtt | Action::Watch | Action::Contains { .. } => return Some(vec![]),
+            // Loads the rest itself, only if its cache is out of date
+            Action::Stats { heatmap: None, .. } => return Some(vec![]),
+            // These don't look at the index
+            Action::CleanSources { .. }
+            | Action::Changes { .. } => {
+                return Some(vec![]);
+            }
+            _ => return None,
+        };
+
+        library::year_shard(path).map(|year| vec![year])
+    }
+}
+
+/// How `po query` prints the files it matches
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum QueryFormat {
+    /// `hash path` lines on stderr
+    Text,
+    /// The `query` document of `po schema` on stdout
+    Json,
+}
+
+#[derive(Subcommand)]
+enum TagAction {
+    /// Add tags to a library file
+    Add {
+        /// Path of the file within the library
+        path: PathBuf,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a library file
+    Rm {
+        /// Path of the file within the library
+        path: PathBuf,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// List the tags of a library file, or every tag in the library with how many files have it
+    List {
+        /// Path of the file within the library
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PrintsAction {
+    /// Set how many prints of the matching files to order, 0 for none
+    Set {
+        count: u32,
+        /// The query selecting files, as with `query`
+        #[arg(required = true)]
+        queries: Vec<String>,
+    },
+    /// List the files with prints to order
+    List,
+}
+
+#[derive(Subcommand)]
+enum RollAction {
+    /// List every roll, with how many files it has and what's known about it
+    List,
+    /// Record the film stock, camera or developed date of a roll, keeping whatever isn't given
+    Set {
+        /// The name the roll was imported with
+        name: String,
+        #[command(flatten)]
+        details: Roll,
+    },
+}
+
+#[derive(Subcommand)]
+enum RegistryAction {
+    /// Replace this library's entries in the registry with every file in the library, for
+    /// libraries which existed before the registry was set up
+    Sync,
+}
+
+#[derive(Subcommand)]
+enum ExifAction {
+    /// Stamp EXIF fields into the JPEGs matching a query, e.g.
+    /// `po exif set --artist "Amy" --copyright "CC-BY" "2025/**"`. Files are rewritten in place, so
+    /// their hashes change, and their names too under hash suffixed naming. Only EXIF is written,
+    /// any XMP packet is left as it was
+    Set {
+        /// Globs matching library paths, files matching any of them are rewritten
+        #[arg(required = true)]
+        queries: Vec<String>,
+        #[arg(long)]
+        artist: Option<String>,
+        #[arg(long)]
+        copyright: Option<String>,
+        /// Print which files would be rewritten without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
-#[derive(Config, Debug)]
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// List every config key with its type, default and accepted values, and the value po is
+    /// running with along with where it was set
+    Docs,
+}
+
+#[derive(Subcommand)]
+enum AnnexAction {
+    /// Print the git-annex key and library path of every file, suitable for `git annex fromkey --batch`
+    Export,
+}
+
+#[derive(Subcommand)]
+enum BackupAction {
+    /// Report library files which are not in the latest snapshot of a backup repository
+    ///
+    /// Exits with an error if anything is not covered.
+    Verify {
+        /// restic repository to check, by its latest snapshot of the library. Files are matched by
+        /// path and size
+        #[arg(long, required_unless_present = "borg", conflicts_with = "borg")]
+        restic: Option<String>,
+
+        /// Host the restic snapshot was taken on, this machine by default
+        #[arg(long, requires = "restic")]
+        host: Option<String>,
+
+        /// borg repository to check. Files are matched by content hash, or by path and size when
+        /// they weren't hashed with SHA256
+        #[arg(long)]
+        borg: Option<String>,
+    },
+}
+
+#[derive(Config, Debug, Serialize)]
+#[serde(crate = "confique::serde")]
 #[config(layer_attr(derive(clap::Args)))]
 struct AppConfig {
-    /// Input paths, not searched recursively
-    #[config(layer_attr(arg(long)))]
-    inputs: Vec<PathBuf>,
+    /// Input paths, only searched recursively with `recursive`. An input can also be a table
+    /// with settings of its own, overriding the ones here for its files, e.g.
+    /// `{ path = "/media/sd", recursive = true, extensions = ["@raw"], sort_policy = "ExifDate" }`.
+    /// Tables can set extensions, recursive, max_depth and sort_policy
+    #[config(env = "PO_INPUTS", parse_env = confique::env::parse::list_by_comma, layer_attr(arg(long)))]
+    inputs: Vec<Input>,
+
+    /// Also import files from subdirectories of the inputs, such as a camera's DCIM/100CANON
+    #[config(env = "PO_RECURSIVE", default = false, layer_attr(arg(long)))]
+    recursive: bool,
+
+    /// How many levels of subdirectories `recursive` goes into, all of them if unset
+    #[config(env = "PO_MAX_DEPTH", layer_attr(arg(long)))]
+    max_depth: Option<usize>,
 
     /// Output root
-    #[config(layer_attr(arg(long)))]
+    #[config(env = "PO_OUTPUT", layer_attr(arg(long)))]
     output: PathBuf,
 
-    /// Extensions to capture within the input paths, in lowercase
-    #[config(layer_attr(arg(long)))]
+    /// Extensions to capture within the input paths. Presets can be used with an @,
+    /// one of @photos, @raw, @video or @all-media. "*" takes any file which looks like
+    /// a photo, video or audio file by its content, whatever its extension
+    #[config(env = "PO_EXTENSIONS", parse_env = confique::env::parse::list_by_comma, default = [], layer_attr(arg(long)))]
     extensions: Vec<String>,
 
-    /// The policy to use when organising files
-    #[config(layer_attr(arg(long)))]
-    sort_policy: SortPolicy
+    /// The same as adding "*" to `extensions`, for when junk is easier to exclude than media to list
+    #[config(env = "PO_ALL_MEDIA", default = false, layer_attr(arg(long, num_args = 0..=1, default_missing_value = "true")))]
+    all_media: bool,
+
+    /// Gitignore-style patterns for files in the inputs which shouldn't be imported. These are
+    /// merged with any `.poignore` file in the library root and in each input
+    #[config(env = "PO_EXCLUDE", parse_env = confique::env::parse::list_by_comma, default = [], layer_attr(arg(long)))]
+    exclude: Vec<String>,
+
+    /// The policy to use when organising files. Once a library has been created this is
+    /// recorded alongside it, and can be left out
+    #[config(env = "PO_SORT_POLICY", layer_attr(arg(long)))]
+    sort_policy: Option<SortPolicy>,
+
+    /// How files are named within the library. Recorded alongside the library like sort_policy,
+    /// use `po naming` to change it for an existing library
+    #[config(env = "PO_NAMING", layer_attr(arg(long)))]
+    naming: Option<NamingScheme>,
+
+    /// How the library index is stored. PerYear splits it by year so commands scoped to a year
+    /// don't load the whole index. Recorded alongside the library, use `po index` to change it
+    #[config(env = "PO_INDEX", layer_attr(arg(long)))]
+    index: Option<IndexLayout>,
+
+    /// How file contents are hashed, sha256 or blake3. Recorded alongside the library like
+    /// sort_policy, and fixed once it's set up
+    #[config(env = "PO_HASH_ALGORITHM", layer_attr(arg(long)))]
+    hash_algorithm: Option<HashAlgorithm>,
+
+    /// Where the Date and ExifDate sort policies put files, instead of `YYYY/M/D/<name>`, e.g.
+    /// "{year}/{year}-{month:02}-{day:02}/{original_name}". Placeholders are year, month, day,
+    /// hour, minute, second, original_name, stem, ext and hash, and can be zero padded like {month:02}
+    #[config(env = "PO_PATH_TEMPLATE", layer_attr(arg(long)))]
+    path_template: Option<String>,
+
+    /// Templates for the recordings of particular devices, instead of `path_template`, by `dji`,
+    /// `gopro` or `insta360`, e.g. `[device_templates]` with `dji = "drone/{year}/{original_name}"`
+    #[config(default = {}, layer_attr(arg(skip)))]
+    device_templates: HashMap<String, String>,
+
+    /// Group runs of at least this many photos shot at a fixed interval, like timelapse frames,
+    /// into a folder of their own. Only used with the Date and ExifDate sort policies
+    #[config(env = "PO_TIMELAPSE_MIN_FRAMES", layer_attr(arg(long)))]
+    timelapse_min_frames: Option<usize>,
+
+    /// What to do with input files once imported. Hold copies them into the library and keeps the
+    /// originals in a dated `imported-originals` folder in their input, see `po clean-sources`.
+    /// Copy leaves the input untouched
+    #[config(env = "PO_SOURCE_RETENTION", default = "Move", layer_attr(arg(long)))]
+    source_retention: SourceRetention,
+
+    /// Rehash every file at its final library path after moving it there, catching corruption
+    /// from flaky drives or enclosures. Slows imports down, as every file is read once more
+    #[config(env = "PO_VERIFY_AFTER_MOVE", default = false, layer_attr(arg(long)))]
+    verify_after_move: bool,
+
+    /// Keep checksum files next to library files, so the library can be checked with `sha256sum -c`
+    /// (`b3sum -c` for blake3) without po. PerDirectory keeps a `SHA256SUMS` in each directory,
+    /// PerFile a `.sha256` next to each file. `po checksums` writes them for files already there
+    #[config(env = "PO_CHECKSUM_FILES", layer_attr(arg(long)))]
+    checksum_files: Option<ChecksumFiles>,
+
+    /// How long ago `po verify` can have last passed a file before `po query --verify-state`
+    /// shows it as due to be verified again, e.g. 90d or 12w
+    #[config(env = "PO_VERIFY_STALE_AFTER", default = "90d", layer_attr(arg(long)))]
+    verify_stale_after: String,
+
+    /// Add a JPEG beside each HEIC imported, for everything which can't open HEICs. Needs
+    /// `heif-convert` from libheif
+    #[config(env = "PO_TRANSCODE_HEIC", default = false, layer_attr(arg(long)))]
+    transcode_heic: bool,
+
+    /// Keep HEICs once they're transcoded. Turn it off to have the JPEG replace the HEIC, which
+    /// is moved to the trash
+    #[config(env = "PO_KEEP_HEIC", default = true, layer_attr(arg(long)))]
+    keep_heic: bool,
+
+    /// Work out a perceptual hash of each photo imported, for `po dedupe --similar`. Slows imports
+    /// down, as every photo is decoded. Photos without one are hashed when dedupe first runs
+    #[config(env = "PO_PERCEPTUAL_HASHES", default = false, layer_attr(arg(long)))]
+    perceptual_hashes: bool,
+
+    /// Leave a `.po-manifest` in each directory files are imported from, listing what was taken,
+    /// when, and where it went in the library
+    #[config(env = "PO_SOURCE_MANIFESTS", default = false, layer_attr(arg(long)))]
+    source_manifests: bool,
+
+    /// Language for po's messages, e.g. "de". Defaults to the language of the system locale
+    #[config(env = "PO_LANGUAGE", layer_attr(arg(long)))]
+    language: Option<String>,
+
+    /// Directory of extra translations, named by language like `de.ftl`. These take precedence
+    /// over the ones built into po
+    #[config(env = "PO_TRANSLATIONS", layer_attr(arg(long)))]
+    translations: Option<PathBuf>,
+
+    /// Picasa's contacts.xml, e.g. `~/AppData/Local/Google/Picasa2/contacts/contacts.xml`, to
+    /// name faces Picasa found in imported photos which their folder's .picasa.ini doesn't
+    #[config(env = "PO_PICASA_CONTACTS", layer_attr(arg(long)))]
+    picasa_contacts: Option<PathBuf>,
+
+    /// How many files to hash at once during imports, by default one per CPU. Setting 1 hashes
+    /// one file at a time, which can be quicker on spinning disks
+    #[config(env = "PO_HASH_WORKERS", layer_attr(arg(long)))]
+    hash_workers: Option<usize>,
+
+    /// Longest side of the previews `po thumbs` makes of each file, in pixels. The first is the
+    /// thumbnail, `_pometa/thumbs/<hash>.jpg`, others are written as `<hash>_<size>.jpg`
+    #[config(env = "PO_THUMB_SIZES", parse_env = confique::env::parse::list_by_comma, default = [320], layer_attr(arg(long)))]
+    thumb_sizes: Vec<u32>,
+
+    /// How many frames from each video `po thumbs` puts side by side in its strip,
+    /// `_pometa/thumbs/<hash>_strip.jpg`. 0 for none. Only used by po built with `--features ffmpeg`
+    #[config(env = "PO_VIDEO_STRIP_FRAMES", default = 5, layer_attr(arg(long)))]
+    video_strip_frames: usize,
+
+    /// Wait for any other po using the library to finish, rather than stopping with an error
+    #[config(env = "PO_WAIT_FOR_LOCK", default = false, layer_attr(arg(long)))]
+    wait_for_lock: bool,
+
+    /// Rough upper bound on memory for large operations, e.g. "512MiB". Limits IO buffers
+    /// and warns when the loaded index takes up much of it
+    #[config(env = "PO_MEMORY_LIMIT", layer_attr(arg(long)))]
+    memory_limit: Option<String>,
+
+    /// How long a file has to go without changing before `po watch` imports it, so files still
+    /// being copied or synced in aren't picked up half written
+    #[config(env = "PO_WATCH_SETTLE_SECS", default = 5, layer_attr(arg(long)))]
+    watch_settle_secs: u64,
+
+    /// What `po watch` does with particular files, the first rule matching a file applying, e.g.
+    /// `{ match = "receipts/**", tags = ["receipt"], path_template = "receipts/{year}/{original_name}" }`.
+    /// Rules can also `skip = true` to leave files in the input
+    #[config(default = [], layer_attr(arg(skip)))]
+    watch_rules: Vec<WatchRule>,
+
+    /// A registry of hashes shared with other libraries, e.g. `~/.local/share/po/registry.db`.
+    /// Imports check new files against the other libraries in it, see `registry_duplicates`
+    #[config(env = "PO_REGISTRY", layer_attr(arg(long)))]
+    registry: Option<PathBuf>,
+
+    /// What this library is called in the registry, defaults to its output path
+    #[config(env = "PO_REGISTRY_NAME", layer_attr(arg(long)))]
+    registry_name: Option<String>,
+
+    /// What imports do with files another library in the registry already has. Flag warns
+    /// about them but imports them anyway, Skip leaves them in the input
+    #[config(env = "PO_REGISTRY_DUPLICATES", default = "Flag", layer_attr(arg(long)))]
+    registry_duplicates: RegistryPolicy,
+
+    /// Name to attribute imports and changes to, defaults to the current OS user
+    #[config(env = "PO_IDENTITY", layer_attr(arg(long)))]
+    identity: Option<String>,
+
+    /// Allow `po self-update` to replace the po binary. Turn off where po is installed by a
+    /// package manager, which should do the updating
+    #[config(env = "PO_SELF_UPDATE", default = true, layer_attr(arg(long)))]
+    self_update: bool,
+
+    /// How long files with a tag are kept before `po prune --policies` deals with them, e.g.
+    /// `[[retention_policies]]` with `tag = "screenshot"`, `older_than = "1y"` and `action = "trash"`.
+    /// `action` can be `trash`, `delete`, which needs `--yes`, or `archive`, which moves files out to `to`
+    #[config(default = [], layer_attr(arg(skip)))]
+    retention_policies: Vec<prune::Policy>,
+
+    /// Named presets for `po export --preset`, e.g. `[export.web]` with `max_dimension = 2048`,
+    /// `format = "jpeg"`, `quality = 82` and `strip_gps = true`
+    #[config(default = {}, layer_attr(arg(skip)))]
+    export: HashMap<String, Preset>,
+
+    /// Settings for `po serve`
+    #[config(nested, layer_attr(command(flatten)))]
+    serve: ServeConfig,
+
+    /// Settings for `po mqtt`
+    #[config(nested, layer_attr(command(flatten)))]
+    mqtt: MqttConfig,
+
+    /// Settings for `po classify`
+    #[config(nested, layer_attr(command(flatten)))]
+    classify: ClassifyConfig,
+
+    /// Settings for `po ocr`
+    #[config(nested, layer_attr(command(flatten)))]
+    ocr: OcrConfig,
+}
+
+/// An input to import from, by its path alone or with settings of its own
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(crate = "confique::serde", untagged)]
+enum Input {
+    Path(PathBuf),
+    Table(InputTable),
+}
+
+/// Settings for the files of one input, each overriding the global one when given
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(crate = "confique::serde", deny_unknown_fields)]
+struct InputTable {
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recursive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort_policy: Option<SortPolicy>,
+}
+
+// For the environment and command line, which only take paths
+impl std::str::FromStr for Input {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Input::Path(PathBuf::from(s)))
+    }
+}
+
+impl Input {
+    fn path(&self) -> &PathBuf {
+        match self {
+            Input::Path(path) => path,
+            Input::Table(table) => &table.path,
+        }
+    }
+
+    fn table(&self) -> Option<&InputTable> {
+        match self {
+            Input::Path(_) => None,
+            Input::Table(table) => Some(table),
+        }
+    }
+}
+
+impl AppConfig {
+    fn input_paths(&self) -> Vec<PathBuf> {
+        self.inputs.iter().map(|i| i.path().clone()).collect()
+    }
+
+    /// The extensions captured from `input`
+    fn extensions_of<'a>(&'a self, input: &'a Input) -> &'a [String] {
+        input.table().and_then(|t| t.extensions.as_deref()).unwrap_or(&self.extensions)
+    }
+
+    fn depth_of(&self, input: &Input) -> Depth {
+        let table = input.table();
+        Depth {
+            recursive: table.and_then(|t| t.recursive).unwrap_or(self.recursive),
+            max_depth: table.and_then(|t| t.max_depth).or(self.max_depth),
+        }
+    }
+
+    /// The sort policy for files from `path`, if the input it's in overrides the library's
+    fn sort_policy_for(&self, path: &Path) -> Option<SortPolicy> {
+        self.inputs
+            .iter()
+            // Watched files come with the input's canonical path
+            .filter(|i| path.starts_with(i.path()) || fs::canonicalize(i.path()).is_ok_and(|p| path.starts_with(p)))
+            .find_map(|i| i.table().and_then(|t| t.sort_policy.clone()))
+    }
+
+    fn registry_options(&self) -> Option<RegistryOptions> {
+        self.registry.as_ref().map(|path| RegistryOptions {
+            path: path.clone(),
+            name: self.registry_name.clone().unwrap_or_else(|| {
+                fs::canonicalize(&self.output).unwrap_or_else(|_| self.output.clone()).display().to_string()
+            }),
+            duplicates: self.registry_duplicates,
+        })
+    }
+
+    fn import_options(&self) -> ImportOptions {
+        ImportOptions {
+            timelapse_min_frames: self.timelapse_min_frames,
+            verify_after_move: self.verify_after_move,
+            source_retention: self.source_retention,
+            source_manifests: self.source_manifests,
+            perceptual_hashes: self.perceptual_hashes,
+            hash_workers: self.hash_workers,
+            clock: Clock::Filesystem,
+            progress: false,
+            registry: self.registry_options(),
+            picasa_contacts: self.picasa_contacts.clone(),
+            classify: self.classify.on_import.then(|| self.classify.clone()),
+            ocr: self.ocr.on_import.then(|| self.ocr.clone()),
+            roll: None,
+            transcode_heic: self.transcode_heic,
+            keep_heic: self.keep_heic,
+            path_template: None,
+            device_templates: self.device_templates.clone(),
+        }
+    }
 }
 
 fn init_logging() -> Result<()> {
@@ -82,6 +1046,7 @@ fn init_logging() -> Result<()> {
         .with_target(false)
         .with_thread_names(false)
         .with_timer(timer)
+        .with_writer(|| progress::LogWriter)
         .compact();
 
     let filter_layer = EnvFilter::try_from_default_env()
@@ -109,101 +1074,2095 @@ fn ensure_directory(path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-#[instrument]
-fn search_input_path(input: &PathBuf, extensions: &[String]) -> Result<Vec<PathBuf>> {
+/// How far into an input's subdirectories to look for files
+#[derive(Debug, Clone, Copy)]
+struct Depth {
+    recursive: bool,
+    max_depth: Option<usize>,
+}
+
+impl Depth {
+    /// Whether files `dirs` subdirectories into an input are looked at
+    fn reaches(self, dirs: usize) -> bool {
+        dirs == 0 || (self.recursive && self.max_depth.is_none_or(|max| dirs <= max))
+    }
+}
+
+#[instrument(skip(ignores))]
+fn search_input_path(input: &PathBuf, extensions: &[String], ignores: &Gitignore, depth: Depth) -> Result<Vec<InputFile>> {
     info!("searching input");
+    let wildcard = extensions::has_wildcard(extensions);
 
     let mut captured = vec![];
-    
-    let paths = fs::read_dir(input)?;
-    for path in paths {
-        let p = path?.path();
-        let span = debug_span!("file_filter", file = p.to_str());
-        let _enter = span.enter();
-        
-        let ext = p
-            .extension()
-            .map(|e|
-                 e.to_string_lossy()
-                 .to_string()
-                 .to_lowercase()
-            );
-        
-        if let Some(ext) = ext {
-            if extensions.contains(&ext) {
-                debug!("capturing file");
-                captured.push(p);
-            } else {
+    let mut pending = vec![(input.clone(), 0)];
+    while let Some((dir, level)) = pending.pop() {
+        for path in fs::read_dir(&dir)? {
+            let entry = path?;
+            let p = entry.path();
+            let span = debug_span!("file_filter", file = p.to_str());
+            let _enter = span.enter();
+
+            // Doesn't follow links, so linked directories can't send the walk round in circles
+            if entry.file_type()?.is_dir() {
+                let descend = depth.recursive && depth.max_depth.is_none_or(|max| level < max);
+                if !descend {
+                    continue;
+                }
+
+                if p.file_name().is_some_and(|n| n == retention::HOLDING_DIR) {
+                    debug!("holding area, not searching it");
+                } else if ignores.matched(&p, true).is_ignore() {
+                    debug!("directory ignored by pattern");
+                } else {
+                    pending.push((p, level + 1));
+                }
+                continue;
+            }
+
+            let listed = extensions::is_listed(extensions, &p);
+            if !listed && !wildcard {
                 debug!("ignoring file");
+                continue;
             }
-        } else {
-            debug!("no extension for file");
+
+            if ignores.matched_path_or_any_parents(&p, false).is_ignore() {
+                debug!("ignored by pattern");
+                continue;
+            }
+
+            // Only stat files we're interested in, and only once
+            let file = InputFile::from_entry(&entry)?;
+            if file.metadata.as_ref().is_some_and(|m| !m.is_file()) {
+                debug!("not a file");
+                continue;
+            }
+
+            // Sniffing reads the file, so only do it for files the extensions didn't already take
+            if !listed && (file.metadata.is_none() || !extensions::is_media(&p)) {
+                debug!("not a photo, video or audio file");
+                continue;
+            }
+
+            debug!("capturing file");
+            captured.push(file);
         }
     }
-    
+
     debug!("captured {} files", captured.len());
     Ok(captured)
 }
 
-fn do_import(library: &mut Library, config: AppConfig) -> Result<()> {
+fn capture_inputs(config: &AppConfig) -> Result<Vec<InputFile>> {
     let mut captured = vec![];
     for input in &config.inputs {
-        captured.extend(search_input_path(input, &config.extensions)?);
+        let ignores = ignores::for_input(input.path(), &config.output, &config.exclude)?;
+        captured.extend(search_input_path(input.path(), config.extensions_of(input), &ignores, config.depth_of(input))?);
     }
 
     info!("captured {} files from {} inputs", captured.len(), config.inputs.len());
-    let new_files = library.process_inputs(&captured)?;
-    
-    info!("got {} new files: {:#?}", new_files.len(), new_files);
-    library.sort_files(new_files, config.sort_policy.clone())?;
+    Ok(captured)
+}
+
+fn do_import(library: &mut Library, config: &AppConfig, roll: Option<String>, roll_details: Roll, json: bool) -> Result<()> {
+    check_roll(roll.as_deref(), &roll_details)?;
+    if let Some(roll) = &roll {
+        rolls::update(library, roll, roll_details)?;
+    }
 
+    let captured = capture_inputs(config)?;
+    let options = ImportOptions { progress: true, roll, ..config.import_options() };
+    import_files(library, config, captured, &options, json)?;
     Ok(())
 }
 
-fn do_query(library: &mut Library, query: String) {
-    for file in library.files() {
-        let fname = file.path_in_library.to_string_lossy().to_string();
-        let matches = glob_match(&query, &fname);
-        
-        if matches {
-            eprintln!("{} {}", file.hash.encode(), fname);
+/// Fails if the roll can't be imported as, or details of a roll are given without one
+fn check_roll(roll: Option<&str>, details: &Roll) -> Result<()> {
+    match roll {
+        Some(roll) => rolls::check_name(roll),
+        None if !details.is_empty() => Err(eyre!("--film-stock, --camera and --developed describe a roll, pass --roll to name it")),
+        None => Ok(()),
+    }
+}
+
+/// Work out where each of `new_files` belongs, by the sort policy of the input it came from,
+/// or else `sort_policy`
+fn plan_import(
+    library: &mut Library,
+    config: &AppConfig,
+    new_files: Vec<UnsortedFile>,
+    sort_policy: SortPolicy,
+    options: &ImportOptions,
+) -> Result<Vec<PlannedFile>> {
+    let mut by_policy: Vec<(SortPolicy, Vec<UnsortedFile>)> = vec![];
+    for file in new_files {
+        let mut policy = config.sort_policy_for(&file.path).unwrap_or_else(|| sort_policy.clone());
+        // A watch rule's template sorts by date, whatever the library does
+        if options.path_template.is_some() && policy == SortPolicy::MoveToRoot {
+            policy = SortPolicy::ExifDate;
+        }
+        match by_policy.iter_mut().find(|(p, _)| *p == policy) {
+            Some((_, files)) => files.push(file),
+            None => by_policy.push((policy, vec![file])),
         }
     }
+
+    let mut plan = vec![];
+    for (policy, files) in by_policy {
+        debug!("planning {} files with {:?}", files.len(), policy);
+        plan.extend(library.plan_sort(files, policy, options)?);
+    }
+    // Each policy's plan is only checked against itself
+    library.deconflict(&mut plan)?;
+    Ok(plan)
 }
 
-fn main() -> Result<()> {
-    init_logging()?;
-    let cli = Cli::parse();
-    
-    info!("starting up!");
-    let config = AppConfig::builder()
-        .preloaded(cli.cli_config)
-        .file(cli.config)
-        .load()
-        .wrap_err("failed to load app config")?;
+/// Report what an import would do. Only reads the inputs, to hash them
+fn do_dry_run(library: &mut Library, config: &AppConfig, roll: Option<String>, roll_details: &Roll) -> Result<()> {
+    check_roll(roll.as_deref(), roll_details)?;
+    let captured = capture_inputs(config)?;
+    let options = ImportOptions { roll, ..config.import_options() };
+    let scan = library.process_inputs(captured, &options)?;
 
-    info!("config loaded: {:#?}", config);
+    for (path, skip) in &scan.skipped {
+        let path = path.display().to_string();
+        let message = match skip {
+            Skip::Temporary => t!("dry-run-temporary", path = path),
+            Skip::InsideLibrary => t!("dry-run-inside-library", path = path),
+            Skip::DanglingLink => t!("dry-run-dangling-link", path = path),
+            Skip::InLibrary(existing) => t!("dry-run-in-library", path = path, existing = existing.display().to_string()),
+            Skip::DuplicateInput(other) => t!("dry-run-duplicate-input", path = path, other = other.display().to_string()),
+            Skip::InOtherLibrary { library, path: existing } => t!(
+                "dry-run-in-other-library",
+                path = path,
+                library = library.as_str(),
+                existing = existing.display().to_string()
+            ),
+        };
+        eprintln!("{message}");
+    }
 
-    let mut library = Library::read_from_disk(config.output.clone())?;
-    debug!("loaded library: {:#?}", library);
+    let skipped = scan.skipped.len();
+    let sort_policy = library.settings().sort_policy.clone();
+    let plan = plan_import(library, config, scan.new, sort_policy, &options)?;
+    for planned in &plan {
+        eprintln!(
+            "{}",
+            t!(
+                "dry-run-new",
+                path = planned.file.path.display().to_string(),
+                destination = planned.path_in_library.display().to_string()
+            )
+        );
+    }
 
-    for input in &config.inputs {
-        ensure_directory(input)?;
+    eprintln!("{}", t!("dry-run-summary", new = plan.len(), skipped = skipped));
+    Ok(())
+}
+
+/// Replay an import against a model of the library, reporting where files end up and which would
+/// land on a path already holding something else
+fn do_simulate(
+    library: &mut Library,
+    config: &AppConfig,
+    sort_policy: Option<SortPolicy>,
+    naming: Option<NamingScheme>,
+    clock: Option<&str>,
+) -> Result<()> {
+    let mut options = config.import_options();
+    if let Some(clock) = clock {
+        options.clock = Clock::Fixed(simulate::parse_clock(clock)?);
+    }
+    if let Some(naming) = naming {
+        library.preview_naming(naming);
+    }
+
+    let captured = capture_inputs(config)?;
+    let scan = library.process_inputs(captured, &options)?;
+    // One given here is tried on every file, whichever input it's from
+    let plan = match sort_policy {
+        Some(sort_policy) => library.plan_sort(scan.new, sort_policy, &options)?,
+        None => {
+            let sort_policy = library.settings().sort_policy.clone();
+            plan_import(library, config, scan.new, sort_policy, &options)?
+        }
+    };
+
+    let mut fs = VirtualFs::from_library(library);
+    let mut changed = HashMap::new();
+    let mut collisions = 0;
+    for planned in &plan {
+        let (path, destination) = (planned.file.path.display().to_string(), planned.path_in_library.display().to_string());
+        match fs.place(&planned.path_in_library, &planned.file.hash) {
+            Placement::Placed => eprintln!("{}", t!("dry-run-new", path = path, destination = destination)),
+            Placement::Replaces(_) => {
+                collisions += 1;
+                eprintln!("{}", t!("simulate-collision", path = path, destination = destination));
+            }
+        }
+
+        let dir = planned.path_in_library.parent().unwrap_or(Path::new("")).to_path_buf();
+        *changed.entry(dir).or_insert(0) += 1;
+    }
+
+    for (dir, files) in fs.directories() {
+        if let Some(new) = changed.get(dir) {
+            eprintln!("{}", t!("simulate-directory", dir = dir.display().to_string(), files = files, new = *new));
+        }
     }
+
+    eprintln!(
+        "{}",
+        t!("simulate-summary", new = plan.len(), directories = changed.len(), skipped = scan.skipped.len(), collisions = collisions)
+    );
+    Ok(())
+}
+
+/// Import `captured` into the library, returning the hashes of the files imported
+fn import_files(
+    library: &mut Library,
+    config: &AppConfig,
+    captured: Vec<InputFile>,
+    options: &ImportOptions,
+    json: bool,
+) -> Result<Vec<FileHash>> {
+    let new_files = library.process_inputs(captured, options)?.new;
     
-    ensure_directory(&config.output)?;
+    let paths: Vec<_> = new_files.iter().map(|f| &f.path).collect();
+    info!("got {} new files: {:#?}", new_files.len(), paths);
+    let found = new_files.len();
+    let sources: HashMap<_, _> = new_files.iter().map(|f| (f.hash.clone(), f.path.clone())).collect();
+    let sort_policy = library.settings().sort_policy.clone();
+    let plan = plan_import(library, config, new_files, sort_policy, options)?;
+    let mut hashes = library.execute_sort(plan, options)?;
 
-    match cli.action {
-        Some(act) => match act {
-            Action::Import => {
-                do_import(&mut library, config)?
+    if !hashes.is_empty() {
+        audit::record(library, "import", &hashes)?;
+    }
+
+    if options.source_manifests {
+        manifest::record(library, hashes.iter().map(|h| (sources[h].as_path(), h)))?;
+    }
+
+    // The HEICs are in the library either way, so they can be transcoded later
+    if options.transcode_heic {
+        match heic::transcode(library, &hashes, options.keep_heic) {
+            Ok(report) => {
+                if !report.added.is_empty() {
+                    audit::record(library, "transcode", &report.added)?;
+                }
+                if !report.replaced.is_empty() {
+                    audit::record(library, "remove --trash", &report.replaced)?;
+                }
+                hashes.retain(|h| !report.replaced.contains(h));
+                hashes.extend(report.added);
+            }
+            Err(e) => warn!("could not transcode the imported HEICs: {e:#}"),
+        }
+    }
+
+    if options.perceptual_hashes {
+        similar::record(library, &hashes)?;
+    }
+
+    // A missing model shouldn't lose the import, the photos can be classified later
+    if let Some(config) = &options.classify {
+        match classify::classify(library, config, &hashes) {
+            Ok(report) if !report.changed.is_empty() => audit::record(library, "classify", &report.changed)?,
+            Ok(_) => {}
+            Err(e) => warn!("could not classify the imported photos: {e:#}"),
+        }
+    }
+
+    if let Some(config) = &options.ocr {
+        match ocr::ocr(library, config, &hashes, ocr::Select::Documents) {
+            Ok(report) if !report.changed.is_empty() => audit::record(library, "ocr", &report.changed)?,
+            Ok(_) => {}
+            Err(e) => warn!("could not read the text in the imported photos: {e:#}"),
+        }
+    }
+
+    hashing::report(library.settings().hash_algorithm);
+
+    if shutdown::requested() {
+        warn!("{}", t!("import-interrupted", imported = hashes.len(), found = found));
+    }
+
+    if json {
+        let imported: HashSet<_> = hashes.iter().collect();
+        schema::print(&ImportReport {
+            version: schema::VERSION,
+            new: found,
+            imported: library
+                .files()
+                .iter()
+                .filter(|f| imported.contains(&f.hash))
+                .map(FileRecord::from)
+                .collect(),
+            interrupted: shutdown::requested(),
+        })?;
+    }
+
+    Ok(hashes)
+}
+
+/// Run `work` while answering other po commands on the daemon socket, sharing `library` with it
+/// so they can ask the daemon instead of reading the library
+fn with_daemon(library: &mut Library, config: &AppConfig, work: impl FnOnce(&Mutex<&mut Library>, &Tasks) -> Result<()>) -> Result<()> {
+    let library = Mutex::new(library);
+    let tasks = Tasks::default();
+    let stop = AtomicBool::new(false);
+    #[cfg(unix)]
+    let server = daemon::Server::bind(&config.output)?;
+
+    std::thread::scope(|scope| {
+        #[cfg(unix)]
+        let listener = scope.spawn(|| {
+            let mut cache = ResponseCache::default();
+            let mut indexes = QueryIndexes::default();
+            server.serve(&stop, |request| match request {
+                // Without the library, so it's answered while an import holds it
+                Request::Tasks => Response::Tasks(tasks.list()),
+                request => {
+                    let mut library = library.lock().expect("library lock not to be poisoned");
+                    handle_request(&mut library, &mut cache, &mut indexes, request)
+                }
+            })
+        });
+
+        let result = work(&library, &tasks);
+
+        stop.store(true, Ordering::Relaxed);
+        #[cfg(unix)]
+        listener.join().expect("daemon listener not to panic")?;
+        result
+    })
+}
+
+/// Whether files with `path`'s extension, or sniffed content, are captured by `extensions`
+fn is_captured(extensions: &[String], path: &Path) -> bool {
+    extensions::is_listed(extensions, path) || (extensions::has_wildcard(extensions) && extensions::is_media(path))
+}
+
+fn do_mqtt(library: &mut Library, config: &AppConfig, json: bool) -> Result<()> {
+    let inputs = config
+        .input_paths()
+        .iter()
+        .map(|i| fs::canonicalize(i).wrap_err(format!("when resolving input {}", i.display())))
+        .collect::<Result<Vec<_>>>()?;
+
+    with_daemon(library, config, |library, tasks| {
+        let _following = tasks.start(format!("following mqtt topic {}", config.mqtt.topic));
+        mqtt::run(&config.mqtt, |path, tags| {
+            // Anyone who can publish to the topic could otherwise have po move any file it can read.
+            // What's imported is the path checked, so a symlink swapped in afterwards can't escape
+            let resolved = fs::canonicalize(&path).wrap_err(format!("when resolving {}", path.display()))?;
+            if !inputs.iter().any(|input| resolved.starts_with(input)) {
+                return Err(eyre!("{} isn't in any of the inputs, add the directory it's in to `inputs` to import it", path.display()));
+            }
+
+            if !is_captured(&config.extensions, &resolved) {
+                debug!("ignoring {} as its extension is not captured", resolved.display());
+                return Ok(());
+            }
+
+            let _importing = tasks.start(format!("importing {}", resolved.display()));
+            let mut library = library.lock().expect("library lock not to be poisoned");
+            let file = InputFile::from_path(resolved)?;
+            let imported = import_files(&mut library, config, vec![file], &config.import_options(), json)?;
+            if !tags.is_empty() {
+                tag_files(&mut library, &imported, &tags)?;
+            }
+            library.persist_to_disk()
+        })
+    })
+}
+
+/// Add `tags` to the files with `hashes`
+fn tag_files(library: &mut Library, hashes: &[FileHash], tags: &[String]) -> Result<()> {
+    let paths: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| hashes.contains(&f.hash))
+        .map(|f| f.path_in_library.clone())
+        .collect();
+
+    let mut tagged = vec![];
+    for path in paths {
+        tagged.push(library.retag(&path, tags, &[])?.hash.clone());
+    }
+
+    audit::record(library, &format!("tag add {}", tags.join(" ")), &tagged)
+}
+
+fn do_watch(library: &mut Library, config: &AppConfig, json: bool) -> Result<()> {
+    watch::check_rules(&config.watch_rules)?;
+    with_daemon(library, config, |library, tasks| watch_inputs(library, tasks, config, json, |_| &[]))
+}
+
+/// Serve uploads while importing them, and anything else arriving in the inputs, as `po watch` does
+fn do_serve(library: &mut Library, config: &AppConfig, json: bool) -> Result<()> {
+    watch::check_rules(&config.watch_rules)?;
+    let staging = serve::prepare(&config.serve)?;
+    let staged = config.inputs.iter().find(|i| fs::canonicalize(i.path()).is_ok_and(|p| p == staging));
+    match staged {
+        Some(input) if !config.depth_of(input).reaches(1) => {
+            return Err(eyre!(
+                "uploads are staged in a directory per token, so the input for serve.staging has to be \
+                 searched one directory deep, e.g. `{{ path = {:?}, recursive = true }}`",
+                input.path()
+            ));
+        }
+        Some(_) => {}
+        None => warn!("serve.staging isn't one of the inputs, so uploads won't be imported"),
+    }
+
+    with_daemon(library, config, |library, tasks| {
+        let stop = AtomicBool::new(false);
+        let _serving = tasks.start(format!("serving uploads on {}", config.serve.listen));
+        std::thread::scope(|scope| {
+            let server = scope.spawn(|| serve::run(&config.serve, &staging, library, &stop));
+            let result = watch_inputs(library, tasks, config, json, |path| serve::tags_for(&config.serve, &staging, path));
+
+            stop.store(true, Ordering::Relaxed);
+            server.join().expect("server not to panic")?;
+            result
+        })
+    })
+}
+
+/// The watch rule and extra tags files are imported with
+type Batch<'a> = (Option<&'a WatchRule>, &'a [String]);
+
+/// Import files from the inputs as they settle until Ctrl-C is pressed, tagging them with what
+/// `extra_tags` gives for their path as well as their watch rule's tags
+fn watch_inputs<'a>(
+    library: &Mutex<&mut Library>,
+    tasks: &Tasks,
+    config: &'a AppConfig,
+    json: bool,
+    extra_tags: impl Fn(&Path) -> &'a [String],
+) -> Result<()> {
+    let inputs = config
+        .inputs
+        .iter()
+        .map(|i| fs::canonicalize(i.path()).wrap_err(format!("when resolving input {}", i.path().display())))
+        .collect::<Result<Vec<_>>>()?;
+    let ignores = inputs
+        .iter()
+        .map(|i| ignores::for_input(i, &config.output, &config.exclude))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The same files a `po import` would take
+    let wanted = |path: &Path| {
+        let found = inputs.iter().zip(&ignores).zip(&config.inputs).find(|((i, _), _)| path.starts_with(i));
+        let Some(((input, ignores), settings)) = found else {
+            return false;
+        };
+        let dirs = path.strip_prefix(input).map_or(0, |p| p.components().count().saturating_sub(1));
+        let name = path.file_name().unwrap_or_default();
+
+        !library::is_temporary_file(path)
+            && name != ignores::IGNORE_FILE
+            && name != manifest::MANIFEST_FILE
+            && !path.components().any(|c| c.as_os_str() == retention::HOLDING_DIR)
+            && config.depth_of(settings).reaches(dirs)
+            && !ignores.matched_path_or_any_parents(path, false).is_ignore()
+            && is_captured(config.extensions_of(settings), path)
+    };
+
+    let watched: Vec<_> = inputs.iter().zip(&config.inputs).map(|(path, i)| (path.clone(), config.depth_of(i).recursive)).collect();
+    let _watching = tasks.start("watching the inputs");
+    watch::run(&watched, Duration::from_secs(config.watch_settle_secs), |paths| {
+        // Files are imported a rule at a time, each with what its rule asks for
+        let mut by_rule: Vec<(Batch, Vec<InputFile>)> = vec![];
+        for path in paths.into_iter().filter(|p| wanted(p)) {
+            let input = inputs.iter().find(|i| path.starts_with(i)).expect("wanted files to be in an input");
+            let rule = watch::rule_for(&config.watch_rules, input, &path);
+            if rule.is_some_and(|r| r.skip) {
+                info!("leaving {} in the input, as a watch rule skips it", path.display());
+                continue;
+            }
+
+            let key = (rule, extra_tags(&path));
+            let file = InputFile::from_path(path)?;
+            let same = |(r, t): &Batch| r.map(std::ptr::from_ref) == key.0.map(std::ptr::from_ref) && *t == key.1;
+            match by_rule.iter_mut().find(|(k, _)| same(k)) {
+                Some((_, files)) => files.push(file),
+                None => by_rule.push((key, vec![file])),
+            }
+        }
+        if by_rule.is_empty() {
+            return Ok(());
+        }
+
+        let count: usize = by_rule.iter().map(|(_, files)| files.len()).sum();
+        let _importing = tasks.start(format!("importing {count} files"));
+        let mut library = library.lock().expect("library lock not to be poisoned");
+        for ((rule, extra), files) in by_rule {
+            let options = ImportOptions { path_template: rule.and_then(|r| r.path_template.clone()), ..config.import_options() };
+            let imported = import_files(&mut library, config, files, &options, json)?;
+            let tags: Vec<_> = rule.map_or(&[][..], |r| &r.tags).iter().chain(extra).cloned().collect();
+            if !tags.is_empty() {
+                tag_files(&mut library, &imported, &tags)?;
+            }
+            // Each import's journal has to be committed before the next can begin
+            library.persist_to_disk()?;
+        }
+        Ok(())
+    })
+}
+
+/// Files matching any of `queries` and none of `excludes`
+fn matching_files<'a>(library: &'a Library, queries: &[String], excludes: &[String]) -> Vec<&'a LibraryFile> {
+    library
+        .files()
+        .iter()
+        .filter(|f| {
+            let path = f.path_in_library.to_string_lossy();
+            queries.iter().any(|q| glob_match(q, path.as_ref())) && !excludes.iter().any(|e| glob_match(e, path.as_ref()))
+        })
+        .collect()
+}
+
+/// The files matching a query. Their last verification is only read with `verify_state`, and
+/// the audit log and filesystem only for files indexed before po kept their import time and size,
+/// or which `indexes` don't have
+fn query_output(
+    library: &Library,
+    queries: &[String],
+    excludes: &[String],
+    filters: &QueryFilters,
+    groups: bool,
+    verify_state: bool,
+    indexes: Option<&QueryIndexes>,
+) -> Result<QueryOutput> {
+    let mut files = filters.apply(library, matching_files(library, queries, excludes), indexes)?;
+    if groups {
+        let matched: HashSet<_> = files.iter().map(|f| &f.path_in_library).collect();
+        let wanted: HashSet<_> = files.iter().filter_map(|f| f.group.as_ref()).collect();
+        files = library
+            .files()
+            .iter()
+            .filter(|f| matched.contains(&f.path_in_library) || f.group.as_ref().is_some_and(|g| wanted.contains(g)))
+            .collect();
+    }
+    let import_times = if files.iter().any(|f| f.imported_at.is_none()) { audit::import_times(library)? } else { HashMap::new() };
+    let last_verified = if verify_state { verify::last_verified(library)? } else { HashMap::new() };
+
+    Ok(QueryOutput {
+        version: schema::VERSION,
+        files: files
+            .into_iter()
+            .map(|f| QueryRecord {
+                file: FileRecord::from(f),
+                size: f.size.or_else(|| fs::metadata(library.absolute_path(f)).ok().map(|m| m.len())),
+                imported_at: f.imported_at.clone().or_else(|| import_times.get(&f.hash.encode()).cloned()),
+                last_verified: last_verified.get(&f.path_in_library).map(|l| l.check.clone()),
+            })
+            .collect(),
+    })
+}
+
+/// Prints whether each file was verified when `verify_state` is given, counting passes longer
+/// ago than it as stale
+fn print_query(output: &QueryOutput, json: bool, verify_state: Option<time::Duration>) -> Result<()> {
+    if json {
+        return schema::print(output);
+    }
+
+    for record in &output.files {
+        let Some(stale_after) = verify_state else {
+            eprintln!("{} {}", record.file.hash, record.file.path);
+            continue;
+        };
+
+        let last = record.last_verified.as_ref();
+        let when = last.map(|l| l.time.get(..10).unwrap_or(&l.time).to_string()).unwrap_or_default();
+        let state = match verify::state(last, stale_after) {
+            VerifyState::Recent => t!("query-verified", date = when),
+            VerifyState::Stale => t!("query-verified-stale", date = when),
+            VerifyState::Never => t!("query-never-verified"),
+            VerifyState::Failed => t!("query-verify-failed", date = when),
+        };
+        eprintln!("{} {}  {state}", record.file.hash, record.file.path);
+    }
+
+    Ok(())
+}
+
+/// Every file in `paths`, walking into directories, leaving out po's own files
+fn files_within(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut pending: Vec<PathBuf> = paths.iter().rev().cloned().collect();
+    while let Some(path) = pending.pop() {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+
+        let mut entries = fs::read_dir(&path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries.into_iter().rev() {
+            let name = entry.file_name().unwrap_or_default();
+            if name == ignores::IGNORE_FILE || name == manifest::MANIFEST_FILE || checksums::is_checksum_file(name) {
+                continue;
+            }
+            pending.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+fn do_contains(library: &mut Library, paths: &[PathBuf], json: bool) -> Result<()> {
+    let mut checked = vec![];
+    for path in files_within(paths)? {
+        let hash = FileHash::from_file(&path, library.settings().hash_algorithm).wrap_err(format!("when hashing {}", path.display()))?;
+        let library_paths: Vec<_> = library
+            .files_with_hash(&hash)?
+            .iter()
+            .map(|f| f.path_in_library.to_string_lossy().to_string())
+            .collect();
+
+        checked.push(ContainedFile {
+            path: path.to_string_lossy().to_string(),
+            hash: hash.encode(),
+            library_paths,
+        });
+    }
+
+    let missing = checked.iter().filter(|f| f.library_paths.is_empty()).count();
+    let total = checked.len();
+    if json {
+        schema::print(&ContainsOutput { version: schema::VERSION, files: checked })?;
+    } else {
+        for file in &checked {
+            match file.library_paths.as_slice() {
+                [] => eprintln!("{}", t!("contains-missing", path = file.path.as_str())),
+                found => eprintln!("{}", t!("contains-found", path = file.path.as_str(), library_path = found.join(", "))),
+            }
+        }
+    }
+
+    if missing > 0 {
+        return Err(eyre!("{}", t!("contains-summary-missing", missing = missing, total = total)));
+    }
+
+    info!("{}", t!("contains-summary-complete", total = total));
+    Ok(())
+}
+
+fn do_diff(library: &Library, dir: &Path, within: Option<&Path>) -> Result<()> {
+    let library_files: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| within.is_none_or(|w| f.path_in_library.starts_with(w)))
+        .collect();
+    let mut by_hash: HashMap<&FileHash, Vec<&LibraryFile>> = HashMap::new();
+    for file in &library_files {
+        by_hash.entry(&file.hash).or_default().push(file);
+    }
+
+    let mut seen = HashSet::new();
+    let (mut only_dir, mut both) = (0, 0);
+    for path in files_within(&[dir.to_path_buf()])? {
+        let hash = FileHash::from_file(&path, library.settings().hash_algorithm).wrap_err(format!("when hashing {}", path.display()))?;
+        let shown = path.strip_prefix(dir).unwrap_or(&path).display();
+        match by_hash.get(&hash) {
+            Some(matches) => {
+                both += 1;
+                seen.insert(hash.clone());
+                for file in matches {
+                    eprintln!("= {} -> {}", shown, file.path_in_library.display());
+                }
+            }
+            None => {
+                only_dir += 1;
+                eprintln!("< {shown}");
+            }
+        }
+    }
+
+    let mut only_library = 0;
+    for file in library_files.iter().filter(|f| !seen.contains(&f.hash)) {
+        only_library += 1;
+        eprintln!("> {}", file.path_in_library.display());
+    }
+
+    info!("{}", t!("diff-summary", only_dir = only_dir, only_library = only_library, both = both));
+    Ok(())
+}
+
+fn show_output(library: &Library, path: &Path) -> Result<ShowOutput> {
+    let file = library
+        .files()
+        .iter()
+        .find(|f| f.path_in_library == path)
+        .ok_or_else(|| eyre!("{} is not in the library", path.display()))?;
+
+    let frames = file
+        .sequence
+        .as_ref()
+        .map(|sequence| library.files().iter().filter(|f| f.sequence.as_ref() == Some(sequence)).count());
+    let group_files = file
+        .group
+        .as_ref()
+        .map(|group| library.files().iter().filter(|f| f.group.as_ref() == Some(group)).count());
+
+    let derivatives = Derivatives::load(library)?
+        .of(&file.hash)
+        .into_iter()
+        .map(|d| DerivativeRecord {
+            hash: d.hash.clone(),
+            path: d.path.to_string_lossy().to_string(),
+            preset: d.preset.clone(),
+            rendered_at: d.time.clone(),
+        })
+        .collect();
+
+    Ok(ShowOutput {
+        version: schema::VERSION,
+        file: FileRecord::from(file),
+        sequence_frames: frames,
+        group_files,
+        derivatives,
+    })
+}
+
+fn print_show(output: &ShowOutput, json: bool) -> Result<()> {
+    if json {
+        return schema::print(output);
+    }
+
+    let file = &output.file;
+    eprintln!("{}", t!("show-path", path = file.path.as_str()));
+    eprintln!("{}", t!("show-hash", hash = file.hash.as_str()));
+    eprintln!("{}", t!("show-imported-by", user = file.imported_by.clone().unwrap_or_else(|| t!("unknown"))));
+    if let (Some(sequence), Some(frames)) = (&file.sequence, output.sequence_frames) {
+        eprintln!("{}", t!("show-sequence", sequence = sequence.as_str(), frames = frames));
+    }
+    if let (Some(group), Some(files)) = (&file.group, output.group_files) {
+        eprintln!("{}", t!("show-group", group = group.as_str(), files = files));
+    }
+    if !file.tags.is_empty() {
+        eprintln!("{}", t!("show-tags", tags = file.tags.join(", ")));
+    }
+    if !file.people.is_empty() {
+        eprintln!("{}", t!("show-people", people = file.people.join(", ")));
+    }
+    if file.prints > 0 {
+        eprintln!("{}", t!("show-prints", count = file.prints));
+    }
+    if let Some(duration) = file.duration {
+        eprintln!("{}", t!("show-duration", seconds = format!("{duration:.1}")));
+    }
+    if let Some(text) = &file.text {
+        eprintln!("{}", t!("show-text", text = text.as_str()));
+    }
+    for derivative in &output.derivatives {
+        eprintln!("{}", t!("show-derived", path = derivative.path.as_str(), preset = derivative.preset.as_str()));
+    }
+
+    Ok(())
+}
+
+/// Answer a command sent to the daemon from another po, from `cache` when the library hasn't changed.
+/// Queries narrow down the files by `indexes`, brought up to date first
+fn handle_request(library: &mut Library, cache: &mut ResponseCache, indexes: &mut QueryIndexes, request: Request) -> Response {
+    // The daemon may have started out loading only part of a per-year index
+    if let Err(e) = library.load_remaining_index() {
+        return Response::Error(format!("could not load the index: {e}"));
+    }
+
+    cache.get_or_answer(request, library.generation(), |request| match request {
+        Request::Query { queries, not, filters, groups, verify_state } => {
+            let updated = indexes.update(library, &filters);
+            match updated.and_then(|()| query_output(library, &queries, &not, &filters, groups, verify_state, Some(indexes))) {
+                Ok(output) => Response::Query(output),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Request::Show { path } => match show_output(library, &path) {
+            Ok(output) => Response::Show(Box::new(output)),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Request::Tag { path, add, remove } => {
+            let tagged = tag_output(library, path.as_deref(), &add, &remove);
+            match tagged.and_then(|output| library.persist_to_disk().map(|()| output)) {
+                Ok(output) => Response::Tag(output),
+                Err(e) => Response::Error(e.to_string()),
+            }
+        }
+        Request::Tasks => unreachable!("tasks to be answered without locking the library"),
+    })
+}
+
+fn print_response(response: Response, json: bool, verify_state: Option<time::Duration>) -> Result<()> {
+    match response {
+        Response::Query(output) => print_query(&output, json, verify_state),
+        Response::Show(output) => print_show(&output, json),
+        Response::Tasks(tasks) => {
+            print_tasks(&tasks);
+            Ok(())
+        }
+        Response::Tag(output) => {
+            print_tags(&output);
+            Ok(())
+        }
+        Response::Error(e) => Err(eyre!("{e}")),
+    }
+}
+
+fn do_inspect(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        let info = metadata::read_capture_info(path)
+            .wrap_err(format!("when reading metadata from {}", path.display()))?;
+
+        let taken = match info.taken {
+            Some(taken) => taken.format(format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"))?,
+            None => t!("unknown"),
+        };
+
+        let camera = info.camera().unwrap_or_else(|| t!("unknown"));
+
+        eprintln!("{}", t!("inspect-taken", path = path.display().to_string(), taken = taken, camera = camera));
+    }
+
+    Ok(())
+}
+
+fn do_annex_export(library: &Library) -> Result<()> {
+    for file in library.files() {
+        let key = annex::key_for(library, file)?;
+        println!("{} {}", key, file.path_in_library.display());
+    }
+
+    Ok(())
+}
+
+fn do_backup_verify(library: &Library, repo: BackupRepo) -> Result<()> {
+    let missing = backup::uncovered(library, &repo)?;
+    for file in &missing {
+        eprintln!("{} {}", file.hash.encode(), file.path_in_library.display());
+    }
+
+    if !missing.is_empty() {
+        return Err(eyre!("{}", t!("backup-missing", missing = missing.len(), total = library.files().len())));
+    }
+
+    info!("{}", t!("backup-complete", total = library.files().len()));
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Record the current index and config as a snapshot
+    Create {
+        name: String,
+    },
+    /// List snapshots, oldest first
+    List,
+    /// Show how the index has changed since a snapshot
+    Diff {
+        name: String,
+    },
+    /// Restore the index to a snapshot. Files on disk are not moved
+    ///
+    /// The current index is snapshotted first, so a rollback can itself be undone.
+    Rollback {
+        name: String,
+    },
+}
+
+fn do_snapshot(library: &mut Library, action: SnapshotAction, config_path: &Path) -> Result<()> {
+    match action {
+        SnapshotAction::Create { name } => snapshot::create(library, &name, config_path)?,
+        SnapshotAction::List => {
+            for name in snapshot::list(library)? {
+                eprintln!("{name}");
+            }
+        }
+        SnapshotAction::Diff { name } => {
+            let before = snapshot::load(library, &name)?;
+            for change in snapshot::diff(&before.files, library.files()) {
+                match change {
+                    Change::Added(f) => eprintln!("+ {}", f.path_in_library.display()),
+                    Change::Removed(f) => eprintln!("- {}", f.path_in_library.display()),
+                    Change::Moved { from, to } => eprintln!(
+                        "~ {} -> {}",
+                        from.path_in_library.display(),
+                        to.path_in_library.display()
+                    ),
+                }
+            }
+        }
+        SnapshotAction::Rollback { name } => {
+            let Snapshot { settings, files } = snapshot::load(library, &name)?;
+
+            let now = time::OffsetDateTime::now_utc()
+                .format(time::macros::format_description!("[year][month][day]-[hour][minute][second]"))?;
+            snapshot::create(library, &format!("pre-rollback-{now}"), config_path)?;
+
+            info!("rolling back to snapshot {} ({} files)", name, files.len());
+            let changed: Vec<_> = snapshot::diff(library.files(), &files)
+                .into_iter()
+                .map(|c| match c {
+                    Change::Added(f) | Change::Removed(f) | Change::Moved { to: f, .. } => f.hash.clone(),
+                })
+                .collect();
+
+            library.replace_files(files);
+            if let Some(settings) = settings {
+                library.replace_settings(settings)?;
+            }
+            audit::record(library, &format!("rollback {name}"), &changed)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum JobsAction {
+    /// List running and interrupted jobs
+    List,
+    /// Show the progress of a job
+    Status {
+        kind: String,
+    },
+    /// Stop a job and forget its progress. Work already done is kept
+    Cancel {
+        kind: String,
+    },
+}
+
+fn describe_job(job: &Job) -> String {
+    let state = if job.is_running() { t!("job-running") } else { t!("job-interrupted") };
+    let mut line = t!(
+        "job-summary",
+        kind = job.kind.as_str(),
+        args = job.args.as_str(),
+        done = job.progress(),
+        total = job.total,
+        percent = job.progress() * 100 / job.total.max(1),
+        state = state,
+    );
+
+    if let Some(remaining) = job.estimated_remaining() {
+        line.push_str(&t!("job-remaining", minutes = remaining.as_secs().div_ceil(60)));
+    }
+    line
+}
+
+fn do_jobs(config: &AppConfig, action: &JobsAction, json: bool) -> Result<()> {
+    let meta_root = config.output.join(library::META_DIR);
+    match action {
+        JobsAction::List => {
+            let jobs = jobs::list(&meta_root)?;
+            let tasks = daemon_tasks(config)?;
+            if json {
+                schema::print(&JobsOutput {
+                    version: schema::VERSION,
+                    jobs: jobs.iter().map(JobRecord::from).collect(),
+                    tasks,
+                })?;
+            } else {
+                for job in &jobs {
+                    eprintln!("{}", describe_job(job));
+                }
+                print_tasks(&tasks);
+            }
+        }
+        JobsAction::Status { kind } if json => {
+            let job = jobs::load(&meta_root, kind)?;
+            schema::print(&JobsOutput {
+                version: schema::VERSION,
+                jobs: vec![JobRecord::from(&job)],
+                tasks: vec![],
+            })?;
+        }
+        JobsAction::Status { kind } => {
+            let job = jobs::load(&meta_root, kind)?;
+            eprintln!("{}", describe_job(&job));
+            eprintln!("{}", t!("job-times", started = job.started.as_str(), updated = job.updated.as_str()));
+        }
+        JobsAction::Cancel { kind } => jobs::cancel(&meta_root, kind)?,
+    }
+
+    Ok(())
+}
+
+/// What a daemon running on the library is doing, nothing if there isn't one
+fn daemon_tasks(config: &AppConfig) -> Result<Vec<Task>> {
+    match daemon::send(&config.output, &Request::Tasks)? {
+        Some(Response::Tasks(tasks)) => Ok(tasks),
+        Some(Response::Error(e)) => Err(eyre!("{e}")),
+        Some(_) => Err(eyre!("the daemon didn't answer with what it's doing")),
+        None => Ok(vec![]),
+    }
+}
+
+fn print_tasks(tasks: &[Task]) {
+    for task in tasks {
+        eprintln!("{}", t!("job-task", name = task.name.as_str(), started = task.started.as_str()));
+    }
+}
+
+fn do_schema(name: Option<&str>) -> Result<()> {
+    let Some(name) = name else {
+        for name in schema::NAMES {
+            println!("{name}");
+        }
+        return Ok(());
+    };
+
+    let schema = schema::schema(name).ok_or_else(|| eyre!("there is no schema for {name:?}, expected one of {}", schema::NAMES.join(", ")))?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+fn do_stats(library: &mut Library, json: bool) -> Result<()> {
+    let stats = stats::get(library)?;
+    if json {
+        return schema::print(&StatsOutput::from(stats));
+    }
+
+    let gib = format!("{:.1}", stats.bytes as f64 / (1024.0 * 1024.0 * 1024.0));
+    eprintln!("{}", t!("stats-files", files = stats.files, gib = gib));
+    for (year, count) in &stats.years {
+        eprintln!("{}", t!("stats-year", year = year.as_str(), files = *count));
+        for (month, count) in stats.months.iter().filter(|(m, _)| m.starts_with(year.as_str())) {
+            eprintln!("{}", t!("stats-month", month = month.as_str(), files = *count));
+        }
+    }
+    if stats.undated > 0 {
+        eprintln!("{}", t!("stats-undated", files = stats.undated));
+    }
+    if stats.duplicates > 0 {
+        eprintln!("{}", t!("stats-duplicates", files = stats.duplicates));
+    }
+
+    let mut extensions: Vec<_> = stats.extensions.iter().collect();
+    extensions.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let extensions: Vec<_> = extensions
+        .into_iter()
+        .map(|(ext, count)| format!("{} {count}", if ext.is_empty() { "-" } else { ext }))
+        .collect();
+    if !extensions.is_empty() {
+        eprintln!("{}", t!("stats-extensions", extensions = extensions.join(", ")));
+    }
+
+    if !stats.largest.is_empty() {
+        eprintln!("{}", t!("stats-largest"));
+        for (path, bytes) in &stats.largest {
+            let mib = format!("{:.1}", *bytes as f64 / (1024.0 * 1024.0));
+            eprintln!("    {}", t!("stats-largest-file", mib = mib, path = path.as_str()));
+        }
+    }
+    if let Some(time) = &stats.last_import {
+        eprintln!("{}", t!("stats-last-import", time = time.as_str()));
+    }
+
+    Ok(())
+}
+
+fn do_heatmap(library: &Library, spec: &str, out: &Path) -> Result<()> {
+    let year = heatmap::parse_year(spec)?;
+    let counts = heatmap::counts(library, year);
+    heatmap::write(&counts, year, out)?;
+
+    let photos: usize = counts.values().sum();
+    eprintln!("{}", t!("stats-heatmap", photos = photos, year = year, path = out.display().to_string()));
+    Ok(())
+}
+
+/// How often `po changes --follow` checks the feed
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+fn do_changes(library: &Library, since: u64, follow: bool, json: bool) -> Result<()> {
+    let mut since = since;
+    loop {
+        let changes = changes::since(library.meta_root(), since)?;
+        if let Some(last) = changes.last() {
+            since = last.generation;
+        }
+
+        if json && (!changes.is_empty() || !follow) {
+            schema::print(&ChangesOutput { version: schema::VERSION, generation: since, changes })?;
+        } else {
+            for change in &changes {
+                let (generation, path) = (change.generation, change.path.as_str());
+                let message = match (change.kind, &change.previous_path) {
+                    (ChangeKind::Modified, Some(previous)) => {
+                        t!("changes-moved", generation = generation, path = path, previous = previous.as_str())
+                    }
+                    (ChangeKind::Added, _) => t!("changes-added", generation = generation, path = path),
+                    (ChangeKind::Removed, _) => t!("changes-removed", generation = generation, path = path),
+                    (ChangeKind::Modified, None) => t!("changes-modified", generation = generation, path = path),
+                };
+                eprintln!("{message}");
+            }
+        }
+
+        if !follow {
+            return Ok(());
+        }
+
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+fn do_verify(library: &Library, config: &AppConfig) -> Result<()> {
+    let report = verify::run(library, config.hash_workers)?;
+    for path in &report.missing {
+        eprintln!("{}", t!("verify-missing", path = path.display().to_string()));
+    }
+    for (path, hash) in &report.mismatched {
+        eprintln!("{}", t!("verify-mismatched", path = path.display().to_string(), hash = hash.encode()));
+    }
+    for path in &report.orphans {
+        eprintln!("{}", t!("verify-orphan", path = path.display().to_string()));
+    }
+
+    let summary = t!(
+        "verify-summary",
+        checked = report.checked,
+        missing = report.missing.len(),
+        mismatched = report.mismatched.len(),
+        orphans = report.orphans.len()
+    );
+    if !report.is_clean() {
+        return Err(eyre!("{summary}"));
+    }
+
+    info!("{summary}");
+    Ok(())
+}
+
+fn do_doctor(config: &AppConfig) -> Result<()> {
+    // Left alone if it isn't there, so checking doesn't create it
+    let others = match config.registry_options() {
+        Some(options) if options.path.exists() => Registry::open(&options)?.other_roots()?,
+        _ => vec![],
+    };
+    let findings = doctor::run(&config.input_paths(), &config.output, &others)?;
+    for finding in &findings {
+        let message = finding.message.as_str();
+        let line = match finding.severity {
+            Severity::Ok => t!("doctor-ok", message = message),
+            Severity::Warning => t!("doctor-warning", message = message),
+            Severity::Problem => t!("doctor-problem", message = message),
+        };
+        eprintln!("{line}");
+        if let Some(fix) = &finding.fix {
+            eprintln!("{}", t!("doctor-fix", fix = fix.as_str()));
+        }
+    }
+
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let summary = t!("doctor-summary", problems = count(Severity::Problem), warnings = count(Severity::Warning));
+    if count(Severity::Problem) > 0 {
+        return Err(eyre!("{summary}"));
+    }
+
+    eprintln!("{summary}");
+    Ok(())
+}
+
+/// Read the config file as a plain table, to tell which keys it sets. A missing file is only
+/// an error when it was asked for, as everything can also be set with flags and env vars
+fn read_config_file(path: &Path, explicit: bool) -> Result<toml::Table> {
+    match fs::read_to_string(path) {
+        Ok(raw) => toml::from_str(&raw).wrap_err(format!("when reading {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+            debug!("no config file at {}, using flags and env vars only", path.display());
+            Ok(toml::Table::new())
+        }
+        Err(e) => Err(e).wrap_err(format!("when reading config file {}", path.display())),
+    }
+}
+
+fn missing_config_error(missing: &[config_docs::KeyDocs], path: &Path) -> color_eyre::eyre::Report {
+    let keys = missing
+        .iter()
+        .map(|key| {
+            let mut ways = vec![format!("`{}` in {}", key.key, path.display())];
+            ways.extend(key.flag.clone());
+            ways.extend(key.env.map(str::to_string));
+            format!("  {}: set it with {}", key.key, ways.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    eyre!("required config is missing, and has no default:\n{keys}")
+}
+
+fn do_rename(library: &mut Library, pattern: &str, to: &str, dry_run: bool) -> Result<()> {
+    let pattern = Regex::new(pattern).wrap_err("when parsing --pattern")?;
+    let plan = rename::plan(library, &pattern, to)?;
+
+    for (from, to) in &plan.renames {
+        eprintln!("{}", t!("rename-planned", from = from.display().to_string(), to = to.display().to_string()));
+    }
+    for collision in &plan.collisions {
+        let message = match collision {
+            rename::Collision::Exists { from, to } => {
+                t!("rename-exists", from = from.display().to_string(), to = to.display().to_string())
+            }
+            rename::Collision::Duplicate { from, other, to } => t!(
+                "rename-duplicate",
+                from = from.display().to_string(),
+                other = other.display().to_string(),
+                to = to.display().to_string()
+            ),
+        };
+        eprintln!("{message}");
+    }
+
+    if !plan.collisions.is_empty() {
+        return Err(eyre!("{}", t!("rename-collisions", count = plan.collisions.len())));
+    }
+    if dry_run {
+        eprintln!("{}", t!("rename-dry-run", count = plan.renames.len()));
+        return Ok(());
+    }
+
+    let (mut renamed, mut errors) = (vec![], vec![]);
+    for (from, to) in &plan.renames {
+        match library.rename_file(from, to) {
+            Ok(hash) => renamed.push(hash),
+            Err(e) => {
+                warn!("could not rename {}: {e:#}", from.display());
+                errors.push(e);
+            }
+        }
+    }
+
+    if !renamed.is_empty() {
+        audit::record(library, &format!("rename {pattern} {to}"), &renamed)?;
+    }
+    info!("{}", t!("naming-renamed", count = renamed.len()));
+    persist_then_fail(library, errors)
+}
+
+/// Fail with the first of `errors`, once the index records what did get done. Those files have
+/// already changed on disk, and the index is only written when a command succeeds
+fn persist_then_fail(library: &mut Library, errors: Vec<color_eyre::Report>) -> Result<()> {
+    let Some(first) = errors.into_iter().next() else {
+        return Ok(());
+    };
+    library.persist_to_disk()?;
+    Err(first)
+}
+
+fn do_remove(library: &mut Library, queries: &[String], trash: bool, dry_run: bool) -> Result<()> {
+    let paths: Vec<_> = matching_files(library, queries, &[]).iter().map(|f| f.path_in_library.clone()).collect();
+
+    if dry_run {
+        for path in &paths {
+            eprintln!("{}", t!("remove-planned", path = path.display().to_string()));
+        }
+        eprintln!("{}", t!("remove-dry-run", count = paths.len()));
+        return Ok(());
+    }
+
+    let (mut removed, mut errors) = (vec![], vec![]);
+    for path in &paths {
+        match library.remove_file(path, trash) {
+            Ok(hash) => removed.push(hash),
+            Err(e) => {
+                warn!("could not remove {}: {e:#}", path.display());
+                errors.push(e);
+                continue;
+            }
+        }
+        let key = if trash { "remove-trashed" } else { "remove-deleted" };
+        eprintln!("{}", t!(key, path = path.display().to_string()));
+    }
+
+    if !removed.is_empty() {
+        audit::record(library, if trash { "remove --trash" } else { "remove" }, &removed)?;
+    }
+    persist_then_fail(library, errors)
+}
+
+fn do_prune(library: &mut Library, policies: &[prune::Policy], dry_run: bool, yes: bool) -> Result<()> {
+    if policies.is_empty() {
+        return Err(eyre!("there are no retention policies, add some as [[retention_policies]] in the config"));
+    }
+    prune::check(library, policies)?;
+
+    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+    let due = prune::due(library, policies, now)?;
+    if dry_run {
+        for due in &due {
+            let path = due.path.display().to_string();
+            eprintln!("{}", t!("prune-planned", action = due.policy.action.name(), path = path, tag = due.policy.tag.as_str()));
+        }
+        eprintln!("{}", t!("prune-dry-run", count = due.len()));
+        return Ok(());
+    }
+
+    let deleting = due.iter().filter(|d| d.policy.action == prune::Action::Delete).count();
+    if deleting > 0 && !yes {
+        return Err(eyre!(
+            "{deleting} files are due to be deleted for good. check them with `po prune --policies --dry-run`, \
+             then run again with --yes to go ahead"
+        ));
+    }
+
+    let report = prune::apply(library, &due);
+    for (action, hashes) in [("prune --trash", &report.trashed), ("prune --delete", &report.deleted), ("prune --archive", &report.archived)] {
+        if !hashes.is_empty() {
+            audit::record(library, action, hashes)?;
+        }
+    }
+    eprintln!(
+        "{}",
+        t!("prune-done", trashed = report.trashed.len(), deleted = report.deleted.len(), archived = report.archived.len())
+    );
+    Ok(())
+}
+
+fn do_rollback(library: &mut Library) -> Result<()> {
+    let Some(report) = journal::rollback(library)? else {
+        eprintln!("{}", t!("rollback-nothing"));
+        return Ok(());
+    };
+
+    if !report.forgotten.is_empty() {
+        audit::record(library, "rollback", &report.forgotten)?;
+    }
+    eprintln!("{}", t!("rollback-done", restored = report.restored, removed = report.removed));
+    if report.missing > 0 {
+        warn!("{}", t!("rollback-missing", count = report.missing));
+    }
+    if report.kept > 0 {
+        warn!("{}", t!("rollback-kept", count = report.kept));
+    }
+    Ok(())
+}
+
+fn do_classify(library: &mut Library, config: &ClassifyConfig, queries: &[String]) -> Result<()> {
+    let hashes: Vec<_> = matching_files(library, queries, &[]).iter().map(|f| f.hash.clone()).collect();
+    let report = classify::classify(library, config, &hashes)?;
+    if !report.changed.is_empty() {
+        audit::record(library, "classify", &report.changed)?;
+    }
+
+    eprintln!("{}", t!("classify-summary", classified = report.classified, changed = report.changed.len()));
+    Ok(())
+}
+
+fn do_ocr(library: &mut Library, config: &OcrConfig, queries: &[String]) -> Result<()> {
+    let hashes: Vec<_> = matching_files(library, queries, &[]).iter().map(|f| f.hash.clone()).collect();
+    let report = ocr::ocr(library, config, &hashes, ocr::Select::All)?;
+    if !report.changed.is_empty() {
+        audit::record(library, "ocr", &report.changed)?;
+    }
+
+    eprintln!("{}", t!("ocr-summary", read = report.read, changed = report.changed.len()));
+    Ok(())
+}
+
+fn do_prints(library: &mut Library, action: PrintsAction) -> Result<()> {
+    let (count, queries) = match action {
+        PrintsAction::Set { count, queries } => (count, queries),
+        PrintsAction::List => {
+            let mut total = 0;
+            for file in library.files().iter().filter(|f| f.prints > 0) {
+                eprintln!("{}", t!("prints-file", path = file.path_in_library.display().to_string(), count = file.prints));
+                total += file.prints;
+            }
+            eprintln!("{}", t!("prints-total", count = total));
+            return Ok(());
+        }
+    };
+
+    let paths: Vec<_> = matching_files(library, &queries, &[]).iter().map(|f| f.path_in_library.clone()).collect();
+    let mut changed = vec![];
+    for path in &paths {
+        let generation = library.generation();
+        let hash = library.set_prints(path, count)?.hash.clone();
+        if library.generation() != generation {
+            eprintln!("{}", t!("prints-file", path = path.display().to_string(), count = count));
+            changed.push(hash);
+        }
+    }
+
+    if !changed.is_empty() {
+        audit::record(library, &format!("prints set {count}"), &changed)?;
+    }
+    Ok(())
+}
+
+fn do_roll(library: &Library, action: RollAction) -> Result<()> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for roll in library.files().iter().filter_map(rolls::of) {
+        *counts.entry(roll).or_default() += 1;
+    }
+
+    match action {
+        RollAction::List => {
+            let known = rolls::load(library)?;
+            let names: BTreeSet<&str> = known.keys().map(String::as_str).chain(counts.keys().copied()).collect();
+            for name in names {
+                let roll = known.get(name).cloned().unwrap_or_default();
+                print_roll(name, &roll, counts.get(name).copied().unwrap_or(0));
+            }
+        }
+        RollAction::Set { name, details } => {
+            // So a typo doesn't start a roll of its own
+            if !counts.contains_key(name.as_str()) && !rolls::load(library)?.contains_key(&name) {
+                return Err(eyre!("there's no roll called {name}, import its scans with `po import --roll {name}` first"));
+            }
+            let roll = rolls::update(library, &name, details)?;
+            print_roll(&name, &roll, counts.get(name.as_str()).copied().unwrap_or(0));
+        }
+    }
+    Ok(())
+}
+
+fn print_roll(name: &str, roll: &Roll, files: usize) {
+    eprintln!("{}", t!("roll-name", roll = name, files = files));
+    if let Some(stock) = &roll.film_stock {
+        eprintln!("{}", t!("roll-film-stock", stock = stock.as_str()));
+    }
+    if let Some(camera) = &roll.camera {
+        eprintln!("{}", t!("roll-camera", camera = camera.as_str()));
+    }
+    if let Some(developed) = &roll.developed {
+        eprintln!("{}", t!("roll-developed", developed = developed.as_str()));
+    }
+}
+
+fn do_print_order(library: &Library, files: &[&LibraryFile], to: &Path, size: &str, dpi: u16) -> Result<()> {
+    let size = PrintSize::parse(size)?;
+    let report = print::to_directory(library, files, to, &size, dpi)?;
+    eprintln!(
+        "{}",
+        t!("print-order-written", photos = report.photos, prints = report.prints, path = to.display().to_string(), skipped = report.skipped)
+    );
+    Ok(())
+}
+
+fn do_registry_sync(library: &Library, config: &AppConfig) -> Result<()> {
+    let options = config
+        .import_options()
+        .registry
+        .ok_or_else(|| eyre!("there is no registry to sync with, set `registry` in the config"))?;
+
+    Registry::open(&options)?.replace(library.files())?;
+    eprintln!("{}", t!("registry-synced", count = library.files().len(), name = options.name.as_str()));
+    Ok(())
+}
+
+fn do_exif_set(library: &mut Library, queries: &[String], fields: ExifFields, dry_run: bool) -> Result<()> {
+    if fields.artist.is_none() && fields.copyright.is_none() {
+        return Err(eyre!("nothing to set, give --artist or --copyright"));
+    }
+
+    let mut paths = vec![];
+    let mut skipped = 0;
+    for file in matching_files(library, queries, &[]) {
+        let mut magic = [0; 2];
+        let read = fs::File::open(library.absolute_path(file)).and_then(|mut f| f.read(&mut magic));
+        if read.is_ok_and(|n| metadata::is_writable(&magic[..n])) {
+            paths.push(file.path_in_library.clone());
+        } else {
+            eprintln!("{}", t!("exif-skipped", path = file.path_in_library.display().to_string()));
+            skipped += 1;
+        }
+    }
+
+    if dry_run {
+        for path in &paths {
+            eprintln!("{}", t!("exif-planned", path = path.display().to_string()));
+        }
+        eprintln!("{}", t!("exif-dry-run", count = paths.len()));
+        return Ok(());
+    }
+
+    let (mut rewritten, mut hashes, mut errors) = (0, vec![], vec![]);
+    for path in &paths {
+        let old = library.files().iter().find(|f| &f.path_in_library == path);
+        // Most software prefers XMP to EXIF where both are set
+        let xmp = old.and_then(|f| metadata::read_xmp(&library.absolute_path(f)).ok().flatten());
+        let xmp_kept = xmp.is_some_and(|xmp| xmp.contains("dc:creator") || xmp.contains("dc:rights"));
+        let old = old.map(|f| f.hash.clone());
+        let file = match library.rewrite_file(path, |content| metadata::set_fields(content, &fields)) {
+            Ok(Some(file)) => file,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("could not rewrite {}: {e:#}", path.display());
+                errors.push(e);
+                continue;
+            }
+        };
+
+        eprintln!("{}", t!("exif-rewritten", path = file.path_in_library.display().to_string()));
+        if xmp_kept {
+            warn!("{}", t!("exif-xmp-kept", path = file.path_in_library.display().to_string()));
+        }
+        hashes.push(file.hash.clone());
+        hashes.extend(old);
+        rewritten += 1;
+    }
+
+    if !hashes.is_empty() {
+        audit::record(library, "exif set", &hashes)?;
+    }
+    eprintln!("{}", t!("exif-summary", count = rewritten, skipped = skipped));
+    persist_then_fail(library, errors)
+}
+
+fn do_tag(library: &mut Library, action: TagAction) -> Result<()> {
+    let (path, add, remove) = match action {
+        TagAction::Add { path, tags } => (Some(path), tags, vec![]),
+        TagAction::Rm { path, tags } => (Some(path), vec![], tags),
+        TagAction::List { path } => (path, vec![], vec![]),
+    };
+
+    print_tags(&tag_output(library, path.as_deref(), &add, &remove)?);
+    Ok(())
+}
+
+/// Add and remove tags on the file at `path`, or count every tag in the library without one
+fn tag_output(library: &mut Library, path: Option<&Path>, add: &[String], remove: &[String]) -> Result<TagOutput> {
+    let Some(path) = path else {
+        let mut counts = BTreeMap::new();
+        for tag in library.files().iter().flat_map(|f| &f.tags) {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+        return Ok(TagOutput::Counts(counts));
+    };
+
+    let generation = library.generation();
+    let file = library.retag(path, add, remove)?;
+    let (hash, tags) = (file.hash.clone(), file.tags.clone());
+    if library.generation() != generation {
+        let action = if add.is_empty() { format!("tag rm {}", remove.join(" ")) } else { format!("tag add {}", add.join(" ")) };
+        audit::record(library, &action, &[hash])?;
+    }
+
+    Ok(TagOutput::File { path: path.to_path_buf(), tags })
+}
+
+fn print_tags(output: &TagOutput) {
+    match output {
+        TagOutput::Counts(counts) => {
+            for (tag, count) in counts {
+                eprintln!("{}", t!("tag-count", tag = tag.as_str(), count = count));
+            }
+        }
+        TagOutput::File { path, tags } => {
+            let path = path.display().to_string();
+            if tags.is_empty() {
+                eprintln!("{}", t!("tag-file-none", path = path));
+            } else {
+                eprintln!("{}", t!("tag-file", path = path, tags = tags.join(", ")));
+            }
+        }
+    }
+}
+
+fn do_config_docs(config: &AppConfig, layers: &config_docs::Layers) -> Result<()> {
+    for key in config_docs::collect(&AppConfig::META, config, layers) {
+        let kind = match (key.kind.as_str(), key.optional) {
+            ("", _) => t!("config-docs-optional"),
+            (kind, true) => format!("{kind}, {}", t!("config-docs-optional")),
+            (kind, false) => kind.to_string(),
+        };
+        eprintln!("{}", t!("config-docs-key", key = key.key, kind = kind));
+        if !key.doc.is_empty() {
+            eprintln!("    {}", key.doc);
+        }
+        if !key.accepted.is_empty() {
+            eprintln!("    {}", t!("config-docs-accepted", values = key.accepted.join(", ")));
+        }
+        if let Some(default) = key.default {
+            eprintln!("    {}", t!("config-docs-default", value = default));
+        }
+
+        let ways: Vec<_> = key.flag.iter().map(String::as_str).chain(key.env).collect();
+        if !ways.is_empty() {
+            eprintln!("    {}", t!("config-docs-set-with", ways = ways.join(", ")));
+        }
+
+        let source = match key.source {
+            config_docs::Source::Cli => "cli",
+            config_docs::Source::Env => "env",
+            config_docs::Source::File => "file",
+            config_docs::Source::Default => "default",
+        };
+        match key.value {
+            Some(value) => eprintln!("    {}", t!("config-docs-value", value = value, source = source)),
+            None => eprintln!("    {}", t!("config-docs-unset")),
+        }
+        eprintln!();
+    }
+
+    Ok(())
+}
+
+fn do_dedupe_similar(library: &Library, query: &str, distance: u32) -> Result<()> {
+    let files = matching_files(library, std::slice::from_ref(&query.to_string()), &[]);
+    let groups = similar::groups(library, &files, distance)?;
+
+    for group in &groups {
+        eprintln!("{}", t!("dedupe-group", count = group.len()));
+        for file in group {
+            eprintln!("    {}", file.path_in_library.display());
+        }
+    }
+
+    eprintln!("{}", t!("dedupe-summary", groups = groups.len(), files = groups.iter().map(Vec::len).sum::<usize>()));
+    Ok(())
+}
+
+fn do_gallery(library: &Library, dest: &Path, url: Option<&str>) -> Result<()> {
+    let report = gallery::generate(library, dest, url)?;
+    if report.pages == 0 && report.thumbnails == 0 {
+        eprintln!("{}", t!("gallery-up-to-date"));
+    } else {
+        eprintln!("{}", t!("gallery-written", pages = report.pages, thumbnails = report.thumbnails));
+    }
+    Ok(())
+}
+
+fn do_remux(library: &Library, queries: &[String], to: &Path, date: Option<&str>) -> Result<()> {
+    let date = date.map(simulate::parse_clock).transpose()?;
+    let files = matching_files(library, queries, &[]);
+    let report = remux::to_directory(library, &files, to, date)?;
+    eprintln!(
+        "{}",
+        t!("remux-summary", remuxed = report.remuxed, up_to_date = report.up_to_date, skipped = report.skipped)
+    );
+    Ok(())
+}
+
+fn do_mirror(library: &Library, query: &str, dest: &Path) -> Result<()> {
+    let files = matching_files(library, &[query.to_string()], &[]);
+    let report = mirror::sync(library, &files, dest, query)?;
+    eprintln!(
+        "{}",
+        t!(
+            "mirror-summary",
+            copied = report.copied,
+            updated = report.updated,
+            removed = report.removed,
+            up_to_date = report.up_to_date,
+            skipped = report.skipped
+        )
+    );
+    if shutdown::requested() {
+        warn!("{}", t!("mirror-interrupted"));
+    }
+    Ok(())
+}
+
+fn do_thumbs(library: &Library, sizes: &[u32], strip_frames: usize) -> Result<()> {
+    let report = thumbs::generate(library, sizes, strip_frames)?;
+    eprintln!(
+        "{}",
+        t!("thumbs-made", made = report.made, existing = report.existing, skipped = report.skipped, removed = report.removed)
+    );
+    Ok(())
+}
+
+fn do_map(library: &Library, queries: &[String], excludes: &[String], out: &Path) -> Result<()> {
+    let files = matching_files(library, queries, excludes);
+    let report = map::write(library, &files, out)?;
+    eprintln!("{}", t!("map-written", placed = report.placed, path = out.display().to_string(), unplaced = report.unplaced));
+    Ok(())
+}
+
+fn do_log_audit(library: &Library, show_hashes: bool) -> Result<()> {
+    for entry in audit::read(library)? {
+        eprintln!(
+            "{}",
+            t!(
+                "audit-entry",
+                time = entry.time.as_str(),
+                action = entry.action.as_str(),
+                user = entry.user.clone().unwrap_or_else(|| t!("unknown")),
+                count = entry.hashes.len(),
+                command = entry.command.join(" "),
+            )
+        );
+
+        if show_hashes {
+            for hash in entry.hashes {
+                eprintln!("  {hash}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    init_logging()?;
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(Action::Import { paths, .. }) = &cli.action && !paths.is_empty() {
+        cli.cli_config.inputs = Some(paths.iter().cloned().map(Input::Path).collect());
+    }
+    if let Some(Action::Query { format: QueryFormat::Json, .. }) = &cli.action {
+        cli.json = true;
+    }
+    
+    info!("starting up!");
+    if cli.json && cli.action.as_ref().is_some_and(|action| !action.has_json()) {
+        let (mut name, mut subcommand) = (vec![], &matches);
+        while let Some((part, next)) = subcommand.subcommand() {
+            name.push(part);
+            subcommand = next;
+        }
+        return Err(eyre!("po {} has no --json output, `po schema` lists the outputs which do", name.join(" ")));
+    }
+    // Needs neither the config nor the library
+    if let Some(Action::Schema { name }) = &cli.action {
+        return do_schema(name.as_deref());
+    }
+    let explicit_config = matches.value_source("config") != Some(ValueSource::DefaultValue);
+    let file = read_config_file(&cli.config, explicit_config)?;
+    let mut cli_overrides = vec![];
+    if cli.copy {
+        cli_overrides.push("source_retention");
+    }
+    if cli.cli_config.inputs.is_some() {
+        cli_overrides.push("inputs");
+    }
+    let command = Cli::command();
+    let layers = config_docs::Layers {
+        file: &file,
+        cli: &matches,
+        command: &command,
+        cli_overrides: &cli_overrides,
+    };
+
+    // Flags take precedence over env vars, which take precedence over the config file
+    let mut builder = AppConfig::builder().preloaded(cli.cli_config).env();
+    if cli.config.exists() {
+        builder = builder.file(&cli.config);
+    }
+    let mut config = match builder.load() {
+        Ok(config) => config,
+        Err(e) => {
+            let missing = config_docs::missing(&AppConfig::META, &layers);
+            if missing.is_empty() {
+                return Err(e).wrap_err("failed to load app config");
+            }
+            return Err(missing_config_error(&missing, &cli.config));
+        }
+    };
+    config.extensions = extensions::expand(&config.extensions)?;
+    if config.all_media && !extensions::has_wildcard(&config.extensions) {
+        config.extensions.push(extensions::WILDCARD.to_string());
+    }
+    for input in &mut config.inputs {
+        if let Input::Table(InputTable { extensions: Some(extensions), .. }) = input {
+            *extensions = extensions::expand(extensions)?;
+        }
+    }
+    devices::check_templates(&config.device_templates)?;
+    // Only needed for inputs without extensions of their own
+    let uncovered = config.inputs.is_empty() || config.inputs.iter().any(|i| i.table().is_none_or(|t| t.extensions.is_none()));
+    if config.extensions.is_empty() && uncovered {
+        return Err(eyre!(
+            "no extensions to import are configured. set `extensions`, e.g. `extensions = [ \"@photos\" ]`, \
+             or `all_media = true` to import any photo, video or audio file"
+        ));
+    }
+    if cli.copy {
+        config.source_retention = SourceRetention::Copy;
+    }
+
+    info!("config loaded: {:#?}", config);
+    i18n::init(config.language.as_deref(), config.translations.as_deref());
+
+    if let Some(Action::Config { action: ConfigAction::Docs }) = &cli.action {
+        return do_config_docs(&config, &layers);
+    }
+
+    // Before the library is opened, as opening it may be what's broken
+    if let Some(Action::Doctor) = &cli.action {
+        return do_doctor(&config);
+    }
+    // Nor should a broken library stop po being updated
+    if let Some(Action::SelfUpdate { check }) = &cli.action {
+        if !config.self_update {
+            return Err(eyre!("self-update is turned off in the config, update po the way it was installed"));
+        }
+        return update::run(*check);
+    }
+
+    // Jobs only read and remove checkpoints, so they can be looked at while a job has the library locked
+    if let Some(Action::Jobs { action }) = &cli.action {
+        return do_jobs(&config, action, cli.json);
+    }
+
+    if let Some(limit) = &config.memory_limit {
+        budget::set_limit(budget::parse_size(limit).wrap_err("when reading memory_limit")?);
+    }
+
+    let verify_state = match &cli.action {
+        Some(Action::Query { verify_state: true, .. }) => {
+            Some(retention::parse_age(&config.verify_stale_after).wrap_err("when reading verify_stale_after")?)
+        }
+        _ => None,
+    };
+
+    // A daemon already has the library loaded, and may be changing it
+    if let Some(request) = cli.action.as_ref().and_then(Action::daemon_request) {
+        match daemon::send(&config.output, &request)? {
+            Some(response) => return print_response(response, cli.json, verify_state),
+            None if cli.connect => {
+                return Err(eyre!("there is no po daemon running on {}, start one with `po watch`, `po serve` or `po mqtt`", config.output.display()));
+            }
+            None => {}
+        }
+    }
+
+    let years = cli.action.as_ref().map_or(Some(vec![]), Action::index_years);
+    let in_memory = matches!(cli.action, Some(Action::Import { dry_run: true, .. } | Action::Simulate { .. }));
+    let mut library = if in_memory {
+        Library::read_into_memory(config.output.clone())?
+    } else if cli.action.as_ref().is_some_and(Action::only_reads) {
+        Library::read_shared(config.output.clone(), years, config.wait_for_lock)?
+    } else {
+        Library::read_from_disk(config.output.clone(), years, config.wait_for_lock)?
+    };
+    if let Some(identity) = &config.identity {
+        library.set_identity(identity.clone());
+    }
+    library.set_checksum_files(config.checksum_files);
+    // Nothing a copy in memory does happens to the real library
+    if !in_memory {
+        library.set_registry(config.registry_options());
+    }
+    library.reconcile_settings(
+        config.sort_policy.clone(),
+        config.naming,
+        config.index,
+        config.hash_algorithm,
+        config.path_template.clone(),
+    )?;
+    debug!("loaded library: {:#?}", library);
+    budget::check_index(library.files().len());
+
+    let inputs = config.input_paths();
+    for input in &inputs {
+        ensure_directory(input)?;
+    }
+    
+    ensure_directory(&config.output)?;
+    let registry = match config.registry_options() {
+        Some(options) if !in_memory => Some(Registry::open(&options)?),
+        _ => None,
+    };
+    let others = registry.as_ref().map(Registry::other_roots).transpose()?.unwrap_or_default();
+    let roots = doctor::check_roots(&inputs, &config.output, &others)?;
+    if let Some(registry) = &registry
+        && !cli.action.as_ref().is_some_and(Action::only_reads)
+    {
+        registry.record_roots(&roots)?;
+    }
+
+    // Only actions which can stop cleanly between files catch Ctrl-C, the rest exit as usual
+    if matches!(cli.action, None | Some(Action::Import { .. } | Action::Naming { .. } | Action::Watch | Action::Serve)) {
+        shutdown::install()?;
+    }
+
+    match cli.action {
+        Some(act) => match act {
+            Action::Import { dry_run: true, roll, roll_details, .. } => {
+                do_dry_run(&mut library, &config, roll, &roll_details)?;
+            }
+            Action::Simulate { sort_policy, naming, clock } => {
+                do_simulate(&mut library, &config, sort_policy, naming, clock.as_deref())?;
+            }
+            Action::Import { dry_run: false, roll, roll_details, .. } => {
+                do_import(&mut library, &config, roll, roll_details, cli.json)?
+            }
+            Action::Query { queries, not, filters, groups, .. } => {
+                let output = query_output(&library, &queries, &not, &filters, groups, verify_state.is_some(), None)?;
+                print_query(&output, cli.json, verify_state)?;
+            }
+            Action::Tag { action } => {
+                do_tag(&mut library, action)?;
+            }
+            Action::Show { path } => {
+                print_show(&show_output(&library, &path)?, cli.json)?;
+            }
+            Action::Contains { paths } => {
+                do_contains(&mut library, &paths, cli.json)?;
+            }
+            Action::Diff { dir, within } => {
+                do_diff(&library, &dir, within.as_deref())?;
+            }
+            Action::Serve => {
+                do_serve(&mut library, &config, cli.json)?;
+            }
+            Action::Mqtt => {
+                do_mqtt(&mut library, &config, cli.json)?;
+            }
+            Action::Watch => {
+                do_watch(&mut library, &config, cli.json)?;
+            }
+            Action::Export { query, to, layout, flatten, template, preset, resize, sort, number, unique_content, immich, immich_api_key, geojson, kml, print, dpi } => {
+                let files = matching_files(&library, std::slice::from_ref(&query), &[]);
+                let layout = if flatten { ExportLayout::Flat } else { layout };
+                let resized = resize.map(|max| {
+                    let preset = Preset { max_dimension: Some(max), format: None, quality: None, strip_gps: false };
+                    (format!("resize-{max}"), preset)
+                });
+                let preset = preset
+                    .map(|name| {
+                        config
+                            .export
+                            .get_key_value(&name)
+                            .map(|(name, preset)| (name.as_str(), preset))
+                            .ok_or_else(|| eyre!("there is no export preset {name:?}, add one as [export.{name}] in the config"))
+                    })
+                    .transpose()?
+                    .or(resized.as_ref().map(|(name, preset)| (name.as_str(), preset)));
+                let options = ExportOptions {
+                    paths: match template {
+                        Some(template) => ExportPaths::Template(template),
+                        None => ExportPaths::Layout(layout),
+                    },
+                    preset,
+                    copy_unrenderable: resize.is_some(),
+                    sort,
+                    number,
+                    unique_content,
+                };
+                match (to, immich, immich_api_key) {
+                    (Some(to), _, _) => match print {
+                        Some(size) => do_print_order(&library, &files, &to, &size, dpi)?,
+                        None => export::to_directory(&library, &files, &to, &options)?,
+                    },
+                    (None, Some(server), Some(key)) => export::to_immich(&library, &files, &server, &key)?,
+                    (None, None, _) if geojson.is_some() || kml.is_some() => {
+                        geo::export(&library, &files, geojson.as_deref(), kml.as_deref())?
+                    }
+                    _ => unreachable!("clap to enforce an export target"),
+                }
+            }
+            Action::Classify { queries } => {
+                do_classify(&mut library, &config.classify, &queries)?;
+            }
+            Action::Ocr { queries } => {
+                do_ocr(&mut library, &config.ocr, &queries)?;
+            }
+            Action::Prints { action } => {
+                do_prints(&mut library, action)?;
+            }
+            Action::Roll { action } => {
+                do_roll(&library, action)?;
+            }
+            Action::Gallery { to, url } => {
+                do_gallery(&library, &to, url.as_deref())?;
+            }
+            Action::Thumbs => {
+                do_thumbs(&library, &config.thumb_sizes, config.video_strip_frames)?;
+            }
+            Action::Remux { queries, to, date } => {
+                do_remux(&library, &queries, &to, date.as_deref())?;
+            }
+            Action::Mirror { query, dest } => {
+                do_mirror(&library, &query, &dest)?;
+            }
+            Action::Map { queries, not, out } => {
+                do_map(&library, &queries, &not, &out)?;
+            }
+            Action::Annex { action: AnnexAction::Export } => {
+                do_annex_export(&library)?;
+            }
+            Action::Backup { action: BackupAction::Verify { restic, host, borg } } => {
+                let repo = match (restic, borg) {
+                    (Some(repo), _) => BackupRepo::Restic { repo, host },
+                    (None, Some(repo)) => BackupRepo::Borg(repo),
+                    _ => unreachable!("clap to enforce a repository"),
+                };
+                do_backup_verify(&library, repo)?;
+            }
+            Action::Snapshot { action } => {
+                do_snapshot(&mut library, action, &cli.config)?;
+            }
+            Action::Naming { scheme, resume } => {
+                let mut job = Job::start(&library, "naming", &format!("{scheme:?}"), library.files().len(), resume)?;
+                let renamed = library.apply_naming(scheme, &mut job)?;
+                info!("{}", t!("naming-renamed", count = renamed.len()));
+                audit::record(&library, &format!("naming {scheme:?}"), &renamed)?;
+
+                // The job is only finished once the index reflects it
+                library.persist_to_disk()?;
+                job.finish(&library)?;
+            }
+            Action::Rollback => {
+                do_rollback(&mut library)?;
+            }
+            Action::Remove { queries, trash, dry_run } => {
+                do_remove(&mut library, &queries, trash, dry_run)?;
+            }
+            Action::Rename { pattern, to, dry_run } => {
+                do_rename(&mut library, &pattern, &to, dry_run)?;
+            }
+            Action::Registry { action: RegistryAction::Sync } => {
+                do_registry_sync(&library, &config)?;
+            }
+            Action::Exif { action: ExifAction::Set { queries, artist, copyright, dry_run } } => {
+                do_exif_set(&mut library, &queries, ExifFields { artist, copyright }, dry_run)?;
+            }
+            Action::Index { layout } => {
+                library.set_index_layout(layout)?;
+                info!("index will be stored as {:?}", layout);
+            }
+            Action::Dedupe { similar: _, distance, query } => {
+                do_dedupe_similar(&library, &query, distance)?;
+            }
+            Action::Log { audit: _, hashes } => {
+                do_log_audit(&library, hashes)?;
+            }
+            Action::CleanSources { older_than } => {
+                let removed = retention::clean(&config.input_paths(), retention::parse_age(&older_than)?)?;
+                info!("{}", t!("clean-sources-removed", count = removed.len()));
+            }
+            Action::Prune { policies: _, dry_run, yes } => {
+                do_prune(&mut library, &config.retention_policies, dry_run, yes)?;
+            }
+            // Answered before the library is loaded
+            Action::Config { .. } | Action::Doctor | Action::SelfUpdate { .. } | Action::Jobs { .. } | Action::Schema { .. } => {
+                unreachable!()
+            }
+            Action::Stats { heatmap: None, .. } => {
+                do_stats(&mut library, cli.json)?;
+            }
+            Action::Stats { heatmap: Some(spec), out } => {
+                do_heatmap(&library, &spec, &out.expect("clap to require --out with --heatmap"))?;
+            }
+            Action::Verify => {
+                do_verify(&library, &config)?;
+            }
+            Action::Checksums => {
+                let mode = config.checksum_files.ok_or_else(|| eyre!("set checksum_files to say which checksum files to write"))?;
+                checksums::write_all(library.output_root(), mode, library.settings().hash_algorithm, library.files())?;
+                eprintln!("{}", t!("checksums-written", files = library.files().len()));
+            }
+            Action::Changes { since, follow } => {
+                do_changes(&library, since, follow, cli.json)?;
             }
-            Action::Query { query } => {
-                do_query(&mut library, query);
+            Action::Inspect { paths } => {
+                do_inspect(&paths)?;
             }
         },
         None => {
-            do_import(&mut library, config)?;
+            do_import(&mut library, &config, None, Roll::default(), cli.json)?;
         }
     }
 
@@ -0,0 +1,79 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::library::{FileHash, Library};
+
+/// Name of the manifest po leaves in source directories
+pub const MANIFEST_FILE: &str = ".po-manifest";
+
+/// One import from a source directory, a line of its manifest
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+pub struct ManifestEntry {
+    pub time: String,
+    /// The library the files went into
+    pub library: String,
+    pub user: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+pub struct ManifestFile {
+    /// Name the file had in the source directory
+    pub name: String,
+    pub hash: String,
+    pub path_in_library: String,
+}
+
+/// Append an entry to the manifest of each directory `imported` files came from.
+/// `imported` pairs each file's original path with its hash
+pub fn record<'a>(library: &Library, imported: impl IntoIterator<Item = (&'a Path, &'a FileHash)>) -> Result<()> {
+    let imported: HashMap<&FileHash, &Path> = imported.into_iter().map(|(source, hash)| (hash, source)).collect();
+
+    let mut by_dir: BTreeMap<PathBuf, Vec<ManifestFile>> = BTreeMap::new();
+    for file in library.files() {
+        let Some(source) = imported.get(&file.hash) else {
+            continue;
+        };
+
+        by_dir
+            .entry(source.parent().unwrap_or(Path::new(".")).to_path_buf())
+            .or_default()
+            .push(ManifestFile {
+                name: source.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                hash: file.hash.encode(),
+                path_in_library: file.path_in_library.to_string_lossy().to_string(),
+            });
+    }
+
+    let time = OffsetDateTime::now_utc().format(&Rfc3339)?;
+    let root = fs::canonicalize(library.output_root())?;
+    for (dir, files) in by_dir {
+        let path = dir.join(MANIFEST_FILE);
+        debug!("recording {} files in {}", files.len(), path.display());
+
+        let entry = ManifestEntry {
+            time: time.clone(),
+            library: root.to_string_lossy().to_string(),
+            user: library.identity().to_string(),
+            files,
+        };
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err(format!("when opening manifest {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    }
+
+    Ok(())
+}
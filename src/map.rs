@@ -0,0 +1,204 @@
+use color_eyre::eyre::{Result, WrapErr};
+use rayon::prelude::*;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use time::macros::format_description;
+use tracing::warn;
+
+use crate::gallery::{escape, relative_link, url_encode};
+use crate::geo;
+use crate::library::{Library, LibraryFile};
+use crate::transcode::{self, OutputFormat, Preset};
+
+const LEAFLET: &str = "https://unpkg.com/leaflet@1.9.4/dist";
+/// As published on leafletjs.com for 1.9.4, so the browser refuses anything else the CDN serves
+const LEAFLET_CSS_INTEGRITY: &str = "sha256-p4NxAoJBhIIN+hmNHrzRCf9tD/miZyoHS5obTRR9BMY=";
+const LEAFLET_JS_INTEGRITY: &str = "sha256-20nQCchB9co0qIjJZRGuk2/Z9VM+kNiyxNV1lvTlZBo=";
+
+/// How close markers get, in pixels, before they're drawn as one
+const CLUSTER_CELL: u32 = 60;
+
+/// Small, as every thumbnail is embedded in the page
+const THUMBNAIL: Preset = Preset {
+    max_dimension: Some(200),
+    format: Some(OutputFormat::Jpeg),
+    quality: Some(70),
+    strip_gps: true,
+};
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub placed: usize,
+    /// Files with no location in their metadata, or whose metadata couldn't be read
+    pub unplaced: usize,
+}
+
+struct Placed {
+    name: String,
+    link: String,
+    latitude: f64,
+    longitude: f64,
+    taken: Option<String>,
+    thumbnail: Option<String>,
+}
+
+/// Write an HTML page to `out` with a marker for each of `files` which has a location, clustered
+/// where they crowd together. Thumbnails are embedded in it, so it only needs the network for
+/// Leaflet and the map tiles, and markers link to the originals relative to `out`
+pub fn write(library: &Library, files: &[&LibraryFile], out: &Path) -> Result<Report> {
+    let dir = match out.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir).wrap_err(format!("when creating {}", dir.display()))?;
+    let originals = relative_link(dir, library.output_root())?;
+
+    let (points, unplaced) = geo::locate(library, files);
+    let paths: Vec<_> = points.iter().map(|p| library.absolute_path(p.file)).collect();
+    let thumbnails: Vec<_> = paths.par_iter().map(|path| thumbnail(path)).collect();
+
+    let placed: Vec<_> = points
+        .iter()
+        .zip(thumbnails)
+        .map(|(point, thumbnail)| {
+            Placed {
+                name: point.name(),
+                link: format!("{originals}{}", url_encode(&point.file.path_in_library.to_string_lossy())),
+                latitude: point.latitude,
+                longitude: point.longitude,
+                taken: point.taken.and_then(|t| t.format(format_description!("[year]-[month]-[day] [hour]:[minute]")).ok()),
+                thumbnail,
+            }
+        })
+        .collect();
+
+    fs::write(out, page(&placed)?).wrap_err(format!("when writing {}", out.display()))?;
+    Ok(Report { placed: placed.len(), unplaced })
+}
+
+/// A JPEG thumbnail of `path` as a data URL, if it's a photo which can be rendered
+fn thumbnail(path: &Path) -> Option<String> {
+    if !transcode::can_render(path).unwrap_or(false) {
+        return None;
+    }
+
+    let mut jpeg = vec![];
+    match transcode::render_into(path, &mut jpeg, &THUMBNAIL) {
+        Ok(_) => Some(format!("data:image/jpeg;base64,{}", base64(&jpeg))),
+        Err(e) => {
+            warn!("could not make a thumbnail of {}: {e:#}", path.display());
+            None
+        }
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn page(placed: &[Placed]) -> Result<String> {
+    let markers: Vec<_> = placed
+        .iter()
+        .map(|p| {
+            json!({
+                "lat": p.latitude,
+                "lon": p.longitude,
+                "name": p.name,
+                "link": p.link,
+                "taken": p.taken,
+                "thumb": p.thumbnail,
+            })
+        })
+        .collect();
+    // So a name can't close the script early
+    let markers = serde_json::to_string(&markers)?.replace("</", "<\\/");
+
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title}</title>
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<link rel="stylesheet" href="{LEAFLET}/leaflet.css" integrity="{LEAFLET_CSS_INTEGRITY}" crossorigin="">
+<script src="{LEAFLET}/leaflet.js" integrity="{LEAFLET_JS_INTEGRITY}" crossorigin=""></script>
+<style>html,body,#map{{height:100%;margin:0}}.photo img{{display:block;max-width:200px;max-height:200px}}.photo div{{font:12px sans-serif;margin-top:4px}}
+.cluster div{{width:36px;height:36px;line-height:36px;border-radius:50%;background:rgba(49,130,189,.85);color:#fff;text-align:center;font:bold 13px sans-serif}}</style>
+</head>
+<body><div id="map"></div>
+<script>
+const photos = {markers};
+const map = L.map('map');
+L.tileLayer('https://tile.openstreetmap.org/{{z}}/{{x}}/{{y}}.png', {{
+  maxZoom: 19,
+  attribution: '&copy; <a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a> contributors',
+}}).addTo(map);
+
+function popup(photo) {{
+  const popup = document.createElement('div');
+  popup.className = 'photo';
+  const link = document.createElement('a');
+  link.href = photo.link;
+  link.target = '_blank';
+  if (photo.thumb) {{
+    const img = document.createElement('img');
+    img.src = photo.thumb;
+    img.alt = photo.name;
+    link.append(img);
+  }} else {{
+    link.textContent = photo.name;
+  }}
+  const caption = document.createElement('div');
+  caption.textContent = photo.taken ? photo.name + ', ' + photo.taken : photo.name;
+  popup.append(link, caption);
+  return popup;
+}}
+
+// Photos close together at the current zoom are drawn as one marker, which zooms in on them
+const markers = L.layerGroup().addTo(map);
+function draw() {{
+  markers.clearLayers();
+  const cells = new Map();
+  for (const photo of photos) {{
+    const point = map.project([photo.lat, photo.lon]);
+    const key = Math.floor(point.x / {CLUSTER_CELL}) + ',' + Math.floor(point.y / {CLUSTER_CELL});
+    if (!cells.has(key)) cells.set(key, []);
+    cells.get(key).push(photo);
+  }}
+  for (const cell of cells.values()) {{
+    if (cell.length === 1 || map.getZoom() >= map.getMaxZoom()) {{
+      for (const photo of cell) {{
+        L.marker([photo.lat, photo.lon], {{ title: photo.name }}).bindPopup(() => popup(photo)).addTo(markers);
+      }}
+      continue;
+    }}
+    const bounds = L.latLngBounds(cell.map(photo => [photo.lat, photo.lon]));
+    const icon = L.divIcon({{ html: '<div>' + cell.length + '</div>', className: 'cluster', iconSize: [36, 36] }});
+    L.marker(bounds.getCenter(), {{ icon }}).on('click', () => map.fitBounds(bounds, {{ padding: [20, 20] }})).addTo(markers);
+  }}
+}}
+
+if (photos.length) {{
+  map.fitBounds(L.latLngBounds(photos.map(photo => [photo.lat, photo.lon])), {{ padding: [20, 20], maxZoom: 15 }});
+}} else {{
+  map.setView([20, 0], 2);
+}}
+draw();
+map.on('zoomend', draw);
+</script>
+</body></html>
+"#,
+        title = escape(&format!("{} photos", placed.len())),
+    ))
+}
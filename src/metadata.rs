@@ -0,0 +1,1059 @@
+use color_eyre::eyre::{eyre, Result};
+use std::fs;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use time::PrimitiveDateTime;
+use time::macros::format_description;
+use tracing::debug;
+
+/// What we can learn about a capture from its embedded metadata, without decoding image data
+#[derive(Debug, Default)]
+pub struct CaptureInfo {
+    pub taken: Option<PrimitiveDateTime>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// Latitude and longitude in degrees, negative south and west
+    pub location: Option<(f64, f64)>,
+    /// How long a video runs, in seconds
+    pub duration: Option<f64>,
+}
+
+const TAG_MAKE: u16 = 0x010f;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_ARTIST: u16 = 0x013b;
+const TAG_COPYRIGHT: u16 = 0x8298;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+
+const TYPE_ASCII: u16 = 2;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+// APP1 segments have a u16 length, which counts itself and the `Exif\0\0` header too
+const MAX_EXIF_TIFF: usize = u16::MAX as usize - 8;
+
+// Guard against garbage offsets sending us off reading huge "IFDs"
+const MAX_IFD_ENTRIES: u16 = 1024;
+
+// Canon's CR3 metadata lives in a uuid box inside moov
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+// Top level atoms QuickTime files written before `ftyp` existed can start with
+const QUICKTIME_ATOMS: &[&[u8; 4]] = &[b"moov", b"mdat", b"wide", b"free", b"skip", b"pnot"];
+
+// MP4 and QuickTime times count seconds from the start of 1904, in UTC
+const QUICKTIME_EPOCH: PrimitiveDateTime = time::macros::datetime!(1904-01-01 0:00);
+
+/// Read capture metadata from TIFF based RAWs (cr2, nef, arw, dng, ...), CR3, RAF, HEIC and JPEG
+/// files, and the creation time and duration of MP4, MOV and AVI videos. Files in other formats, or
+/// without metadata, give an empty `CaptureInfo`
+pub fn read_capture_info(path: &Path) -> Result<CaptureInfo> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0; 16];
+    let read = reader.read(&mut magic)?;
+    let magic = &magic[..read];
+
+    let info = if magic.starts_with(b"II") || magic.starts_with(b"MM") {
+        from_tiff(&mut reader, 0)?
+    } else if magic.starts_with(&[0xff, 0xd8]) {
+        from_jpeg(&mut reader, 0)?
+    } else if magic.starts_with(b"FUJIFILMCCD-RAW") {
+        from_raf(&mut reader)?
+    } else if magic.get(4..8).is_some_and(|kind| kind == b"ftyp" || QUICKTIME_ATOMS.iter().any(|a| kind == *a)) {
+        from_bmff(&mut reader)?
+    } else if magic.starts_with(b"RIFF") && magic.get(8..12) == Some(b"AVI ") {
+        from_avi(&mut reader)?
+    } else {
+        debug!("no known metadata container in {}", path.display());
+        CaptureInfo::default()
+    };
+
+    Ok(info)
+}
+
+impl CaptureInfo {
+    /// The camera's make and model, without the make twice as most cameras already repeat it in
+    /// the model
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{make} {model}")),
+            (Some(camera), None) | (None, Some(camera)) => Some(camera.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+fn parse_exif_date(raw: &str) -> Option<PrimitiveDateTime> {
+    let format = format_description!("[year]:[month]:[day] [hour]:[minute]:[second]");
+    PrimitiveDateTime::parse(raw.trim_end_matches('\0').trim(), &format).ok()
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: [u8; 4],
+}
+
+/// A TIFF structure starting at `base` within some larger file
+struct Tiff<'a, R> {
+    reader: &'a mut R,
+    base: u64,
+    little_endian: bool,
+}
+
+impl<'a, R: Read + Seek> Tiff<'a, R> {
+    fn new(reader: &'a mut R, base: u64) -> Result<Self> {
+        reader.seek(SeekFrom::Start(base))?;
+        let mut order = [0; 2];
+        reader.read_exact(&mut order)?;
+
+        let little_endian = match &order {
+            b"II" => true,
+            b"MM" => false,
+            _ => return Err(eyre!("invalid tiff byte order")),
+        };
+
+        Ok(Self { reader, base, little_endian })
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let mut buf = [0; 2];
+        self.reader.read_exact(&mut buf)?;
+        Ok(self.decode_u16(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.reader.read_exact(&mut buf)?;
+        Ok(self.decode_u32(buf))
+    }
+
+    fn decode_u16(&self, buf: [u8; 2]) -> u16 {
+        if self.little_endian { u16::from_le_bytes(buf) } else { u16::from_be_bytes(buf) }
+    }
+
+    fn decode_u32(&self, buf: [u8; 4]) -> u32 {
+        if self.little_endian { u32::from_le_bytes(buf) } else { u32::from_be_bytes(buf) }
+    }
+
+    fn seek(&mut self, offset: u32) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        Ok(())
+    }
+
+    fn first_ifd(&mut self) -> Result<u32> {
+        // Skip the byte order and the magic number, which varies between raw formats
+        self.seek(4)?;
+        self.u32()
+    }
+
+    fn read_ifd(&mut self, offset: u32) -> Result<Vec<Entry>> {
+        self.seek(offset)?;
+        let count = self.u16()?;
+        if count > MAX_IFD_ENTRIES {
+            return Err(eyre!("ifd at {offset} claims {count} entries, likely not an ifd"));
+        }
+
+        let mut entries = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            let tag = self.u16()?;
+            let kind = self.u16()?;
+            let count = self.u32()?;
+            let mut value = [0; 4];
+            self.reader.read_exact(&mut value)?;
+            entries.push(Entry { tag, kind, count, value });
+        }
+
+        Ok(entries)
+    }
+
+    fn ascii(&mut self, entry: &Entry) -> Result<Option<String>> {
+        if entry.kind != TYPE_ASCII {
+            return Ok(None);
+        }
+
+        let bytes = if entry.count <= 4 {
+            entry.value[..entry.count as usize].to_vec()
+        } else {
+            // Read through `take`, so a garbage count can't allocate more than the file holds
+            self.seek(self.decode_u32(entry.value))?;
+            let mut buf = vec![];
+            self.reader.by_ref().take(entry.count.into()).read_to_end(&mut buf)?;
+            if buf.len() < entry.count as usize {
+                return Err(eyre!("tag {:#06x} runs past the end of the file", entry.tag));
+            }
+            buf
+        };
+
+        let s = String::from_utf8_lossy(&bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        Ok(Some(s).filter(|s| !s.is_empty()))
+    }
+
+    fn long(&self, entry: &Entry) -> Option<u32> {
+        (entry.kind == TYPE_LONG).then(|| self.decode_u32(entry.value))
+    }
+
+    /// Degrees from three rationals, as degrees, minutes and seconds
+    fn degrees(&mut self, entry: &Entry) -> Result<Option<f64>> {
+        if entry.kind != TYPE_RATIONAL || entry.count != 3 {
+            return Ok(None);
+        }
+
+        self.seek(self.decode_u32(entry.value))?;
+        let mut parts = [0.0; 3];
+        for part in &mut parts {
+            let (numerator, denominator) = (self.u32()?, self.u32()?);
+            if denominator == 0 {
+                return Ok(None);
+            }
+            *part = f64::from(numerator) / f64::from(denominator);
+        }
+        Ok(Some(parts[0] + parts[1] / 60.0 + parts[2] / 3600.0))
+    }
+
+    /// Fill `info` from an IFD0-like directory, returning the EXIF IFD offset if it has one
+    fn read_primary(&mut self, offset: u32, info: &mut CaptureInfo) -> Result<Option<u32>> {
+        let mut exif = None;
+        let mut gps = None;
+        for entry in self.read_ifd(offset)? {
+            match entry.tag {
+                TAG_MAKE => info.make = self.ascii(&entry)?,
+                TAG_MODEL => info.model = self.ascii(&entry)?,
+                TAG_DATE_TIME if info.taken.is_none() => {
+                    info.taken = self.ascii(&entry)?.as_deref().and_then(parse_exif_date)
+                }
+                TAG_EXIF_IFD => exif = self.long(&entry),
+                TAG_GPS_IFD => gps = self.long(&entry),
+                _ => {}
+            }
+        }
+
+        // A broken location shouldn't cost us the date
+        if let Some(gps) = gps
+            && let Err(e) = self.read_gps(gps, info)
+        {
+            debug!("could not read gps ifd: {e}");
+        }
+        Ok(exif)
+    }
+
+    /// Fill `info` from a GPS directory
+    fn read_gps(&mut self, offset: u32, info: &mut CaptureInfo) -> Result<()> {
+        let (mut latitude, mut longitude) = (None, None);
+        let (mut south, mut west) = (false, false);
+        for entry in self.read_ifd(offset)? {
+            match entry.tag {
+                TAG_GPS_LATITUDE_REF => south = self.ascii(&entry)?.as_deref() == Some("S"),
+                TAG_GPS_LATITUDE => latitude = self.degrees(&entry)?,
+                TAG_GPS_LONGITUDE_REF => west = self.ascii(&entry)?.as_deref() == Some("W"),
+                TAG_GPS_LONGITUDE => longitude = self.degrees(&entry)?,
+                _ => {}
+            }
+        }
+
+        // Some cameras write zeros when they had no fix
+        if let (Some(latitude), Some(longitude)) = (latitude, longitude)
+            && (latitude, longitude) != (0.0, 0.0)
+        {
+            info.location = Some((
+                if south { -latitude } else { latitude },
+                if west { -longitude } else { longitude },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fill `info` from an EXIF directory. DateTimeOriginal beats the primary DateTime,
+    /// which some software rewrites on edit
+    fn read_exif(&mut self, offset: u32, info: &mut CaptureInfo) -> Result<()> {
+        for entry in self.read_ifd(offset)? {
+            if entry.tag == TAG_DATE_TIME_ORIGINAL
+                && let Some(taken) = self.ascii(&entry)?.as_deref().and_then(parse_exif_date)
+            {
+                info.taken = Some(taken);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn from_tiff<R: Read + Seek>(reader: &mut R, base: u64) -> Result<CaptureInfo> {
+    let mut tiff = Tiff::new(reader, base)?;
+    let mut info = CaptureInfo::default();
+
+    let ifd0 = tiff.first_ifd()?;
+    if let Some(exif) = tiff.read_primary(ifd0, &mut info)? {
+        tiff.read_exif(exif, &mut info)?;
+    }
+
+    Ok(info)
+}
+
+/// Ownership fields to stamp into a file's EXIF, `None` leaves a field as it is
+#[derive(Debug, Default)]
+pub struct ExifFields {
+    pub artist: Option<String>,
+    pub copyright: Option<String>,
+}
+
+impl ExifFields {
+    fn tags(&self) -> Vec<(u16, &str)> {
+        [(TAG_ARTIST, &self.artist), (TAG_COPYRIGHT, &self.copyright)]
+            .into_iter()
+            .filter_map(|(tag, value)| value.as_deref().map(|v| (tag, v)))
+            .collect()
+    }
+}
+
+/// Whether `set_fields` can write into `content`. Only JPEG is, as rewriting RAW containers
+/// risks the maker notes and previews other software relies on
+pub fn is_writable(content: &[u8]) -> bool {
+    content.starts_with(&[0xff, 0xd8])
+}
+
+/// `jpeg` with `fields` set in its EXIF, adding an EXIF segment if it has none
+pub fn set_fields(jpeg: &[u8], fields: &ExifFields) -> Result<Vec<u8>> {
+    if !is_writable(jpeg) {
+        return Err(eyre!("only jpeg files can have their exif rewritten"));
+    }
+
+    let (segment, tiff) = match find_exif_segment(jpeg)? {
+        Some(range) => (range.clone(), jpeg[range.start + 10..range.end].to_vec()),
+        None => {
+            // Straight after SOI, or after the JFIF segment which has to come first
+            let at = match jpeg.get(2..6) {
+                Some(&[0xff, 0xe0, hi, lo]) => 4 + usize::from(u16::from_be_bytes([hi, lo])),
+                _ => 2,
+            };
+            if at > jpeg.len() {
+                return Err(eyre!("jfif segment runs past the end of the file"));
+            }
+            // An empty little endian IFD0
+            (at..at, b"II*\0\x08\0\0\0\0\0\0\0\0\0".to_vec())
+        }
+    };
+
+    let original = tiff.clone();
+    let tiff = set_ascii_tags(tiff, &fields.tags())?;
+    if tiff == original && !segment.is_empty() {
+        return Ok(jpeg.to_vec());
+    }
+    if tiff.len() > MAX_EXIF_TIFF {
+        return Err(eyre!("exif would be {} bytes, more than fits in a jpeg segment", tiff.len()));
+    }
+
+    let mut out = Vec::with_capacity(jpeg.len() + tiff.len());
+    out.extend_from_slice(&jpeg[..segment.start]);
+    out.extend_from_slice(&[0xff, 0xe1]);
+    out.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    out.extend_from_slice(b"Exif\0\0");
+    out.extend_from_slice(&tiff);
+    out.extend_from_slice(&jpeg[segment.end..]);
+    Ok(out)
+}
+
+/// Where the APP1 `Exif` segment of a JPEG is, marker included
+fn find_exif_segment(jpeg: &[u8]) -> Result<Option<std::ops::Range<usize>>> {
+    let mut pos = 2;
+    loop {
+        let Some(&[0xff, marker, len_hi, len_lo]) = jpeg.get(pos..pos + 4) else {
+            return Err(eyre!("invalid jpeg marker at {pos}"));
+        };
+        if marker == 0xda || marker == 0xd9 {
+            return Ok(None);
+        }
+
+        let len = usize::from(u16::from_be_bytes([len_hi, len_lo]));
+        let end = pos + 2 + len;
+        if marker == 0xe1 && jpeg.get(pos + 4..pos + 10) == Some(b"Exif\0\0") {
+            // The length counts itself and `Exif\0\0`, so anything shorter is cut off mid header
+            if len < 8 || end > jpeg.len() {
+                return Err(eyre!("exif segment at {pos} is {len} bytes, which doesn't fit"));
+            }
+            return Ok(Some(pos..end));
+        }
+        pos = end;
+    }
+}
+
+/// Set ASCII `tags` in IFD0 of a TIFF structure. The new IFD0 and values are appended, and the
+/// old ones left in place, so no offset into the rest of the structure has to change. `tiff` is
+/// returned as it was if the tags already have those values
+fn set_ascii_tags(mut tiff: Vec<u8>, tags: &[(u16, &str)]) -> Result<Vec<u8>> {
+    let (little_endian, mut entries, next, unchanged) = {
+        let mut cursor = Cursor::new(&tiff);
+        let mut reader = Tiff::new(&mut cursor, 0)?;
+        let ifd0 = reader.first_ifd()?;
+        let entries = reader.read_ifd(ifd0)?;
+        let next = reader.u32()?;
+
+        let mut unchanged = true;
+        for (tag, value) in tags {
+            let current = match entries.iter().find(|e| e.tag == *tag) {
+                Some(entry) => reader.ascii(entry)?,
+                None => None,
+            };
+            unchanged &= current.as_deref() == Some(value.trim());
+        }
+        (reader.little_endian, entries, next, unchanged)
+    };
+    if unchanged {
+        return Ok(tiff);
+    }
+
+    let u32_bytes = |n: u32| if little_endian { n.to_le_bytes() } else { n.to_be_bytes() };
+    let u16_bytes = |n: u16| if little_endian { n.to_le_bytes() } else { n.to_be_bytes() };
+
+    entries.retain(|e| !tags.iter().any(|(tag, _)| *tag == e.tag));
+    for (tag, value) in tags {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+
+        let mut inline = [0; 4];
+        if bytes.len() <= 4 {
+            inline[..bytes.len()].copy_from_slice(&bytes);
+        } else {
+            // Offsets have to be even
+            if tiff.len() % 2 == 1 {
+                tiff.push(0);
+            }
+            inline = u32_bytes(tiff.len() as u32);
+            tiff.extend_from_slice(&bytes);
+        }
+        entries.push(Entry { tag: *tag, kind: TYPE_ASCII, count: bytes.len() as u32, value: inline });
+    }
+    entries.sort_by_key(|e| e.tag);
+
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let ifd0 = tiff.len() as u32;
+    tiff.extend_from_slice(&u16_bytes(entries.len() as u16));
+    for entry in &entries {
+        tiff.extend_from_slice(&u16_bytes(entry.tag));
+        tiff.extend_from_slice(&u16_bytes(entry.kind));
+        tiff.extend_from_slice(&u32_bytes(entry.count));
+        tiff.extend_from_slice(&entry.value);
+    }
+    tiff.extend_from_slice(&u32_bytes(next));
+    tiff[4..8].copy_from_slice(&u32_bytes(ifd0));
+
+    Ok(tiff)
+}
+
+/// Bytes taken by one value of a TIFF field type
+fn type_size(kind: u16) -> usize {
+    match kind {
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+/// Blank out the GPS IFD of a raw EXIF chunk (a TIFF structure, as found after `Exif\0\0`),
+/// values included, leaving an empty IFD in its place. Returns whether there was one
+pub fn strip_gps(exif: &mut [u8]) -> Result<bool> {
+    let (gps, blank) = {
+        let mut cursor = Cursor::new(&*exif);
+        let mut tiff = Tiff::new(&mut cursor, 0)?;
+        let ifd0 = tiff.first_ifd()?;
+        let Some(pointer) = tiff.read_ifd(ifd0)?.into_iter().find(|e| e.tag == TAG_GPS_IFD) else {
+            return Ok(false);
+        };
+
+        let gps = tiff.decode_u32(pointer.value) as usize;
+        let entries = tiff.read_ifd(gps as u32)?;
+
+        // The entries themselves, then any values too big to be stored inline
+        let table = gps + 2..gps + 2 + entries.len() * 12;
+        let mut blank = vec![table];
+        for entry in &entries {
+            let size = type_size(entry.kind) * entry.count as usize;
+            if size > 4 {
+                let offset = tiff.decode_u32(entry.value) as usize;
+                blank.push(offset..offset + size);
+            }
+        }
+        (gps, blank)
+    };
+
+    // A zero entry count, and the zeroed entries read as no next IFD
+    exif[gps..gps + 2].fill(0);
+    for range in blank {
+        if let Some(bytes) = exif.get_mut(range) {
+            bytes.fill(0);
+        }
+    }
+
+    Ok(true)
+}
+
+fn from_jpeg<R: Read + Seek>(reader: &mut R, base: u64) -> Result<CaptureInfo> {
+    // Skip SOI, then walk the marker segments looking for APP1 "Exif"
+    let mut pos = base + 2;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+
+        let [0xff, marker, len_hi, len_lo] = header else {
+            return Err(eyre!("invalid jpeg marker at {pos}"));
+        };
+
+        // Start of scan means image data, there are no more metadata segments
+        if marker == 0xda || marker == 0xd9 {
+            return Ok(CaptureInfo::default());
+        }
+
+        let len = u64::from(u16::from_be_bytes([len_hi, len_lo]));
+        if marker == 0xe1 {
+            let mut ident = [0; 6];
+            reader.read_exact(&mut ident)?;
+            if &ident == b"Exif\0\0" {
+                return from_tiff(reader, pos + 10);
+            }
+        }
+
+        pos += 2 + len;
+    }
+}
+
+/// The XMP packet embedded in a JPEG, if it has one
+pub fn read_xmp(path: &Path) -> Result<Option<String>> {
+    const XMP_IDENT: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0; 2];
+    if reader.read(&mut magic)? < 2 || magic != [0xff, 0xd8] {
+        return Ok(None);
+    }
+
+    let mut pos = 2;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+
+        let [0xff, marker, len_hi, len_lo] = header else {
+            return Err(eyre!("invalid jpeg marker at {pos}"));
+        };
+        if marker == 0xda || marker == 0xd9 {
+            return Ok(None);
+        }
+
+        let len = u64::from(u16::from_be_bytes([len_hi, len_lo]));
+        if marker == 0xe1 && len as usize > 2 + XMP_IDENT.len() {
+            let mut segment = vec![0; len as usize - 2];
+            reader.read_exact(&mut segment)?;
+            if let Some(packet) = segment.strip_prefix(XMP_IDENT) {
+                return Ok(Some(String::from_utf8_lossy(packet).into_owned()));
+            }
+        }
+
+        pos += 2 + len;
+    }
+}
+
+fn from_raf<R: Read + Seek>(reader: &mut R) -> Result<CaptureInfo> {
+    // RAF embeds a full JPEG preview carrying the EXIF, its offset is a big endian u32 at 84
+    reader.seek(SeekFrom::Start(84))?;
+    let mut offset = [0; 4];
+    reader.read_exact(&mut offset)?;
+    from_jpeg(reader, u32::from_be_bytes(offset).into())
+}
+
+struct BmffBox {
+    kind: [u8; 4],
+    start: u64,
+    end: u64,
+}
+
+/// ISO base media boxes between `start` and `end`
+fn bmff_boxes<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Vec<BmffBox>> {
+    let mut boxes = vec![];
+    let mut pos = start;
+
+    while pos.saturating_add(8) <= end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 8];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+
+        let size = u32::from_be_bytes(header[..4].try_into().expect("slice to be 4 bytes"));
+        let kind: [u8; 4] = header[4..].try_into().expect("slice to be 4 bytes");
+        let (size, header_len) = match size {
+            0 => (end - pos, 8),
+            1 => {
+                let mut large = [0; 8];
+                reader.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            n => (u64::from(n), 8),
+        };
+
+        let box_end = pos.checked_add(size).filter(|_| size >= header_len);
+        let Some(box_end) = box_end else {
+            return Err(eyre!("invalid box size {size} at {pos}"));
+        };
+
+        boxes.push(BmffBox { kind, start: pos + header_len, end: box_end });
+        pos = box_end;
+    }
+
+    Ok(boxes)
+}
+
+fn from_bmff<R: Read + Seek>(reader: &mut R) -> Result<CaptureInfo> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut info = CaptureInfo::default();
+
+    let boxes = bmff_boxes(reader, 0, end)?;
+    // HEIC and AVIF keep their EXIF as an item, where the movie boxes would be
+    if let Some(meta) = boxes.iter().find(|b| &b.kind == b"meta")
+        && let Some(tiff) = heif_exif(reader, meta)?
+    {
+        return from_tiff(reader, tiff);
+    }
+
+    let Some(moov) = boxes.into_iter().find(|b| &b.kind == b"moov") else {
+        return Ok(info);
+    };
+
+    let children = bmff_boxes(reader, moov.start, moov.end)?;
+    for uuid in children.iter().filter(|b| &b.kind == b"uuid") {
+        let mut id = [0; 16];
+        reader.seek(SeekFrom::Start(uuid.start))?;
+        reader.read_exact(&mut id)?;
+        if id != CANON_UUID {
+            continue;
+        }
+
+        // CMT1 holds IFD0, CMT2 the EXIF IFD and CMT4 the GPS IFD, each as a standalone TIFF structure
+        for b in bmff_boxes(reader, uuid.start + 16, uuid.end)? {
+            match &b.kind {
+                b"CMT1" => {
+                    let mut tiff = Tiff::new(reader, b.start)?;
+                    let ifd = tiff.first_ifd()?;
+                    tiff.read_primary(ifd, &mut info)?;
+                }
+                b"CMT2" => {
+                    let mut tiff = Tiff::new(reader, b.start)?;
+                    let ifd = tiff.first_ifd()?;
+                    tiff.read_exif(ifd, &mut info)?;
+                }
+                b"CMT4" => {
+                    let mut tiff = Tiff::new(reader, b.start)?;
+                    let ifd = tiff.first_ifd()?;
+                    if let Err(e) = tiff.read_gps(ifd, &mut info) {
+                        debug!("could not read gps from CMT4: {e}");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // A CR3's EXIF date is in the camera's time zone, so it's preferred to the movie header's
+    if let Some(mvhd) = children.iter().find(|b| &b.kind == b"mvhd") {
+        let (created, duration) = movie_header(reader, mvhd)?;
+        info.taken = info.taken.or(created);
+        info.duration = duration;
+    }
+
+    Ok(info)
+}
+
+/// Where the TIFF structure of a HEIF's `Exif` item starts, going by the item info and locations
+/// in its `meta` box
+fn heif_exif<R: Read + Seek>(reader: &mut R, meta: &BmffBox) -> Result<Option<u64>> {
+    // `meta` and the boxes within it used here are full boxes, with a version and flags first
+    let children = bmff_boxes(reader, meta.start + 4, meta.end)?;
+    let (Some(iinf), Some(iloc)) = (
+        children.iter().find(|b| &b.kind == b"iinf"),
+        children.iter().find(|b| &b.kind == b"iloc"),
+    ) else {
+        return Ok(None);
+    };
+
+    reader.seek(SeekFrom::Start(iinf.start))?;
+    let version = read_be(reader, 4)? >> 24;
+    let entries = iinf.start + 4 + if version == 0 { 2 } else { 4 };
+    let mut exif_item = None;
+    for infe in bmff_boxes(reader, entries, iinf.end)?.iter().filter(|b| &b.kind == b"infe") {
+        reader.seek(SeekFrom::Start(infe.start))?;
+        // Item types came with version 2
+        let version = read_be(reader, 4)? >> 24;
+        if version < 2 {
+            continue;
+        }
+        let id = read_be(reader, if version == 2 { 2 } else { 4 })?;
+        let _protection = read_be(reader, 2)?;
+        let mut kind = [0; 4];
+        reader.read_exact(&mut kind)?;
+        if &kind == b"Exif" {
+            exif_item = Some(id);
+            break;
+        }
+    }
+    let Some(exif_item) = exif_item else {
+        return Ok(None);
+    };
+
+    reader.seek(SeekFrom::Start(iloc.start))?;
+    let version = read_be(reader, 4)? >> 24;
+    let sizes = read_be(reader, 2)?;
+    let (offset_size, length_size, base_size) = ((sizes >> 12) & 0xf, (sizes >> 8) & 0xf, (sizes >> 4) & 0xf);
+    let index_size = if version > 0 { sizes & 0xf } else { 0 };
+    let id_size = if version < 2 { 2 } else { 4 };
+    let items = read_be(reader, id_size)?;
+    for _ in 0..items {
+        let id = read_be(reader, id_size)?;
+        let construction = if version > 0 { read_be(reader, 2)? & 0xf } else { 0 };
+        let _data_reference = read_be(reader, 2)?;
+        let base = read_be(reader, base_size)?;
+        let mut start = None;
+        for _ in 0..read_be(reader, 2)? {
+            let _index = read_be(reader, index_size)?;
+            let offset = read_be(reader, offset_size)?;
+            let _length = read_be(reader, length_size)?;
+            start.get_or_insert(base.checked_add(offset));
+        }
+
+        // Items can also live within `meta` itself, which cameras don't do for EXIF
+        if id != exif_item || construction != 0 {
+            continue;
+        }
+        // Offsets which overflow can only come from a broken file
+        let Some(Some(start)) = start else {
+            return Ok(None);
+        };
+        // The item starts with how far past that the TIFF header is, after `Exif\0\0` usually
+        reader.seek(SeekFrom::Start(start))?;
+        let header_offset = read_be(reader, 4)?;
+        return Ok(start.checked_add(4 + header_offset));
+    }
+
+    Ok(None)
+}
+
+/// A big endian unsigned integer `len` bytes long, from 0 to 8
+fn read_be<R: Read>(reader: &mut R, len: u64) -> Result<u64> {
+    let mut buf = [0; 8];
+    let len = usize::try_from(len)?.min(8);
+    reader.read_exact(&mut buf[8 - len..])?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// When a movie was created and how long it runs, from its `mvhd` box. Cameras which don't know
+/// the time write 0
+fn movie_header<R: Read + Seek>(reader: &mut R, mvhd: &BmffBox) -> Result<(Option<PrimitiveDateTime>, Option<f64>)> {
+    reader.seek(SeekFrom::Start(mvhd.start))?;
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+
+    let mut u32_field = || -> Result<u64> {
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf).into())
+    };
+    let (created, timescale, duration) = if version[0] == 1 {
+        let created = u32_field()? << 32 | u32_field()?;
+        let _modified = u32_field()? << 32 | u32_field()?;
+        let timescale = u32_field()?;
+        (created, timescale, u32_field()? << 32 | u32_field()?)
+    } else {
+        let created = u32_field()?;
+        let _modified = u32_field()?;
+        (created, u32_field()?, u32_field()?)
+    };
+
+    let created = (created > 0)
+        .then(|| QUICKTIME_EPOCH.checked_add(time::Duration::seconds(created.try_into().ok()?)))
+        .flatten();
+    let duration = (timescale > 0).then(|| duration as f64 / timescale as f64);
+    Ok((created, duration))
+}
+
+/// The creation time and duration of an AVI, from the `IDIT` or `ICRD` chunks cameras and
+/// editors write, and the main header's frame rate and count
+fn from_avi<R: Read + Seek>(reader: &mut R) -> Result<CaptureInfo> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut info = CaptureInfo::default();
+
+    let mut lists = vec![(12, end)];
+    while let Some((start, end)) = lists.pop() {
+        let mut pos = start;
+        while pos + 8 <= end {
+            reader.seek(SeekFrom::Start(pos))?;
+            let mut header = [0; 8];
+            if reader.read_exact(&mut header).is_err() {
+                break;
+            }
+            let id: [u8; 4] = header[..4].try_into().expect("slice to be 4 bytes");
+            let size = u64::from(u32::from_le_bytes(header[4..].try_into().expect("slice to be 4 bytes")));
+            let data = pos + 8;
+
+            match &id {
+                b"LIST" => {
+                    let mut kind = [0; 4];
+                    reader.read_exact(&mut kind)?;
+                    // The frames themselves, which can be most of the file
+                    if &kind != b"movi" {
+                        lists.push((data + 4, (data + size).min(end)));
+                    }
+                }
+                b"avih" if size >= 20 => {
+                    let mut avih = [0; 20];
+                    reader.read_exact(&mut avih)?;
+                    let frame_micros = u32::from_le_bytes(avih[..4].try_into().expect("slice to be 4 bytes"));
+                    let frames = u32::from_le_bytes(avih[16..].try_into().expect("slice to be 4 bytes"));
+                    if frame_micros > 0 {
+                        info.duration = Some(f64::from(frame_micros) * f64::from(frames) / 1_000_000.0);
+                    }
+                }
+                b"IDIT" | b"ICRD" if size <= 64 => {
+                    let mut raw = vec![0; size as usize];
+                    reader.read_exact(&mut raw)?;
+                    let raw = String::from_utf8_lossy(&raw);
+                    info.taken = info.taken.or_else(|| parse_riff_date(raw.trim_end_matches(['\0', '\n', ' '])));
+                }
+                _ => {}
+            }
+
+            // Chunks are padded to an even length
+            pos = data + size + (size & 1);
+        }
+    }
+
+    Ok(info)
+}
+
+/// `Mon Jan 01 12:00:00 2024` as cameras write in `IDIT`, or `2024-01-01` as in `ICRD`
+fn parse_riff_date(raw: &str) -> Option<PrimitiveDateTime> {
+    let written = format_description!(
+        "[weekday repr:short case_sensitive:false] [month repr:short case_sensitive:false] [day padding:none] \
+         [hour]:[minute]:[second] [year]"
+    );
+    PrimitiveDateTime::parse(&raw.split_whitespace().collect::<Vec<_>>().join(" "), written)
+        .ok()
+        .or_else(|| parse_exif_date(raw))
+        .or_else(|| {
+            time::Date::parse(raw.get(..10)?, format_description!("[year]-[month]-[day]"))
+                .ok()
+                .map(|d| d.midnight())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    /// A little endian TIFF structure whose IFD0 only holds a DateTime
+    fn tiff_with_date() -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&TAG_DATE_TIME.to_le_bytes());
+        tiff.extend_from_slice(&TYPE_ASCII.to_le_bytes());
+        tiff.extend_from_slice(&20u32.to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(b"2024:01:02 03:04:05\0");
+        tiff
+    }
+
+    /// The value of an ASCII tag in IFD0
+    fn ascii_tag(tiff: &[u8], tag: u16) -> Option<String> {
+        let mut cursor = Cursor::new(tiff);
+        let mut tiff = Tiff::new(&mut cursor, 0).unwrap();
+        let ifd0 = tiff.first_ifd().unwrap();
+        let entry = tiff.read_ifd(ifd0).unwrap().into_iter().find(|e| e.tag == tag)?;
+        tiff.ascii(&entry).unwrap()
+    }
+
+    fn segment(marker: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xff, marker];
+        out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8];
+        out.extend(segments.concat());
+        out.extend_from_slice(&[0xff, 0xda, 0, 2, 0xff, 0xd9]);
+        out
+    }
+
+    fn exif_segment() -> Vec<u8> {
+        segment(0xe1, &[b"Exif\0\0".as_slice(), &tiff_with_date()].concat())
+    }
+
+    fn bmff(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn riff(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    #[test]
+    fn finds_the_exif_segment() {
+        let jfif = segment(0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        let content = jpeg(&[jfif.clone(), exif_segment()]);
+
+        let range = find_exif_segment(&content).unwrap().expect("an exif segment");
+        assert_eq!(range, 2 + jfif.len()..2 + jfif.len() + exif_segment().len());
+        assert_eq!(from_jpeg(&mut Cursor::new(&content), 0).unwrap().taken, Some(datetime!(2024-01-02 03:04:05)));
+
+        assert_eq!(find_exif_segment(&jpeg(&[jfif])).unwrap(), None);
+        assert!(find_exif_segment(&[0xff, 0xd8, 0xff]).is_err());
+
+        // Lengths too short for the header, or past the end of the file
+        for len in [0u16, 7, 0xffff] {
+            let mut content = jpeg(&[exif_segment()]);
+            content[4..6].copy_from_slice(&len.to_be_bytes());
+            assert!(find_exif_segment(&content).is_err(), "length {len}");
+            assert!(set_fields(&content, &ExifFields { artist: Some("Amy".into()), copyright: None }).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_ascii_values_past_the_end() {
+        let mut tiff = tiff_with_date();
+        tiff[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(from_tiff(&mut Cursor::new(&tiff), 0).is_err());
+    }
+
+    #[test]
+    fn rejects_boxes_past_u64() {
+        let mut large = 1u32.to_be_bytes().to_vec();
+        large.extend_from_slice(b"mdat");
+        large.extend_from_slice(&u64::MAX.to_be_bytes());
+        let content = [bmff(b"ftyp", b"heic\0\0\0\0"), large].concat();
+        assert!(bmff_boxes(&mut Cursor::new(&content), 0, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn sets_fields_in_place_or_adds_a_segment() {
+        let fields = ExifFields { artist: Some("Amy".into()), copyright: Some("CC BY 4.0".into()) };
+        for original in [jpeg(&[exif_segment()]), jpeg(&[])] {
+            let written = set_fields(&original, &fields).unwrap();
+            let range = find_exif_segment(&written).unwrap().expect("an exif segment");
+
+            let tiff = &written[range.start + 10..range.end];
+            assert_eq!(ascii_tag(tiff, TAG_ARTIST).as_deref(), Some("Amy"));
+            assert_eq!(ascii_tag(tiff, TAG_COPYRIGHT).as_deref(), Some("CC BY 4.0"));
+
+            // Already set, so nothing changes
+            assert_eq!(set_fields(&written, &fields).unwrap(), written);
+        }
+        assert!(set_fields(b"not a jpeg", &fields).is_err());
+    }
+
+    #[test]
+    fn reads_the_movie_header() {
+        let mut mvhd = vec![0; 4];
+        // 2024-01-02 03:04:05 in seconds since 1904
+        let created = (datetime!(2024-01-02 03:04:05) - QUICKTIME_EPOCH).whole_seconds() as u32;
+        for field in [created, created, 1000, 12_500] {
+            mvhd.extend_from_slice(&field.to_be_bytes());
+        }
+        let content = [bmff(b"ftyp", b"qt  \0\0\0\0"), bmff(b"moov", &bmff(b"mvhd", &mvhd))].concat();
+
+        let info = from_bmff(&mut Cursor::new(&content)).unwrap();
+        assert_eq!(info.taken, Some(datetime!(2024-01-02 03:04:05)));
+        assert_eq!(info.duration, Some(12.5));
+    }
+
+    #[test]
+    fn reads_heif_exif_items() {
+        let mut infe = 0x0200_0000u32.to_be_bytes().to_vec();
+        infe.extend_from_slice(&7u16.to_be_bytes());
+        infe.extend_from_slice(&0u16.to_be_bytes());
+        infe.extend_from_slice(b"Exif\0");
+        let iinf = bmff(b"iinf", &[[0; 4].as_slice(), &1u16.to_be_bytes(), &bmff(b"infe", &infe)].concat());
+
+        let iloc = |base: Option<u64>, offset: u32, length: u32| {
+            let mut body = vec![0; 4];
+            // 4 byte offsets and lengths, and an 8 byte base offset if there is one
+            body.extend_from_slice(&if base.is_some() { 0x4480u16 } else { 0x4400 }.to_be_bytes());
+            for field in [1u16, 7, 0] {
+                body.extend_from_slice(&field.to_be_bytes());
+            }
+            if let Some(base) = base {
+                body.extend_from_slice(&base.to_be_bytes());
+            }
+            body.extend_from_slice(&1u16.to_be_bytes());
+            body.extend_from_slice(&offset.to_be_bytes());
+            body.extend_from_slice(&length.to_be_bytes());
+            bmff(b"iloc", &body)
+        };
+        let item = [6u32.to_be_bytes().as_slice(), b"Exif\0\0", &tiff_with_date()].concat();
+
+        let ftyp = bmff(b"ftyp", b"heic\0\0\0\0");
+        // The iloc is the same size whatever it points at
+        let meta_len = bmff(b"meta", &[[0; 4].as_slice(), &iinf, &iloc(None, 0, 0)].concat()).len();
+        let offset = (ftyp.len() + meta_len + 8) as u32;
+        let meta = bmff(b"meta", &[[0; 4].as_slice(), &iinf, &iloc(None, offset, item.len() as u32)].concat());
+        let content = [ftyp, meta, bmff(b"mdat", &item)].concat();
+
+        let info = from_bmff(&mut Cursor::new(&content)).unwrap();
+        assert_eq!(info.taken, Some(datetime!(2024-01-02 03:04:05)));
+
+        // A base and offset which overflow when added
+        let meta = bmff(b"meta", &[[0; 4].as_slice(), &iinf, &iloc(Some(u64::MAX), 1, 0)].concat());
+        let content = [bmff(b"ftyp", b"heic\0\0\0\0"), meta].concat();
+        let meta = &bmff_boxes(&mut Cursor::new(&content), 0, content.len() as u64).unwrap()[1];
+        assert_eq!(heif_exif(&mut Cursor::new(&content), meta).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_avi_headers() {
+        let mut avih = vec![0; 56];
+        avih[..4].copy_from_slice(&40_000u32.to_le_bytes());
+        avih[16..20].copy_from_slice(&250u32.to_le_bytes());
+        let hdrl = riff(b"LIST", &[b"hdrl".as_slice(), &riff(b"avih", &avih)].concat());
+        let info = riff(b"LIST", &[b"INFO".as_slice(), &riff(b"ICRD", b"2024-01-02\0")].concat());
+        let idit = riff(b"IDIT", b"Tue Jan  2 03:04:05 2024\n\0");
+        let movi = riff(b"LIST", &[b"movi".as_slice(), &riff(b"00dc", b"frame")].concat());
+        let body = [b"AVI ".as_slice(), &hdrl, &movi, &idit, &info].concat();
+        let content = riff(b"RIFF", &body);
+
+        let info = from_avi(&mut Cursor::new(&content)).unwrap();
+        assert_eq!(info.duration, Some(10.0));
+        assert_eq!(info.taken, Some(datetime!(2024-01-02 03:04:05)));
+    }
+
+    #[test]
+    fn parses_riff_dates() {
+        assert_eq!(parse_riff_date("Tue Jan 02 03:04:05 2024"), Some(datetime!(2024-01-02 03:04:05)));
+        assert_eq!(parse_riff_date("2024:01:02 03:04:05"), Some(datetime!(2024-01-02 03:04:05)));
+        assert_eq!(parse_riff_date("2024-01-02"), Some(datetime!(2024-01-02 0:00)));
+        assert_eq!(parse_riff_date("yesterday"), None);
+    }
+}
@@ -0,0 +1,179 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, info, warn};
+
+use crate::library::{copy_file, FileHash, Library, LibraryFile, TEMPORARY_SUFFIX};
+use crate::shutdown;
+use crate::storage;
+
+/// What po last wrote to a mirror, kept in the mirror itself so the drive can be updated from
+/// another copy of the library
+pub const STATE_FILE: &str = ".po-mirror.json";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(crate = "confique::serde")]
+struct State {
+    /// The query the mirror was last brought up to date with
+    query: String,
+    /// Each file po put in the mirror, by its path within it
+    files: BTreeMap<PathBuf, Mirrored>,
+}
+
+/// A file as po left it in the mirror, to tell whether it's been touched since without reading it
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+struct Mirrored {
+    hash: String,
+    size: u64,
+    /// Seconds since the Unix epoch
+    modified: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub copied: usize,
+    /// Files replaced, as they changed in the library or were changed in the mirror
+    pub updated: usize,
+    /// Files which no longer match the query, or left the library
+    pub removed: usize,
+    pub up_to_date: usize,
+    /// Files which couldn't be copied, or whose path in the mirror has something po didn't put there
+    pub skipped: usize,
+}
+
+/// Make `dest` hold exactly `files`, at their paths within the library, copying what's new or
+/// changed and removing what po put there before which `query` no longer matches. Only files po
+/// put in the mirror are ever replaced or removed, which are tracked in [`STATE_FILE`] there
+pub fn sync(library: &Library, files: &[&LibraryFile], dest: &Path, query: &str) -> Result<Report> {
+    let existing = dest.ancestors().find(|p| p.exists()).unwrap_or(dest);
+    if library.contains_path(existing)? {
+        return Err(eyre!("{} is inside the library, mirror to somewhere outside it", dest.display()));
+    }
+    fs::create_dir_all(dest).wrap_err(format!("when creating {}", dest.display()))?;
+
+    let state_path = dest.join(STATE_FILE);
+    let mut state = load(&state_path)?;
+    if !state.query.is_empty() && state.query != query {
+        info!("the mirror was made with {:?} before, files only it matched are removed", state.query);
+    }
+    state.query = query.to_string();
+
+    let mut report = Report::default();
+    let wanted: HashSet<_> = files.iter().map(|f| f.path_in_library.as_path()).collect();
+    // Removed first, to make room on the drive for what's copied
+    let stale: Vec<_> = state.files.keys().filter(|p| !wanted.contains(p.as_path())).cloned().collect();
+    for path in stale {
+        if shutdown::requested() {
+            break;
+        }
+
+        let output = dest.join(&path);
+        info!("removing {}", output.display());
+        match fs::remove_file(&output) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => debug!("{} was already gone", output.display()),
+            removed => removed.wrap_err(format!("when removing {}", output.display()))?,
+        }
+        remove_empty_parents(dest, &output);
+        state.files.remove(&path);
+        report.removed += 1;
+    }
+
+    for file in files {
+        if shutdown::requested() {
+            break;
+        }
+
+        let output = dest.join(&file.path_in_library);
+        let hash = file.hash.encode();
+        let recorded = state.files.get(&file.path_in_library);
+        let on_disk = stat(&output);
+        match (recorded, on_disk) {
+            (Some(recorded), Some((size, modified)))
+                if recorded.hash == hash && recorded.size == size && recorded.modified == modified =>
+            {
+                report.up_to_date += 1;
+                continue;
+            }
+            // Copied by a run which was cut short before it could record it
+            (None, Some((size, modified))) if FileHash::from_file(&output, file.hash.algorithm())? == file.hash => {
+                debug!("{} is already in the mirror, recording it", output.display());
+                state.files.insert(file.path_in_library.clone(), Mirrored { hash, size, modified });
+                report.up_to_date += 1;
+                continue;
+            }
+            (None, Some(_)) => {
+                warn!("{} is in the mirror but po didn't put it there, skipping", output.display());
+                report.skipped += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let source = library.absolute_path(file);
+        info!("mirroring {} to {}", source.display(), output.display());
+        if let Err(e) = copy_into_place(&source, &output) {
+            warn!("could not mirror {}: {e:#}", source.display());
+            report.skipped += 1;
+            continue;
+        }
+
+        let Some((size, modified)) = stat(&output) else {
+            return Err(eyre!("{} is missing after copying it", output.display()));
+        };
+        match on_disk {
+            Some(_) => report.updated += 1,
+            None => report.copied += 1,
+        }
+        state.files.insert(file.path_in_library.clone(), Mirrored { hash, size, modified });
+    }
+
+    storage::write_atomically(&state_path, serde_json::to_string_pretty(&state)?.as_bytes())?;
+    Ok(report)
+}
+
+fn load(path: &Path) -> Result<State> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+        state => serde_json::from_str(&state.wrap_err(format!("when reading {}", path.display()))?)
+            .wrap_err(format!("when parsing {}, delete it to mirror everything again", path.display())),
+    }
+}
+
+/// The size and modification time of the file at `path`, or `None` if there isn't one
+fn stat(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some((metadata.len(), modified))
+}
+
+/// Copy `source` to `output` through a partial file beside it, so an interrupted copy never
+/// leaves a truncated file at `output`
+fn copy_into_place(source: &Path, output: &Path) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(TEMPORARY_SUFFIX);
+    let partial = PathBuf::from(partial);
+    if let Err(e) = copy_file(source, &partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(e.into());
+    }
+    fs::rename(&partial, output)?;
+    Ok(())
+}
+
+/// Remove the directories `removed` was in which are now empty, up to `dest`
+fn remove_empty_parents(dest: &Path, removed: &Path) {
+    for dir in removed.ancestors().skip(1).take_while(|d| *d != dest) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
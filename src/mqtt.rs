@@ -0,0 +1,207 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::Config;
+use confique::serde::{Serialize, Serializer};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde_json::Value;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+#[derive(Config, Serialize)]
+#[serde(crate = "confique::serde")]
+#[config(layer_attr(derive(clap::Args)))]
+pub struct MqttConfig {
+    /// Broker to connect to when running `po mqtt`
+    #[config(env = "PO_MQTT_HOST", layer_attr(arg(long = "mqtt-host")))]
+    pub host: Option<String>,
+
+    /// Broker port
+    #[config(env = "PO_MQTT_PORT", default = 1883, layer_attr(arg(long = "mqtt-port")))]
+    pub port: u16,
+
+    /// Topic to subscribe to, MQTT wildcards are allowed
+    #[config(env = "PO_MQTT_TOPIC", default = "po/import", layer_attr(arg(long = "mqtt-topic")))]
+    pub topic: String,
+
+    /// Dotted path to the field holding the file path in JSON payloads (e.g. "after.snapshot_path").
+    /// When unset the whole payload is treated as the path
+    #[config(env = "PO_MQTT_PATH_FIELD", layer_attr(arg(long = "mqtt-path-field")))]
+    pub path_field: Option<String>,
+
+    /// Directory that relative paths from events are resolved against
+    #[config(env = "PO_MQTT_PATH_PREFIX", layer_attr(arg(long = "mqtt-path-prefix")))]
+    pub path_prefix: Option<PathBuf>,
+
+    /// Dotted paths to fields in JSON payloads whose values tag the imported file (e.g. ["after.label",
+    /// "after.camera"]). Fields holding a string or a list of strings are used, any others are skipped
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub tag_fields: Vec<String>,
+
+    /// Client id to identify as
+    #[config(env = "PO_MQTT_CLIENT_ID", default = "po", layer_attr(arg(long = "mqtt-client-id")))]
+    pub client_id: String,
+
+    #[config(env = "PO_MQTT_USERNAME", layer_attr(arg(long = "mqtt-username")))]
+    pub username: Option<String>,
+
+    #[config(env = "PO_MQTT_PASSWORD", layer_attr(arg(long = "mqtt-password")))]
+    #[serde(serialize_with = "redact_password")]
+    pub password: Option<String>,
+}
+
+// Don't leak the password when the config gets logged
+impl Debug for MqttConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("topic", &self.topic)
+            .field("path_field", &self.path_field)
+            .field("path_prefix", &self.path_prefix)
+            .field("tag_fields", &self.tag_fields)
+            .field("client_id", &self.client_id)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+fn redact_password<S: Serializer>(password: &Option<String>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    password.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+/// Subscribe to the configured topic, calling `on_event` with every file path carried by an event,
+/// and the tags taken from it
+pub fn run(config: &MqttConfig, mut on_event: impl FnMut(PathBuf, Vec<String>) -> Result<()>) -> Result<()> {
+    let host = config
+        .host
+        .clone()
+        .ok_or_else(|| eyre!("mqtt.host must be set to subscribe to events"))?;
+
+    let mut options = MqttOptions::new(&config.client_id, host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let (Some(user), Some(pass)) = (&config.username, &config.password) {
+        options.set_credentials(user, pass);
+    }
+
+    let (client, mut connection) = Client::new(options, 16);
+    client
+        .subscribe(&config.topic, QoS::AtLeastOnce)
+        .wrap_err("when subscribing to topic")?;
+
+    info!("subscribed to {} on {}:{}", config.topic, config.host.as_deref().unwrap_or_default(), config.port);
+
+    for event in connection.iter() {
+        let publish = match event {
+            Ok(Event::Incoming(Packet::Publish(p))) => p,
+            Ok(other) => {
+                debug!("ignoring event {:?}", other);
+                continue;
+            }
+            Err(e) => {
+                // rumqttc reconnects on the next poll, so back off and carry on
+                warn!("connection error: {e}");
+                std::thread::sleep(Duration::from_secs(5));
+                continue;
+            }
+        };
+
+        let path = match extract_path(config, &publish.payload) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("could not get a path from message on {}: {e:#}", publish.topic);
+                continue;
+            }
+        };
+
+        let tags = extract_tags(config, &publish.payload);
+        info!("event on {} for {} {:?}", publish.topic, path.display(), tags);
+        if let Err(e) = on_event(path, tags) {
+            warn!("failed to import from event: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_path(config: &MqttConfig, payload: &[u8]) -> Result<PathBuf> {
+    let raw = match &config.path_field {
+        None => std::str::from_utf8(payload)?.trim().to_string(),
+        Some(field) => {
+            let value: Value = serde_json::from_slice(payload)
+                .wrap_err("payload was not valid json")?;
+
+            lookup(&value, field)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| eyre!("payload had no string field {field}"))?
+                .to_string()
+        }
+    };
+
+    let path = PathBuf::from(raw);
+    match &config.path_prefix {
+        Some(prefix) if path.is_relative() => Ok(prefix.join(path)),
+        _ => Ok(path),
+    }
+}
+
+/// The tags `config.tag_fields` pick out of a JSON payload, with whitespace in them replaced
+fn extract_tags(config: &MqttConfig, payload: &[u8]) -> Vec<String> {
+    if config.tag_fields.is_empty() {
+        return vec![];
+    }
+
+    let Ok(value) = serde_json::from_slice::<Value>(payload) else {
+        return vec![];
+    };
+
+    let mut tags: Vec<String> = config
+        .tag_fields
+        .iter()
+        .filter_map(|field| lookup(&value, field))
+        .flat_map(|v| match v {
+            Value::String(s) => vec![s.as_str()],
+            Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        })
+        .map(|tag| tag.split_whitespace().collect::<Vec<_>>().join("_"))
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// The value at the dotted path `field` in `value`
+fn lookup<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    field.split('.').try_fold(value, |v, key| v.get(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(path_field: Option<&str>, tag_fields: &[&str]) -> MqttConfig {
+        MqttConfig {
+            host: None,
+            port: 1883,
+            topic: "po/import".to_string(),
+            path_field: path_field.map(str::to_string),
+            path_prefix: Some(PathBuf::from("/mnt/nvr")),
+            tag_fields: tag_fields.iter().map(|f| f.to_string()).collect(),
+            client_id: "po".to_string(),
+            username: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn events_carry_paths_and_tags() {
+        let payload = br#"{"after": {"snapshot_path": "front/1.jpg", "label": "person", "camera": "front door", "zones": ["drive", "path"], "score": 0.9}}"#;
+        let config = config(Some("after.snapshot_path"), &["after.label", "after.camera", "after.zones", "after.score", "after.missing"]);
+
+        assert_eq!(extract_path(&config, payload).unwrap(), PathBuf::from("/mnt/nvr/front/1.jpg"));
+        assert_eq!(extract_tags(&config, payload), ["drive", "front_door", "path", "person"]);
+    }
+}
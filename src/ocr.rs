@@ -0,0 +1,171 @@
+use color_eyre::eyre::Result;
+use confique::Config;
+use confique::serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::library::{FileHash, Library};
+use crate::transcode;
+
+#[derive(Config, Debug, Serialize, Clone)]
+#[serde(crate = "confique::serde")]
+#[config(layer_attr(derive(clap::Args)))]
+pub struct OcrConfig {
+    /// Tesseract languages to read text in, joined with `+`, e.g. `eng+deu`. Only used by po
+    /// built with `--features ocr`
+    #[config(env = "PO_OCR_LANGUAGES", default = "eng", layer_attr(arg(long = "ocr-languages")))]
+    pub languages: String,
+
+    /// Directory of Tesseract's language data, by default wherever it was installed to
+    #[config(env = "PO_OCR_DATAPATH", layer_attr(arg(long = "ocr-datapath")))]
+    pub datapath: Option<PathBuf>,
+
+    /// Read the text in imported screenshots, and photos with any of `tags`
+    #[config(env = "PO_OCR_ON_IMPORT", default = false, layer_attr(arg(id = "ocr_on_import", long = "ocr-on-import")))]
+    pub on_import: bool,
+
+    /// Tags marking imported photos as documents worth reading, such as those `po classify`
+    /// adds. Imports run the classifier first, so its tags can be used here
+    #[config(
+        env = "PO_OCR_TAGS",
+        parse_env = confique::env::parse::list_by_comma,
+        default = ["auto:documents"],
+        layer_attr(arg(long = "ocr-tags"))
+    )]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub read: usize,
+    /// Files whose text changed
+    pub changed: Vec<FileHash>,
+}
+
+/// Which of the files to read
+pub enum Select {
+    /// Every photo, for when they were picked out by hand
+    All,
+    /// Screenshots, and photos with one of the configured tags
+    Documents,
+}
+
+/// Read the text in each of `hashes` which is a photo, and keep it in the index for
+/// `po query --text`, replacing whatever was read from it before
+pub fn ocr(library: &mut Library, config: &OcrConfig, hashes: &[FileHash], select: Select) -> Result<Report> {
+    let reader = engine::Reader::new(config)?;
+
+    let wanted: HashSet<_> = hashes.iter().collect();
+    let files: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| wanted.contains(&f.hash))
+        .filter(|f| match select {
+            Select::All => true,
+            Select::Documents => is_screenshot(&f.path_in_library) || f.tags.iter().any(|t| config.tags.contains(t)),
+        })
+        .map(|f| (f.path_in_library.clone(), library.absolute_path(f)))
+        .collect();
+
+    let mut report = Report::default();
+    for (path_in_library, path) in files {
+        if !transcode::can_render(&path).unwrap_or(false) {
+            debug!("{} is not a photo which can be read, skipping", path.display());
+            continue;
+        }
+
+        let text = match reader.read(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("could not read the text in {}: {e:#}", path.display());
+                continue;
+            }
+        };
+        report.read += 1;
+
+        // Kept on one line, as the text index has a line per property
+        let words: Vec<_> = text.split_whitespace().collect();
+        let before = library.generation();
+        let hash = library.set_text(&path_in_library, (!words.is_empty()).then(|| words.join(" ")))?.hash.clone();
+        if library.generation() != before {
+            info!("read {} words from {}", words.len(), path_in_library.display());
+            report.changed.push(hash);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Whether a file is named as phones and desktops name screenshots, or is a PNG, which cameras
+/// don't write
+fn is_screenshot(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    name.contains("screenshot") || name.starts_with("screen shot") || path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"))
+}
+
+#[cfg(feature = "ocr")]
+mod engine {
+    use color_eyre::eyre::{eyre, Result};
+    use image::ImageFormat;
+    use std::io::Cursor;
+    use std::path::Path;
+    use tesseract::Tesseract;
+
+    use super::OcrConfig;
+    use crate::transcode;
+
+    pub struct Reader {
+        datapath: Option<String>,
+        languages: String,
+    }
+
+    impl Reader {
+        pub fn new(config: &OcrConfig) -> Result<Reader> {
+            let reader = Reader {
+                datapath: config.datapath.as_ref().map(|p| p.to_string_lossy().into_owned()),
+                languages: config.languages.clone(),
+            };
+            // So missing language data is found before reading anything
+            reader.tesseract()?;
+            Ok(reader)
+        }
+
+        fn tesseract(&self) -> Result<Tesseract> {
+            Tesseract::new(self.datapath.as_deref(), Some(&self.languages))
+                .map_err(|e| eyre!("could not start Tesseract for {}: {e}, is its language data installed?", self.languages))
+        }
+
+        pub fn read(&self, path: &Path) -> Result<String> {
+            // Decoded here rather than by Tesseract, so the photo is the right way up and
+            // anything po can read works
+            let (image, _, _) = transcode::load(path)?;
+            let mut png = vec![];
+            image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+            let mut tesseract = self.tesseract()?.set_image_from_mem(&png)?.recognize()?;
+            Ok(tesseract.get_text()?)
+        }
+    }
+}
+
+/// Stands in for Tesseract in builds without the ocr feature
+#[cfg(not(feature = "ocr"))]
+mod engine {
+    use color_eyre::eyre::{eyre, Result};
+    use std::path::Path;
+
+    use super::OcrConfig;
+
+    pub struct Reader;
+
+    impl Reader {
+        pub fn new(_config: &OcrConfig) -> Result<Reader> {
+            Err(eyre!("this po was built without OCR, build it with `--features ocr` to use it"))
+        }
+
+        pub fn read(&self, _path: &Path) -> Result<String> {
+            unreachable!("readers can't be made without the ocr feature")
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use color_eyre::eyre::{eyre, Result};
+use image::imageops::FilterType;
+use std::path::Path;
+use tract_onnx::prelude::*;
+
+use crate::transcode;
+
+/// The per channel mean and standard deviation ImageNet models are trained to expect
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// An image classifier taking one RGB image, `1x3xSIZExSIZE` normalised as for ImageNet, and
+/// giving a score for each class
+pub struct Model {
+    plan: TypedRunnableModel<TypedModel>,
+    input_size: u32,
+}
+
+impl Model {
+    pub fn load(path: &Path, input_size: u32) -> Result<Model> {
+        let side = input_size as usize;
+        let plan = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|model| model.with_input_fact(0, f32::fact([1, 3, side, side]).into()))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(|e| eyre!("could not load the classifier {}: {e:#}", path.display()))?;
+
+        Ok(Model { plan, input_size })
+    }
+
+    /// The model's score for each of its classes, for the photo at `path`
+    pub fn scores(&self, path: &Path) -> Result<Vec<f32>> {
+        let (image, _, _) = transcode::load(path)?;
+        // Cropped to the middle, as the models are trained on square images
+        let image = image.resize_to_fill(self.input_size, self.input_size, FilterType::Triangle).to_rgb8();
+
+        let side = self.input_size as usize;
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, side, side), |(_, c, y, x)| {
+            let value = f32::from(image.get_pixel(x as u32, y as u32)[c]) / 255.0;
+            (value - MEAN[c]) / STD[c]
+        })
+        .into();
+
+        let outputs = self.plan.run(tvec!(input.into())).map_err(|e| eyre!("{e:#}"))?;
+        let scores = outputs[0].to_array_view::<f32>().map_err(|e| eyre!("{e:#}"))?;
+        Ok(scores.iter().copied().collect())
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::extensions;
+use crate::library::{FileHash, UnsortedFile};
+
+/// Files shot together and kept together, like `IMG_0001.CR3`, the `IMG_0001.JPG` the camera
+/// wrote alongside it and the `IMG_0001.xmp` Lightroom added
+#[derive(Debug)]
+pub struct Group {
+    pub name: String,
+    /// The file the others follow into the library, the raw file if there is one
+    pub lead: FileHash,
+    /// The rest of the group, without the lead
+    pub members: Vec<FileHash>,
+}
+
+/// Find files in the same folder which share a name up to their first `.`, ignoring case. Groups
+/// of only sidecars have nothing to date them by, so are left alone
+pub fn detect(files: &[UnsortedFile]) -> Vec<Group> {
+    let mut by_name: BTreeMap<(PathBuf, String), Vec<&UnsortedFile>> = BTreeMap::new();
+    for file in files {
+        let Some(stem) = stem(&file.path) else { continue };
+        let dir = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        by_name.entry((dir, stem.to_lowercase())).or_default().push(file);
+    }
+
+    let mut groups = vec![];
+    for (_, mut files) in by_name.into_iter().filter(|(_, files)| files.len() > 1) {
+        files.sort_by_key(|f| (rank(&f.path), f.path.clone()));
+        let (lead, members) = files.split_first().expect("groups to have files");
+        if extensions::is_sidecar(&lead.path) {
+            continue;
+        }
+
+        groups.push(Group {
+            name: format!("{}-{}", stem(&lead.path).unwrap_or_default(), &lead.hash.encode()[..8]),
+            lead: lead.hash.clone(),
+            members: members.iter().map(|f| f.hash.clone()).collect(),
+        });
+    }
+
+    debug!("detected {} groups of paired files", groups.len());
+    groups
+}
+
+/// `IMG_0001` for `IMG_0001.CR3` and darktable's `IMG_0001.CR3.xmp` alike
+fn stem(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    name.split('.').next().filter(|s| !s.is_empty())
+}
+
+/// Which file leads a group, lowest first
+fn rank(path: &Path) -> u8 {
+    if extensions::is_raw(path) {
+        0
+    } else if extensions::is_sidecar(path) {
+        2
+    } else {
+        1
+    }
+}
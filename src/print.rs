@@ -0,0 +1,166 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::imageops::FilterType;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::library::{Library, LibraryFile};
+use crate::metadata;
+use crate::transcode;
+
+pub const DEFAULT_DPI: u16 = 300;
+
+/// High, as labs print what they're sent and there's no getting detail back
+const QUALITY: u8 = 95;
+
+/// What the order is listed in, alongside the photos
+const MANIFEST: &str = "order.csv";
+
+/// The size of a print, in inches, whichever way round the photo is
+#[derive(Debug, Clone)]
+pub struct PrintSize {
+    /// As it was given, e.g. `6x4` or `15x10cm`, for the manifest
+    pub name: String,
+    long: f64,
+    short: f64,
+}
+
+impl PrintSize {
+    /// Read a size like `6x4`, `6x4in` or `15x10cm`. Inches unless it says otherwise
+    pub fn parse(spec: &str) -> Result<PrintSize> {
+        let invalid = || eyre!("{spec:?} is not a print size, expected one like 6x4, 6x4in or 15x10cm");
+
+        let (dimensions, per_inch) = match spec.strip_suffix("cm") {
+            Some(dimensions) => (dimensions, 2.54),
+            None => (spec.strip_suffix("in").unwrap_or(spec), 1.0),
+        };
+        let (a, b) = dimensions.split_once('x').ok_or_else(invalid)?;
+        let a: f64 = a.trim().parse().map_err(|_| invalid())?;
+        let b: f64 = b.trim().parse().map_err(|_| invalid())?;
+        if !(a > 0.0 && b > 0.0) {
+            return Err(invalid());
+        }
+
+        Ok(PrintSize { name: spec.to_string(), long: a.max(b) / per_inch, short: a.min(b) / per_inch })
+    }
+
+    /// Pixels to fill the print at `dpi`, turned to match a `width` by `height` photo
+    fn pixels(&self, dpi: u16, width: u32, height: u32) -> (u32, u32) {
+        let long = (self.long * f64::from(dpi)).round() as u32;
+        let short = (self.short * f64::from(dpi)).round() as u32;
+        if width >= height { (long, short) } else { (short, long) }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Photos written, one per line of the manifest
+    pub photos: usize,
+    /// Prints ordered across all of them
+    pub prints: u32,
+    /// Matching files with prints to order which aren't photos that can be rendered
+    pub skipped: usize,
+}
+
+struct Line {
+    name: String,
+    quantity: u32,
+    width: u32,
+    height: u32,
+    original: PathBuf,
+}
+
+/// Write a print order to `dest`: the matching files with prints to order as numbered JPEGs
+/// scaled to fit `size` at `dpi`, and an `order.csv` listing how many of each to print.
+/// Anything already there from an earlier order is replaced
+pub fn to_directory(library: &Library, files: &[&LibraryFile], dest: &Path, size: &PrintSize, dpi: u16) -> Result<Report> {
+    let mut files: Vec<_> = files.iter().filter(|f| f.prints > 0).collect();
+    if files.is_empty() {
+        return Err(eyre!("none of the matching files have prints to order, set how many with `po prints set`"));
+    }
+    files.sort_by(|a, b| a.path_in_library.cmp(&b.path_in_library));
+    fs::create_dir_all(dest).wrap_err(format!("when creating {}", dest.display()))?;
+
+    let mut report = Report::default();
+    let mut lines = vec![];
+    let width = files.len().to_string().len().max(4);
+    for file in files {
+        let source = library.absolute_path(file);
+        if !transcode::can_render(&source)? {
+            warn!("{} is not a photo which can be printed, skipping", source.display());
+            report.skipped += 1;
+            continue;
+        }
+
+        let stem = file.path_in_library.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let name = format!("{:0width$}_{stem}.jpg", lines.len() + 1);
+        let output = dest.join(&name);
+        info!("rendering {} for printing to {}", source.display(), output.display());
+        let (width, height) = render(&source, &output, size, dpi)
+            .wrap_err(format!("when rendering {} to {}", source.display(), output.display()))?;
+
+        report.photos += 1;
+        report.prints += file.prints;
+        lines.push(Line { name, quantity: file.prints, width, height, original: file.path_in_library.clone() });
+    }
+
+    let path = dest.join(MANIFEST);
+    fs::write(&path, manifest(&lines, size, dpi)).wrap_err(format!("when writing {}", path.display()))?;
+    Ok(report)
+}
+
+/// Scale `source` to fit `size` at `dpi` and write it to `dest` as a JPEG tagged with that
+/// resolution. Returns the size it was written at, in pixels
+fn render(source: &Path, dest: &Path, size: &PrintSize, dpi: u16) -> Result<(u32, u32)> {
+    let (image, exif, _) = transcode::load(source)?;
+    let (box_width, box_height) = size.pixels(dpi, image.width(), image.height());
+
+    let scale = (f64::from(box_width) / f64::from(image.width())).min(f64::from(box_height) / f64::from(image.height()));
+    if scale > 1.0 {
+        warn!(
+            "{} is too small to print at {} dpi, it will print at about {} dpi",
+            source.display(),
+            dpi,
+            (f64::from(dpi) / scale).round()
+        );
+    }
+    let image = image.resize(box_width, box_height, FilterType::Lanczos3);
+
+    // Prints get handed around, so don't say where they were taken
+    let exif = exif.and_then(|mut exif| metadata::strip_gps(&mut exif).ok().map(|_| exif));
+
+    let mut out = BufWriter::new(fs::File::create(dest)?);
+    let mut encoder = JpegEncoder::new_with_quality(&mut out, QUALITY);
+    encoder.set_pixel_density(PixelDensity::dpi(dpi));
+    transcode::encode(&image, encoder, exif)?;
+    out.flush()?;
+
+    Ok((image.width(), image.height()))
+}
+
+fn manifest(lines: &[Line], size: &PrintSize, dpi: u16) -> String {
+    let mut csv = String::from("file,quantity,size,dpi,width,height,original\n");
+    for line in lines {
+        csv.push_str(&format!(
+            "{},{},{},{dpi},{},{},{}\n",
+            csv_field(&line.name),
+            line.quantity,
+            csv_field(&size.name),
+            line.width,
+            line.height,
+            csv_field(&line.original.to_string_lossy())
+        ));
+    }
+    csv
+}
+
+/// Quote `value` if it would otherwise break the row up
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,67 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// The bar being drawn, which logs have to be written around
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// A progress bar on stderr, cleared again when dropped. Nothing is drawn when stderr
+/// isn't a terminal, so piped and logged output stays clean
+pub struct Progress(ProgressBar);
+
+impl Progress {
+    /// Progress through `total` bytes, e.g. of files being hashed
+    pub fn bytes(enabled: bool, total: u64, message: String) -> Progress {
+        Progress::start(enabled, total, message, "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+    }
+
+    /// Progress through `total` files
+    pub fn files(enabled: bool, total: u64, message: String) -> Progress {
+        Progress::start(enabled, total, message, "{msg} [{bar:30}] {pos}/{len} ({eta})")
+    }
+
+    fn start(enabled: bool, total: u64, message: String, template: &str) -> Progress {
+        let bar = if enabled && io::stderr().is_terminal() {
+            ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr())
+        } else {
+            ProgressBar::hidden()
+        };
+
+        bar.set_style(
+            ProgressStyle::with_template(template)
+                .expect("progress template to be valid")
+                .progress_chars("=> "),
+        );
+        bar.set_message(message);
+
+        *ACTIVE.lock().expect("progress lock to not be poisoned") = Some(bar.clone());
+        Progress(bar)
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.0.inc(delta);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+        ACTIVE.lock().expect("progress lock to not be poisoned").take();
+    }
+}
+
+/// Writes to stderr, hiding the progress bar while it does so logs don't tear through it
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &*ACTIVE.lock().expect("progress lock to not be poisoned") {
+            Some(bar) => bar.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,202 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use tracing::{debug, warn};
+
+use crate::library::{FileHash, Library, LibraryFile};
+use crate::retention;
+
+/// How long files with a tag are kept, e.g.
+/// `{ tag = "screenshot", older_than = "1y", action = "trash" }`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(crate = "confique::serde", deny_unknown_fields)]
+pub struct Policy {
+    pub tag: String,
+    /// How long after they were taken files are kept, e.g. `90d` or `2y`
+    pub older_than: String,
+    pub action: Action,
+    /// Where `archive` moves files to, at their paths within the library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<PathBuf>,
+}
+
+/// What happens to a file once a policy says it's too old
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum Action {
+    /// Move it to `_pometa/trash`, to be restored by hand
+    Trash,
+    /// Delete it for good, which `po prune` only does with `--yes`
+    Delete,
+    /// Move it out of the library to the policy's `to`, such as slower, cheaper storage
+    Archive,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Trash => "trash",
+            Action::Delete => "delete",
+            Action::Archive => "archive",
+        }
+    }
+}
+
+/// A file which a policy says is too old
+#[derive(Debug)]
+pub struct Due<'a> {
+    pub path: PathBuf,
+    pub policy: &'a Policy,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub trashed: Vec<FileHash>,
+    pub deleted: Vec<FileHash>,
+    pub archived: Vec<FileHash>,
+}
+
+/// Fails unless every policy can be carried out
+pub fn check(library: &Library, policies: &[Policy]) -> Result<()> {
+    for policy in policies {
+        if policy.tag.is_empty() {
+            return Err(eyre!("retention policies need a tag to apply to"));
+        }
+        retention::parse_age(&policy.older_than).wrap_err(format!("in the retention policy for {}", policy.tag))?;
+
+        match (policy.action, &policy.to) {
+            (Action::Archive, None) => {
+                return Err(eyre!("the retention policy for {} archives files, so needs `to` to say where", policy.tag));
+            }
+            (Action::Archive, Some(to)) => {
+                let existing = to.ancestors().find(|p| p.exists()).unwrap_or(to);
+                if library.contains_path(existing)? {
+                    return Err(eyre!("the retention policy for {} archives into the library, set `to` outside it", policy.tag));
+                }
+            }
+            (_, Some(_)) => warn!("the retention policy for {} only uses `to` to archive, ignoring it", policy.tag),
+            (_, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The files in `library` which `policies` say are too old as of `now`. A file with the tags of
+/// several policies goes by the first. Files are aged by when they were taken, or else when they
+/// were imported, and those po knows neither for, or which would only be due past the year 9999,
+/// are left alone
+pub fn due<'a>(library: &Library, policies: &'a [Policy], now: OffsetDateTime) -> Result<Vec<Due<'a>>> {
+    let limits = policies
+        .iter()
+        .map(|p| Ok((p, retention::parse_age(&p.older_than)?)))
+        .collect::<Result<Vec<(&Policy, Duration)>>>()?;
+
+    let now = PrimitiveDateTime::new(now.date(), now.time());
+    let mut due = vec![];
+    for file in library.files() {
+        let Some((policy, age)) = limits.iter().find(|(p, _)| file.tags.contains(&p.tag)) else {
+            continue;
+        };
+        let Some(dated) = dated(file) else {
+            debug!("{} has no date, so {} can't age it", file.path_in_library.display(), policy.tag);
+            continue;
+        };
+
+        if dated.checked_add(*age).is_some_and(|due| due < now) {
+            due.push(Due { path: file.path_in_library.clone(), policy });
+        }
+    }
+
+    due.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(due)
+}
+
+/// Carry out the policies for each of `due`. Files which can't be are warned about and left as
+/// they are
+pub fn apply(library: &mut Library, due: &[Due]) -> Report {
+    let mut report = Report::default();
+    for Due { path, policy } in due {
+        let (done, result) = match policy.action {
+            Action::Trash => (&mut report.trashed, library.remove_file(path, true)),
+            Action::Delete => (&mut report.deleted, library.remove_file(path, false)),
+            Action::Archive => {
+                let to: &Path = policy.to.as_deref().expect("archive policies to have been checked for a `to`");
+                (&mut report.archived, library.archive_file(path, to))
+            }
+        };
+        match result {
+            Ok(hash) => done.push(hash),
+            Err(e) => warn!("could not {} {}: {e:#}", policy.action.name(), path.display()),
+        }
+    }
+    report
+}
+
+/// When `file` was taken, or else imported
+fn dated(file: &LibraryFile) -> Option<PrimitiveDateTime> {
+    file.taken().or_else(|| {
+        let imported = OffsetDateTime::parse(file.imported_at.as_deref()?, &Rfc3339).ok()?;
+        Some(PrimitiveDateTime::new(imported.date(), imported.time()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+
+    fn policy(tag: &str, older_than: &str) -> Policy {
+        Policy { tag: tag.into(), older_than: older_than.into(), action: Action::Trash, to: None }
+    }
+
+    fn tagged(name: &str, tag: &str, taken: Option<&str>, imported_at: Option<&str>) -> LibraryFile {
+        let mut file = indexed(name, name);
+        file.tags = vec![tag.into()];
+        file.taken = taken.map(str::to_string);
+        file.imported_at = imported_at.map(str::to_string);
+        file
+    }
+
+    #[test]
+    fn due_goes_by_taken_then_imported() {
+        let root = scratch_dir("prune-due");
+        let library = library(&root, vec![
+            tagged("old.png", "screenshot", Some("2020-01-01T00:00:00"), None),
+            tagged("new.png", "screenshot", Some("2024-05-30T00:00:00"), None),
+            tagged("imported.png", "screenshot", None, Some("2020-01-01T00:00:00Z")),
+            tagged("undated.png", "screenshot", None, None),
+            tagged("other.png", "keep", Some("2000-01-01T00:00:00"), None),
+        ]);
+
+        let policies = [policy("screenshot", "30d")];
+        let now = time::macros::datetime!(2024-06-01 0:00 UTC);
+        let due: Vec<_> = due(&library, &policies, now).unwrap().into_iter().map(|d| d.path).collect();
+        assert_eq!(due, [PathBuf::from("imported.png"), PathBuf::from("old.png")]);
+    }
+
+    #[test]
+    fn due_goes_by_the_first_matching_policy() {
+        let root = scratch_dir("prune-first");
+        let mut file = tagged("both.png", "a", Some("2024-01-01T00:00:00"), None);
+        file.tags.push("b".into());
+        let library = library(&root, vec![file]);
+
+        let now = time::macros::datetime!(2024-06-01 0:00 UTC);
+        let policies = [policy("a", "1y"), policy("b", "1d")];
+        assert!(due(&library, &policies, now).unwrap().is_empty());
+
+        let policies = [policy("b", "1d"), policy("a", "1y")];
+        assert_eq!(due(&library, &policies, now).unwrap()[0].policy.tag, "b");
+    }
+
+    #[test]
+    fn due_leaves_files_aged_past_9999() {
+        let root = scratch_dir("prune-overflow");
+        let library = library(&root, vec![tagged("future.png", "a", Some("9999-06-01T00:00:00"), None)]);
+
+        let now = time::macros::datetime!(2024-06-01 0:00 UTC);
+        assert!(due(&library, &[policy("a", "1000y")], now).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+use color_eyre::eyre::Result;
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::PrimitiveDateTime;
+use tracing::{info, warn};
+
+use crate::budget;
+use crate::changes;
+use crate::export::capture_date;
+use crate::library::{Library, LibraryFile};
+use crate::metadata;
+use crate::rolls;
+use crate::simulate;
+
+// Rough size of one file's entries across the indexes
+const INDEX_ENTRY_BYTES: u64 = 384;
+
+/// What the files a query matches have to have, besides a path matching its globs
+#[derive(clap::Args, Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct QueryFilters {
+    /// Only match files imported by this user
+    #[arg(long)]
+    pub imported_by: Option<String>,
+
+    /// Only match files with this tag, can be given more than once to require several. A
+    /// trailing `*` matches any tag starting with what's before it, e.g. `auto:*`
+    #[arg(long = "tag", value_name = "TAG")]
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Leave out files with this tag, which can end in `*` as with `--tag`. Can be given more
+    /// than once to leave out several
+    #[arg(long = "without-tag", value_name = "TAG")]
+    #[serde(default)]
+    pub without_tags: Vec<String>,
+
+    /// Only match files this person was marked in by another tool, can be given more than
+    /// once to require several
+    #[arg(long = "person", value_name = "PERSON")]
+    #[serde(default)]
+    pub people: Vec<String>,
+
+    /// Only match files whose text, read by `po ocr`, has every word of this in it as a whole word, ignoring case
+    #[arg(long)]
+    #[serde(default)]
+    pub text: Option<String>,
+
+    /// Only match files taken at or after this time, `2025-01-01` or `2025-01-01 20:30`
+    #[arg(long)]
+    #[serde(default)]
+    pub after: Option<String>,
+
+    /// Only match files taken before this time, `2025-03-01` or `2025-03-01 20:30`
+    #[arg(long)]
+    #[serde(default)]
+    pub before: Option<String>,
+
+    /// Only match files with this extension, e.g. cr3. Can be given more than once to match any of several
+    #[arg(long = "ext", value_name = "EXT")]
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// Only match files at least this big, e.g. 10MB
+    #[arg(long)]
+    #[serde(default)]
+    pub min_size: Option<String>,
+
+    /// Only match files no bigger than this, e.g. 2GB
+    #[arg(long)]
+    #[serde(default)]
+    pub max_size: Option<String>,
+
+    /// Only match files imported as part of this roll, with `po import --roll`
+    #[arg(long)]
+    #[serde(default)]
+    pub roll: Option<String>,
+
+    /// Only match files from rolls of this film, ignoring case, e.g. "portra 400"
+    #[arg(long)]
+    #[serde(default)]
+    pub film_stock: Option<String>,
+
+    /// Only match files taken with this camera, by make and model as `po inspect` shows them,
+    /// ignoring case
+    #[arg(long)]
+    #[serde(default)]
+    pub camera: Option<String>,
+}
+
+impl QueryFilters {
+    /// The files in `files` which pass every filter. Dates and sizes come from the index, and
+    /// from the files themselves for those imported before po kept track of them, unless
+    /// `indexes` already has them
+    pub fn apply<'a>(&self, library: &Library, files: Vec<&'a LibraryFile>, indexes: Option<&QueryIndexes>) -> Result<Vec<&'a LibraryFile>> {
+        let indexes = indexes.filter(|i| i.is_built());
+        let candidates = indexes.and_then(|i| i.candidates(self));
+        let files = match &candidates {
+            Some(candidates) => files.into_iter().filter(|f| candidates.contains(f.path_in_library.as_path())).collect(),
+            None => files,
+        };
+        let entry = |f: &LibraryFile| indexes.and_then(|i| i.entries.get(&f.path_in_library));
+        let camera = self.camera.as_deref().map(str::to_lowercase);
+
+        let after = self.after.as_deref().map(simulate::parse_clock).transpose()?;
+        let before = self.before.as_deref().map(simulate::parse_clock).transpose()?;
+        let min_size = self.min_size.as_deref().map(budget::parse_size).transpose()?;
+        let max_size = self.max_size.as_deref().map(budget::parse_size).transpose()?;
+        let words = self.text.as_deref().map(words).unwrap_or_default();
+        let indexed = if words.is_empty() { None } else { library.search_text(&words)? };
+        let extensions: Vec<_> = self.extensions.iter().map(|e| e.trim_start_matches('.').to_lowercase()).collect();
+        let stock_rolls: Option<HashSet<_>> = match &self.film_stock {
+            Some(stock) => Some(
+                rolls::load(library)?
+                    .into_iter()
+                    .filter(|(_, roll)| roll.film_stock.as_ref().is_some_and(|s| s.eq_ignore_ascii_case(stock)))
+                    .map(|(name, _)| name)
+                    .collect(),
+            ),
+            None => None,
+        };
+
+        Ok(files
+            .into_iter()
+            .filter(|f| self.imported_by.is_none() || f.imported_by == self.imported_by)
+            .filter(|f| self.tags.iter().all(|t| has_tag(f, t)))
+            .filter(|f| !self.without_tags.iter().any(|t| has_tag(f, t)))
+            .filter(|f| self.roll.is_none() || rolls::of(f) == self.roll.as_deref())
+            .filter(|f| stock_rolls.as_ref().is_none_or(|names| rolls::of(f).is_some_and(|r| names.contains(r))))
+            .filter(|f| self.people.iter().all(|p| f.people.iter().any(|q| q.eq_ignore_ascii_case(p))))
+            .filter(|f| {
+                words.is_empty()
+                    || match &indexed {
+                        Some(paths) => paths.contains(&f.path_in_library),
+                        None => f.text.as_deref().is_some_and(|text| {
+                            let text = self::words(text);
+                            words.iter().all(|w| text.contains(w))
+                        }),
+                    }
+            })
+            .filter(|f| {
+                extensions.is_empty()
+                    || f.path_in_library
+                        .extension()
+                        .is_some_and(|e| extensions.contains(&e.to_string_lossy().to_lowercase()))
+            })
+            .filter(|f| {
+                if min_size.is_none() && max_size.is_none() {
+                    return true;
+                }
+                let size = f.size.or_else(|| fs::metadata(library.absolute_path(f)).ok().map(|m| m.len()));
+                size.is_some_and(|s| min_size.is_none_or(|min| s >= min) && max_size.is_none_or(|max| s <= max))
+            })
+            .filter(|f| {
+                if after.is_none() && before.is_none() {
+                    return true;
+                }
+                let taken = match entry(f) {
+                    Some(entry) => entry.taken,
+                    None => taken(library, f),
+                };
+                taken.is_some_and(|t| after.is_none_or(|after| t >= after) && before.is_none_or(|before| t < before))
+            })
+            .filter(|f| {
+                let Some(camera) = &camera else {
+                    return true;
+                };
+                let found = match entry(f).filter(|_| indexes.is_some_and(|i| i.cameras.is_some())) {
+                    Some(entry) => entry.camera.clone(),
+                    None => camera_of(library, f),
+                };
+                found.is_some_and(|c| c.to_lowercase() == *camera)
+            })
+            .collect())
+    }
+}
+
+/// When `file` was taken, by the index or else its own metadata
+fn taken(library: &Library, file: &LibraryFile) -> Option<PrimitiveDateTime> {
+    file.taken().or_else(|| capture_date(&library.absolute_path(file)).ok())
+}
+
+fn camera_of(library: &Library, file: &LibraryFile) -> Option<String> {
+    metadata::read_capture_info(&library.absolute_path(file)).ok()?.camera()
+}
+
+/// What a file is filed under in [`QueryIndexes`]
+#[derive(Debug, Default)]
+struct Entry {
+    taken: Option<PrimitiveDateTime>,
+    tags: Vec<String>,
+    /// Lowercased. Only known once the camera index is built
+    camera: Option<String>,
+}
+
+/// Files by when they were taken, their tags and their camera, which a daemon keeps as the library
+/// changes, so queries filtering on them needn't go through every file or read its metadata
+#[derive(Debug, Default)]
+pub struct QueryIndexes {
+    /// How far through the change feed the indexes are up to date with, `None` until they're built
+    feed_offset: Option<u64>,
+    taken: BTreeMap<PrimitiveDateTime, HashSet<PathBuf>>,
+    tags: BTreeMap<String, HashSet<PathBuf>>,
+    /// Lowercased. Only built once a query asks for a camera, as that reads every file's metadata
+    cameras: Option<BTreeMap<String, HashSet<PathBuf>>>,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl QueryIndexes {
+    pub fn is_built(&self) -> bool {
+        self.feed_offset.is_some()
+    }
+
+    /// Bring the indexes up to date with `library`, following the change feed since they were last
+    /// updated, and build the camera index if `filters` ask for one. They're dropped, and queries
+    /// go through every file, when they'd take up more of the memory budget than caches may
+    pub fn update(&mut self, library: &Library, filters: &QueryFilters) -> Result<()> {
+        let estimated = library.files().len() as u64 * INDEX_ENTRY_BYTES;
+        if budget::cache_bytes().is_some_and(|bytes| estimated > bytes) {
+            if self.is_built() {
+                warn!("the query indexes would take up too much of memory_limit, so they're dropped");
+            }
+            *self = QueryIndexes::default();
+            return Ok(());
+        }
+
+        let feed = self.feed_offset.map(|offset| changes::read_from(library.meta_root(), offset)).transpose()?.flatten();
+        match feed {
+            Some((changes, offset)) => {
+                // Unsaved changes are in the feed later on too, when those files are indexed again
+                let touched: HashSet<&Path> = changes
+                    .iter()
+                    .chain(library.unsaved_changes())
+                    .flat_map(|c| [Some(Path::new(&c.path)), c.previous_path.as_deref().map(Path::new)])
+                    .flatten()
+                    .collect();
+                for path in &touched {
+                    self.remove(path);
+                }
+                for file in library.files().iter().filter(|f| touched.contains(f.path_in_library.as_path())) {
+                    self.insert(library, file);
+                }
+                self.feed_offset = Some(offset);
+            }
+            None => self.rebuild(library)?,
+        }
+
+        if filters.camera.is_some() && self.cameras.is_none() {
+            info!("reading the camera of {} files to index them", self.entries.len());
+            let mut cameras: BTreeMap<String, HashSet<PathBuf>> = BTreeMap::new();
+            for file in library.files() {
+                let camera = camera_of(library, file).map(|c| c.to_lowercase());
+                if let Some(camera) = &camera {
+                    cameras.entry(camera.clone()).or_default().insert(file.path_in_library.clone());
+                }
+                self.entries.entry(file.path_in_library.clone()).or_default().camera = camera;
+            }
+            self.cameras = Some(cameras);
+        }
+        Ok(())
+    }
+
+    fn rebuild(&mut self, library: &Library) -> Result<()> {
+        info!("indexing {} files for queries", library.files().len());
+        let (_, offset) = changes::read_from(library.meta_root(), 0)?.unwrap_or_default();
+        let cameras = self.cameras.is_some();
+        *self = QueryIndexes { feed_offset: Some(offset), cameras: cameras.then(BTreeMap::new), ..QueryIndexes::default() };
+        for file in library.files() {
+            self.insert(library, file);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, library: &Library, file: &LibraryFile) {
+        let path = &file.path_in_library;
+        let entry = Entry {
+            taken: taken(library, file),
+            tags: file.tags.clone(),
+            camera: self.cameras.as_ref().and_then(|_| camera_of(library, file)).map(|c| c.to_lowercase()),
+        };
+
+        if let Some(taken) = entry.taken {
+            self.taken.entry(taken).or_default().insert(path.clone());
+        }
+        for tag in &entry.tags {
+            self.tags.entry(tag.clone()).or_default().insert(path.clone());
+        }
+        if let (Some(cameras), Some(camera)) = (&mut self.cameras, &entry.camera) {
+            cameras.entry(camera.clone()).or_default().insert(path.clone());
+        }
+        self.entries.insert(path.clone(), entry);
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let Some(entry) = self.entries.remove(path) else {
+            return;
+        };
+
+        fn unfile<K: Ord>(index: &mut BTreeMap<K, HashSet<PathBuf>>, key: K, path: &Path) {
+            if let Some(paths) = index.get_mut(&key) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    index.remove(&key);
+                }
+            }
+        }
+        if let Some(taken) = entry.taken {
+            unfile(&mut self.taken, taken, path);
+        }
+        for tag in entry.tags {
+            unfile(&mut self.tags, tag, path);
+        }
+        if let (Some(cameras), Some(camera)) = (&mut self.cameras, entry.camera) {
+            unfile(cameras, camera, path);
+        }
+    }
+
+    /// The only files which can pass the filters indexed here, `None` if none of them are used
+    fn candidates(&self, filters: &QueryFilters) -> Option<HashSet<&Path>> {
+        let mut sets: Vec<HashSet<&Path>> = vec![];
+        for tag in &filters.tags {
+            let paths = match tag.strip_suffix('*') {
+                Some(prefix) => self
+                    .tags
+                    .range(prefix.to_string()..)
+                    .take_while(|(t, _)| t.starts_with(prefix))
+                    .flat_map(|(_, paths)| paths)
+                    .map(PathBuf::as_path)
+                    .collect(),
+                None => self.tags.get(tag).into_iter().flatten().map(PathBuf::as_path).collect(),
+            };
+            sets.push(paths);
+        }
+
+        let after = filters.after.as_deref().and_then(|a| simulate::parse_clock(a).ok());
+        let before = filters.before.as_deref().and_then(|b| simulate::parse_clock(b).ok());
+        if after.is_some() || before.is_some() {
+            let range = (after.map_or(std::ops::Bound::Unbounded, std::ops::Bound::Included), before.map_or(std::ops::Bound::Unbounded, std::ops::Bound::Excluded));
+            let paths = match (after, before) {
+                (Some(after), Some(before)) if after >= before => HashSet::new(),
+                _ => self.taken.range(range).flat_map(|(_, paths)| paths).map(PathBuf::as_path).collect(),
+            };
+            sets.push(paths);
+        }
+
+        if let (Some(camera), Some(cameras)) = (&filters.camera, &self.cameras) {
+            sets.push(cameras.get(&camera.to_lowercase()).into_iter().flatten().map(PathBuf::as_path).collect());
+        }
+
+        let mut sets = sets.into_iter();
+        let first = sets.next()?;
+        Some(sets.fold(first, |all, set| all.intersection(&set).copied().collect()))
+    }
+}
+
+/// The words in `text`, lowercased, split at anything which isn't a letter or digit as the index's
+/// full text search does
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether `file` has `tag`, or a tag starting with it if it ends in `*`
+fn has_tag(file: &LibraryFile, tag: &str) -> bool {
+    match tag.strip_suffix('*') {
+        Some(prefix) => file.tags.iter().any(|t| t.starts_with(prefix)),
+        None => file.tags.iter().any(|t| t == tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+
+    fn file(content: &str, path: &str, taken: &str, tags: &[&str]) -> LibraryFile {
+        let mut file = indexed(content, path);
+        file.taken = Some(taken.to_string());
+        file.tags = tags.iter().map(|t| t.to_string()).collect();
+        file
+    }
+
+    fn matching(indexes: &QueryIndexes, filters: &QueryFilters) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = indexes.candidates(filters).unwrap().into_iter().map(Path::to_path_buf).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn narrows_by_tag_prefix_and_date() {
+        let root = scratch_dir("query-indexes");
+        let library = library(&root, vec![
+            file("a", "a.jpg", "2024-01-02T10:00:00", &["trip/rome", "best"]),
+            file("b", "b.jpg", "2024-03-04T10:00:00", &["trip/oslo"]),
+            file("c", "c.jpg", "2024-05-06T10:00:00", &["best"]),
+        ]);
+        let mut indexes = QueryIndexes::default();
+        indexes.update(&library, &QueryFilters::default()).unwrap();
+
+        let trips = QueryFilters { tags: vec!["trip/*".into()], ..Default::default() };
+        assert_eq!(matching(&indexes, &trips), [PathBuf::from("a.jpg"), PathBuf::from("b.jpg")]);
+        let spring = QueryFilters { tags: vec!["best".into()], after: Some("2024-02-01".into()), ..Default::default() };
+        assert_eq!(matching(&indexes, &spring), [PathBuf::from("c.jpg")]);
+        assert!(indexes.candidates(&QueryFilters::default()).is_none());
+    }
+
+    #[test]
+    fn follows_tags_and_removals() {
+        let root = scratch_dir("query-updates");
+        let mut library = library(&root, vec![
+            file("a", "a.jpg", "2024-01-02T10:00:00", &["best"]),
+            file("b", "b.jpg", "2024-03-04T10:00:00", &[]),
+        ]);
+        let mut indexes = QueryIndexes::default();
+        let best = QueryFilters { tags: vec!["best".into()], ..Default::default() };
+        indexes.update(&library, &best).unwrap();
+
+        library.retag(Path::new("b.jpg"), &["best".into()], &[]).unwrap();
+        library.remove_file(Path::new("a.jpg"), false).unwrap();
+        indexes.update(&library, &best).unwrap();
+        assert_eq!(matching(&indexes, &best), [PathBuf::from("b.jpg")]);
+        assert!(!indexes.entries.contains_key(Path::new("a.jpg")));
+
+        let files = library.files().iter().collect();
+        let found: Vec<_> = best.apply(&library, files, Some(&indexes)).unwrap().iter().map(|f| f.path_in_library.clone()).collect();
+        assert_eq!(found, [PathBuf::from("b.jpg")]);
+    }
+
+    #[test]
+    fn indexes_cameras_once_asked_for() {
+        let root = scratch_dir("query-cameras");
+        let library = library(&root, vec![file("a", "a.jpg", "2024-01-02T10:00:00", &[])]);
+        let mut indexes = QueryIndexes::default();
+        indexes.update(&library, &QueryFilters::default()).unwrap();
+        assert!(indexes.cameras.is_none());
+
+        // Not on disk, so there's no camera to read
+        let camera = QueryFilters { camera: Some("Fujifilm X100V".into()), ..Default::default() };
+        indexes.update(&library, &camera).unwrap();
+        assert_eq!(matching(&indexes, &camera), Vec::<PathBuf>::new());
+    }
+}
@@ -0,0 +1,214 @@
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::changes::{Change, ChangeKind};
+use crate::library::{FileHash, LibraryFile};
+
+/// What an import does with a file another library sharing the registry already has
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+pub enum RegistryPolicy {
+    /// Warn about it, but import it anyway
+    #[default]
+    Flag,
+    /// Leave it in the input
+    Skip,
+}
+
+/// Which registry an import checks against, and as which library
+#[derive(Debug, Clone)]
+pub struct RegistryOptions {
+    pub path: PathBuf,
+    /// What this library is called in the registry
+    pub name: String,
+    pub duplicates: RegistryPolicy,
+}
+
+/// Hashes of the files in every library sharing it, so several libraries can avoid holding
+/// the same files. An SQLite database, as libraries may import into it at the same time
+pub struct Registry {
+    db: Connection,
+    name: String,
+}
+
+/// Changes to the registry's tables, in order, as for the index. Its `user_version` is how many
+/// have been applied
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE IF NOT EXISTS files (
+        hash TEXT NOT NULL,
+        library TEXT NOT NULL,
+        path TEXT NOT NULL,
+        PRIMARY KEY (hash, library)
+    );
+    ",
+    // NULL for files recorded before it was kept, which were all SHA256
+    "ALTER TABLE files ADD COLUMN algorithm TEXT;",
+    // The library's output and inputs, canonicalised, one per line
+    "
+    CREATE TABLE IF NOT EXISTS libraries (
+        name TEXT PRIMARY KEY,
+        roots TEXT NOT NULL
+    );
+    ",
+];
+
+impl Registry {
+    pub fn open(options: &RegistryOptions) -> Result<Registry> {
+        let path = &options.path;
+        let mut db = Connection::open(path).wrap_err(format!("when opening the registry {}", path.display()))?;
+        db.pragma_update(None, "journal_mode", "WAL")?;
+        // Another library's import may be recording into it
+        db.busy_timeout(Duration::from_secs(30))?;
+
+        // Immediate, so two libraries opening an old registry at once don't both upgrade it
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            return Err(eyre!("the registry {} was written by a newer version of po, update po to use it", path.display()));
+        }
+        for migration in &MIGRATIONS[version..] {
+            tx.execute_batch(migration)?;
+        }
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        tx.commit()?;
+
+        Ok(Registry { db, name: options.name.clone() })
+    }
+
+    /// Another library which has a file with content `hash`, and its path there
+    pub fn elsewhere(&self, hash: &FileHash) -> Result<Option<(String, PathBuf)>> {
+        let found = self
+            .db
+            .query_row(
+                "SELECT library, path FROM files
+                 WHERE hash = ?1 AND COALESCE(algorithm, 'sha256') = ?2 AND library != ?3
+                 ORDER BY library LIMIT 1",
+                params![hash.encode(), hash.algorithm().name(), self.name],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+
+        Ok(found.map(|(library, path)| (library, path.into())))
+    }
+
+    /// The roots every other library sharing the registry was last used with, by library name
+    pub fn other_roots(&self) -> Result<Vec<(String, Vec<PathBuf>)>> {
+        let mut query = self.db.prepare("SELECT name, roots FROM libraries WHERE name != ?1 ORDER BY name")?;
+        let rows = query.query_map(params![self.name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut libraries = vec![];
+        for row in rows {
+            let (name, roots) = row?;
+            libraries.push((name, roots.lines().map(PathBuf::from).collect()));
+        }
+        Ok(libraries)
+    }
+
+    /// Record the roots this library is used with, for other libraries to check theirs against
+    pub fn record_roots(&self, roots: &[PathBuf]) -> Result<()> {
+        let roots = roots.iter().map(|r| r.to_string_lossy()).collect::<Vec<_>>().join("\n");
+        self.db.execute("INSERT OR REPLACE INTO libraries (name, roots) VALUES (?1, ?2)", params![self.name, roots])?;
+        Ok(())
+    }
+
+    /// Bring this library's entries up to date with `changes`, looking up what was added or moved in `files`
+    pub fn apply(&mut self, changes: &[Change], files: &[LibraryFile]) -> Result<()> {
+        let tx = self.db.transaction()?;
+        for change in changes {
+            if change.kind == ChangeKind::Removed {
+                tx.execute(
+                    "DELETE FROM files WHERE hash = ?1 AND library = ?2 AND path = ?3",
+                    params![change.hash, self.name, change.path],
+                )?;
+                continue;
+            }
+
+            // Gone again later in the same run
+            let Some(file) = files.iter().find(|f| f.path_in_library.to_string_lossy() == change.path) else {
+                continue;
+            };
+            insert(&tx, &self.name, [file])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace this library's entries with `files`, for libraries which existed before the
+    /// registry, or have had files taken out since
+    pub fn replace<'a>(&mut self, files: impl IntoIterator<Item = &'a LibraryFile>) -> Result<()> {
+        let tx = self.db.transaction()?;
+        tx.execute("DELETE FROM files WHERE library = ?1", params![self.name])?;
+        insert(&tx, &self.name, files)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn insert<'a>(tx: &Transaction, library: &str, files: impl IntoIterator<Item = &'a LibraryFile>) -> Result<()> {
+    let mut insert = tx.prepare("INSERT OR REPLACE INTO files (hash, library, path, algorithm) VALUES (?1, ?2, ?3, ?4)")?;
+    for file in files {
+        insert.execute(params![
+            file.hash.encode(),
+            library,
+            file.path_in_library.to_string_lossy(),
+            file.hash.algorithm().name()
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, scratch_dir};
+    use crate::hashing::HashAlgorithm;
+    use std::path::Path;
+
+    fn open(path: &Path, name: &str) -> Registry {
+        let options = RegistryOptions { path: path.to_path_buf(), name: name.to_string(), duplicates: RegistryPolicy::Flag };
+        Registry::open(&options).unwrap()
+    }
+
+    #[test]
+    fn follows_the_change_feed() {
+        let dir = scratch_dir("registry");
+        let path = dir.join("registry.db");
+        let mut family = open(&path, "family");
+        let work = open(&path, "work");
+
+        let file = indexed("beach", "2024/beach.jpg");
+        family.apply(&[Change::new(1, ChangeKind::Added, &file.hash, &file.path_in_library)], std::slice::from_ref(&file)).unwrap();
+        assert_eq!(work.elsewhere(&file.hash).unwrap(), Some(("family".to_string(), "2024/beach.jpg".into())));
+        assert_eq!(family.elsewhere(&file.hash).unwrap(), None);
+
+        // The same digest from another algorithm isn't the same content
+        let blake3 = FileHash::decode(&file.hash.encode(), HashAlgorithm::Blake3).unwrap();
+        assert_eq!(work.elsewhere(&blake3).unwrap(), None);
+
+        let moved = indexed("beach", "2024/07/beach.jpg");
+        let change = Change::moved(2, &moved.hash, &file.path_in_library, &moved.path_in_library);
+        family.apply(&[change], std::slice::from_ref(&moved)).unwrap();
+        assert_eq!(work.elsewhere(&file.hash).unwrap(), Some(("family".to_string(), "2024/07/beach.jpg".into())));
+
+        family.apply(&[Change::new(3, ChangeKind::Removed, &moved.hash, &moved.path_in_library)], &[]).unwrap();
+        assert_eq!(work.elsewhere(&file.hash).unwrap(), None);
+    }
+
+    #[test]
+    fn keeps_each_librarys_roots() {
+        let dir = scratch_dir("registry-roots");
+        let path = dir.join("registry.db");
+        let family = open(&path, "family");
+        let work = open(&path, "work");
+
+        family.record_roots(&["/photos".into(), "/sd".into()]).unwrap();
+        family.record_roots(&["/photos".into(), "/camera".into()]).unwrap();
+        assert_eq!(work.other_roots().unwrap(), [("family".to_string(), vec![PathBuf::from("/photos"), PathBuf::from("/camera")])]);
+        assert!(family.other_roots().unwrap().is_empty());
+    }
+}
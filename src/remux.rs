@@ -0,0 +1,120 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::Serialize;
+use std::fs;
+use std::path::Path;
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+use tracing::{debug, info, warn};
+
+use crate::derivatives::{Derivatives, Existing};
+use crate::devices;
+use crate::library::{FileHash, Library, LibraryFile, TEMPORARY_SUFFIX};
+use crate::video;
+
+/// What remuxed copies are recorded as in the derivatives
+const DERIVATIVE: &str = "remux";
+
+/// What a remuxed copy was made with, so changing the date makes it out of date
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+struct Settings {
+    creation_time: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub remuxed: usize,
+    pub up_to_date: usize,
+    /// Files which aren't MP4 or MOV videos, have no date, or didn't remux cleanly
+    pub skipped: usize,
+}
+
+/// Write a copy of each video in `files` to `dest`, mirroring its path within the library, with
+/// its container's creation time set to `date`, or else the start time in its name for action
+/// cams which put one there, or else when it was taken by the index. Nothing is re-encoded, and
+/// each copy's streams are checked against the original's before it's kept. Copies are tracked
+/// as derivatives, leaving the originals in the library untouched
+pub fn to_directory(library: &Library, files: &[&LibraryFile], dest: &Path, date: Option<PrimitiveDateTime>) -> Result<Report> {
+    if !video::SUPPORTED {
+        return Err(eyre!("this po was built without ffmpeg, build it with `--features ffmpeg` to remux videos"));
+    }
+
+    let mut derivatives = Derivatives::load(library)?;
+    let mut report = Report::default();
+    for file in files {
+        let source = library.absolute_path(file);
+        let Some(format) = video::container(&source) else {
+            debug!("{} is not an MP4 or MOV video, skipping", source.display());
+            report.skipped += 1;
+            continue;
+        };
+
+        let taken = date
+            .or_else(|| devices::recognise(&source).and_then(|d| d.taken))
+            .or_else(|| file.taken());
+        let Some(taken) = taken else {
+            warn!("po doesn't know when {} was taken, pass --date to set it, skipping", source.display());
+            report.skipped += 1;
+            continue;
+        };
+        // Action cams keep their local time in the container, in place of UTC
+        let settings = Settings {
+            creation_time: format!("{}Z", taken.format(format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"))?),
+        };
+
+        let output = dest.join(&file.path_in_library);
+        if output.exists() {
+            match derivatives.check(&output, &file.hash, DERIVATIVE, &settings)? {
+                Existing::UpToDate => {
+                    debug!("{} is up to date, skipping", output.display());
+                    report.up_to_date += 1;
+                    continue;
+                }
+                Existing::OutOfDate => info!("{} is out of date, remuxing it again", output.display()),
+                Existing::Unrecorded => {
+                    warn!("{} already exists, skipping", output.display());
+                    report.skipped += 1;
+                    continue;
+                }
+            }
+        }
+
+        if FileHash::from_file(&source, file.hash.algorithm())? != file.hash {
+            warn!("{} has changed since it was imported, run `po verify`, skipping", source.display());
+            report.skipped += 1;
+            continue;
+        }
+
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        info!("remuxing {} to {} dated {}", source.display(), output.display(), settings.creation_time);
+        let mut partial = output.as_os_str().to_owned();
+        partial.push(TEMPORARY_SUFFIX);
+        let partial = Path::new(&partial);
+        if let Err(e) = remux_checked(&source, partial, &settings.creation_time, format) {
+            let _ = fs::remove_file(partial);
+            warn!("could not remux {}: {e:#}", source.display());
+            report.skipped += 1;
+            continue;
+        }
+
+        fs::rename(partial, &output)?;
+        derivatives.record(&output, &file.hash, DERIVATIVE, &settings)?;
+        report.remuxed += 1;
+    }
+
+    Ok(report)
+}
+
+/// Remux `source` to `output`, failing unless every stream came through bit for bit
+fn remux_checked(source: &Path, output: &Path, creation_time: &str, format: &str) -> Result<()> {
+    video::remux(source, output, creation_time, format)?;
+    let before = video::stream_hashes(source).wrap_err("when hashing the original's streams")?;
+    let after = video::stream_hashes(output).wrap_err("when hashing the remuxed streams")?;
+    if before != after {
+        return Err(eyre!("its streams changed in the remux, so the copy was thrown away"));
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use color_eyre::eyre::{eyre, Result};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::library::Library;
+
+/// Renames a pattern gives across the library, and the ones which can't go ahead
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Library paths, from and to
+    pub renames: Vec<(PathBuf, PathBuf)>,
+    pub collisions: Vec<Collision>,
+}
+
+#[derive(Debug)]
+pub enum Collision {
+    /// Something is already at the new path, in the index or on disk
+    Exists { from: PathBuf, to: PathBuf },
+    /// Another file would be renamed to the same path
+    Duplicate { from: PathBuf, other: PathBuf, to: PathBuf },
+}
+
+/// Work out how `to` renames every file whose name `pattern` matches. Only the first match in
+/// each name is replaced, and `to` can refer to groups like `$1`. Directories are left alone
+pub fn plan(library: &Library, pattern: &Regex, to: &str) -> Result<Plan> {
+    let mut plan = Plan::default();
+    let indexed: HashSet<_> = library.files().iter().map(|f| f.path_in_library.clone()).collect();
+    let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for file in library.files() {
+        let Some(name) = file.path_in_library.file_name().map(|n| n.to_string_lossy()) else {
+            continue;
+        };
+        if !pattern.is_match(&name) {
+            continue;
+        }
+
+        let new_name = pattern.replace(&name, to);
+        if new_name.is_empty() || new_name.contains(['/', '\\']) || new_name == "." || new_name == ".." {
+            return Err(eyre!("{name} would be renamed to {new_name:?}, which is not a file name"));
+        }
+        if new_name == name {
+            continue;
+        }
+
+        let from = file.path_in_library.clone();
+        let new_path = from.with_file_name(new_name.as_ref());
+
+        // Even a file which is itself being renamed away counts, as the order renames happen in matters then
+        if indexed.contains(&new_path) || library.output_root().join(&new_path).exists() {
+            plan.collisions.push(Collision::Exists { from, to: new_path });
+        } else if let Some(other) = claimed.get(&new_path) {
+            plan.collisions.push(Collision::Duplicate { from, other: other.clone(), to: new_path });
+        } else {
+            claimed.insert(new_path.clone(), from.clone());
+            plan.renames.push((from, new_path));
+        }
+    }
+
+    Ok(plan)
+}
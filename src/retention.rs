@@ -0,0 +1,130 @@
+use color_eyre::eyre::{eyre, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::{Date, Duration, OffsetDateTime};
+use tracing::{debug, info, warn};
+
+use crate::library::FileHash;
+
+/// Directory within an input that imported originals are held in
+pub const HOLDING_DIR: &str = "imported-originals";
+
+// Far beyond any sensible age, and small enough to add to or take from any date po deals with
+const MAX_AGE_DAYS: i64 = 1000 * 365;
+
+const DAY_FORMAT: &[time::format_description::FormatItem<'static>] =
+    time::macros::format_description!("[year]-[month]-[day]");
+
+/// Parse ages like `30d`, `2w` or `1y`, a year being 365 days. A bare number is taken as days.
+/// Ages over 1000 years are rejected
+pub fn parse_age(raw: &str) -> Result<Duration> {
+    let raw = raw.trim();
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+
+    let number: i64 = number
+        .parse()
+        .map_err(|_| eyre!("{raw:?} is not an age, expected something like 30d"))?;
+
+    let days_per_unit = match unit {
+        "" | "d" => 1,
+        "w" => 7,
+        "y" => 365,
+        other => return Err(eyre!("unknown age unit {other:?} in {raw:?}, expected d, w or y")),
+    };
+
+    match number.checked_mul(days_per_unit) {
+        Some(days) if days <= MAX_AGE_DAYS => Ok(Duration::days(days)),
+        _ => Err(eyre!("{raw:?} is too long an age, the most is 1000y")),
+    }
+}
+
+/// Move an imported original into today's folder of the holding area next to it
+pub fn hold(source: &Path, hash: &FileHash) -> Result<PathBuf> {
+    let input = source.parent().unwrap_or(Path::new("."));
+    let today = OffsetDateTime::now_utc().date().format(DAY_FORMAT)?;
+    let dir = input.join(HOLDING_DIR).join(today);
+    fs::create_dir_all(&dir)?;
+
+    let name = source.file_name().expect("source to be a file");
+    let mut held = dir.join(name);
+    if held.exists() {
+        // Cameras reuse names once their counter wraps
+        let mut unique = name.to_os_string();
+        unique.push(format!(".{}", &hash.encode()[..8]));
+        held = dir.join(unique);
+    }
+
+    fs::rename(source, &held)?;
+    debug!("held {} at {}", source.display(), held.display());
+    Ok(held)
+}
+
+/// The holding areas within `input`, which with recursive imports can be in any of its subdirectories
+fn holding_areas(input: &Path) -> Result<Vec<PathBuf>> {
+    let mut areas = vec![];
+    let mut pending = vec![input.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            if entry.file_name() == HOLDING_DIR {
+                areas.push(entry.path());
+            } else {
+                pending.push(entry.path());
+            }
+        }
+    }
+
+    Ok(areas)
+}
+
+/// Delete held originals from the holding areas of each input which are older than `older_than`.
+/// Returns the folders removed
+pub fn clean(inputs: &[PathBuf], older_than: Duration) -> Result<Vec<PathBuf>> {
+    let cutoff = OffsetDateTime::now_utc().date() - older_than;
+    let mut removed = vec![];
+
+    for holding in inputs.iter().map(|i| holding_areas(i)).collect::<Result<Vec<_>>>()?.concat() {
+        for entry in fs::read_dir(&holding)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let Ok(held_on) = Date::parse(&name, DAY_FORMAT) else {
+                warn!("{} is not a dated holding folder, leaving it alone", path.display());
+                continue;
+            };
+
+            if held_on < cutoff {
+                info!("removing originals held on {} from {}", held_on, holding.display());
+                fs::remove_dir_all(&path)?;
+                removed.push(path);
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30").unwrap(), Duration::days(30));
+        assert_eq!(parse_age(" 30d ").unwrap(), Duration::days(30));
+        assert_eq!(parse_age("2w").unwrap(), Duration::weeks(2));
+        assert_eq!(parse_age("1y").unwrap(), Duration::days(365));
+        assert_eq!(parse_age("1000y").unwrap(), Duration::days(365_000));
+    }
+
+    #[test]
+    fn rejects_bad_ages() {
+        for raw in ["", "d", "-1d", "1.5d", "3m", "1 y", "1001y", "999999999999999999w", "99999999999999999999"] {
+            assert!(parse_age(raw).is_err(), "{raw:?} should not parse");
+        }
+    }
+}
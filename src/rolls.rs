@@ -0,0 +1,99 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use time::macros::format_description;
+use time::Date;
+
+use crate::library::{Library, LibraryFile};
+use crate::storage;
+
+/// What's known about each roll, by name, within `_pometa`
+const STORE_FILE: &str = "rolls.json";
+/// Where files imported as part of a roll are kept, in a directory named after it
+pub const ROLLS_DIR: &str = "rolls";
+/// What a file's tag starts with when it was imported as part of a roll, followed by its name
+pub const TAG_PREFIX: &str = "roll:";
+
+/// A film roll, or a session of scanning, whose scans were imported together with `po import --roll`
+#[derive(clap::Args, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(crate = "confique::serde")]
+pub struct Roll {
+    /// The film the roll was, e.g. "Portra 400"
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub film_stock: Option<String>,
+
+    /// The camera the roll was shot on
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+
+    /// When the roll was developed, e.g. 2024-09-30
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub developed: Option<String>,
+}
+
+impl Roll {
+    /// Whether nothing is known about the roll besides its name
+    pub fn is_empty(&self) -> bool {
+        *self == Roll::default()
+    }
+}
+
+/// Every roll in the library with what's known about it, by name
+pub fn load(library: &Library) -> Result<BTreeMap<String, Roll>> {
+    let path = library.meta_root().join(STORE_FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let rolls = fs::read_to_string(&path).wrap_err(format!("when reading {}", path.display()))?;
+    serde_json::from_str(&rolls).wrap_err("when parsing rolls, likely library corruption")
+}
+
+/// Record what's given in `details` about the roll `name`, keeping what was known before where
+/// it's left out. Returns everything now known about it
+pub fn update(library: &Library, name: &str, details: Roll) -> Result<Roll> {
+    check_name(name)?;
+    if let Some(developed) = &details.developed {
+        Date::parse(developed, format_description!("[year]-[month]-[day]"))
+            .wrap_err(format!("the developed date should look like 2024-09-30, not {developed}"))?;
+    }
+
+    let mut rolls = load(library)?;
+    let roll = rolls.entry(name.to_string()).or_default();
+    roll.film_stock = details.film_stock.or(roll.film_stock.take());
+    roll.camera = details.camera.or(roll.camera.take());
+    roll.developed = details.developed.or(roll.developed.take());
+    let roll = roll.clone();
+
+    let path = library.meta_root().join(STORE_FILE);
+    storage::write_atomically(&path, serde_json::to_string_pretty(&rolls)?.as_bytes())?;
+    Ok(roll)
+}
+
+/// Fails unless `name` can name a directory and a tag, as rolls are kept in both
+pub fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) || name.contains(char::is_whitespace) {
+        return Err(eyre!("{name:?} can't name a roll, use something like Portra400-2024-09 without spaces or slashes"));
+    }
+    Ok(())
+}
+
+/// Where the files of the roll `name` are sorted to, within the library
+pub fn dir(name: &str) -> PathBuf {
+    PathBuf::from(ROLLS_DIR).join(name)
+}
+
+/// The tag files imported as part of the roll `name` are given
+pub fn tag(name: &str) -> String {
+    format!("{TAG_PREFIX}{name}")
+}
+
+/// The roll `file` was imported as part of, if any
+pub fn of(file: &LibraryFile) -> Option<&str> {
+    file.tags.iter().find_map(|t| t.strip_prefix(TAG_PREFIX))
+}
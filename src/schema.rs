@@ -0,0 +1,486 @@
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use confique::serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::changes::Change;
+use crate::daemon::Task;
+use crate::hashing::HashAlgorithm;
+use crate::jobs::Job;
+use crate::library::LibraryFile;
+use crate::stats::Stats;
+
+/// Bumped whenever a `--json` output changes in a way which could break consumers.
+/// Adding fields doesn't count
+pub const VERSION: u32 = 1;
+
+/// Outputs which have a schema, for `po schema`
+pub const NAMES: &[&str] = &["query", "show", "import", "jobs", "contains", "changes", "stats"];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct FileRecord {
+    pub hash: String,
+    /// What made `hash`, see [`crate::hashing::HashAlgorithm::name`]
+    #[serde(default = "sha256")]
+    pub algorithm: String,
+    pub path: String,
+    pub imported_by: Option<String>,
+    pub sequence: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub prints: u32,
+    #[serde(default)]
+    pub people: Vec<String>,
+    #[serde(default)]
+    pub taken: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+impl From<&LibraryFile> for FileRecord {
+    fn from(file: &LibraryFile) -> Self {
+        FileRecord {
+            hash: file.hash.encode(),
+            algorithm: file.hash.algorithm().name().to_string(),
+            path: file.path_in_library.to_string_lossy().to_string(),
+            imported_by: file.imported_by.clone(),
+            sequence: file.sequence.clone(),
+            group: file.group.clone(),
+            tags: file.tags.clone(),
+            prints: file.prints,
+            people: file.people.clone(),
+            taken: file.taken.clone(),
+            text: file.text.clone(),
+            duration: file.duration,
+        }
+    }
+}
+
+// Every file was hashed with SHA256 before there was a choice
+fn sha256() -> String {
+    HashAlgorithm::Sha256.name().to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct QueryOutput {
+    pub version: u32,
+    pub files: Vec<QueryRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct QueryRecord {
+    #[serde(flatten)]
+    pub file: FileRecord,
+    /// Bytes, as indexed, or else as on disk. `None` if the file is missing from the library
+    pub size: Option<u64>,
+    /// RFC 3339, as indexed, or else from the audit log. `None` for files imported before it was kept
+    pub imported_at: Option<String>,
+    /// The last time `po verify` checked the file, `None` if it never has or `--verify-state` wasn't given
+    #[serde(default)]
+    pub last_verified: Option<VerifiedRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "confique::serde")]
+pub struct VerifiedRecord {
+    /// RFC 3339
+    pub time: String,
+    /// Whether the file was there with the content the index has
+    pub passed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct ShowOutput {
+    pub version: u32,
+    pub file: FileRecord,
+    /// How many frames the file's sequence has, if it's part of one
+    pub sequence_frames: Option<usize>,
+    /// How many files the file's group of paired files has, if it's in one
+    #[serde(default)]
+    pub group_files: Option<usize>,
+    /// Copies rendered from it with export presets
+    #[serde(default)]
+    pub derivatives: Vec<DerivativeRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct DerivativeRecord {
+    pub hash: String,
+    pub path: String,
+    pub preset: String,
+    /// RFC 3339
+    pub rendered_at: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct ImportReport {
+    pub version: u32,
+    /// Files found which weren't in the library yet
+    pub new: usize,
+    pub imported: Vec<FileRecord>,
+    /// Whether the import was stopped before every new file was imported
+    pub interrupted: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct JobRecord {
+    pub kind: String,
+    pub args: String,
+    pub started: String,
+    pub updated: String,
+    pub total: usize,
+    pub done: usize,
+    pub running: bool,
+    pub estimated_remaining_secs: Option<u64>,
+}
+
+impl From<&Job> for JobRecord {
+    fn from(job: &Job) -> Self {
+        JobRecord {
+            kind: job.kind.clone(),
+            args: job.args.clone(),
+            started: job.started.clone(),
+            updated: job.updated.clone(),
+            total: job.total,
+            done: job.progress(),
+            running: job.is_running(),
+            estimated_remaining_secs: job.estimated_remaining().map(|d| d.as_secs()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct JobsOutput {
+    pub version: u32,
+    pub jobs: Vec<JobRecord>,
+    /// What a daemon running on the library is doing, empty when there isn't one
+    pub tasks: Vec<Task>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct ContainedFile {
+    pub path: String,
+    pub hash: String,
+    /// Where the file's content is in the library, empty when it isn't
+    pub library_paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct ContainsOutput {
+    pub version: u32,
+    pub files: Vec<ContainedFile>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct ChangesOutput {
+    pub version: u32,
+    /// The generation of the last change listed, to pass as `--since` next time
+    pub generation: u64,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct LargestFile {
+    pub path: String,
+    pub bytes: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct StatsOutput {
+    pub version: u32,
+    pub files: usize,
+    pub bytes: u64,
+    pub years: BTreeMap<String, usize>,
+    pub months: BTreeMap<String, usize>,
+    pub undated: usize,
+    pub duplicates: usize,
+    pub extensions: BTreeMap<String, usize>,
+    pub largest: Vec<LargestFile>,
+    pub last_import: Option<String>,
+}
+
+impl From<Stats> for StatsOutput {
+    fn from(stats: Stats) -> Self {
+        StatsOutput {
+            version: VERSION,
+            files: stats.files,
+            bytes: stats.bytes,
+            years: stats.years,
+            months: stats.months,
+            undated: stats.undated,
+            duplicates: stats.duplicates,
+            extensions: stats.extensions,
+            largest: stats.largest.into_iter().map(|(path, bytes)| LargestFile { path, bytes }).collect(),
+            last_import: stats.last_import,
+        }
+    }
+}
+
+/// Print `output` to stdout as a single line of JSON
+pub fn print(output: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string(output)?);
+    Ok(())
+}
+
+fn counts(description: &str) -> Value {
+    json!({ "type": "object", "description": description, "additionalProperties": { "type": "integer" } })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+fn algorithms() -> Vec<&'static str> {
+    HashAlgorithm::value_variants().iter().map(|a| a.name()).collect()
+}
+
+fn file_record() -> Value {
+    json!({
+        "type": "object",
+        "required": ["hash", "algorithm", "path", "imported_by", "sequence", "group", "tags", "prints", "people", "taken", "text", "duration"],
+        "properties": {
+            "hash": { "type": "string", "description": "hex encoded hash of the file's content, made with the library's algorithm" },
+            "algorithm": { "enum": algorithms(), "description": "the hash algorithm which made hash" },
+            "path": { "type": "string", "description": "path within the library" },
+            "imported_by": nullable("string"),
+            "sequence": nullable("string"),
+            "group": nullable("string"),
+            "tags": { "type": "array", "items": { "type": "string" } },
+            "prints": { "type": "integer", "description": "how many prints of it to order, 0 for none" },
+            "people": { "type": "array", "items": { "type": "string" }, "description": "people other tools marked in it" },
+            "taken": { "type": ["string", "null"], "description": "when it was taken, YYYY-MM-DDTHH:MM:SS in the camera's time zone" },
+            "text": { "type": ["string", "null"], "description": "text read out of it by po ocr" },
+            "duration": { "type": ["number", "null"], "description": "how long it runs in seconds, for videos" },
+        }
+    })
+}
+
+fn query_record() -> Value {
+    let mut record = file_record();
+    record["required"].as_array_mut().expect("required to be an array").extend([json!("size"), json!("imported_at"), json!("last_verified")]);
+    record["properties"]["size"] = json!({ "type": ["integer", "null"], "description": "bytes, as of when it was imported or last rewritten. null if po has no record and the file is missing" });
+    record["properties"]["imported_at"] = json!({ "type": ["string", "null"], "description": "RFC 3339, null if po has no record of it" });
+    record["properties"]["last_verified"] = json!({
+        "type": ["object", "null"],
+        "description": "the last time po verify checked the file, null if it never has or without --verify-state",
+        "required": ["time", "passed"],
+        "properties": {
+            "time": { "type": "string", "description": "RFC 3339" },
+            "passed": { "type": "boolean", "description": "false if it was missing or its content had changed" },
+        }
+    });
+    record
+}
+
+fn document(name: &str, required: &[&str], properties: Value) -> Value {
+    let mut properties = properties;
+    properties["version"] = json!({ "const": VERSION });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("po/{name}/v{VERSION}"),
+        "title": format!("po {name} --json"),
+        "type": "object",
+        "required": required,
+        "properties": properties,
+    })
+}
+
+/// The JSON schema for the `--json` output of `name`
+pub fn schema(name: &str) -> Option<Value> {
+    let schema = match name {
+        "query" => document(
+            name,
+            &["version", "files"],
+            json!({ "files": { "type": "array", "items": query_record() } }),
+        ),
+        "show" => document(
+            name,
+            &["version", "file", "sequence_frames", "group_files", "derivatives"],
+            json!({
+                "file": file_record(),
+                "sequence_frames": nullable("integer"),
+                "group_files": nullable("integer"),
+                "derivatives": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["hash", "path", "preset", "rendered_at"],
+                        "properties": {
+                            "hash": { "type": "string", "description": "hex encoded hash of the rendered copy, made with the same algorithm as the file's" },
+                            "path": { "type": "string", "description": "absolute path it was exported to" },
+                            "preset": { "type": "string" },
+                            "rendered_at": { "type": "string", "description": "RFC 3339" },
+                        }
+                    }
+                },
+            }),
+        ),
+        "import" => document(
+            name,
+            &["version", "new", "imported", "interrupted"],
+            json!({
+                "new": { "type": "integer" },
+                "imported": { "type": "array", "items": file_record() },
+                "interrupted": { "type": "boolean" },
+            }),
+        ),
+        "jobs" => document(
+            name,
+            &["version", "jobs", "tasks"],
+            json!({
+                "jobs": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["kind", "args", "started", "updated", "total", "done", "running", "estimated_remaining_secs"],
+                        "properties": {
+                            "kind": { "type": "string" },
+                            "args": { "type": "string" },
+                            "started": { "type": "string", "format": "date-time" },
+                            "updated": { "type": "string", "format": "date-time" },
+                            "total": { "type": "integer" },
+                            "done": { "type": "integer" },
+                            "running": { "type": "boolean" },
+                            "estimated_remaining_secs": nullable("integer"),
+                        }
+                    }
+                },
+                "tasks": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["name", "started"],
+                        "properties": {
+                            "name": { "type": "string" },
+                            "started": { "type": "string", "format": "date-time" },
+                        }
+                    }
+                }
+            }),
+        ),
+        "contains" => document(
+            name,
+            &["version", "files"],
+            json!({
+                "files": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["path", "hash", "library_paths"],
+                        "properties": {
+                            "path": { "type": "string", "description": "the file as given, or found in a given directory" },
+                            "hash": { "type": "string", "description": "hex encoded, made with the library's hash algorithm" },
+                            "library_paths": { "type": "array", "items": { "type": "string" } },
+                        }
+                    }
+                }
+            }),
+        ),
+        "changes" => document(
+            name,
+            &["version", "generation", "changes"],
+            json!({
+                "generation": { "type": "integer" },
+                "changes": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["generation", "time", "kind", "hash", "path"],
+                        "properties": {
+                            "generation": { "type": "integer" },
+                            "time": { "type": "string", "format": "date-time" },
+                            "kind": { "enum": ["added", "removed", "modified"] },
+                            "hash": { "type": "string", "description": "hex encoded, made with the library's hash algorithm" },
+                            "path": { "type": "string", "description": "path within the library, after the change" },
+                            "previous_path": { "type": "string", "description": "where a modified file was before" },
+                        }
+                    }
+                }
+            }),
+        ),
+        "stats" => document(
+            name,
+            &["version", "files", "bytes", "years", "months", "undated", "duplicates", "extensions", "largest", "last_import"],
+            json!({
+                "files": { "type": "integer" },
+                "bytes": { "type": "integer", "description": "total size of every file in the library" },
+                "years": counts("files in each year folder"),
+                "months": counts("files in each month folder, by YYYY-MM"),
+                "undated": { "type": "integer", "description": "files outside any year folder" },
+                "duplicates": { "type": "integer", "description": "files whose content is the same as another's" },
+                "extensions": counts("files with each extension, lowercased"),
+                "largest": {
+                    "type": "array",
+                    "description": "the biggest files, biggest first",
+                    "items": {
+                        "type": "object",
+                        "required": ["path", "bytes"],
+                        "properties": {
+                            "path": { "type": "string" },
+                            "bytes": { "type": "integer" },
+                        }
+                    }
+                },
+                "last_import": { "type": ["string", "null"], "description": "RFC 3339, null if po has no record of one" },
+            }),
+        ),
+        _ => return None,
+    };
+
+    Some(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_output_has_a_schema() {
+        for name in NAMES {
+            let schema = schema(name).unwrap_or_else(|| panic!("{name} to have a schema"));
+            assert_eq!(schema["properties"]["version"]["const"], VERSION);
+        }
+        assert!(schema("doctor").is_none());
+    }
+
+    #[test]
+    fn stats_list_the_largest_files_by_name() {
+        let stats = Stats {
+            files: 1,
+            bytes: 3,
+            years: BTreeMap::new(),
+            undated: 1,
+            duplicates: 0,
+            extensions: BTreeMap::from([("jpg".to_string(), 1)]),
+            months: BTreeMap::new(),
+            largest: vec![("a.jpg".to_string(), 3)],
+            last_import: None,
+        };
+        let output = serde_json::to_value(StatsOutput::from(stats)).unwrap();
+        assert_eq!(output["largest"], json!([{ "path": "a.jpg", "bytes": 3 }]));
+
+        let required = schema("stats").unwrap()["required"].clone();
+        let keys: Vec<_> = output.as_object().unwrap().keys().map(|k| json!(k)).collect();
+        assert!(keys.iter().all(|k| required.as_array().unwrap().contains(k)), "{keys:?} are all in {required}");
+    }
+}
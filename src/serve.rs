@@ -0,0 +1,365 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::Config;
+use confique::serde::{Deserialize, Serialize, Serializer};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{debug, info, instrument, warn};
+
+use crate::budget;
+use crate::changes;
+use crate::library::{Library, TEMPORARY_SUFFIX};
+use crate::schema::{self, ChangesOutput};
+use crate::thumbs;
+
+#[derive(Config, Debug, Serialize)]
+#[serde(crate = "confique::serde")]
+#[config(layer_attr(derive(clap::Args)))]
+pub struct ServeConfig {
+    /// Address to listen on when running `po serve`
+    #[config(env = "PO_SERVE_LISTEN", default = "127.0.0.1:8080", layer_attr(arg(long = "serve-listen")))]
+    pub listen: String,
+
+    /// Directory uploaded files are dropped into, in a directory per token. Add it to `inputs`,
+    /// searched at least one directory deep, to have them imported
+    #[config(env = "PO_SERVE_STAGING", layer_attr(arg(long = "serve-staging")))]
+    pub staging: Option<PathBuf>,
+
+    /// Largest file which can be uploaded, e.g. `512MiB`
+    #[config(env = "PO_SERVE_MAX_UPLOAD", default = "4GiB", layer_attr(arg(long = "serve-max-upload")))]
+    pub max_upload: String,
+
+    /// Tokens which are allowed to upload files
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub uploads: Vec<UploadToken>,
+
+    /// Tokens which can read what's in the library, like the change feed, but not upload.
+    /// Upload tokens can read it too
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub readers: Vec<UploadToken>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(crate = "confique::serde")]
+pub struct UploadToken {
+    pub name: String,
+    #[serde(serialize_with = "redact_token")]
+    pub token: String,
+    /// Tags given to files uploaded with this token once they're imported
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// Don't leak secrets when the config gets logged
+impl Debug for UploadToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UploadToken")
+            .field("name", &self.name)
+            .field("token", &"<redacted>")
+            .field("tags", &self.tags)
+            .finish()
+    }
+}
+
+fn redact_token<S: Serializer>(_: &str, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str("<redacted>")
+}
+
+const UPLOAD_PAGE: &str = include_str!("upload.html");
+
+/// How often the server checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The staging directory, created if need be, once the tokens have been checked
+pub fn prepare(config: &ServeConfig) -> Result<PathBuf> {
+    let staging = config
+        .staging
+        .clone()
+        .ok_or_else(|| eyre!("serve.staging must be set to receive uploads"))?;
+
+    if config.uploads.is_empty() {
+        warn!("no upload tokens configured, all uploads will be rejected");
+    }
+
+    for token in &config.uploads {
+        // Uploads are staged in a directory named after their token
+        if sanitise_file_name(&token.name).as_ref() != Some(&token.name) {
+            return Err(eyre!("upload token name {:?} can't be used as a directory name", token.name));
+        }
+        if let Some(tag) = token.tags.iter().find(|t| t.is_empty() || t.contains(char::is_whitespace)) {
+            return Err(eyre!("upload token {:?} has tag {tag:?}, tags can't be empty or contain whitespace", token.name));
+        }
+    }
+    budget::parse_size(&config.max_upload).wrap_err("when reading serve.max_upload")?;
+
+    fs::create_dir_all(&staging)
+        .wrap_err(format!("when creating staging directory {}", staging.display()))?;
+    fs::canonicalize(&staging).wrap_err(format!("when resolving staging directory {}", staging.display()))
+}
+
+/// The tags for `path`, a file in `staging`, from the token it was uploaded with
+pub fn tags_for<'a>(config: &'a ServeConfig, staging: &Path, path: &Path) -> &'a [String] {
+    let token = path
+        .strip_prefix(staging)
+        .ok()
+        .and_then(|p| p.components().next())
+        .and_then(|dir| config.uploads.iter().find(|t| dir.as_os_str() == t.name.as_str()));
+
+    token.map_or(&[], |t| &t.tags)
+}
+
+/// Serve uploads into `staging`, and the change feed and previews of `library`, until `stop` is set
+pub fn run(config: &ServeConfig, staging: &Path, library: &Mutex<&mut Library>, stop: &AtomicBool) -> Result<()> {
+    let max_upload = budget::parse_size(&config.max_upload).wrap_err("when reading serve.max_upload")?;
+    let server = Server::http(&config.listen)
+        .map_err(|e| eyre!("could not listen on {}: {e}", config.listen))?;
+
+    info!("listening on {}, staging uploads into {}", config.listen, staging.display());
+
+    while !stop.load(Ordering::Relaxed) {
+        let Some(request) = server.recv_timeout(POLL_INTERVAL)? else {
+            continue;
+        };
+        if let Err(e) = handle(request, config, staging, max_upload, library) {
+            warn!("failed to handle request: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(method = %request.method(), url = request.url()))]
+fn handle(mut request: Request, config: &ServeConfig, staging: &Path, max_upload: u64, library: &Mutex<&mut Library>) -> Result<()> {
+    debug!("handling request");
+
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    match (request.method(), path) {
+        (Method::Get, "/") => {
+            let header = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..])
+                .expect("header to be valid");
+            request.respond(Response::from_string(UPLOAD_PAGE).with_header(header))?;
+        }
+        (Method::Get, "/changes") => {
+            // Lists every path and hash in the library
+            if authorise(&request, config.uploads.iter().chain(&config.readers)).is_none() {
+                request.respond(Response::from_string("invalid token").with_status_code(401))?;
+                return Ok(());
+            }
+
+            // Feed consumers poll with the generation they last saw, as `po changes --since` does
+            let since = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("since="))
+                .map(str::parse)
+                .unwrap_or(Ok(0));
+            let Ok(since) = since else {
+                request.respond(Response::from_string("invalid since").with_status_code(400))?;
+                return Ok(());
+            };
+
+            let meta_root = library.lock().expect("library lock not to be poisoned").meta_root().to_path_buf();
+            let changes = changes::since(&meta_root, since)?;
+            let output = ChangesOutput {
+                version: schema::VERSION,
+                generation: changes.last().map_or(since, |c| c.generation),
+                changes,
+            };
+
+            let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("header to be valid");
+            request.respond(Response::from_string(serde_json::to_string(&output)?).with_header(header))?;
+        }
+        (Method::Get, p) if p.starts_with("/thumbs/") => {
+            // Show what's in the library as much as the change feed does
+            if authorise(&request, config.uploads.iter().chain(&config.readers)).is_none() {
+                request.respond(Response::from_string("invalid token").with_status_code(401))?;
+                return Ok(());
+            }
+
+            // Previews made by `po thumbs`, so clients can show videos and raws too
+            let path = {
+                let library = library.lock().expect("library lock not to be poisoned");
+                sanitise_file_name(&p["/thumbs/".len()..]).and_then(|n| thumbs::named_path(&library, &n))
+            };
+            let Some(file) = path.and_then(|p| fs::File::open(p).ok()) else {
+                request.respond(Response::from_string("not found").with_status_code(404))?;
+                return Ok(());
+            };
+
+            let header = Header::from_bytes(&b"Content-Type"[..], &b"image/jpeg"[..])
+                .expect("header to be valid");
+            request.respond(Response::from_file(file).with_header(header))?;
+        }
+        (Method::Put, p) if p.starts_with("/upload/") => {
+            let Some(uploader) = authorise(&request, &config.uploads) else {
+                request.respond(Response::from_string("invalid upload token").with_status_code(401))?;
+                return Ok(());
+            };
+
+            let Some(file_name) = sanitise_file_name(&p["/upload/".len()..]) else {
+                request.respond(Response::from_string("invalid file name").with_status_code(400))?;
+                return Ok(());
+            };
+
+            if request.body_length().is_some_and(|length| length as u64 > max_upload) {
+                request.respond(Response::from_string("file too large").with_status_code(413))?;
+                return Ok(());
+            }
+
+            let staging = staging.join(&uploader.name);
+            fs::create_dir_all(&staging)?;
+            let output = staging.join(&file_name);
+            if output.exists() {
+                request.respond(Response::from_string("file already staged").with_status_code(409))?;
+                return Ok(());
+            }
+
+            // Write to a temporary name first so the importer never sees half-written files
+            let partial = staging.join(format!(".{file_name}{TEMPORARY_SUFFIX}"));
+            let written = {
+                let mut file = fs::File::create(&partial)?;
+                // Chunked uploads don't say how long they are up front
+                io::copy(&mut request.as_reader().take(max_upload + 1), &mut file)
+            };
+
+            match written {
+                Ok(bytes) if bytes > max_upload => {
+                    fs::remove_file(&partial)?;
+                    request.respond(Response::from_string("file too large").with_status_code(413))?;
+                }
+                Ok(bytes) => {
+                    fs::rename(&partial, &output)?;
+                    info!("{} uploaded {} ({} bytes)", uploader.name, output.display(), bytes);
+                    request.respond(Response::from_string("ok").with_status_code(201))?;
+                }
+                Err(e) => {
+                    fs::remove_file(&partial)?;
+                    request.respond(Response::from_string("upload failed").with_status_code(500))?;
+                    return Err(e).wrap_err("when receiving upload");
+                }
+            }
+        }
+        _ => {
+            request.respond(Response::from_string("not found").with_status_code(404))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn authorise<'a>(request: &Request, tokens: impl IntoIterator<Item = &'a UploadToken>) -> Option<&'a UploadToken> {
+    let header = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))?;
+
+    let token = header.value.as_str().strip_prefix("Bearer ")?;
+    tokens.into_iter().find(|t| t.token == token)
+}
+
+fn sanitise_file_name(raw: &str) -> Option<String> {
+    let decoded = percent_decode(raw)?;
+    let invalid = decoded.is_empty()
+        || decoded.starts_with('.')
+        || decoded.contains(['/', '\\', '\0']);
+
+    if invalid {
+        None
+    } else {
+        Some(decoded)
+    }
+}
+
+fn percent_decode(raw: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut bytes = raw.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hi = bytes.next()?;
+            let lo = bytes.next()?;
+            let hex = [hi, lo];
+            let decoded = u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?;
+            out.push(decoded);
+        } else {
+            out.push(b);
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{library, scratch_dir};
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    fn config(listen: String, staging: &Path) -> ServeConfig {
+        ServeConfig {
+            listen,
+            staging: Some(staging.to_path_buf()),
+            max_upload: "8".into(),
+            uploads: vec![UploadToken { name: "phone".into(), token: "secret".into(), tags: vec!["phone".into()] }],
+            readers: vec![],
+        }
+    }
+
+    /// The status of a PUT of `body` to `path`
+    fn put(listen: &str, path: &str, token: &str, body: &str) -> u16 {
+        let mut stream = (0..50)
+            .find_map(|_| TcpStream::connect(listen).inspect_err(|_| std::thread::sleep(Duration::from_millis(20))).ok())
+            .expect("server to be listening");
+        write!(stream, "PUT {path} HTTP/1.1\r\nHost: po\r\nAuthorization: Bearer {token}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("a status")
+    }
+
+    #[test]
+    fn tags_come_from_the_token_uploaded_with() {
+        let staging = Path::new("/staging");
+        let config = config(String::new(), staging);
+
+        assert_eq!(tags_for(&config, staging, Path::new("/staging/phone/a.jpg")), ["phone"]);
+        assert!(tags_for(&config, staging, Path::new("/staging/other/a.jpg")).is_empty());
+        assert!(tags_for(&config, staging, Path::new("/elsewhere/phone/a.jpg")).is_empty());
+    }
+
+    #[test]
+    fn stages_uploads_per_token_up_to_the_limit() {
+        let root = scratch_dir("serve");
+        let staging = scratch_dir("serve-staging");
+        let listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let config = config(listen.clone(), &staging);
+        let mut library = library(&root, vec![]);
+        let library = Mutex::new(&mut library);
+        let stop = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let server = scope.spawn(|| run(&config, &staging, &library, &stop));
+
+            assert_eq!(put(&listen, "/upload/a.jpg", "wrong", "small"), 401);
+            assert_eq!(put(&listen, "/upload/a.jpg", "secret", "small"), 201);
+            assert_eq!(put(&listen, "/upload/a.jpg", "secret", "small"), 409);
+            assert_eq!(put(&listen, "/upload/b.jpg", "secret", "much too large"), 413);
+            assert_eq!(put(&listen, "/upload/.hidden", "secret", "small"), 400);
+
+            stop.store(true, Ordering::Relaxed);
+            server.join().unwrap().unwrap();
+        });
+
+        assert_eq!(fs::read_to_string(staging.join("phone/a.jpg")).unwrap(), "small");
+        assert!(!staging.join("phone/b.jpg").exists());
+        assert_eq!(fs::read_dir(staging.join("phone")).unwrap().count(), 1);
+    }
+}
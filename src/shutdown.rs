@@ -0,0 +1,24 @@
+use color_eyre::eyre::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+use crate::t;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C so the current file can be finished and the index written before exiting.
+/// A second Ctrl-C exits straight away
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        warn!("{}", t!("shutdown-requested"));
+    })?;
+    Ok(())
+}
+
+/// Whether Ctrl-C was pressed, and work should stop at the next safe point
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
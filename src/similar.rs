@@ -0,0 +1,152 @@
+use color_eyre::eyre::{Result, WrapErr};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::library::{FileHash, Library, LibraryFile};
+use crate::transcode;
+
+/// Perceptual hashes of library files, as `<hash> <dhash>` lines keyed by content hash. Files
+/// which can't be decoded are recorded with a `-` so they aren't tried again
+const STORE_FILE: &str = "perceptual";
+
+/// Photos whose hashes differ in at most this many of their 64 bits count as the same picture
+pub const DEFAULT_DISTANCE: u32 = 6;
+
+fn store_path(library: &Library) -> PathBuf {
+    library.meta_root().join(STORE_FILE)
+}
+
+fn read_store(library: &Library) -> Result<HashMap<String, Option<u64>>> {
+    let path = store_path(library);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let mut store = HashMap::new();
+    for line in fs::read_to_string(&path)?.lines() {
+        let Some((hash, dhash)) = line.split_once(' ') else {
+            warn!("ignoring malformed line in {}: {line:?}", path.display());
+            continue;
+        };
+        store.insert(hash.to_string(), u64::from_str_radix(dhash, 16).ok());
+    }
+    Ok(store)
+}
+
+fn write_store(library: &Library, store: &HashMap<String, Option<u64>>) -> Result<()> {
+    let mut lines: Vec<_> = store
+        .iter()
+        .map(|(hash, dhash)| match dhash {
+            Some(dhash) => format!("{hash} {dhash:016x}\n"),
+            None => format!("{hash} -\n"),
+        })
+        .collect();
+    lines.sort();
+    fs::write(store_path(library), lines.concat()).wrap_err("when writing perceptual hashes")
+}
+
+/// A difference hash: the image shrunk to 9x8 greys, one bit per pair of neighbouring pixels
+/// saying whether brightness goes up. Survives resizing and recompression, unlike content hashes.
+/// `None` for files which aren't photos po can decode
+pub fn dhash(path: &Path) -> Result<Option<u64>> {
+    if !transcode::can_render(path)? {
+        return Ok(None);
+    }
+
+    let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y)[0] > small.get_pixel(x, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+    Ok(Some(hash))
+}
+
+/// Work out the perceptual hashes of the files with content `hashes` which don't have one yet
+pub fn record(library: &Library, hashes: &[FileHash]) -> Result<()> {
+    let mut store = read_store(library)?;
+    let wanted: HashSet<_> = hashes.iter().collect();
+    let missing: Vec<_> = library
+        .files()
+        .iter()
+        .filter(|f| wanted.contains(&f.hash) && !store.contains_key(&f.hash.encode()))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    debug!("working out perceptual hashes of {} files", missing.len());
+    let paths: Vec<_> = missing.iter().map(|f| (f.hash.encode(), library.absolute_path(f))).collect();
+    let computed: Vec<_> = paths
+        .into_par_iter()
+        .filter_map(|(hash, path)| match dhash(&path) {
+            Ok(dhash) => Some((hash, dhash)),
+            // Left out, to be tried again next time
+            Err(e) => {
+                warn!("could not work out a perceptual hash of {}: {e:#}", path.display());
+                None
+            }
+        })
+        .collect();
+
+    store.extend(computed);
+    write_store(library, &store)
+}
+
+/// Groups of `files` which look alike, differing in at most `distance` bits of their perceptual
+/// hashes. Files missing from the store are hashed first
+pub fn groups<'a>(library: &Library, files: &[&'a LibraryFile], distance: u32) -> Result<Vec<Vec<&'a LibraryFile>>> {
+    let hashes: Vec<_> = files.iter().map(|f| f.hash.clone()).collect();
+    record(library, &hashes)?;
+    let store = read_store(library)?;
+
+    let hashed: Vec<(&LibraryFile, u64)> = files
+        .iter()
+        .filter_map(|f| store.get(&f.hash.encode()).copied().flatten().map(|d| (*f, d)))
+        .collect();
+
+    // Union find over every pair close enough, so chains of near matches end up together
+    let mut parent: Vec<usize> = (0..hashed.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            // Byte identical copies are the content hash's job
+            if hashed[i].0.hash == hashed[j].0.hash || (hashed[i].1 ^ hashed[j].1).count_ones() > distance {
+                continue;
+            }
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a] = b;
+        }
+    }
+
+    let mut grouped: HashMap<usize, Vec<&LibraryFile>> = HashMap::new();
+    for (i, (file, _)) in hashed.iter().enumerate() {
+        grouped.entry(root(&mut parent, i)).or_default().push(file);
+    }
+
+    let mut groups: Vec<_> = grouped.into_values().filter(|g| g.len() > 1).collect();
+    for group in &mut groups {
+        group.sort_by(|a, b| a.path_in_library.cmp(&b.path_in_library));
+    }
+    groups.sort_by(|a, b| a[0].path_in_library.cmp(&b[0].path_in_library));
+    Ok(groups)
+}
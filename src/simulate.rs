@@ -0,0 +1,60 @@
+use color_eyre::eyre::{eyre, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use time::macros::format_description;
+use time::{Date, PrimitiveDateTime, Time};
+
+use crate::library::{FileHash, Library};
+
+/// The library's tree as its index describes it, which import plans can be replayed against to see
+/// where files would end up, without looking at or touching the disk
+#[derive(Debug, Default)]
+pub struct VirtualFs {
+    files: BTreeMap<PathBuf, FileHash>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Placement {
+    Placed,
+    /// The path already holds other content
+    Replaces(FileHash),
+}
+
+impl VirtualFs {
+    pub fn from_library(library: &Library) -> VirtualFs {
+        VirtualFs {
+            files: library
+                .files()
+                .iter()
+                .map(|f| (f.path_in_library.clone(), f.hash.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn place(&mut self, path: &Path, hash: &FileHash) -> Placement {
+        match self.files.insert(path.to_path_buf(), hash.clone()) {
+            Some(existing) if &existing != hash => Placement::Replaces(existing),
+            _ => Placement::Placed,
+        }
+    }
+
+    /// How many files each directory holds
+    pub fn directories(&self) -> BTreeMap<&Path, usize> {
+        let mut directories = BTreeMap::new();
+        for path in self.files.keys() {
+            *directories.entry(path.parent().unwrap_or(Path::new(""))).or_default() += 1;
+        }
+
+        directories
+    }
+}
+
+/// Read a time for a fixed clock, either `2025-07-14` or `2025-07-14 20:30`
+pub fn parse_clock(value: &str) -> Result<PrimitiveDateTime> {
+    let with_time = format_description!("[year]-[month]-[day] [hour]:[minute]");
+    let date_only = format_description!("[year]-[month]-[day]");
+
+    PrimitiveDateTime::parse(value, with_time)
+        .or_else(|_| Date::parse(value, date_only).map(|d| PrimitiveDateTime::new(d, Time::MIDNIGHT)))
+        .map_err(|_| eyre!("{value} is not a time, expected something like 2025-07-14 or 2025-07-14 20:30"))
+}
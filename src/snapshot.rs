@@ -0,0 +1,165 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, instrument};
+
+use crate::library::{decode_hash_file, Library, LibraryFile, LibrarySettings};
+use crate::storage;
+
+/// The library as it was when a snapshot was taken
+pub struct Snapshot {
+    /// `None` for snapshots taken before po kept the library's settings in them
+    pub settings: Option<LibrarySettings>,
+    pub files: Vec<LibraryFile>,
+}
+
+pub enum Change<'a> {
+    Added(&'a LibraryFile),
+    Removed(&'a LibraryFile),
+    Moved { from: &'a LibraryFile, to: &'a LibraryFile },
+}
+
+fn snapshots_root(library: &Library) -> PathBuf {
+    library.meta_root().join("snapshots")
+}
+
+fn snapshot_dir(library: &Library, name: &str) -> Result<PathBuf> {
+    let invalid = name.is_empty()
+        || name.starts_with('.')
+        || name.contains(['/', '\\']);
+
+    if invalid {
+        return Err(eyre!("{name:?} is not a valid snapshot name"));
+    }
+
+    Ok(snapshots_root(library).join(name))
+}
+
+/// Record the current index and library settings, and the config file if there is one, as an
+/// immutable snapshot. They're written the way the library keeps them, see [`storage::write_copy`]
+#[instrument(skip(library))]
+pub fn create(library: &Library, name: &str, config_path: &Path) -> Result<()> {
+    let dir = snapshot_dir(library, name)?;
+    if dir.exists() {
+        return Err(eyre!("snapshot {name} already exists"));
+    }
+
+    fs::create_dir_all(&dir)?;
+    storage::write_copy(&dir, library.settings(), library.files())
+        .wrap_err(format!("when writing snapshot {name}"))?;
+
+    if config_path.exists() {
+        fs::copy(config_path, dir.join("config.toml"))
+            .wrap_err(format!("when copying config {}", config_path.display()))?;
+    }
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let mut perms = fs::metadata(&path)?.permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms)?;
+    }
+
+    info!("created snapshot {} of {} files", name, library.files().len());
+    Ok(())
+}
+
+/// Snapshot names, oldest first
+pub fn list(library: &Library) -> Result<Vec<String>> {
+    let root = snapshots_root(library);
+    if !root.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let created = entry.metadata()?.modified()?;
+        snapshots.push((created, entry.file_name().to_string_lossy().to_string()));
+    }
+
+    snapshots.sort();
+    Ok(snapshots.into_iter().map(|(_, name)| name).collect())
+}
+
+pub fn load(library: &Library, name: &str) -> Result<Snapshot> {
+    let dir = snapshot_dir(library, name)?;
+    if dir.join(storage::DATABASE_FILE).exists() {
+        let (settings, files) = storage::read_copy(&dir).wrap_err(format!("when reading snapshot {name}"))?;
+        return Ok(Snapshot { settings, files });
+    }
+
+    // Snapshots used to only hold the index, in the text format it was kept in
+    let content = fs::read_to_string(dir.join(storage::HASH_FILE))
+        .wrap_err(format!("could not read snapshot {name}, does it exist?"))?;
+    let files = decode_hash_file(&content)
+        .wrap_err(format!("when reading snapshot {name}"))?;
+    Ok(Snapshot { settings: None, files })
+}
+
+/// What changed going from `before` to `after`, matching files up by hash
+pub fn diff<'a>(before: &'a [LibraryFile], after: &'a [LibraryFile]) -> Vec<Change<'a>> {
+    let before_by_hash: HashMap<_, _> = before.iter().map(|f| (&f.hash, f)).collect();
+    let after_by_hash: HashMap<_, _> = after.iter().map(|f| (&f.hash, f)).collect();
+
+    let mut changes = vec![];
+    for file in before {
+        match after_by_hash.get(&file.hash) {
+            None => changes.push(Change::Removed(file)),
+            Some(now) if now.path_in_library != file.path_in_library => {
+                changes.push(Change::Moved { from: file, to: now })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for file in after {
+        if !before_by_hash.contains_key(&file.hash) {
+            changes.push(Change::Added(file));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::tests::{indexed, library, scratch_dir};
+    use crate::library::{encode_hash_file, NamingScheme};
+
+    #[test]
+    fn keeps_the_index_and_settings() {
+        let root = scratch_dir("snapshot");
+        let mut tagged = indexed("b", "b.jpg");
+        tagged.tags = vec!["trip".into()];
+        let mut library = library(&root, vec![indexed("a", "a.jpg"), tagged]);
+        let mut settings = library.settings().clone();
+        settings.naming = NamingScheme::HashSuffix;
+        library.replace_settings(settings).unwrap();
+
+        create(&library, "before", &root.join("po.toml")).unwrap();
+        assert!(create(&library, "before", &root.join("po.toml")).is_err());
+        assert!(fs::metadata(snapshot_dir(&library, "before").unwrap().join(storage::DATABASE_FILE)).unwrap().permissions().readonly());
+
+        let snapshot = load(&library, "before").unwrap();
+        assert_eq!(snapshot.settings.unwrap().naming, NamingScheme::HashSuffix);
+        let files: Vec<_> = snapshot.files.iter().map(|f| (f.path_in_library.clone(), f.tags.clone())).collect();
+        assert_eq!(files, [(PathBuf::from("a.jpg"), vec![]), (PathBuf::from("b.jpg"), vec!["trip".to_string()])]);
+        assert!(diff(&snapshot.files, library.files()).is_empty());
+    }
+
+    #[test]
+    fn reads_snapshots_of_only_the_index() {
+        let root = scratch_dir("snapshot-legacy");
+        let library = library(&root, vec![]);
+        let dir = snapshot_dir(&library, "old").unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(storage::HASH_FILE), encode_hash_file([&indexed("a", "a.jpg")])).unwrap();
+
+        let snapshot = load(&library, "old").unwrap();
+        assert!(snapshot.settings.is_none());
+        assert!(matches!(diff(&snapshot.files, library.files())[..], [Change::Removed(f)] if f.path_in_library == Path::new("a.jpg")));
+    }
+}
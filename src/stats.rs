@@ -0,0 +1,146 @@
+use color_eyre::eyre::Result;
+use confique::serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::library::{year_shard, Library};
+
+const CACHE_FILE: &str = "stats.cache";
+
+/// How many of the biggest files to list
+const LARGEST: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "confique::serde")]
+pub struct Stats {
+    pub files: usize,
+    /// Total size of every file in the library
+    pub bytes: u64,
+    /// Files in each year folder
+    pub years: BTreeMap<String, usize>,
+    /// Files outside any year folder, as with the MoveToRoot sort policy
+    pub undated: usize,
+    /// Files whose content is the same as another's
+    pub duplicates: usize,
+    /// Files with each extension, lowercased
+    pub extensions: BTreeMap<String, usize>,
+    /// Files in each month folder, as `YYYY-MM`
+    pub months: BTreeMap<String, usize>,
+    /// The biggest files and their sizes, biggest first
+    pub largest: Vec<(String, u64)>,
+    /// When files were last imported, RFC 3339. Imports from before po kept track don't count
+    pub last_import: Option<String>,
+}
+
+/// Stats as of a library generation, see [`Library::generation`]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "confique::serde")]
+struct Cached {
+    generation: u64,
+    stats: Stats,
+}
+
+fn cache_path(library: &Library) -> PathBuf {
+    library.meta_root().join(CACHE_FILE)
+}
+
+/// The library's stats, from the cache unless the library has changed since they were worked out.
+/// Working them out means loading the whole index, and reading the size of files imported before
+/// the index kept track of sizes
+pub fn get(library: &mut Library) -> Result<Stats> {
+    let path = cache_path(library);
+    match fs::read_to_string(&path).map(|s| serde_json::from_str::<Cached>(&s)) {
+        Ok(Ok(cached)) if cached.generation == library.generation() => {
+            debug!("using stats cached at generation {}", cached.generation);
+            return Ok(cached.stats);
+        }
+        Ok(Ok(cached)) => debug!("stats are from generation {}, the library is at {}", cached.generation, library.generation()),
+        Ok(Err(e)) => debug!("could not read stats cache: {e}"),
+        Err(_) => debug!("no stats cache"),
+    }
+
+    library.load_remaining_index()?;
+    let stats = compute(library);
+
+    let cached = Cached { generation: library.generation(), stats };
+    fs::write(&path, serde_json::to_string(&cached)?)?;
+    Ok(cached.stats)
+}
+
+fn compute(library: &Library) -> Stats {
+    let mut stats = Stats {
+        files: library.files().len(),
+        bytes: 0,
+        years: BTreeMap::new(),
+        undated: 0,
+        duplicates: 0,
+        extensions: BTreeMap::new(),
+        months: BTreeMap::new(),
+        largest: vec![],
+        last_import: None,
+    };
+    let mut hashes = HashSet::new();
+    let mut sizes = vec![];
+
+    for file in library.files() {
+        match year_shard(&file.path_in_library) {
+            Some(year) => *stats.years.entry(year).or_default() += 1,
+            None => stats.undated += 1,
+        }
+        if let Some(month) = month_shard(&file.path_in_library) {
+            *stats.months.entry(month).or_default() += 1;
+        }
+
+        let extension = file
+            .path_in_library
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        *stats.extensions.entry(extension).or_default() += 1;
+
+        if !hashes.insert(&file.hash) {
+            stats.duplicates += 1;
+        }
+
+        if file.imported_at > stats.last_import {
+            stats.last_import = file.imported_at.clone();
+        }
+
+        let size = match file.size {
+            Some(size) => size,
+            None => match fs::metadata(library.absolute_path(file)) {
+                Ok(meta) => meta.len(),
+                Err(e) => {
+                    warn!("could not read the size of {}: {e}", file.path_in_library.display());
+                    continue;
+                }
+            },
+        };
+        stats.bytes += size;
+        sizes.push((size, &file.path_in_library));
+    }
+
+    sizes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    stats.largest = sizes
+        .into_iter()
+        .take(LARGEST)
+        .map(|(size, path)| (path.to_string_lossy().to_string(), size))
+        .collect();
+
+    stats
+}
+
+/// `YYYY-MM` for paths under a month folder, like `2025/7/14` from the Date sort policy or
+/// `2025/2025-07-14` from a path template
+fn month_shard(path_in_library: &Path) -> Option<String> {
+    let year = year_shard(path_in_library)?;
+    let mut components = path_in_library.components();
+    let second = components.nth(1)?.as_os_str().to_str()?;
+    // A file directly in the year folder, rather than a month folder
+    components.next()?;
+    let month = second.strip_prefix(&format!("{year}-")).unwrap_or(second);
+    let month: u8 = month.get(..2).filter(|m| m.bytes().all(|b| b.is_ascii_digit())).unwrap_or(month).parse().ok()?;
+    (1..=12).contains(&month).then(|| format!("{year}-{month:02}"))
+}
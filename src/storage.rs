@@ -0,0 +1,791 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::bloom::BloomFilter;
+use crate::changes::{self, Change};
+use crate::hashing::HashAlgorithm;
+use crate::library::{
+    decode_hash_file, encode_hash_file, is_temporary_file, year_shard, FileHash, IndexLayout, LibraryFile,
+    LibrarySettings, TEMPORARY_SUFFIX,
+};
+
+/// Where a library keeps its index and the metadata describing it. The library's files themselves
+/// always live on the filesystem, this only covers what po knows about them
+pub trait Storage: Debug + Send {
+    fn read_settings(&self) -> Result<Option<LibrarySettings>>;
+    fn write_settings(&mut self, settings: &LibrarySettings) -> Result<()>;
+
+    /// Index entries in the years `wanted` accepts, which is passed `None` for entries outside any year.
+    /// A single index can't be read in parts, so it's read whole if `wanted` accepts those
+    fn read_index(&self, layout: IndexLayout, wanted: &dyn Fn(Option<&str>) -> bool) -> Result<Vec<LibraryFile>>;
+    /// Replace the index with `files`. With a per-year index, years which weren't `loaded`
+    /// keep what they had, `None` means every year was
+    fn write_index(&mut self, layout: IndexLayout, files: &[LibraryFile], loaded: Option<&[String]>) -> Result<()>;
+    /// Bring the index up to date with `files` after `changes`, as `write_index` does. Storages
+    /// which can update entries in place only write those at the paths `changes` touched
+    fn update_index(&mut self, layout: IndexLayout, files: &[LibraryFile], changes: &[Change], loaded: Option<&[String]>) -> Result<()> {
+        let _ = changes;
+        self.write_index(layout, files, loaded)
+    }
+
+    fn read_bloom(&self) -> Result<BloomFilter>;
+    fn write_bloom(&mut self, bloom: &BloomFilter) -> Result<()>;
+
+    fn read_generation(&self) -> Result<u64>;
+    fn write_generation(&mut self, generation: u64) -> Result<()>;
+    fn append_changes(&mut self, changes: &[Change]) -> Result<()>;
+
+    /// Paths of the stored files whose text has every one of `words` in it as a whole word, ignoring
+    /// case. `None` when this storage keeps no index of the text, for the caller to search it itself
+    fn search_text(&self, words: &[String]) -> Result<Option<HashSet<PathBuf>>> {
+        let _ = words;
+        Ok(None)
+    }
+}
+
+/// Replace `path` with `content`, so a crash part way through leaves either the old version or
+/// the new one, never half of each. It's written alongside first and renamed into place
+pub fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMPORARY_SUFFIX);
+    let temp = PathBuf::from(temp);
+
+    let mut file = fs::File::create(&temp).wrap_err(format!("when creating {}", temp.display()))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp, path).wrap_err(format!("when moving {} into place", temp.display()))?;
+
+    // The rename itself only survives a crash once the directory holding it is synced
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn is_loaded(loaded: Option<&[String]>, year: &str) -> bool {
+    loaded.is_none_or(|y| y.iter().any(|l| l == year))
+}
+
+/// Text files under `_pometa`, how po kept the index before it moved into SQLite. Settings, the bloom
+/// filter and the change feed are still kept this way
+#[derive(Debug)]
+pub struct FileStorage {
+    meta_root: PathBuf,
+}
+
+const GENERATION_FILE: &str = "generation";
+pub const HASH_FILE: &str = "hashes";
+const INDEX_DIR: &str = "index";
+/// The hash file as it was before it was last written, to recover from if the new one is damaged
+const HASH_BACKUP_FILE: &str = "hashes.bak";
+
+impl FileStorage {
+    pub fn new(meta_root: PathBuf) -> FileStorage {
+        FileStorage { meta_root }
+    }
+
+    fn read_hash_file(&self) -> Result<Vec<LibraryFile>> {
+        let path = self.meta_root.join(HASH_FILE);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let files = decode_hash_file(&fs::read_to_string(&path)?);
+        let backup = self.meta_root.join(HASH_BACKUP_FILE);
+        if files.is_err() && backup.exists() {
+            return files.wrap_err(format!(
+                "when reading {}. the version before it was last written is in {}",
+                path.display(),
+                backup.display()
+            ));
+        }
+        files
+    }
+
+    /// Write the hash file, keeping what it held before as [`HASH_BACKUP_FILE`]
+    fn write_hash_file<'a>(&self, files: impl IntoIterator<Item = &'a LibraryFile>) -> Result<()> {
+        let path = self.meta_root.join(HASH_FILE);
+        if path.exists() {
+            let backup = self.meta_root.join(HASH_BACKUP_FILE);
+            if backup.exists() {
+                fs::remove_file(&backup)?;
+            }
+            // The new version replaces the name rather than the content, so a link keeps the old one
+            if fs::hard_link(&path, &backup).is_err() {
+                fs::copy(&path, &backup)?;
+            }
+        }
+
+        write_atomically(&path, encode_hash_file(files).as_bytes())
+    }
+}
+
+impl Storage for FileStorage {
+    fn read_settings(&self) -> Result<Option<LibrarySettings>> {
+        let path = self.meta_root.join("config");
+        if !path.exists() {
+            return Ok(None)
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let settings = toml::from_str(&content)
+            .wrap_err(format!("when parsing library settings ({})", path.display()))?;
+
+        Ok(Some(settings))
+    }
+
+    fn write_settings(&mut self, settings: &LibrarySettings) -> Result<()> {
+        write_atomically(&self.meta_root.join("config"), toml::to_string(settings)?.as_bytes())
+    }
+
+    fn read_index(&self, layout: IndexLayout, wanted: &dyn Fn(Option<&str>) -> bool) -> Result<Vec<LibraryFile>> {
+        let mut files = if wanted(None) { self.read_hash_file()? } else { vec![] };
+        let index_root = self.meta_root.join(INDEX_DIR);
+
+        if layout == IndexLayout::PerYear && index_root.exists() {
+            for entry in fs::read_dir(&index_root)? {
+                let entry = entry?;
+                let year = entry.file_name().to_string_lossy().to_string();
+                // Left by a write which was cut short
+                if is_temporary_file(&entry.path()) || !wanted(Some(&year)) {
+                    continue;
+                }
+
+                let content = fs::read_to_string(entry.path())?;
+                files.extend(
+                    decode_hash_file(&content)
+                        .wrap_err(format!("when reading index for {year}"))?
+                );
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn write_index(&mut self, layout: IndexLayout, files: &[LibraryFile], loaded: Option<&[String]>) -> Result<()> {
+        let index_root = self.meta_root.join(INDEX_DIR);
+
+        match layout {
+            IndexLayout::Single => {
+                self.write_hash_file(files)?;
+
+                // Left behind when converting from a per-year index
+                if index_root.exists() {
+                    fs::remove_dir_all(&index_root)?;
+                }
+            }
+            IndexLayout::PerYear => {
+                let mut shards: HashMap<Option<String>, Vec<&LibraryFile>> = HashMap::new();
+                for file in files {
+                    shards.entry(year_shard(&file.path_in_library)).or_default().push(file);
+                }
+
+                fs::create_dir_all(&index_root)?;
+                self.write_hash_file(shards.remove(&None).unwrap_or_default())?;
+
+                // Years which are loaded but no longer have files need their shard removed
+                for entry in fs::read_dir(&index_root)? {
+                    let year = entry?.file_name().to_string_lossy().to_string();
+                    if is_loaded(loaded, &year) && !shards.contains_key(&Some(year.clone())) {
+                        fs::remove_file(index_root.join(&year))?;
+                    }
+                }
+
+                for (year, files) in shards {
+                    let year = year.expect("files outside any year to be written already");
+                    let path = index_root.join(&year);
+
+                    // New files in a year we never loaded go alongside what's already there
+                    if !is_loaded(loaded, &year) && path.exists() {
+                        let ours: HashSet<_> = files.iter().map(|f| &f.hash).collect();
+                        let existing = decode_hash_file(&fs::read_to_string(&path)?)?;
+                        let existing = existing.iter().filter(|f| !ours.contains(&f.hash));
+                        write_atomically(&path, encode_hash_file(existing.chain(files)).as_bytes())?;
+                    } else {
+                        write_atomically(&path, encode_hash_file(files).as_bytes())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_bloom(&self) -> Result<BloomFilter> {
+        BloomFilter::read(&self.meta_root.join("bloom"))
+    }
+
+    fn write_bloom(&mut self, bloom: &BloomFilter) -> Result<()> {
+        bloom.write(&self.meta_root.join("bloom"))
+    }
+
+    fn read_generation(&self) -> Result<u64> {
+        let path = self.meta_root.join(GENERATION_FILE);
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        fs::read_to_string(&path)?
+            .trim()
+            .parse()
+            .wrap_err(format!("when reading {}, likely library corruption", path.display()))
+    }
+
+    fn write_generation(&mut self, generation: u64) -> Result<()> {
+        write_atomically(&self.meta_root.join(GENERATION_FILE), generation.to_string().as_bytes())
+    }
+
+    fn append_changes(&mut self, changes: &[Change]) -> Result<()> {
+        changes::append(&self.meta_root, changes)
+    }
+}
+
+/// The index in an SQLite database, `_pometa/library.db`, so years and hashes can be looked up without
+/// reading all of it, and files can carry more metadata as po grows. Everything else is kept as files
+#[derive(Debug)]
+pub struct SqliteStorage {
+    db: Connection,
+    files: FileStorage,
+}
+
+pub const DATABASE_FILE: &str = "library.db";
+
+/// Changes to the database's tables, in order. Its `user_version` is how many have been applied,
+/// and opening it applies the rest
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE files (
+        path TEXT PRIMARY KEY NOT NULL,
+        hash TEXT NOT NULL,
+        year TEXT,
+        imported_by TEXT,
+        sequence TEXT
+    );
+    CREATE INDEX files_hash ON files (hash);
+    CREATE INDEX files_year ON files (year);
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );
+    ",
+    // Space separated, as in the text index
+    "ALTER TABLE files ADD COLUMN tags TEXT;",
+    "
+    ALTER TABLE files ADD COLUMN size INTEGER;
+    ALTER TABLE files ADD COLUMN imported_at TEXT;
+    ",
+    // `group` is an SQL keyword
+    "ALTER TABLE files ADD COLUMN file_group TEXT;",
+    // NULL for files hashed before there was a choice, which were all SHA256
+    "ALTER TABLE files ADD COLUMN algorithm TEXT;",
+    "ALTER TABLE files ADD COLUMN prints INTEGER NOT NULL DEFAULT 0;",
+    // Separated by semicolons, as in the text index
+    "ALTER TABLE files ADD COLUMN people TEXT;",
+    "ALTER TABLE files ADD COLUMN taken TEXT;",
+    "ALTER TABLE files ADD COLUMN text TEXT;",
+    // Seconds
+    "ALTER TABLE files ADD COLUMN duration REAL;",
+    // Kept in step with `files` by rowid, which stays put as po never VACUUMs. The REPLACE in
+    // INSERT_FILE needs `recursive_triggers` for the delete trigger to see what it replaces
+    "
+    CREATE VIRTUAL TABLE files_text USING fts5(text);
+    INSERT INTO files_text (rowid, text) SELECT rowid, text FROM files WHERE text IS NOT NULL;
+    CREATE TRIGGER files_text_insert AFTER INSERT ON files WHEN new.text IS NOT NULL BEGIN
+        INSERT INTO files_text (rowid, text) VALUES (new.rowid, new.text);
+    END;
+    CREATE TRIGGER files_text_delete AFTER DELETE ON files WHEN old.text IS NOT NULL BEGIN
+        DELETE FROM files_text WHERE rowid = old.rowid;
+    END;
+    CREATE TRIGGER files_text_update AFTER UPDATE OF text ON files BEGIN
+        DELETE FROM files_text WHERE rowid = old.rowid;
+        INSERT INTO files_text (rowid, text) SELECT new.rowid, new.text WHERE new.text IS NOT NULL;
+    END;
+    ",
+];
+
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// The schema version of the index in `meta_root` and the newest this po knows, read without
+/// upgrading it. `None` when there's no database yet
+pub fn schema_version(meta_root: &Path) -> Result<Option<(u32, u32)>> {
+    let path = meta_root.join(DATABASE_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let db = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .wrap_err(format!("when opening {}", path.display()))?;
+    let version: u32 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(Some((version, SCHEMA_VERSION)))
+}
+
+/// Whether opening the metadata in `meta_root` would change it, creating the database, upgrading
+/// its schema, or moving a text index into it
+pub fn needs_upgrade(meta_root: &Path) -> Result<bool> {
+    if meta_root.join(HASH_FILE).exists() {
+        return Ok(true);
+    }
+
+    Ok(schema_version(meta_root)?.is_none_or(|(found, supported)| found < supported))
+}
+
+const INSERT_FILE: &str = "INSERT OR REPLACE INTO files (path, hash, year, imported_by, sequence, tags, size, imported_at, file_group, algorithm, prints, people, taken, text, duration)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)";
+
+fn insert_file(insert: &mut rusqlite::Statement, file: &LibraryFile) -> Result<()> {
+    insert.execute(params![
+        file.path_in_library.to_string_lossy(),
+        file.hash.encode(),
+        year_shard(&file.path_in_library),
+        file.imported_by,
+        file.sequence,
+        (!file.tags.is_empty()).then(|| file.tags.join(" ")),
+        file.size,
+        file.imported_at,
+        file.group,
+        file.hash.algorithm().name(),
+        file.prints,
+        (!file.people.is_empty()).then(|| file.people.join(";")),
+        file.taken,
+        file.text,
+        file.duration,
+    ])?;
+    Ok(())
+}
+
+impl SqliteStorage {
+    pub fn open(meta_root: &Path) -> Result<SqliteStorage> {
+        let path = meta_root.join(DATABASE_FILE);
+        let mut db = Connection::open(&path).wrap_err(format!("when opening {}", path.display()))?;
+        // Lets other po commands read the index while a daemon is writing it
+        db.pragma_update(None, "journal_mode", "WAL")?;
+        db.pragma_update(None, "recursive_triggers", true)?;
+
+        let version: u32 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(eyre!("{} was written by a newer version of po, update po to use this library", path.display()));
+        }
+
+        if version < SCHEMA_VERSION {
+            debug!("upgrading {} from schema version {} to {}", path.display(), version, SCHEMA_VERSION);
+            let tx = db.transaction()?;
+            for migration in &MIGRATIONS[version as usize..] {
+                tx.execute_batch(migration)?;
+            }
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            tx.commit()?;
+        }
+
+        Ok(SqliteStorage { db, files: FileStorage::new(meta_root.to_path_buf()) })
+    }
+
+    /// Open the database without changing it, so it can be read while another po holds the lock.
+    /// It isn't upgraded, so this fails when it's from an older or newer po
+    pub fn open_read_only(meta_root: &Path) -> Result<SqliteStorage> {
+        let path = meta_root.join(DATABASE_FILE);
+        let db = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .wrap_err(format!("when opening {}", path.display()))?;
+
+        let version: u32 = db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(eyre!("{} was written by a newer version of po, update po to use this library", path.display()));
+        }
+
+        if version < SCHEMA_VERSION {
+            return Err(eyre!(
+                "{} is at schema {version}, run a po command which changes the library to upgrade it to {SCHEMA_VERSION}",
+                path.display()
+            ));
+        }
+
+        Ok(SqliteStorage { db, files: FileStorage::new(meta_root.to_path_buf()) })
+    }
+}
+
+/// Write `settings` and an index of `files` into `dir` as a library's metadata would be, for
+/// keeping a copy of it. The database isn't left in WAL mode, so it can be opened read only
+/// once it's been made read only
+pub fn write_copy(dir: &Path, settings: &LibrarySettings, files: &[LibraryFile]) -> Result<()> {
+    let mut storage = SqliteStorage::open(dir)?;
+    storage.write_settings(settings)?;
+    storage.write_index(settings.index, files, None)?;
+    storage.db.pragma_update(None, "journal_mode", "DELETE")?;
+    Ok(())
+}
+
+/// The settings and index [`write_copy`] wrote into `dir`
+pub fn read_copy(dir: &Path) -> Result<(Option<LibrarySettings>, Vec<LibraryFile>)> {
+    let storage = SqliteStorage::open_read_only(dir)?;
+    let settings = storage.read_settings()?;
+    let layout = settings.as_ref().map(|s| s.index).unwrap_or_default();
+    let files = storage.read_index(layout, &|_| true)?;
+    Ok((settings, files))
+}
+
+impl Storage for SqliteStorage {
+    fn read_settings(&self) -> Result<Option<LibrarySettings>> {
+        self.files.read_settings()
+    }
+
+    fn write_settings(&mut self, settings: &LibrarySettings) -> Result<()> {
+        self.files.write_settings(settings)
+    }
+
+    fn read_index(&self, _layout: IndexLayout, wanted: &dyn Fn(Option<&str>) -> bool) -> Result<Vec<LibraryFile>> {
+        let years = self
+            .db
+            .prepare("SELECT DISTINCT year FROM files")?
+            .query_map([], |row| row.get::<_, Option<String>>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut select = self
+            .db
+            .prepare("SELECT hash, path, imported_by, sequence, tags, size, imported_at, file_group, algorithm, prints, people, taken, text, duration FROM files WHERE year IS ?1")?;
+        let mut files = vec![];
+        for year in years.into_iter().filter(|y| wanted(y.as_deref())) {
+            let rows = select.query_map([&year], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get(9)?,
+                    row.get::<_, Option<String>>(10)?,
+                    row.get(11)?,
+                    row.get(12)?,
+                    row.get(13)?,
+                ))
+            })?;
+
+            for row in rows {
+                let (hash, path, imported_by, sequence, tags, size, imported_at, group, algorithm, prints, people, taken, text, duration) = row?;
+                let algorithm = match algorithm {
+                    Some(name) => HashAlgorithm::from_name(&name)
+                        .ok_or_else(|| eyre!("unknown hash algorithm {name}, likely library corruption"))?,
+                    None => HashAlgorithm::Sha256,
+                };
+                files.push(LibraryFile {
+                    hash: FileHash::decode(&hash, algorithm).wrap_err("when reading the index, likely library corruption")?,
+                    path_in_library: path.into(),
+                    imported_by,
+                    sequence,
+                    group,
+                    tags: tags.map(|t| t.split_whitespace().map(str::to_string).collect()).unwrap_or_default(),
+                    prints,
+                    people: people.map(|p| p.split(';').map(str::to_string).collect()).unwrap_or_default(),
+                    size,
+                    imported_at,
+                    taken,
+                    text,
+                    duration,
+                });
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn write_index(&mut self, _layout: IndexLayout, files: &[LibraryFile], loaded: Option<&[String]>) -> Result<()> {
+        let tx = self.db.transaction()?;
+        match loaded {
+            None => {
+                tx.execute("DELETE FROM files", [])?;
+            }
+            Some(years) => {
+                tx.execute("DELETE FROM files WHERE year IS NULL", [])?;
+                for year in years {
+                    tx.execute("DELETE FROM files WHERE year = ?1", [year])?;
+                }
+            }
+        }
+
+        {
+            let mut insert = tx.prepare(INSERT_FILE)?;
+            for file in files {
+                insert_file(&mut insert, file)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_index(&mut self, _layout: IndexLayout, files: &[LibraryFile], changes: &[Change], _loaded: Option<&[String]>) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let touched: HashSet<&str> = changes
+            .iter()
+            .flat_map(|c| [Some(c.path.as_str()), c.previous_path.as_deref()])
+            .flatten()
+            .collect();
+
+        let tx = self.db.transaction()?;
+        {
+            let mut delete = tx.prepare("DELETE FROM files WHERE path = ?1")?;
+            for path in &touched {
+                delete.execute([path])?;
+            }
+
+            let mut insert = tx.prepare(INSERT_FILE)?;
+            for file in files.iter().filter(|f| touched.contains(f.path_in_library.to_string_lossy().as_ref())) {
+                insert_file(&mut insert, file)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn read_bloom(&self) -> Result<BloomFilter> {
+        self.files.read_bloom()
+    }
+
+    fn write_bloom(&mut self, bloom: &BloomFilter) -> Result<()> {
+        self.files.write_bloom(bloom)
+    }
+
+    fn read_generation(&self) -> Result<u64> {
+        let generation: Option<String> = self
+            .db
+            .query_row("SELECT value FROM meta WHERE key = 'generation'", [], |row| row.get(0))
+            .optional()?;
+
+        generation.map_or(Ok(0), |g| g.parse().wrap_err("when reading the library generation, likely library corruption"))
+    }
+
+    fn write_generation(&mut self, generation: u64) -> Result<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('generation', ?1)",
+            [generation.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn search_text(&self, words: &[String]) -> Result<Option<HashSet<PathBuf>>> {
+        // Quoted, so FTS5 takes each as a word rather than query syntax
+        let query = words.iter().map(|w| format!("\"{}\"", w.replace('"', "\"\""))).collect::<Vec<_>>().join(" ");
+        let paths = self
+            .db
+            .prepare("SELECT files.path FROM files_text JOIN files ON files.rowid = files_text.rowid WHERE files_text MATCH ?1")?
+            .query_map([query], |row| row.get::<_, String>(0))?
+            .map(|path| path.map(PathBuf::from))
+            .collect::<Result<HashSet<_>, _>>()?;
+        Ok(Some(paths))
+    }
+
+    fn append_changes(&mut self, changes: &[Change]) -> Result<()> {
+        self.files.append_changes(changes)
+    }
+}
+
+/// Open the library metadata in `meta_root`, moving an index kept in text files by older versions of po
+/// into SQLite first. The text files are kept with a `.v1` suffix, in case anything goes wrong
+pub fn open(meta_root: &Path) -> Result<Box<dyn Storage>> {
+    let mut storage = SqliteStorage::open(meta_root)?;
+
+    // Only renamed once the database has everything, so an interrupted migration starts again
+    let hash_path = meta_root.join(HASH_FILE);
+    if hash_path.exists() {
+        let legacy = FileStorage::new(meta_root.to_path_buf());
+        let layout = legacy.read_settings()?.map(|s| s.index).unwrap_or_default();
+        let files = legacy.read_index(layout, &|_| true)?;
+        info!("moving the index of {} files into {}", files.len(), DATABASE_FILE);
+
+        storage.write_index(layout, &files, None)?;
+        storage.write_generation(legacy.read_generation()?)?;
+
+        let index_root = meta_root.join(INDEX_DIR);
+        if index_root.exists() {
+            fs::rename(&index_root, meta_root.join(format!("{INDEX_DIR}.v1")))?;
+        }
+        fs::rename(&hash_path, meta_root.join(format!("{HASH_FILE}.v1")))?;
+        let generation_path = meta_root.join(GENERATION_FILE);
+        if generation_path.exists() {
+            fs::remove_file(generation_path)?;
+        }
+    }
+
+    Ok(Box::new(storage))
+}
+
+/// Keeps everything in memory, for working with a library without writing its metadata anywhere.
+/// Nothing survives the storage being dropped
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    settings: Option<LibrarySettings>,
+    files: Vec<LibraryFile>,
+    bloom: Option<BloomFilter>,
+    generation: u64,
+    changes: Vec<Change>,
+}
+
+impl MemoryStorage {
+    /// Everything `storage` holds, except its change feed
+    pub fn copy_of(storage: &dyn Storage) -> Result<MemoryStorage> {
+        let settings = storage.read_settings()?;
+        let layout = settings.as_ref().map(|s| s.index).unwrap_or_default();
+
+        Ok(MemoryStorage {
+            files: storage.read_index(layout, &|_| true)?,
+            bloom: storage.read_bloom().ok(),
+            generation: storage.read_generation()?,
+            settings,
+            changes: vec![],
+        })
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read_settings(&self) -> Result<Option<LibrarySettings>> {
+        Ok(self.settings.clone())
+    }
+
+    fn write_settings(&mut self, settings: &LibrarySettings) -> Result<()> {
+        self.settings = Some(settings.clone());
+        Ok(())
+    }
+
+    fn read_index(&self, _layout: IndexLayout, wanted: &dyn Fn(Option<&str>) -> bool) -> Result<Vec<LibraryFile>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|f| wanted(year_shard(&f.path_in_library).as_deref()))
+            .cloned()
+            .collect())
+    }
+
+    fn write_index(&mut self, _layout: IndexLayout, files: &[LibraryFile], loaded: Option<&[String]>) -> Result<()> {
+        let ours: HashSet<_> = files.iter().map(|f| &f.hash).collect();
+        let kept = self.files.iter().filter(|f| {
+            let year = year_shard(&f.path_in_library);
+            !year.is_none_or(|y| is_loaded(loaded, &y)) && !ours.contains(&f.hash)
+        });
+
+        self.files = kept.chain(files).cloned().collect();
+        Ok(())
+    }
+
+    fn read_bloom(&self) -> Result<BloomFilter> {
+        self.bloom.clone().ok_or_else(|| eyre!("no bloom filter has been written"))
+    }
+
+    fn write_bloom(&mut self, bloom: &BloomFilter) -> Result<()> {
+        self.bloom = Some(bloom.clone());
+        Ok(())
+    }
+
+    fn read_generation(&self) -> Result<u64> {
+        Ok(self.generation)
+    }
+
+    fn write_generation(&mut self, generation: u64) -> Result<()> {
+        self.generation = generation;
+        Ok(())
+    }
+
+    fn append_changes(&mut self, changes: &[Change]) -> Result<()> {
+        self.changes.extend_from_slice(changes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::ChangeKind;
+    use crate::library::tests::{indexed, scratch_dir};
+
+    #[test]
+    fn sqlite_updates_only_changed_entries() {
+        let meta_root = scratch_dir("sqlite-update");
+        let mut storage = SqliteStorage::open(&meta_root).unwrap();
+        let (a, b, c) = (indexed("a", "2024/a.jpg"), indexed("b", "2024/b.jpg"), indexed("c", "c.jpg"));
+        storage.write_index(IndexLayout::Single, &[a.clone(), b.clone(), c.clone()], None).unwrap();
+
+        // b is tagged, c removed and a new file added. a is left out of `files`, which only an
+        // untouched entry can be without being dropped
+        let mut tagged = b.clone();
+        tagged.tags = vec!["x".into()];
+        let d = indexed("d", "2025/d.jpg");
+        let changes = [
+            Change::new(1, ChangeKind::Modified, &b.hash, &b.path_in_library),
+            Change::new(2, ChangeKind::Removed, &c.hash, &c.path_in_library),
+            Change::new(3, ChangeKind::Added, &d.hash, &d.path_in_library),
+        ];
+        storage.update_index(IndexLayout::Single, &[tagged, d], &changes, None).unwrap();
+
+        let mut files: Vec<_> = storage
+            .read_index(IndexLayout::Single, &|_| true)
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.path_in_library.to_string_lossy().to_string(), f.tags))
+            .collect();
+        files.sort();
+        assert_eq!(files, [
+            ("2024/a.jpg".to_string(), vec![]),
+            ("2024/b.jpg".to_string(), vec!["x".to_string()]),
+            ("2025/d.jpg".to_string(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn sqlite_follows_moves() {
+        let meta_root = scratch_dir("sqlite-move");
+        let mut storage = SqliteStorage::open(&meta_root).unwrap();
+        let a = indexed("a", "2024/a.jpg");
+        storage.write_index(IndexLayout::Single, std::slice::from_ref(&a), None).unwrap();
+
+        let moved = indexed("a", "2024/renamed.jpg");
+        let changes = [Change::moved(1, &a.hash, &a.path_in_library, &moved.path_in_library)];
+        storage.update_index(IndexLayout::Single, std::slice::from_ref(&moved), &changes, None).unwrap();
+
+        let files = storage.read_index(IndexLayout::Single, &|_| true).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path_in_library, moved.path_in_library);
+    }
+
+    #[test]
+    fn sqlite_searches_text_by_whole_word() {
+        let meta_root = scratch_dir("sqlite-text");
+        let mut storage = SqliteStorage::open(&meta_root).unwrap();
+        let mut invoice = indexed("a", "2024/invoice.png");
+        invoice.text = Some("INVOICE #1042, total due".into());
+        let mut invoices = indexed("b", "2024/invoices.png");
+        invoices.text = Some("Unpaid invoices".into());
+        storage.write_index(IndexLayout::Single, &[invoice.clone(), invoices.clone()], None).unwrap();
+
+        let search = |storage: &SqliteStorage, words: &[&str]| {
+            let words: Vec<_> = words.iter().map(|w| w.to_string()).collect();
+            let mut paths: Vec<_> = storage.search_text(&words).unwrap().unwrap().into_iter().collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(search(&storage, &["invoice"]), [PathBuf::from("2024/invoice.png")]);
+        assert_eq!(search(&storage, &["invoice", "due"]), [PathBuf::from("2024/invoice.png")]);
+        assert_eq!(search(&storage, &["1042\" OR \"unpaid"]), Vec::<PathBuf>::new());
+
+        // Replaced text is searched, and removed files aren't found
+        invoices.text = Some("paid invoice".into());
+        let changes = [
+            Change::new(1, ChangeKind::Modified, &invoices.hash, &invoices.path_in_library),
+            Change::new(2, ChangeKind::Removed, &invoice.hash, &invoice.path_in_library),
+        ];
+        storage.update_index(IndexLayout::Single, std::slice::from_ref(&invoices), &changes, None).unwrap();
+        assert_eq!(search(&storage, &["invoice"]), [PathBuf::from("2024/invoices.png")]);
+        assert_eq!(search(&storage, &["unpaid"]), Vec::<PathBuf>::new());
+    }
+}
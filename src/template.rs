@@ -0,0 +1,86 @@
+use color_eyre::eyre::{eyre, Result};
+use std::path::{Component, PathBuf};
+
+/// Fill in the `{placeholder}`s of `template` with what `value` gives for each, `None` meaning
+/// there's no such placeholder. Placeholders can be padded to a width, `{month:02}` with zeros
+/// and `{name:10}` with spaces
+pub fn expand(template: &str, mut value: impl FnMut(&str) -> Result<Option<String>>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| eyre!("unclosed {{ in template {template:?}"))?;
+        let placeholder = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, spec)) => {
+                let width: usize = spec
+                    .parse()
+                    .map_err(|_| eyre!("{{{placeholder}}} in template {template:?} should be padded to a width like {{{name}:02}}"))?;
+                (name, Some((width, if spec.starts_with('0') { '0' } else { ' ' })))
+            }
+            None => (placeholder, None),
+        };
+
+        let value = value(name)?.ok_or_else(|| eyre!("unknown placeholder {{{name}}} in template {template:?}"))?;
+        if let Some((width, fill)) = width {
+            expanded.extend(std::iter::repeat_n(fill, width.saturating_sub(value.chars().count())));
+        }
+        expanded.push_str(&value);
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// `expanded` as a relative path, with `/` separating directories. Errors if it would leave the
+/// directory it's relative to
+pub fn relative_path(template: &str, expanded: &str) -> Result<PathBuf> {
+    // Empty segments come from placeholders which expand to nothing, like {dir} for files at the root
+    let path: PathBuf = expanded.split('/').filter(|part| !part.is_empty()).collect();
+    if path.as_os_str().is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(eyre!("template {template:?} gave {path:?}, which is not a relative path"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(name: &str) -> Result<Option<String>> {
+        Ok(match name {
+            "year" => Some("2024".into()),
+            "month" => Some("3".into()),
+            "name" => Some("IMG_0001.jpg".into()),
+            "empty" => Some(String::new()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn expands_and_pads() {
+        assert_eq!(expand("{year}/{month:02}/{name}", values).unwrap(), "2024/03/IMG_0001.jpg");
+        assert_eq!(expand("[{month:3}]", values).unwrap(), "[  3]");
+        assert_eq!(expand("no placeholders", values).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(expand("{year", values).is_err());
+        assert!(expand("{unknown}", values).is_err());
+        assert!(expand("{month:x}", values).is_err());
+    }
+
+    #[test]
+    fn relative_paths_stay_inside() {
+        assert_eq!(relative_path("t", "/2024//03/a.jpg").unwrap(), PathBuf::from("2024/03/a.jpg"));
+        assert!(relative_path("t", "2024/../../a.jpg").is_err());
+        assert!(relative_path("t", "").is_err());
+        assert!(relative_path("t", "./a.jpg").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use crate::budget;
+use crate::extensions;
+use crate::library::{Library, TEMPORARY_SUFFIX};
+use crate::progress::Progress;
+use crate::transcode::{self, OutputFormat, Preset};
+use crate::video;
+
+/// Previews of library files within `_pometa`, named by the content hash of the file they show
+const THUMBS_DIR: &str = "thumbs";
+/// The sizes the previews were last made at, so they're all made again when those change
+const STATE_FILE: &str = "thumbs.json";
+const QUALITY: u8 = 80;
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(crate = "confique::serde")]
+struct State {
+    sizes: Vec<u32>,
+    #[serde(default)]
+    strip_frames: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub made: usize,
+    /// Files there's already a preview of
+    pub existing: usize,
+    /// Files previews can't be made of, such as raws, and videos without ffmpeg
+    pub skipped: usize,
+    pub removed: usize,
+}
+
+/// One of the previews of a file
+#[derive(Clone, Copy)]
+enum Preview {
+    /// Scaled to fit a square of this size
+    Sized(u32),
+    /// Frames from through a video side by side, each fitting the thumbnail size
+    Strip,
+}
+
+/// Where the preview of the file with `hash` is kept at `size`. The first of the configured
+/// sizes is the thumbnail, `<hash>.jpg`, and any others are `<hash>_<size>.jpg`
+pub fn path(library: &Library, sizes: &[u32], hash: &str, size: u32) -> PathBuf {
+    if sizes.first() == Some(&size) {
+        return thumbnail_path(library, hash);
+    }
+    library.meta_root().join(THUMBS_DIR).join(format!("{hash}_{size}.jpg"))
+}
+
+/// Where the thumbnail of the file with `hash` is kept, whatever size it was made at
+pub fn thumbnail_path(library: &Library, hash: &str) -> PathBuf {
+    library.meta_root().join(THUMBS_DIR).join(format!("{hash}.jpg"))
+}
+
+/// Where the preview called `name`, like `<hash>.jpg` or `<hash>_strip.jpg`, is kept. `None` for
+/// names no preview could have
+pub fn named_path(library: &Library, name: &str) -> Option<PathBuf> {
+    let stem = name.strip_suffix(".jpg")?;
+    let (hash, variant) = stem.split_once('_').unwrap_or((stem, ""));
+    let valid = !hash.is_empty()
+        && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        && (variant.is_empty() || variant == "strip" || variant.bytes().all(|b| b.is_ascii_digit()));
+
+    valid.then(|| thumbnail_path(library, hash).with_file_name(name))
+}
+
+/// Where the strip of frames from the video with `hash` is kept
+pub fn strip_path(library: &Library, hash: &str) -> PathBuf {
+    library.meta_root().join(THUMBS_DIR).join(format!("{hash}_strip.jpg"))
+}
+
+/// Make previews of every file in the library which doesn't have them yet, no bigger than each
+/// of `sizes` on their longest side, and remove those of files no longer in it. Videos get theirs
+/// from a frame in the middle, and a strip of `strip_frames` frames from through them too, when
+/// po is built with ffmpeg support
+pub fn generate(library: &Library, sizes: &[u32], strip_frames: usize) -> Result<Report> {
+    if sizes.is_empty() || sizes.contains(&0) {
+        return Err(eyre!("thumbnail sizes have to be given, and bigger than 0"));
+    }
+
+    let dir = library.meta_root().join(THUMBS_DIR);
+    fs::create_dir_all(&dir).wrap_err(format!("when creating {}", dir.display()))?;
+
+    let state = State { sizes: sizes.to_vec(), strip_frames };
+    let state_path = dir.join(STATE_FILE);
+    let previous = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<State>(&s).ok());
+    let mut report = Report::default();
+    if previous.is_some_and(|p| p != state) {
+        info!("thumbnail settings changed, making them all again");
+        report.removed += clear(&dir, &HashSet::new())?;
+    }
+
+    // By hash, as files with the same content share their previews
+    let files: BTreeMap<_, _> = library.files().iter().map(|f| (f.hash.encode(), library.absolute_path(f))).collect();
+    let previews: Vec<_> = files
+        .into_iter()
+        .map(|(hash, source)| {
+            let mut previews: Vec<_> = sizes.iter().map(|size| (Preview::Sized(*size), path(library, sizes, &hash, *size))).collect();
+            if strip_frames > 0 && extensions::is_video(&source) {
+                previews.push((Preview::Strip, strip_path(library, &hash)));
+            }
+            (source, previews)
+        })
+        .collect();
+
+    let wanted: HashSet<_> = previews
+        .iter()
+        .flat_map(|(_, previews)| previews)
+        .filter_map(|(_, p)| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .collect();
+    report.removed += clear(&dir, &wanted)?;
+
+    let missing: Vec<_> = previews
+        .into_iter()
+        .filter_map(|(source, mut previews)| {
+            previews.retain(|(_, preview)| !preview.exists());
+            if previews.is_empty() {
+                report.existing += 1;
+                return None;
+            }
+            Some((source, previews))
+        })
+        .collect();
+
+    // Each render holds a whole decoded image, so only as many as the memory budget allows
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(budget::render_workers()).build()?;
+    info!("making previews of {} files with {} workers", missing.len(), pool.current_num_threads());
+    let progress = Progress::files(true, missing.len() as u64, crate::t!("progress-thumbs"));
+    let made: Vec<_> = pool.install(|| {
+        missing
+            .into_par_iter()
+            .map(|(source, previews)| {
+                let made = match render(&source, &previews, sizes[0], strip_frames) {
+                    Ok(made) => made,
+                    Err(e) => {
+                        warn!("could not make a thumbnail of {}: {e:#}", source.display());
+                        false
+                    }
+                };
+                progress.inc(1);
+                made
+            })
+            .collect()
+    });
+    drop(progress);
+
+    report.made = made.iter().filter(|m| **m).count();
+    report.skipped = made.len() - report.made;
+    fs::write(&state_path, serde_json::to_string(&state)?)?;
+    Ok(report)
+}
+
+/// Whether previews could be made of `source`, as they can't of raw files, nor of videos
+/// without ffmpeg
+fn render(source: &Path, previews: &[(Preview, PathBuf)], thumbnail_size: u32, strip_frames: usize) -> Result<bool> {
+    if transcode::can_render(source)? {
+        for (preview, path) in previews {
+            let Preview::Sized(size) = preview else { continue };
+            let preset = Preset {
+                max_dimension: Some(*size),
+                format: Some(OutputFormat::Jpeg),
+                quality: Some(QUALITY),
+                strip_gps: true,
+            };
+            let partial = partial_path(path);
+            transcode::render(source, &partial, &preset)?;
+            fs::rename(&partial, path)?;
+        }
+        return Ok(true);
+    }
+
+    if !video::SUPPORTED || !extensions::is_video(source) {
+        debug!("can't make a thumbnail of {}, skipping", source.display());
+        return Ok(false);
+    }
+
+    let largest = previews
+        .iter()
+        .filter_map(|(preview, _)| match preview {
+            Preview::Sized(size) => Some(*size),
+            Preview::Strip => None,
+        })
+        .chain([thumbnail_size])
+        .max()
+        .expect("there to be a size");
+    // The thumbnail is whichever of the strip's frames is nearest the middle
+    let count = strip_frames.max(1);
+    let frames = video::frames(source, count, largest)?;
+    let middle = frames.get(count / 2).ok_or_else(|| eyre!("ffmpeg gave no frames of {}", source.display()))?;
+
+    for (preview, path) in previews {
+        let image = match preview {
+            Preview::Sized(size) => middle.resize(*size, *size, FilterType::Lanczos3),
+            Preview::Strip => strip(&frames, thumbnail_size),
+        };
+        write_jpeg(&image, path)?;
+    }
+    Ok(true)
+}
+
+/// `frames` side by side, each scaled to fit a square of `size`
+fn strip(frames: &[DynamicImage], size: u32) -> DynamicImage {
+    let frames: Vec<_> = frames.iter().map(|f| f.resize(size, size, FilterType::Lanczos3).to_rgb8()).collect();
+    let height = frames.iter().map(|f| f.height()).max().unwrap_or(1);
+    let mut strip = RgbImage::new(frames.iter().map(|f| f.width()).sum::<u32>().max(1), height);
+    let mut x = 0;
+    for frame in frames {
+        image::imageops::replace(&mut strip, &frame, x as i64, 0);
+        x += frame.width();
+    }
+    DynamicImage::ImageRgb8(strip)
+}
+
+fn write_jpeg(image: &DynamicImage, path: &Path) -> Result<()> {
+    let partial = partial_path(path);
+    {
+        let out = BufWriter::new(fs::File::create(&partial)?);
+        transcode::encode(&DynamicImage::ImageRgb8(image.to_rgb8()), JpegEncoder::new_with_quality(out, QUALITY), None)?;
+    }
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Where a preview is written before it's renamed into place, so one cut short by a crash
+/// isn't taken as done next time
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(TEMPORARY_SUFFIX);
+    PathBuf::from(partial)
+}
+
+/// Remove the previews in `dir` which aren't `wanted`, returning how many there were
+fn clear(dir: &Path, wanted: &HashSet<String>) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == STATE_FILE || wanted.contains(&name) {
+            continue;
+        }
+        fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
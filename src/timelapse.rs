@@ -0,0 +1,72 @@
+use time::{Duration, PrimitiveDateTime};
+use tracing::debug;
+
+use crate::library::{capture_time, FileHash, UnsortedFile};
+
+/// Frames shot one after another at a fixed interval
+#[derive(Debug)]
+pub struct Sequence {
+    pub start: PrimitiveDateTime,
+    pub frames: Vec<FileHash>,
+}
+
+impl Sequence {
+    pub fn name(&self) -> String {
+        let format = time::macros::format_description!("timelapse-[year][month][day]-[hour][minute][second]");
+        self.start.format(&format).expect("sequence name to format")
+    }
+}
+
+// EXIF times only have second precision, so allow the interval to wobble by one
+const INTERVAL_TOLERANCE: Duration = Duration::SECOND;
+
+/// Find runs of at least `min_frames` files captured at a fixed interval
+pub fn detect(files: &[UnsortedFile], min_frames: usize) -> Vec<Sequence> {
+    let mut timed: Vec<(PrimitiveDateTime, &FileHash)> = files
+        .iter()
+        .filter_map(|f| Some((capture_time(f)?, &f.hash)))
+        .collect();
+    timed.sort();
+
+    let mut sequences = vec![];
+    let mut run: Vec<(PrimitiveDateTime, &FileHash)> = vec![];
+    let mut interval = Duration::ZERO;
+
+    let mut finish = |run: &mut Vec<(PrimitiveDateTime, &FileHash)>| {
+        if run.len() >= min_frames.max(2) {
+            sequences.push(Sequence {
+                start: run[0].0,
+                frames: run.iter().map(|(_, h)| (*h).clone()).collect(),
+            });
+        }
+        run.clear();
+    };
+
+    for frame in timed {
+        let Some(&(last, _)) = run.last() else {
+            run.push(frame);
+            continue;
+        };
+
+        let gap = frame.0 - last;
+        let fits = match run.len() {
+            // The second frame sets the interval for the run
+            1 => gap > Duration::ZERO,
+            _ => (gap - interval).abs() <= INTERVAL_TOLERANCE,
+        };
+
+        if fits {
+            if run.len() == 1 {
+                interval = gap;
+            }
+            run.push(frame);
+        } else {
+            finish(&mut run);
+            run.push(frame);
+        }
+    }
+    finish(&mut run);
+
+    debug!("detected {} timelapse sequences", sequences.len());
+    sequences
+}
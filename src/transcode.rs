@@ -0,0 +1,140 @@
+use color_eyre::eyre::Result;
+use confique::serde::{Deserialize, Serialize};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::{debug, warn};
+
+use crate::metadata;
+
+const DEFAULT_QUALITY: u8 = 85;
+
+/// How to resize and re-encode images, e.g. for sharing on the web
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "confique::serde")]
+pub struct Preset {
+    /// Scale images down so neither side is longer than this
+    pub max_dimension: Option<u32>,
+    /// Format to write, by default the one the image is already in
+    pub format: Option<OutputFormat>,
+    /// JPEG quality, 1 to 100
+    pub quality: Option<u8>,
+    /// Remove location data from the embedded metadata
+    #[serde(default)]
+    pub strip_gps: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(crate = "confique::serde", rename_all = "lowercase")]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    /// Lossless, `quality` is ignored
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+/// Whether `render` can read `path`. Raw files and videos can't be rendered
+pub fn can_render(path: &Path) -> Result<bool> {
+    let format = ImageReader::open(path)?.with_guessed_format()?.format();
+    Ok(matches!(format, Some(ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)))
+}
+
+/// Write `source` to `dest` as `preset` describes. Images are turned upright, and keep their
+/// embedded metadata unless it can't be carried over
+pub fn render(source: &Path, dest: &Path, preset: &Preset) -> Result<()> {
+    let mut out = BufWriter::new(fs::File::create(dest)?);
+    let format = render_into(source, &mut out, preset)?;
+    out.flush()?;
+
+    debug!("rendered {} to {} as {:?}", source.display(), dest.display(), format);
+    Ok(())
+}
+
+/// As [`render`], writing to `out`. Returns the format it was written in
+pub fn render_into(source: &Path, mut out: impl Write, preset: &Preset) -> Result<OutputFormat> {
+    let (mut image, exif, input_format) = load(source)?;
+
+    if let Some(max) = preset.max_dimension
+        && (image.width() > max || image.height() > max)
+    {
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+
+    let exif = exif.and_then(|mut exif| {
+        if !preset.strip_gps {
+            return Some(exif);
+        }
+
+        match metadata::strip_gps(&mut exif) {
+            Ok(_) => Some(exif),
+            Err(e) => {
+                warn!("could not strip gps from {} ({e}), dropping its metadata", source.display());
+                None
+            }
+        }
+    });
+
+    let format = preset.format.unwrap_or(match input_format {
+        Some(ImageFormat::Png) => OutputFormat::Png,
+        Some(ImageFormat::WebP) => OutputFormat::Webp,
+        _ => OutputFormat::Jpeg,
+    });
+
+    match format {
+        OutputFormat::Jpeg => {
+            let quality = preset.quality.unwrap_or(DEFAULT_QUALITY).clamp(1, 100);
+            encode(&image, JpegEncoder::new_with_quality(&mut out, quality), exif)?
+        }
+        OutputFormat::Png => encode(&image, PngEncoder::new(&mut out), exif)?,
+        OutputFormat::Webp => encode(&image, WebPEncoder::new_lossless(&mut out), exif)?,
+    }
+
+    Ok(format)
+}
+
+/// Decode `source` and turn it upright. Returns its embedded metadata too, without the
+/// orientation, and the format it was in
+pub fn load(source: &Path) -> Result<(DynamicImage, Option<Vec<u8>>, Option<ImageFormat>)> {
+    let reader = ImageReader::open(source)?.with_guessed_format()?;
+    let format = reader.format();
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let exif = decoder.exif_metadata()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let exif = exif.map(|mut exif| {
+        // The orientation has been applied, so viewers mustn't apply it again
+        let _ = Orientation::remove_from_exif_chunk(&mut exif);
+        exif
+    });
+    Ok((image, exif, format))
+}
+
+pub fn encode(image: &DynamicImage, mut encoder: impl ImageEncoder, exif: Option<Vec<u8>>) -> Result<()> {
+    if let Some(exif) = exif
+        && let Err(e) = encoder.set_exif_metadata(exif)
+    {
+        debug!("metadata not carried over: {e}");
+    }
+
+    image.write_with_encoder(encoder)?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::Deserialize;
+use minisign_verify::{PublicKey, Signature};
+use semver::Version;
+use std::env;
+use std::fs;
+use tracing::{debug, info, instrument};
+
+const RELEASES: &str = "https://api.github.com/repos/nullishamy/po/releases/latest";
+
+/// Key release binaries are signed with, set from the `PO_RELEASE_PUBLIC_KEY` environment variable
+/// when building. Builds without one can't verify downloads, so they refuse to update themselves
+const PUBLIC_KEY: Option<&str> = option_env!("PO_RELEASE_PUBLIC_KEY");
+
+/// Releases are far bigger than ureq's default body limit
+const MAX_DOWNLOAD: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "confique::serde")]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "confique::serde")]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// The name of the release binary built for this platform, as named by the release workflow
+fn asset_name() -> Result<&'static str> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => Ok("po-linux-amd64"),
+        ("linux", "aarch64") => Ok("po-linux-arm64"),
+        ("windows", "x86_64") => Ok("po-amd64.exe"),
+        ("macos", "x86_64") => Ok("po-darwin-amd64"),
+        ("macos", "aarch64") => Ok("po-darwin-arm64"),
+        (os, arch) => Err(eyre!("there are no release binaries for {os} on {arch}, update po by rebuilding it")),
+    }
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    debug!("downloading {url}");
+    let mut response = ureq::get(url)
+        .header("User-Agent", concat!("po/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .wrap_err(format!("when downloading {url}"))?;
+
+    Ok(response.body_mut().with_config().limit(MAX_DOWNLOAD).read_to_vec()?)
+}
+
+/// Check the signature's trusted comment, which CI sets to `po <tag> <asset>` and the signature
+/// covers, so an older signed binary or one for another platform can't pass as this release
+fn check_trusted_comment(comment: &str, version: &Version, name: &str) -> Result<()> {
+    let signed = match comment.split_whitespace().collect::<Vec<_>>()[..] {
+        ["po", tag, asset] => Version::parse(tag.trim_start_matches('v')).ok().map(|v| (v, asset)),
+        _ => None,
+    };
+
+    match signed {
+        Some((signed, asset)) if signed == *version && asset == name => Ok(()),
+        _ => Err(eyre!("the signature for {name} was made for {comment:?}, not po {version}, not updating")),
+    }
+}
+
+/// Check for a newer release and, unless `check_only`, replace the running binary with it.
+/// Downloads must carry a valid signature from the release key before anything is replaced
+#[instrument]
+pub fn run(check_only: bool) -> Result<()> {
+    let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+    let mut response = ureq::get(RELEASES)
+        .header("User-Agent", concat!("po/", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .call()
+        .wrap_err("when checking for new releases")?;
+    let release: Release = response.body_mut().read_json()?;
+
+    let latest = Version::parse(release.tag_name.trim_start_matches('v'))
+        .wrap_err(format!("release {} is not a version", release.tag_name))?;
+    if latest <= current {
+        info!("po {current} is up to date");
+        return Ok(());
+    }
+
+    info!("po {latest} is available, this is {current}");
+    if check_only {
+        return Ok(());
+    }
+
+    let key = PUBLIC_KEY
+        .ok_or_else(|| eyre!("this build of po has no release key to verify updates with, download {latest} by hand"))?;
+    let key = PublicKey::from_base64(key).map_err(|e| eyre!("the release key built into po is invalid: {e}"))?;
+
+    let name = asset_name()?;
+    let find = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|a| a.name == name)
+            .ok_or_else(|| eyre!("release {} has no {name}", release.tag_name))
+    };
+    let binary = download(&find(name)?.browser_download_url)?;
+    let signature = download(&find(&format!("{name}.minisig"))?.browser_download_url)?;
+
+    let signature = Signature::decode(&String::from_utf8_lossy(&signature))
+        .map_err(|e| eyre!("the signature for {name} could not be read: {e}"))?;
+    key.verify(&binary, &signature, false)
+        .map_err(|e| eyre!("{name} from release {} failed signature verification, not updating: {e}", release.tag_name))?;
+    check_trusted_comment(signature.trusted_comment(), &latest, name)?;
+    debug!("signature verified");
+
+    // Stage next to the running binary, so the replacement is a rename on the same filesystem
+    let exe = env::current_exe()?;
+    let staged = exe.with_file_name(format!(".{name}.update"));
+    fs::write(&staged, &binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+
+    let replaced = self_replace::self_replace(&staged).wrap_err(format!("when replacing {}", exe.display()));
+    fs::remove_file(&staged)?;
+    replaced?;
+
+    info!("updated po to {latest}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusted_comment_names_the_release_and_binary() {
+        let version = Version::parse("1.4.0").unwrap();
+        check_trusted_comment("po v1.4.0 po-linux-amd64", &version, "po-linux-amd64").unwrap();
+        check_trusted_comment("po 1.4.0 po-linux-amd64", &version, "po-linux-amd64").unwrap();
+
+        for comment in ["po v1.3.0 po-linux-amd64", "po v1.4.0 po-linux-arm64", "timestamp:1700000000", "po v1.4.0", ""] {
+            assert!(check_trusted_comment(comment, &version, "po-linux-amd64").is_err(), "{comment:?} should be refused");
+        }
+    }
+}
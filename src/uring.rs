@@ -0,0 +1,202 @@
+use io_uring::{opcode, types, IoUring};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use tracing::{debug, warn};
+
+use crate::budget;
+
+/// How many chunks are kept in flight at once
+const DEPTH: usize = 8;
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+thread_local! {
+    // Created on first use. `None` once we know io_uring isn't available, e.g. old kernels or
+    // container seccomp policies, so callers fall back to plain reads
+    static RING: RefCell<Option<Option<IoUring>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with this thread's ring, or return `None` if io_uring can't be used here
+fn with_ring<T>(f: impl FnOnce(&mut IoUring) -> io::Result<T>) -> Option<io::Result<T>> {
+    RING.with_borrow_mut(|ring| {
+        let ring = ring.get_or_insert_with(|| match IoUring::new(DEPTH as u32 * 2) {
+            Ok(ring) => Some(ring),
+            Err(e) => {
+                warn!("io_uring is unavailable ({e}), falling back to regular IO");
+                None
+            }
+        });
+
+        ring.as_mut().map(f)
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Read,
+    Writing,
+}
+
+struct Slot {
+    buf: Vec<u8>,
+    offset: u64,
+    len: usize,
+    done: usize,
+    state: State,
+    in_flight: bool,
+}
+
+/// Read `source` start to end with several reads in flight, handing each chunk to `on_chunk`
+/// in order and, if `dest` is given, writing it there at the same offset
+fn stream(
+    ring: &mut IoUring,
+    source: &fs::File,
+    dest: Option<&fs::File>,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> io::Result<u64> {
+    let size = source.metadata()?.len();
+    let mut slots: Vec<Slot> = (0..budget::io_buffer_bytes(DEPTH * CHUNK_SIZE, CHUNK_SIZE) / CHUNK_SIZE)
+        .map(|_| Slot {
+            buf: vec![0; CHUNK_SIZE],
+            offset: 0,
+            len: 0,
+            done: 0,
+            state: State::Idle,
+            in_flight: false,
+        })
+        .collect();
+
+    let mut next_read = 0;
+    let mut next_consume = 0;
+
+    loop {
+        // Keep every idle buffer busy reading the next chunk
+        for slot in slots.iter_mut().filter(|s| s.state == State::Idle) {
+            if next_read >= size {
+                break;
+            }
+
+            slot.offset = next_read;
+            slot.len = (size - next_read).min(CHUNK_SIZE as u64) as usize;
+            slot.done = 0;
+            slot.state = State::Reading;
+            next_read += slot.len as u64;
+        }
+
+        for (i, slot) in slots.iter_mut().enumerate() {
+            if slot.in_flight || !matches!(slot.state, State::Reading | State::Writing) {
+                continue;
+            }
+
+            // Short reads and writes leave the rest of the chunk to resubmit
+            let remaining = &mut slot.buf[slot.done..slot.len];
+            let offset = slot.offset + slot.done as u64;
+            let entry = match (slot.state, dest) {
+                (State::Writing, Some(dest)) => {
+                    opcode::Write::new(types::Fd(dest.as_raw_fd()), remaining.as_ptr(), remaining.len() as u32)
+                        .offset(offset)
+                        .build()
+                }
+                _ => opcode::Read::new(types::Fd(source.as_raw_fd()), remaining.as_mut_ptr(), remaining.len() as u32)
+                    .offset(offset)
+                    .build(),
+            };
+
+            // SAFETY: the buffer lives in `slots`, which outlives the operation as we always
+            // wait for everything in flight before returning
+            unsafe {
+                ring.submission()
+                    .push(&entry.user_data(i as u64))
+                    .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+            }
+            slot.in_flight = true;
+        }
+
+        if next_consume >= size && slots.iter().all(|s| s.state == State::Idle) {
+            return Ok(size);
+        }
+
+        if let Err(e) = ring.submit_and_wait(1) {
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // We can't know what the kernel still holds, so leak the buffers rather than free them under it
+            std::mem::forget(slots);
+            return Err(e);
+        }
+
+        let completions: Vec<_> = ring.completion().map(|c| (c.user_data() as usize, c.result())).collect();
+        for &(i, _) in &completions {
+            slots[i].in_flight = false;
+        }
+
+        for (i, result) in completions {
+            let slot = &mut slots[i];
+            let error = match result {
+                r if r < 0 => Some(io::Error::from_raw_os_error(-r)),
+                0 => Some(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being read")),
+                _ => None,
+            };
+            if let Some(e) = error {
+                let in_flight = slots.iter().filter(|s| s.in_flight).count();
+                if drain(ring, in_flight).is_err() {
+                    std::mem::forget(slots);
+                }
+                return Err(e);
+            }
+
+            slot.done += result as usize;
+            if slot.done == slot.len {
+                slot.state = match slot.state {
+                    State::Reading => State::Read,
+                    _ => State::Idle,
+                };
+            }
+        }
+
+        // Hand over finished chunks in file order
+        while let Some(slot) = slots.iter_mut().find(|s| s.state == State::Read && s.offset == next_consume) {
+            on_chunk(&slot.buf[..slot.len]);
+            next_consume += slot.len as u64;
+
+            slot.done = 0;
+            slot.state = if dest.is_some() { State::Writing } else { State::Idle };
+        }
+    }
+}
+
+/// Wait for `in_flight` operations to finish, so no buffer is freed while the kernel uses it
+fn drain(ring: &mut IoUring, mut in_flight: usize) -> io::Result<()> {
+    while in_flight > 0 {
+        ring.submit_and_wait(1)?;
+        in_flight -= ring.completion().count().min(in_flight);
+    }
+    Ok(())
+}
+
+/// Feed the contents of `path` to `on_chunk`, returning how many bytes were read.
+/// `None` if io_uring isn't available
+pub fn read_file(path: &Path, on_chunk: impl FnMut(&[u8])) -> Option<io::Result<u64>> {
+    with_ring(|ring| {
+        let file = fs::File::open(path)?;
+        let read = stream(ring, &file, None, on_chunk)?;
+        debug!("read {} with io_uring", path.display());
+        Ok(read)
+    })
+}
+
+/// Copy `from` to `to`, as `fs::copy`. `None` if io_uring isn't available
+pub fn copy_file(from: &Path, to: &Path) -> Option<io::Result<u64>> {
+    with_ring(|ring| {
+        let source = fs::File::open(from)?;
+        let dest = fs::File::create(to)?;
+        let copied = stream(ring, &source, Some(&dest), |_| {})?;
+        fs::set_permissions(to, source.metadata()?.permissions())?;
+        debug!("copied {} to {} with io_uring", from.display(), to.display());
+        Ok(copied)
+    })
+}
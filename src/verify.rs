@@ -0,0 +1,196 @@
+use color_eyre::eyre::{Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, info, instrument};
+
+use crate::budget;
+use crate::checksums;
+use crate::ignores::IGNORE_FILE;
+use crate::library::{FileHash, Library, LibraryFile, META_DIR};
+use crate::schema::VerifiedRecord;
+use crate::storage;
+
+/// How the last `po verify` went for each library file, by path, within `_pometa`
+const STATE_FILE: &str = "verified.json";
+
+/// The last time `po verify` checked a file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "confique::serde")]
+pub struct LastVerified {
+    /// The hash the index had for the file then, so a file with other content since isn't
+    /// taken as verified
+    pub hash: String,
+    #[serde(flatten)]
+    pub check: VerifiedRecord,
+}
+
+/// Where a file stands with `po verify`, for `po query --verify-state`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyState {
+    /// Passed within `verify_stale_after`
+    Recent,
+    /// Passed, but longer ago than `verify_stale_after`
+    Stale,
+    Never,
+    /// Missing or changed when it was last checked
+    Failed,
+}
+
+/// Where a file whose last check was `last` stands now, counting passes older than
+/// `stale_after` as stale
+pub fn state(last: Option<&VerifiedRecord>, stale_after: Duration) -> VerifyState {
+    let Some(last) = last else {
+        return VerifyState::Never;
+    };
+    if !last.passed {
+        return VerifyState::Failed;
+    }
+
+    let age = OffsetDateTime::parse(&last.time, &Rfc3339).map(|t| OffsetDateTime::now_utc() - t);
+    match age {
+        Ok(age) if age <= stale_after => VerifyState::Recent,
+        _ => VerifyState::Stale,
+    }
+}
+
+/// When each of the library's files was last verified, by path. Left out for files with
+/// other content since
+pub fn last_verified(library: &Library) -> Result<HashMap<PathBuf, LastVerified>> {
+    let path = library.meta_root().join(STATE_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let state: HashMap<PathBuf, LastVerified> = serde_json::from_str(&fs::read_to_string(&path)?)
+        .wrap_err("when parsing verification state, likely library corruption")?;
+    let current: HashMap<_, _> = library.files().iter().map(|f| (f.path_in_library.as_path(), f.hash.encode())).collect();
+    Ok(state
+        .into_iter()
+        .filter(|(path, last)| current.get(path.as_path()) == Some(&last.hash))
+        .collect())
+}
+
+/// Note that every one of `files` was just verified, and which of them `failed`
+fn record(library: &Library, files: &[LibraryFile], failed: &HashSet<&Path>) -> Result<()> {
+    let time = OffsetDateTime::now_utc().format(&Rfc3339)?;
+    let state: HashMap<_, _> = files
+        .iter()
+        .map(|f| {
+            let passed = !failed.contains(f.path_in_library.as_path());
+            let check = VerifiedRecord { time: time.clone(), passed };
+            (&f.path_in_library, LastVerified { hash: f.hash.encode(), check })
+        })
+        .collect();
+
+    storage::write_atomically(&library.meta_root().join(STATE_FILE), serde_json::to_string(&state)?.as_bytes())
+}
+
+/// What checking the library against its files on disk found
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checked: usize,
+    /// Library paths the index has, but which aren't on disk
+    pub missing: Vec<PathBuf>,
+    /// Library paths whose content isn't what the index says, with the hash it has now
+    pub mismatched: Vec<(PathBuf, FileHash)>,
+    /// Files in the library's tree which the index doesn't know about
+    pub orphans: Vec<PathBuf>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty() && self.orphans.is_empty()
+    }
+}
+
+enum Checked {
+    Ok,
+    Missing,
+    Mismatched(FileHash),
+}
+
+/// Hash every file in the library again and compare it with the index, then look for files the
+/// index doesn't have. `workers` is how many files to hash at once, `None` for one per CPU
+#[instrument(skip(library))]
+pub fn run(library: &Library, workers: Option<usize>) -> Result<Report> {
+    let root = library.output_root();
+    let files = library.files();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(workers.unwrap_or(0)).build()?;
+    budget::set_io_workers(pool.current_num_threads());
+    info!("verifying {} files with {} workers", files.len(), pool.current_num_threads());
+
+    let results: Vec<Result<Checked>> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|file| {
+                let path = root.join(&file.path_in_library);
+                if !path.exists() {
+                    return Ok(Checked::Missing);
+                }
+
+                let hash = FileHash::from_file(&path, file.hash.algorithm()).wrap_err(format!("when hashing {}", path.display()))?;
+                Ok(if hash == file.hash { Checked::Ok } else { Checked::Mismatched(hash) })
+            })
+            .collect()
+    });
+
+    let mut report = Report { checked: results.len(), ..Report::default() };
+    for (file, result) in files.iter().zip(results) {
+        match result? {
+            Checked::Ok => {}
+            Checked::Missing => report.missing.push(file.path_in_library.clone()),
+            Checked::Mismatched(hash) => report.mismatched.push((file.path_in_library.clone(), hash)),
+        }
+    }
+
+    let failed: HashSet<_> = report
+        .missing
+        .iter()
+        .chain(report.mismatched.iter().map(|(path, _)| path))
+        .map(PathBuf::as_path)
+        .collect();
+    record(library, files, &failed)?;
+
+    let known: HashSet<_> = files.iter().map(|f| f.path_in_library.as_path()).collect();
+    for path in library_tree(root)? {
+        let relative = path.strip_prefix(root).expect("tree to be within the library");
+        if !known.contains(relative) {
+            debug!("{} is not in the index", relative.display());
+            report.orphans.push(relative.to_path_buf());
+        }
+    }
+
+    Ok(report)
+}
+
+/// Every file in the library's tree, leaving out po's own
+pub fn library_tree(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if dir == root && (entry.file_name() == META_DIR || entry.file_name() == IGNORE_FILE) {
+                continue;
+            }
+            if checksums::is_checksum_file(&entry.file_name()) {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,93 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use image::{DynamicImage, ImageFormat};
+use std::path::Path;
+use std::process::{Command, Output};
+use tracing::debug;
+
+/// Whether this po takes frames from videos, which needs ffmpeg and ffprobe on the PATH
+pub const SUPPORTED: bool = cfg!(feature = "ffmpeg");
+
+/// `count` frames spread evenly through the video at `path`, each from the middle of an equal
+/// part of it so the black frames at the very start and end are passed over. Frames are scaled
+/// to fit within `size` and turned upright
+pub fn frames(path: &Path, count: usize, size: u32) -> Result<Vec<DynamicImage>> {
+    let duration = duration(path)?;
+    (0..count)
+        .map(|i| {
+            let at = duration * (i as f64 + 0.5) / count as f64;
+            let mut command = Command::new("ffmpeg");
+            command
+                .args(["-v", "error", "-ss", &format!("{at:.3}"), "-i"])
+                .arg(path)
+                .args(["-frames:v", "1", "-vf", &format!("scale={size}:{size}:force_original_aspect_ratio=decrease")])
+                .args(["-f", "image2pipe", "-c:v", "png", "-"]);
+            let output = run(command)?;
+            image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)
+                .wrap_err(format!("when reading the frame of {} at {at:.1}s", path.display()))
+        })
+        .collect()
+}
+
+/// The ffmpeg muxer for the container of the video at `path`, if it's one po can remux: MP4,
+/// and the MP4 based formats of action cams, or MOV
+pub fn container(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    match ext.as_str() {
+        "mp4" | "m4v" | "insv" | "lrv" => Some("mp4"),
+        "mov" => Some("mov"),
+        _ => None,
+    }
+}
+
+/// Copy every stream of the video at `source` to `output` without re-encoding them, with
+/// `creation_time` in its container in place of whatever the camera wrote. `format` is the muxer
+/// from [`container`], as `output` may not have the extension to tell
+pub fn remux(source: &Path, output: &Path, creation_time: &str, format: &str) -> Result<()> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-v", "error", "-n", "-i"])
+        .arg(source)
+        .args(["-map", "0", "-c", "copy", "-map_metadata", "0"])
+        .args(["-metadata", &format!("creation_time={creation_time}"), "-f", format])
+        .arg(output);
+    run(command)?;
+    Ok(())
+}
+
+/// A SHA-256 of each stream's packets in the video at `path`, which a remux leaves the same
+pub fn stream_hashes(path: &Path) -> Result<String> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-map", "0", "-c", "copy", "-f", "streamhash", "-hash", "sha256", "-"]);
+    let output = run(command)?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// How long the video at `path` is, in seconds
+fn duration(path: &Path) -> Result<f64> {
+    let mut command = Command::new("ffprobe");
+    command
+        .args(["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path);
+    let output = run(command)?;
+
+    let duration = String::from_utf8_lossy(&output.stdout);
+    duration
+        .trim()
+        .parse()
+        .wrap_err(format!("ffprobe gave {} a duration of {:?}", path.display(), duration.trim()))
+}
+
+fn run(mut command: Command) -> Result<Output> {
+    debug!("running {:?}", command);
+    let output = command
+        .output()
+        .wrap_err(format!("when running {:?}, is ffmpeg installed?", command.get_program()))?;
+
+    if !output.status.success() {
+        return Err(eyre!("{:?} failed: {}", command.get_program(), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output)
+}
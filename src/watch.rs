@@ -0,0 +1,184 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use confique::serde::{Deserialize, Serialize};
+use fast_glob::glob_match;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::library;
+use crate::shutdown;
+
+/// How often pending files are checked for having settled, and Ctrl-C for having been pressed
+const TICK: Duration = Duration::from_millis(250);
+
+/// A file seen changing, which isn't imported until it stops
+struct Pending {
+    changed: Instant,
+    /// Its size when it last changed, `None` if it couldn't be read
+    size: Option<u64>,
+}
+
+/// What `po watch` does with the files matching a pattern, so one watched folder can fan out into
+/// different parts of the library
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "confique::serde", deny_unknown_fields)]
+pub struct WatchRule {
+    /// Glob matched against the file's path within its input, e.g. `receipts/**`, or against its
+    /// name when there's no `/` in it, e.g. `Screenshot_*`
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Tags to give the file once it's imported
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Sort the file by date into this template instead of the library's, see `path_template`
+    pub path_template: Option<String>,
+    /// Leave the file in the input
+    #[serde(default)]
+    pub skip: bool,
+}
+
+impl WatchRule {
+    fn matches(&self, within_input: &Path) -> bool {
+        let path = match self.pattern.contains('/') {
+            true => within_input.to_string_lossy(),
+            false => within_input.file_name().unwrap_or_default().to_string_lossy(),
+        };
+        glob_match(&self.pattern, path.as_ref())
+    }
+}
+
+/// Fails on rules which would only go wrong once a file had been moved
+pub fn check_rules(rules: &[WatchRule]) -> Result<()> {
+    for rule in rules {
+        if let Some(tag) = rule.tags.iter().find(|t| t.is_empty() || t.contains(char::is_whitespace)) {
+            return Err(eyre!("the watch rule for {:?} has tag {tag:?}, tags can't be empty or contain whitespace", rule.pattern));
+        }
+        if let Some(template) = &rule.path_template {
+            library::check_path_template(template).wrap_err(format!("in the watch rule for {:?}", rule.pattern))?;
+        }
+    }
+    Ok(())
+}
+
+/// The first of `rules` matching `path`, a file in `input`
+pub fn rule_for<'a>(rules: &'a [WatchRule], input: &Path, path: &Path) -> Option<&'a WatchRule> {
+    let within_input = path.strip_prefix(input).ok()?;
+    rules.iter().find(|rule| rule.matches(within_input))
+}
+
+/// Watch `inputs`, and the subdirectories of those marked recursive, for files being created or
+/// changed, calling `on_settled` with each batch of files which haven't changed for `settle`, so
+/// half written files aren't picked up. Runs until Ctrl-C is pressed
+pub fn run(
+    inputs: &[(PathBuf, bool)],
+    settle: Duration,
+    mut on_settled: impl FnMut(Vec<PathBuf>) -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).wrap_err("when starting to watch for changes")?;
+    for (input, recursive) in inputs {
+        let mode = if *recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher
+            .watch(input, mode)
+            .wrap_err(format!("when watching {}", input.display()))?;
+        info!("watching {}", input.display());
+    }
+
+    let mut pending = HashMap::new();
+    while !shutdown::requested() {
+        match rx.recv_timeout(TICK) {
+            Ok(Ok(event)) => note(&mut pending, event, inputs),
+            Ok(Err(e)) => warn!("error while watching: {e}"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(eyre!("stopped receiving changes to the inputs")),
+        }
+
+        let settled = take_settled(&mut pending, settle);
+        if settled.is_empty() {
+            continue;
+        }
+
+        debug!("{} files settled", settled.len());
+        if let Err(e) = on_settled(settled) {
+            warn!("failed to import watched files: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+fn note(pending: &mut HashMap<PathBuf, Pending>, event: Event, inputs: &[(PathBuf, bool)]) {
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        return;
+    }
+
+    let mut paths = event.paths;
+    while let Some(path) = paths.pop() {
+        // A directory moved in whole brings files which won't each have an event of their own
+        if path.is_dir() {
+            let recursive = inputs.iter().any(|(input, recursive)| *recursive && path.starts_with(input));
+            if recursive && let Ok(entries) = fs::read_dir(&path) {
+                paths.extend(entries.flatten().map(|e| e.path()));
+            }
+            continue;
+        }
+
+        let size = fs::metadata(&path).ok().map(|m| m.len());
+        pending.insert(path, Pending { changed: Instant::now(), size });
+    }
+}
+
+/// Files which have gone `settle` without changing, also checking their size hasn't moved on
+/// in case a writer's events were missed
+fn take_settled(pending: &mut HashMap<PathBuf, Pending>, settle: Duration) -> Vec<PathBuf> {
+    let mut settled = vec![];
+    pending.retain(|path, file| {
+        if file.changed.elapsed() < settle {
+            return true;
+        }
+
+        match fs::metadata(path) {
+            Ok(meta) if !meta.is_file() => false,
+            Ok(meta) if Some(meta.len()) != file.size => {
+                file.changed = Instant::now();
+                file.size = Some(meta.len());
+                true
+            }
+            Ok(_) => {
+                settled.push(path.clone());
+                false
+            }
+            // Moved away or deleted before it settled
+            Err(_) => false,
+        }
+    });
+
+    settled.sort();
+    settled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, skip: bool) -> WatchRule {
+        WatchRule { pattern: pattern.to_string(), tags: vec![], path_template: None, skip }
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let rules = [rule("receipts/**", false), rule("*.tmp", true), rule("Screenshot_*", false)];
+        let input = Path::new("/inbox");
+        let pattern = |path: &str| rule_for(&rules, input, Path::new(path)).map(|r| r.pattern.as_str());
+
+        assert_eq!(pattern("/inbox/receipts/2024/shop.tmp"), Some("receipts/**"));
+        assert_eq!(pattern("/inbox/phone/Screenshot_1.png"), Some("Screenshot_*"));
+        assert_eq!(pattern("/inbox/upload.tmp"), Some("*.tmp"));
+        assert_eq!(pattern("/inbox/IMG_1.jpg"), None);
+        assert_eq!(pattern("/elsewhere/receipts/shop.jpg"), None);
+    }
+}